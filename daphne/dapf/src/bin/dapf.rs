@@ -7,7 +7,7 @@ use daphne::{
     constants,
     hpke::HpkeReceiverConfig,
     messages::{BatchSelector, CollectReq, CollectResp, HpkeConfig, Id, Query},
-    DapMeasurement, DapVersion, ProblemDetails, VdafConfig,
+    AggShareCompression, DapMeasurement, DapVersion, ProblemDetails, VdafConfig,
 };
 use prio::codec::{Decode, Encode, ParameterizedEncode};
 use reqwest::blocking::{Client, ClientBuilder};
@@ -155,6 +155,7 @@ async fn main() -> Result<()> {
                 task_id,
                 query,
                 agg_param: Vec::default(),
+                collector_hpke_config_id: None,
             };
 
             let mut headers = reqwest::header::HeaderMap::new();
@@ -220,6 +221,7 @@ async fn main() -> Result<()> {
                     &batch_selector,
                     collect_resp.report_count,
                     collect_resp.encrypted_agg_shares,
+                    AggShareCompression::None, // TODO make a parameter
                     version,
                 )
                 .await?;
@@ -5,8 +5,7 @@
 
 use crate::{
     constants::{
-        media_type_from_leader, MEDIA_TYPE_AGG_CONT_REQ, MEDIA_TYPE_AGG_INIT_REQ,
-        MEDIA_TYPE_AGG_SHARE_REQ, MEDIA_TYPE_COLLECT_REQ,
+        media_type_for, media_type_from_leader, MediaTypeKind, MEDIA_TYPE_FIXED_SIZE_BATCHES,
     },
     messages::{constant_time_eq, Id},
     DapError, DapRequest,
@@ -114,12 +113,11 @@ pub trait BearerTokenProvider<'a> {
         // following RFC 6750, Section 2.1. Note that we would also need to replace `From<String>
         // for BearerToken` with `TryFrom<String>` so that a `DapError` can be returned if the
         // token is not formatted properly.
-        if matches!(
-            req.media_type,
-            Some(MEDIA_TYPE_AGG_INIT_REQ)
-                | Some(MEDIA_TYPE_AGG_CONT_REQ)
-                | Some(MEDIA_TYPE_AGG_SHARE_REQ)
-        ) {
+        let media_type_is = |kind| req.media_type == Some(media_type_for(kind, req.version));
+        if media_type_is(MediaTypeKind::AggInitReq)
+            || media_type_is(MediaTypeKind::AggContReq)
+            || media_type_is(MediaTypeKind::AggShareReq)
+        {
             if let Some(ref got) = req.sender_auth {
                 if let Some(expected) = self.get_leader_bearer_token_for(task_id).await? {
                     return Ok(got == expected.as_ref());
@@ -128,7 +126,9 @@ pub trait BearerTokenProvider<'a> {
             }
         }
 
-        if matches!(req.media_type, Some(MEDIA_TYPE_COLLECT_REQ)) {
+        if media_type_is(MediaTypeKind::CollectReq)
+            || req.media_type == Some(MEDIA_TYPE_FIXED_SIZE_BATCHES)
+        {
             if let Some(ref got) = req.sender_auth {
                 if let Some(expected) = self.get_collector_bearer_token_for(task_id).await? {
                     return Ok(got == expected.as_ref());
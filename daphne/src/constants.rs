@@ -3,6 +3,8 @@
 
 //! Constants used in the DAP protocol.
 
+use crate::DapVersion;
+
 // Media types for HTTP requests.
 //
 // TODO spec: Decide if media type should be enforced. (We currently don't.) In any case, it may be
@@ -18,9 +20,76 @@ pub const MEDIA_TYPE_AGG_SHARE_RESP: &str = "application/dap-aggregate-share-res
 pub const MEDIA_TYPE_COLLECT_REQ: &str = "application/dap-collect-req";
 pub const MEDIA_TYPE_COLLECT_RESP: &str = "application/dap-collect-resp";
 
+// Media type for the Aggregator's capabilities. This endpoint is not defined by the DAP spec.
+pub const MEDIA_TYPE_AGGREGATOR_INFO: &str = "application/json";
+
+// Media type for requesting the batches of a fixed-size task that are ready to be collected. This
+// endpoint is not defined by the DAP spec.
+pub const MEDIA_TYPE_FIXED_SIZE_BATCHES: &str = "application/dap-fixed-size-batches";
+
+// HTTP header carrying the key with which the Helper recognizes a retried AggregateInitializeReq
+// (see `DapRequest::idempotency_key`). The value is the URL-safe, unpadded base64 encoding of the
+// key. This header is not defined by the DAP spec.
+pub const HTTP_HEADER_IDEMPOTENCY_KEY: &str = "dap-idempotency-key";
+
+// HTTP header carrying the ID of the Collector's HPKE config under which the Helper is to encrypt
+// its aggregate share (see `DapRequest::collector_hpke_config_id`). The value is the decimal
+// encoding of the ID. This header is not defined by the DAP spec.
+pub const HTTP_HEADER_COLLECTOR_HPKE_CONFIG_ID: &str = "dap-collector-hpke-config-id";
+
+/// The kind of a DAP message sent in an HTTP request or response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MediaTypeKind {
+    HpkeConfig,
+    Report,
+    AggInitReq,
+    AggInitResp,
+    AggContReq,
+    AggContResp,
+    AggShareReq,
+    AggShareResp,
+    CollectReq,
+    CollectResp,
+}
+
+impl MediaTypeKind {
+    /// Every kind of message.
+    pub const ALL: [Self; 10] = [
+        Self::HpkeConfig,
+        Self::Report,
+        Self::AggInitReq,
+        Self::AggInitResp,
+        Self::AggContReq,
+        Self::AggContResp,
+        Self::AggShareReq,
+        Self::AggShareResp,
+        Self::CollectReq,
+        Self::CollectResp,
+    ];
+}
+
+/// Return the media type for the given kind of message in the given DAP version.
+pub fn media_type_for(kind: MediaTypeKind, version: DapVersion) -> &'static str {
+    match version {
+        // The media types are the same in each version we support.
+        DapVersion::Draft02 | DapVersion::Draft03 | DapVersion::Unknown => match kind {
+            MediaTypeKind::HpkeConfig => MEDIA_TYPE_HPKE_CONFIG,
+            MediaTypeKind::Report => MEDIA_TYPE_REPORT,
+            MediaTypeKind::AggInitReq => MEDIA_TYPE_AGG_INIT_REQ,
+            MediaTypeKind::AggInitResp => MEDIA_TYPE_AGG_INIT_RESP,
+            MediaTypeKind::AggContReq => MEDIA_TYPE_AGG_CONT_REQ,
+            MediaTypeKind::AggContResp => MEDIA_TYPE_AGG_CONT_RESP,
+            MediaTypeKind::AggShareReq => MEDIA_TYPE_AGG_SHARE_REQ,
+            MediaTypeKind::AggShareResp => MEDIA_TYPE_AGG_SHARE_RESP,
+            MediaTypeKind::CollectReq => MEDIA_TYPE_COLLECT_REQ,
+            MediaTypeKind::CollectResp => MEDIA_TYPE_COLLECT_RESP,
+        },
+    }
+}
+
 /// Check if the provided value for the HTTP Content-Type is valid media type for DAP. If so, then
 /// return a static reference to the media type.
-pub fn media_type_for(content_type: &str) -> Option<&'static str> {
+pub fn parse_media_type(content_type: &str) -> Option<&'static str> {
     match content_type {
         MEDIA_TYPE_HPKE_CONFIG => Some(MEDIA_TYPE_HPKE_CONFIG),
         MEDIA_TYPE_REPORT => Some(MEDIA_TYPE_REPORT),
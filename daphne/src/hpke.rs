@@ -16,12 +16,12 @@ use crate::{
         decode_u16_bytes, encode_u16_bytes, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId,
         HpkeKemId, Id, TransitionFailure,
     },
-    DapError,
+    DapAbort, DapError, DapVersion,
 };
 use async_trait::async_trait;
 use prio::codec::{CodecError, Decode, Encode};
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
+use std::{future::Future, io::Cursor};
 
 impl From<HpkeError> for DapError {
     fn from(_e: HpkeError) -> Self {
@@ -35,6 +35,61 @@ impl From<Error> for DapError {
     }
 }
 
+const CTX_INPUT_SHARE_DRAFT02: &[u8] = b"dap-02 input share";
+const CTX_INPUT_SHARE_DRAFT03: &[u8] = b"dap-03 input share";
+const CTX_AGG_SHARE_DRAFT02: &[u8] = b"dap-02 aggregate share";
+const CTX_AGG_SHARE_DRAFT03: &[u8] = b"dap-03 aggregate share";
+const CTX_ROLE_COLLECTOR: u8 = 0;
+const CTX_ROLE_CLIENT: u8 = 1;
+const CTX_ROLE_LEADER: u8 = 2;
+const CTX_ROLE_HELPER: u8 = 3;
+
+fn unimplemented_version() -> DapError {
+    DapError::Abort(DapAbort::BadRequest("unimplemented version".to_string()))
+}
+
+fn info_for(text: &[u8], sender: u8, receiver: u8) -> Vec<u8> {
+    let mut info = Vec::with_capacity(text.len() + 2);
+    info.extend_from_slice(text);
+    info.push(sender);
+    info.push(receiver);
+    info
+}
+
+/// HPKE `info` string with which a Client seals a report's input share to the Leader (if
+/// `is_leader` is set) or the Helper.
+///
+/// Note that the task ID is not part of `info`: it is bound to the ciphertext by the AAD.
+pub fn report_info(version: DapVersion, is_leader: bool) -> Result<Vec<u8>, DapError> {
+    let text = match version {
+        DapVersion::Draft02 => CTX_INPUT_SHARE_DRAFT02,
+        DapVersion::Draft03 => CTX_INPUT_SHARE_DRAFT03,
+        _ => return Err(unimplemented_version()),
+    };
+    let receiver = if is_leader {
+        CTX_ROLE_LEADER
+    } else {
+        CTX_ROLE_HELPER
+    };
+    Ok(info_for(text, CTX_ROLE_CLIENT, receiver))
+}
+
+/// HPKE `info` string with which the Leader (if `is_leader` is set) or the Helper seals its
+/// aggregate share to the Collector.
+pub fn agg_share_info(version: DapVersion, is_leader: bool) -> Result<Vec<u8>, DapError> {
+    let text = match version {
+        DapVersion::Draft02 => CTX_AGG_SHARE_DRAFT02,
+        DapVersion::Draft03 => CTX_AGG_SHARE_DRAFT03,
+        _ => return Err(unimplemented_version()),
+    };
+    let sender = if is_leader {
+        CTX_ROLE_LEADER
+    } else {
+        CTX_ROLE_HELPER
+    };
+    Ok(info_for(text, sender, CTX_ROLE_COLLECTOR))
+}
+
 fn check_suite<T: HpkeCrypto>(
     kem_id: HpkeKemId,
     kdf_id: HpkeKdfId,
@@ -185,6 +240,24 @@ impl HpkeReceiverConfig {
     pub fn new(config: HpkeConfig, secret_key: Vec<u8>) -> Self {
         HpkeReceiverConfig { config, secret_key }
     }
+
+    /// Check that the secret key corresponds to the public key and KEM advertised in the HPKE
+    /// config by encrypting a message to the config and decrypting it with the secret key. A
+    /// mismatch would otherwise only surface as a failure to decrypt each report.
+    pub fn self_check(&self) -> Result<(), DapError> {
+        let mismatch = || {
+            DapError::Fatal(format!(
+                "secret key does not match HPKE config {} (KEM {:?})",
+                self.config.id, self.config.kem_id
+            ))
+        };
+        let plaintext = b"self check";
+        let (enc, ciphertext) = self.encrypt(&[], &[], plaintext).map_err(|_| mismatch())?;
+        match self.decrypt(&[], &[], &enc, &ciphertext) {
+            Ok(decrypted) if decrypted == plaintext => Ok(()),
+            _ => Err(mismatch()),
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -216,6 +289,77 @@ impl<'a> HpkeDecrypter<'a> for HpkeReceiverConfig {
     }
 }
 
+/// HPKE decrypter for deployments in which the HPKE secret keys are held by an external key
+/// management service (KMS). The decrypter only knows the public HPKE configurations; opening a
+/// ciphertext is delegated to `open`, which is passed the HPKE config ID, info string, AAD, and
+/// ciphertext.
+pub struct KmsHpkeDecrypter<F> {
+    hpke_config_list: Vec<HpkeConfig>,
+    open: F,
+}
+
+impl<F, Fut> KmsHpkeDecrypter<F>
+where
+    F: Fn(u8, Vec<u8>, Vec<u8>, HpkeCiphertext) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, DapError>>,
+{
+    /// Create a new KMS decrypter for the given list of HPKE configurations. The first
+    /// configuration in the list is advertised to Clients.
+    pub fn new(hpke_config_list: Vec<HpkeConfig>, open: F) -> Self {
+        Self {
+            hpke_config_list,
+            open,
+        }
+    }
+
+    fn get_hpke_config(&self, config_id: u8) -> Option<&HpkeConfig> {
+        self.hpke_config_list
+            .iter()
+            .find(|config| config.id == config_id)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a, F, Fut> HpkeDecrypter<'a> for KmsHpkeDecrypter<F>
+where
+    F: Fn(u8, Vec<u8>, Vec<u8>, HpkeCiphertext) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, DapError>>,
+{
+    type WrappedHpkeConfig = &'a HpkeConfig;
+
+    async fn get_hpke_config_for(
+        &'a self,
+        _task_id: Option<&Id>,
+    ) -> Result<Self::WrappedHpkeConfig, DapError> {
+        self.hpke_config_list
+            .first()
+            .ok_or_else(|| DapError::fatal("empty HPKE config list"))
+    }
+
+    async fn can_hpke_decrypt(&self, _task_id: &Id, config_id: u8) -> Result<bool, DapError> {
+        Ok(self.get_hpke_config(config_id).is_some())
+    }
+
+    async fn hpke_decrypt(
+        &self,
+        _task_id: &Id,
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &HpkeCiphertext,
+    ) -> Result<Vec<u8>, DapError> {
+        if self.get_hpke_config(ciphertext.config_id).is_none() {
+            return Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId));
+        }
+        (self.open)(
+            ciphertext.config_id,
+            info.to_vec(),
+            aad.to_vec(),
+            ciphertext.clone(),
+        )
+        .await
+    }
+}
+
 impl Encode for HpkeReceiverConfig {
     fn encode(&self, bytes: &mut Vec<u8>) {
         self.config.encode(bytes);
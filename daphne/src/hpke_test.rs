@@ -1,8 +1,13 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::hpke::HpkeReceiverConfig;
-use crate::messages::HpkeKemId;
+use crate::hpke::{
+    agg_share_info, report_info, HpkeDecrypter, HpkeReceiverConfig, KmsHpkeDecrypter,
+};
+use crate::messages::{HpkeAeadId, HpkeCiphertext, HpkeKdfId, HpkeKemId, Id, TransitionFailure};
+use crate::{DapAbort, DapError, DapVersion};
+use assert_matches::assert_matches;
+use std::{cell::RefCell, rc::Rc};
 
 #[test]
 fn encrypt_roundtrip_x25519_hkdf_sha256() {
@@ -31,3 +36,139 @@ fn encrypt_roundtrip_p256_hkdf_sha256() {
         plaintext
     );
 }
+
+#[test]
+fn self_check() {
+    for kem_id in [HpkeKemId::X25519HkdfSha256, HpkeKemId::P256HkdfSha256] {
+        HpkeReceiverConfig::gen(23, kem_id)
+            .unwrap()
+            .self_check()
+            .unwrap();
+    }
+}
+
+#[test]
+fn self_check_fail_kem_mismatch() {
+    // Advertise an X25519 config while holding a P-256 key pair.
+    let mut config = HpkeReceiverConfig::gen(23, HpkeKemId::P256HkdfSha256).unwrap();
+    config.config.kem_id = HpkeKemId::X25519HkdfSha256;
+    assert_matches!(config.self_check(), Err(DapError::Fatal(..)));
+
+    // Advertise the public key of a different key pair.
+    let mut config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256).unwrap();
+    config.config.public_key = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256)
+        .unwrap()
+        .config
+        .public_key;
+    assert_matches!(config.self_check(), Err(DapError::Fatal(..)));
+}
+
+#[test]
+fn hpke_config_accessors() {
+    for kem_id in [HpkeKemId::X25519HkdfSha256, HpkeKemId::P256HkdfSha256] {
+        let config = HpkeReceiverConfig::gen(23, kem_id).unwrap().config;
+        assert!(!config.public_key_bytes().is_empty());
+        assert_eq!(config.kem_id(), kem_id);
+        assert_eq!(config.kdf_id(), HpkeKdfId::HkdfSha256);
+        assert_eq!(config.aead_id(), HpkeAeadId::Aes128Gcm);
+    }
+}
+
+#[tokio::test]
+async fn kms_hpke_decrypter() {
+    let info = b"info string";
+    let aad = b"associated data";
+    let plaintext = b"plaintext";
+    let task_id = Id([1; 32]);
+
+    // The mock KMS holds the secret key and records the ID of each HPKE config it is asked to use.
+    let kms_config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256).unwrap();
+    let requested_config_ids = Rc::new(RefCell::new(Vec::new()));
+    let decrypter = {
+        let kms_config = kms_config.clone();
+        let requested_config_ids = requested_config_ids.clone();
+        KmsHpkeDecrypter::new(
+            vec![kms_config.config.clone()],
+            move |config_id, info: Vec<u8>, aad: Vec<u8>, ciphertext: HpkeCiphertext| {
+                let kms_config = kms_config.clone();
+                let requested_config_ids = requested_config_ids.clone();
+                async move {
+                    requested_config_ids.borrow_mut().push(config_id);
+                    kms_config.decrypt(&info, &aad, &ciphertext.enc, &ciphertext.payload)
+                }
+            },
+        )
+    };
+
+    assert_eq!(
+        decrypter.get_hpke_config_for(Some(&task_id)).await.unwrap(),
+        &kms_config.config
+    );
+    assert!(decrypter.can_hpke_decrypt(&task_id, 23).await.unwrap());
+    assert!(!decrypter.can_hpke_decrypt(&task_id, 24).await.unwrap());
+
+    let (enc, payload) = kms_config.encrypt(info, aad, plaintext).unwrap();
+    let mut ciphertext = HpkeCiphertext {
+        config_id: 23,
+        enc,
+        payload,
+    };
+    assert_eq!(
+        decrypter
+            .hpke_decrypt(&task_id, info, aad, &ciphertext)
+            .await
+            .unwrap(),
+        plaintext
+    );
+    assert_eq!(*requested_config_ids.borrow(), vec![23]);
+
+    // Ciphertexts for unknown HPKE configs are rejected without calling the KMS.
+    ciphertext.config_id = 24;
+    assert_matches!(
+        decrypter
+            .hpke_decrypt(&task_id, info, aad, &ciphertext)
+            .await,
+        Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
+    );
+    assert_eq!(*requested_config_ids.borrow(), vec![23]);
+}
+
+#[test]
+fn report_info_golden() {
+    assert_eq!(
+        report_info(DapVersion::Draft02, true).unwrap(),
+        b"dap-02 input share\x01\x02"
+    );
+    assert_eq!(
+        report_info(DapVersion::Draft02, false).unwrap(),
+        b"dap-02 input share\x01\x03"
+    );
+    assert_eq!(
+        report_info(DapVersion::Draft03, true).unwrap(),
+        b"dap-03 input share\x01\x02"
+    );
+    assert_eq!(
+        report_info(DapVersion::Draft03, false).unwrap(),
+        b"dap-03 input share\x01\x03"
+    );
+    assert_matches!(
+        report_info(DapVersion::Unknown, true),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+}
+
+#[test]
+fn agg_share_info_golden() {
+    assert_eq!(
+        agg_share_info(DapVersion::Draft02, true).unwrap(),
+        b"dap-02 aggregate share\x02\x00"
+    );
+    assert_eq!(
+        agg_share_info(DapVersion::Draft03, false).unwrap(),
+        b"dap-03 aggregate share\x03\x00"
+    );
+    assert_matches!(
+        agg_share_info(DapVersion::Unknown, false),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+}
@@ -13,6 +13,10 @@
 //! protocol is required to implement. For example, the `daphne_worker` crate implements a backend
 //! for the DAP Leader and Helper. See the [`crate::roles`](roles) module for details.
 //!
+//! The roles, along with the mock backend in [`crate::testing`](testing), are enabled by the
+//! `roles` feature, which is on by default. Clients that only need to encode and decode messages
+//! and produce reports may disable it.
+//!
 //! Daphne is not yet feature complete. Known issues include:
 //!
 //! * The collect sub-protocol has not yet been fully implemented. In particular, Daphne Aggreators
@@ -31,12 +35,18 @@
 use crate::{
     hpke::HpkeReceiverConfig,
     messages::{
-        BatchSelector, CollectResp, Duration, HpkeConfig, Id, Interval, PartialBatchSelector,
+        decode_u32_bytes, encode_u32_bytes, AggregateResp, BatchSelector, CollectReq, CollectResp,
+        Duration, Extension, HpkeConfig, Id, Interval, PartialBatchSelector, Query, Report,
         ReportId, ReportMetadata, Time, TransitionFailure,
     },
     vdaf::{
-        prio2::prio2_decode_prepare_state,
-        prio3::{prio3_append_prepare_state, prio3_decode_prepare_state},
+        prio2::{
+            prio2_decode_prepare_message, prio2_decode_prepare_state, prio2_encode_prepare_message,
+        },
+        prio3::{
+            prio3_append_prepare_state, prio3_decode_prepare_message, prio3_decode_prepare_state,
+            prio3_encode_prepare_message,
+        },
         VdafAggregateShare, VdafError, VdafMessage, VdafState, VdafVerifyKey,
     },
 };
@@ -45,10 +55,12 @@ use prio::{
     codec::{CodecError, Decode, Encode},
     vdaf::Aggregatable as AggregatableTrait,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
+    io::{Cursor, Read},
+    sync::Mutex,
 };
 use taskprov::TaskprovVersion;
 use url::Url;
@@ -108,6 +120,14 @@ impl From<VdafError> for DapError {
 /// DAP aborts.
 #[derive(Debug, thiserror::Error)]
 pub enum DapAbort {
+    /// Aggregate share too large. Sent by the Helper in response to an AggregateShareReq whose
+    /// batch spans more buckets than the Helper is willing to aggregate in a single request. The
+    /// Leader may retry with a smaller batch.
+    //
+    // TODO spec: Define this error type.
+    #[error("aggregateShareTooLarge")]
+    AggregateShareTooLarge,
+
     /// Bad request. Sent in response to an HTTP request that couldn't be handled preoprly.
     //
     // TODO spec: Decide whether to specify this.
@@ -118,10 +138,18 @@ pub enum DapAbort {
     #[error("batchInvalid")]
     BatchInvalid,
 
-    /// Batch mismatch. Sent in response to an AggregateShareReq.
+    /// Batch mismatch. Sent in response to an AggregateShareReq, or an AggregateInitializeReq for
+    /// an aggregation job that was initialized for a different batch.
     #[error("batchMismatch")]
     BatchMismatch,
 
+    /// Batch not ready. Sent in response to a CollectReq for a batch that contains reports that
+    /// have been uploaded but not yet aggregated.
+    //
+    // TODO spec: Define this error type.
+    #[error("batchNotReady")]
+    BatchNotReady,
+
     /// Batch overlap. Sent in response to an CollectReq for which the Leader detects the same
     /// Collector requesting an aggregate share which it has collected in the past.
     #[error("batchOverlap")]
@@ -148,9 +176,13 @@ pub enum DapAbort {
     #[error("missingTaskID")]
     MissingTaskId,
 
-    /// Query mismatch. Sent in response to a CollectReq or AggregateShareReq.
+    /// Query mismatch. Sent in response to a CollectReq, AggregateInitializeReq, or
+    /// AggregateShareReq whose query type does not match the task's query configuration.
     #[error("queryMismatch")]
-    QueryMismatch,
+    QueryMismatch {
+        expected: QueryKind,
+        actual: QueryKind,
+    },
 
     /// Replayed report. Sent in response to an upload request containing a Report that has been replayed.
     //
@@ -168,6 +200,20 @@ pub enum DapAbort {
     #[error("staleReport")]
     StaleReport,
 
+    /// Task not started. Sent in response to an upload or aggregation request containing a report
+    /// from before the task's start time.
+    //
+    // TODO spec: Define this error type.
+    #[error("taskNotStarted")]
+    TaskNotStarted,
+
+    /// Too many requests. Sent when the Aggregator is temporarily unable to handle the request,
+    /// e.g., because it is overloaded. The sender should retry after `retry_after` seconds.
+    //
+    // TODO spec: Define this error type.
+    #[error("tooManyRequests")]
+    TooManyRequests { retry_after: Duration },
+
     /// Unauthorized HTTP request.
     #[error("unauthorizedRequest")]
     UnauthorizedRequest,
@@ -198,22 +244,29 @@ impl DapAbort {
     /// request was targeted and `task_id` is the associated TaskID.
     pub fn to_problem_details(&self) -> ProblemDetails {
         let (typ, detail) = match self {
-            Self::BatchInvalid
+            Self::AggregateShareTooLarge
+            | Self::BatchInvalid
             | Self::BatchMismatch
+            | Self::BatchNotReady
             | Self::BatchOverlap
             | Self::InvalidBatchSize
             | Self::InvalidProtocolVersion
             | Self::InvalidTask
-            | Self::QueryMismatch
             | Self::MissingTaskId
             | Self::ReplayedReport
             | Self::ReportTooLate
             | Self::StaleReport
+            | Self::TaskNotStarted
+            | Self::TooManyRequests { .. }
             | Self::UnauthorizedRequest
             | Self::UnrecognizedAggregationJob
             | Self::UnrecognizedHpkeConfig
             | Self::UnrecognizedMessage
             | Self::UnrecognizedTask => (self.to_string(), None),
+            Self::QueryMismatch { expected, actual } => (
+                self.to_string(),
+                Some(format!("expected {} query, got {}", expected, actual)),
+            ),
             Self::BadRequest(s) => ("badRequest".to_string(), Some(s.clone())),
             Self::Internal(e) => ("internalError".to_string(), Some(e.to_string())),
         };
@@ -225,6 +278,15 @@ impl DapAbort {
             detail,
         }
     }
+
+    /// Return the number of seconds after which the request that caused this abort may be
+    /// retried, if the abort is transient. Gateways may translate this to a `Retry-After` header.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::TooManyRequests { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
 }
 
 impl From<DapError> for DapAbort {
@@ -282,13 +344,18 @@ pub enum DapVersion {
     Unknown,
 }
 
+impl DapVersion {
+    /// The versions this implementation supports. Any other version is parsed as
+    /// [`DapVersion::Unknown`] and rejected.
+    pub const SUPPORTED: [Self; 2] = [DapVersion::Draft02, DapVersion::Draft03];
+}
+
 impl From<&str> for DapVersion {
     fn from(version: &str) -> Self {
-        match version {
-            "v02" => DapVersion::Draft02,
-            "v03" => DapVersion::Draft03,
-            _ => DapVersion::Unknown,
-        }
+        DapVersion::SUPPORTED
+            .into_iter()
+            .find(|supported| supported.as_ref() == version)
+            .unwrap_or(DapVersion::Unknown)
     }
 }
 
@@ -326,15 +393,18 @@ pub struct DapGlobalConfig {
     /// ```
     ///
     /// Thus, storage is only guaranteed for the previous epoch, the current epoch, and the next
-    /// epoch.
-    //
-    // TODO(issue #100) Implement the rejection mechanism described here.
+    /// epoch. Replay protection is only provided for reports within this window.
     pub report_storage_epoch_duration: Duration,
 
     /// Maximum interval duration permitted in CollectReq.
     /// Prevents Collectors from requesting wide range or reports.
     pub max_batch_duration: Duration,
 
+    /// If set, then the reports in a fixed-size batch must fall within `max_batch_duration` of
+    /// one another. Collecting a batch that violates this constraint is aborted.
+    #[serde(default)]
+    pub enforce_fixed_size_batch_duration: bool,
+
     /// Lower bound of an acceptable batch interval for collect requests.
     /// Batch intervals cannot start more than `min_batch_interval_start`
     /// apart from the current batch interval.
@@ -353,17 +423,243 @@ pub struct DapGlobalConfig {
     /// receiver config.
     pub supported_hpke_kems: Vec<HpkeKemId>,
 
+    /// Maximum number of extensions permitted in a report's metadata. Reports with more
+    /// extensions are rejected. If not set, the number of extensions is not limited.
+    #[serde(default = "unlimited_usize")]
+    pub max_extensions: usize,
+
+    /// Maximum length (in bytes) of the payload of a report extension. Reports with a larger
+    /// extension are rejected. If not set, the length is not limited.
+    #[serde(default = "unlimited_usize")]
+    pub max_extension_len: usize,
+
     /// Is the taskprov extension allowed?
     pub allow_taskprov: bool,
 
     /// Which taskprov draft should be used?
     pub taskprov_version: TaskprovVersion,
+
+    /// Minimum `time_precision` of a task provisioned via taskprov. The Aggregator opts out of
+    /// tasks with a smaller time precision. If not set, there is no minimum.
+    #[serde(default)]
+    pub min_time_precision: Duration,
+
+    /// Maximum number of batch buckets (i.e., time windows of length `time_precision`) that a
+    /// task provisioned via taskprov may span between now and its expiration. The Aggregator opts
+    /// out of tasks that exceed this limit. If not set, the number of buckets is not limited.
+    #[serde(default = "unlimited_u64")]
+    pub max_batch_buckets: u64,
+
+    /// Query types that a task provisioned via taskprov may use. The Aggregator opts out of tasks
+    /// with any other query type. If not set, all query types are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_query_types: Option<Vec<QueryKind>>,
+
+    /// Maximum number of collect jobs that may be pending for a task at once. The Leader rejects
+    /// collect requests for the task until one of the pending jobs is completed. If not set, the
+    /// number of pending collect jobs is not limited.
+    #[serde(default = "unlimited_usize")]
+    pub max_pending_collect_jobs: usize,
+
+    /// Maximum number of batch buckets that the Helper aggregates in response to a single
+    /// aggregate-share request. Larger requests are rejected. If not set, the number of buckets is
+    /// not limited.
+    #[serde(default = "unlimited_u64")]
+    pub max_agg_share_buckets: u64,
+
+    /// Time to live of the Helper's state for an aggregation job. Backends drop older state in
+    /// [`DapHelper::expire_jobs`](crate::roles::DapHelper::expire_jobs) or by other means, e.g.,
+    /// the worker deletes it when the storage for the job is garbage collected. The Helper also
+    /// ignores a stored response to an AggregateInitializeReq (see [`DapStoredAggInitResp`])
+    /// older than this. If not set, jobs do not expire.
+    #[serde(default = "unlimited_u64")]
+    pub job_ttl: Duration,
+
+    /// Time to live of a pending collect job at the Leader. Collect jobs that are still pending
+    /// this long after the collect request was received are expired by
+    /// [`DapLeader::process`](crate::roles::DapLeader::process). If not set, collect jobs do not
+    /// expire.
+    #[serde(default = "unlimited_u64")]
+    pub collect_job_ttl: Duration,
+
+    /// How to respond to an HPKE config request that does not specify a task ID.
+    #[serde(default)]
+    pub hpke_config_missing_task_id_policy: HpkeConfigMissingTaskIdPolicy,
+
+    /// How the Aggregator compresses aggregate shares before encrypting them to the Collector.
+    #[serde(default)]
+    pub agg_share_compression: AggShareCompression,
+
+    /// How differential privacy noise is split between the Leader and Helper. Each Aggregator
+    /// adds its share of the noise to its aggregate share before encrypting it to the Collector.
+    #[serde(default)]
+    pub noise_split: NoiseSplit,
+
+    /// The algorithm used to compute the checksum of a batch. The Leader and Helper must use the
+    /// same algorithm, otherwise their checksums won't match and aggregate-share requests are
+    /// rejected with [`DapAbort::BatchMismatch`].
+    #[serde(default)]
+    pub checksum_algorithm: ChecksumAlgorithm,
+}
+
+// Defaults for the limits in [`DapGlobalConfig`] that are not enforced unless configured.
+fn unlimited_usize() -> usize {
+    usize::MAX
+}
+
+fn unlimited_u64() -> u64 {
+    u64::MAX
+}
+
+/// Capabilities of an Aggregator, advertised to Clients and Collectors so that they can be
+/// discovered without out-of-band configuration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapAggregatorInfo {
+    /// DAP versions supported by the Aggregator.
+    pub supported_versions: Vec<DapVersion>,
+
+    /// HPKE KEM types supported by the Aggregator.
+    pub supported_hpke_kems: Vec<HpkeKemId>,
+
+    /// Whether the Aggregator accepts tasks provisioned via taskprov.
+    pub allow_taskprov: bool,
+}
+
+/// How an Aggregator responds to an HPKE config request that does not specify a task ID.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HpkeConfigMissingTaskIdPolicy {
+    /// Abort the request with [`DapAbort::MissingTaskId`].
+    Abort,
+
+    /// Respond with the newest HPKE config used for all tasks.
+    ReturnDefault,
+}
+
+impl Default for HpkeConfigMissingTaskIdPolicy {
+    fn default() -> Self {
+        Self::ReturnDefault
+    }
+}
+
+/// Compression applied to an aggregate share before it is encrypted to the Collector.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggShareCompression {
+    /// Send the encoded aggregate share as is.
+    None,
+
+    /// Compress the encoded aggregate share with gzip.
+    Gzip,
+}
+
+impl Default for AggShareCompression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// How the differential privacy noise added to the aggregate result is split between the
+/// Aggregators.
+///
+/// The noise is sampled from a centered binomial distribution and added to each element of the
+/// aggregate share, modulo the field of the VDAF. Negative noise wraps around, so the Collector
+/// must interpret elements of the aggregate result close to the field modulus as negative values.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseSplit {
+    /// Neither Aggregator adds noise.
+    None,
+
+    /// The Leader and Helper each add noise with variance `variance / 2`, so that the noise in
+    /// the aggregate result has variance `variance`.
+    Even { variance: u64 },
+}
+
+impl Default for NoiseSplit {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Algorithm used to compute the checksum of a batch from the IDs of the reports it contains.
+///
+/// To add an algorithm, add a variant and handle it in [`ChecksumAlgorithm::compute`]. Aggregate
+/// shares are merged incrementally by XORing their checksums (see [`DapAggregateShare::merge`]),
+/// so the checksum of a set of reports must be the XOR of the checksums of each report.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// The XOR of the SHA-256 hashes of the report IDs, as specified by DAP. See
+    /// [`compute_checksum`].
+    Sha256Xor,
+}
+
+impl Default for ChecksumAlgorithm {
+    fn default() -> Self {
+        Self::Sha256Xor
+    }
+}
+
+impl ChecksumAlgorithm {
+    /// Compute the checksum of a batch from the IDs of the reports it contains.
+    pub fn compute(&self, report_ids: &[ReportId]) -> [u8; 32] {
+        match self {
+            Self::Sha256Xor => compute_checksum(report_ids),
+        }
+    }
 }
 
 impl DapGlobalConfig {
+    /// Return the start of the report storage epoch containing `time`.
+    pub fn report_storage_epoch(&self, time: Time) -> Time {
+        time - (time % self.report_storage_epoch_duration)
+    }
+
+    /// Check whether a report with timestamp `time` falls into the report storage window at time
+    /// `now`, i.e., whether its epoch is the previous, current, or next epoch. Reports outside of
+    /// this window are not protected against replay and must be rejected.
+    pub fn is_report_in_storage_window(&self, now: Time, time: Time) -> bool {
+        let epoch = self.report_storage_epoch(time);
+        let current_epoch = self.report_storage_epoch(now);
+        epoch.saturating_add(self.report_storage_epoch_duration) >= current_epoch
+            && epoch <= current_epoch.saturating_add(self.report_storage_epoch_duration)
+    }
+
+    /// Check that a task provisioned via taskprov respects the limits on the time precision and
+    /// number of batch buckets and uses an allowed query type. If not, the Aggregator must opt out
+    /// of the task.
+    pub fn check_taskprov_task_config(
+        &self,
+        task_config: &DapTaskConfig,
+        now: Time,
+    ) -> Result<(), DapError> {
+        if task_config.time_precision < self.min_time_precision {
+            return Err(DapError::Abort(DapAbort::InvalidTask));
+        }
+
+        let batch_buckets = task_config
+            .expiration
+            .saturating_sub(now)
+            .checked_div(task_config.time_precision)
+            .ok_or(DapError::Abort(DapAbort::InvalidTask))?;
+        if batch_buckets > self.max_batch_buckets {
+            return Err(DapError::Abort(DapAbort::InvalidTask));
+        }
+
+        if let Some(ref allowed_query_types) = self.allowed_query_types {
+            if !allowed_query_types.contains(&QueryKind::from(&task_config.query)) {
+                return Err(DapError::Abort(DapAbort::InvalidTask));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Generate a list of HPKE receiver configurations, one for each element of supported KEM
     /// algorithm. `first_config_id` is used as the first config ID; subsequent IDs are chosen by
-    /// incrementing `first_config_id`.
+    /// incrementing `first_config_id`. Each configuration is checked with
+    /// [`HpkeReceiverConfig::self_check`] before it is returned.
     pub fn gen_hpke_receiver_config_list(
         &self,
         first_config_id: u8,
@@ -372,7 +668,9 @@ impl DapGlobalConfig {
         let kem_ids = self.supported_hpke_kems.clone();
         kem_ids.into_iter().enumerate().map(move |(i, kem_id)| {
             let (config_id, _overflowed) = first_config_id.overflowing_add(i as u8);
-            HpkeReceiverConfig::gen(config_id, kem_id)
+            let hpke_receiver_config = HpkeReceiverConfig::gen(config_id, kem_id)?;
+            hpke_receiver_config.self_check()?;
+            Ok(hpke_receiver_config)
         })
     }
 }
@@ -392,6 +690,59 @@ pub enum DapQueryConfig {
     FixedSize { max_batch_size: u64 },
 }
 
+/// The kind of a query, batch selector, or query configuration.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryKind {
+    TimeInterval,
+    FixedSize,
+}
+
+impl std::fmt::Display for QueryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimeInterval => write!(f, "time_interval"),
+            Self::FixedSize => write!(f, "fixed_size"),
+        }
+    }
+}
+
+impl From<&DapQueryConfig> for QueryKind {
+    fn from(query_config: &DapQueryConfig) -> Self {
+        match query_config {
+            DapQueryConfig::TimeInterval => Self::TimeInterval,
+            DapQueryConfig::FixedSize { .. } => Self::FixedSize,
+        }
+    }
+}
+
+impl From<&BatchSelector> for QueryKind {
+    fn from(batch_sel: &BatchSelector) -> Self {
+        match batch_sel {
+            BatchSelector::TimeInterval { .. } => Self::TimeInterval,
+            BatchSelector::FixedSizeByBatchId { .. } => Self::FixedSize,
+        }
+    }
+}
+
+impl From<&Query> for QueryKind {
+    fn from(query: &Query) -> Self {
+        match query {
+            Query::TimeInterval { .. } => Self::TimeInterval,
+            Query::FixedSizeByBatchId { .. } | Query::FixedSizeCurrentBatch => Self::FixedSize,
+        }
+    }
+}
+
+impl From<&PartialBatchSelector> for QueryKind {
+    fn from(part_batch_sel: &PartialBatchSelector) -> Self {
+        match part_batch_sel {
+            PartialBatchSelector::TimeInterval => Self::TimeInterval,
+            PartialBatchSelector::FixedSizeByBatchId { .. } => Self::FixedSize,
+        }
+    }
+}
+
 impl DapQueryConfig {
     pub(crate) fn is_valid_part_batch_sel(&self, part_batch_sel: &PartialBatchSelector) -> bool {
         matches!(
@@ -424,14 +775,67 @@ impl DapQueryConfig {
 ///
 /// A bucket is the smallest, disjoint set of reports that can be queried: For time-interval
 /// queries, the bucket to which a report is assigned is determined by truncating its timestamp by
-/// the task's `time_precision` parameter; for fixed-size queries, the span consists of a single
-/// bucket, which is the batch determined by the batch ID (i.e., the partial batch selector).
+/// the task's `time_precision` parameter (or its `storage_precision`, if set); for fixed-size
+/// queries, the span consists of a single bucket, which is the batch determined by the batch ID
+/// (i.e., the partial batch selector).
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub enum DapBatchBucket<'a> {
     FixedSize { batch_id: &'a Id },
     TimeInterval { batch_window: Time },
 }
 
+impl<'a> DapBatchBucket<'a> {
+    // TODO(cjpatton) Figure out how to use `ToOwned` properly. The lifetime parameter causes
+    // confusion for the compiler for implementing `Borrow`. The goal is to avoid cloning the
+    // bucket each time we need to check if it exists in the set.
+    pub fn to_owned_bucket(&self) -> DapBatchBucketOwned {
+        match self {
+            Self::FixedSize { batch_id } => DapBatchBucketOwned::FixedSize {
+                batch_id: (*batch_id).clone(),
+            },
+            Self::TimeInterval { batch_window } => DapBatchBucketOwned::TimeInterval {
+                batch_window: *batch_window,
+            },
+        }
+    }
+}
+
+/// An owned [`DapBatchBucket`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DapBatchBucketOwned {
+    FixedSize { batch_id: Id },
+    TimeInterval { batch_window: Time },
+}
+
+impl From<DapBatchBucketOwned> for PartialBatchSelector {
+    fn from(bucket: DapBatchBucketOwned) -> Self {
+        match bucket {
+            DapBatchBucketOwned::FixedSize { batch_id } => Self::FixedSizeByBatchId { batch_id },
+            DapBatchBucketOwned::TimeInterval { .. } => Self::TimeInterval,
+        }
+    }
+}
+
+/// Deserialize either a sequence of values or a single value, which is treated as a sequence of
+/// length one.
+pub fn deserialize_one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
 /// Per-task DAP parameters.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct DapTaskConfig {
@@ -448,6 +852,18 @@ pub struct DapTaskConfig {
     /// constrain the batch interval of time=interval queries.
     pub time_precision: Duration,
 
+    /// Granularity of the buckets into which an Aggregator stores aggregate shares for a
+    /// "time-interval" task. This must be a non-zero divisor of `time_precision` (see
+    /// [`Self::validate`]): each batch window is split into sub-buckets of this length, which are
+    /// summed when the batch is collected. If not set, buckets are batch windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_precision: Option<Duration>,
+
+    /// The time at which the task becomes active. Reports with timestamps before this time are
+    /// rejected.
+    #[serde(default)]
+    pub start: Time,
+
     /// The time at which the task expires.
     pub expiration: Time,
 
@@ -463,11 +879,168 @@ pub struct DapTaskConfig {
     /// VDAF verification key shared by the Aggregators. Used to aggregate reports.
     pub vdaf_verify_key: VdafVerifyKey,
 
-    /// The Collector's HPKE configuration for this task.
-    pub collector_hpke_config: HpkeConfig,
+    /// The Collectors' HPKE configurations for this task. A Collector may indicate which of
+    /// these the aggregate shares are encrypted under; by default, the first one is used.
+    ///
+    /// For compatibility with task configs that have a single Collector HPKE config, this may
+    /// also be deserialized from a single config and under the name `collector_hpke_config`.
+    #[serde(
+        alias = "collector_hpke_config",
+        deserialize_with = "deserialize_one_or_many"
+    )]
+    pub collector_hpke_configs: Vec<HpkeConfig>,
+
+    /// The maximum number of reports for this task that the Leader stores while they wait to be
+    /// aggregated. Once the limit is reached, uploads are rejected until an aggregation job frees
+    /// up space. If not set, the number of pending reports is not limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pending_reports: Option<u64>,
+
+    /// The types of report extensions permitted for this task. Reports carrying an extension of
+    /// any other type are rejected: the Leader aborts the upload and the Helper fails the report
+    /// with `report-dropped`. If not set, extensions of any type are permitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_extension_types: Option<Vec<u16>>,
+
+    /// If set, reports must be sealed to the Aggregator's latest HPKE config, i.e., the one it
+    /// currently advertises. Reports sealed to an older config are rejected with
+    /// `hpke-unknown-config-id`, even if the Aggregator could still decrypt them. This is intended
+    /// to move Clients onto the new config after a key rotation.
+    #[serde(default)]
+    pub require_latest_hpke_config: bool,
+
+    /// If set, the Leader rejects uploaded reports for a "time-interval" task whose timestamp is
+    /// not a multiple of the task's time precision. Otherwise, the report's timestamp is
+    /// truncated to determine the batch window it belongs to.
+    #[serde(default)]
+    pub require_aligned_report_time: bool,
+
+    /// Global parameters for this task. If set, these take precedence over the Aggregator's
+    /// global configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_config_override: Option<DapGlobalConfig>,
+}
+
+/// A parameter of a [`DapTaskConfig`] that the Leader and Helper must agree on. See
+/// [`DapTaskConfig::diff`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskConfigFieldDiff {
+    Version,
+    LeaderUrl,
+    HelperUrl,
+    TimePrecision,
+    MinBatchSize,
+    Query,
+    Vdaf,
+    CollectorHpkeConfigs,
 }
 
 impl DapTaskConfig {
+    /// Return the global parameters to use for this task: the task's override, if set, or
+    /// `default` otherwise.
+    pub fn global_config<'a>(&'a self, default: &'a DapGlobalConfig) -> &'a DapGlobalConfig {
+        self.global_config_override.as_ref().unwrap_or(default)
+    }
+
+    /// Check that each of the report's extensions is recognized and of a type permitted for this
+    /// task.
+    pub fn allows_report_extensions(&self, metadata: &ReportMetadata) -> bool {
+        metadata.extensions.iter().all(|extension| {
+            if matches!(extension, Extension::Unhandled { .. }) {
+                return false;
+            }
+            match self.allowed_extension_types {
+                Some(ref allowed_extension_types) => {
+                    allowed_extension_types.contains(&extension.type_code())
+                }
+                None => true,
+            }
+        })
+    }
+
+    /// Return the Collector's HPKE configuration with the given config ID, or the first of the
+    /// task's Collector HPKE configurations if no config ID is specified.
+    pub fn collector_hpke_config(&self, config_id: Option<u8>) -> Option<&HpkeConfig> {
+        match config_id {
+            Some(config_id) => self
+                .collector_hpke_configs
+                .iter()
+                .find(|hpke_config| hpke_config.id == config_id),
+            None => self.collector_hpke_configs.first(),
+        }
+    }
+
+    /// Compute a hash of the parameters that the Leader and Helper must agree on for this task:
+    /// the protocol version, the Aggregator URLs, the time precision, the minimum batch size, the
+    /// query and VDAF configurations, and the Collector's HPKE configurations. Operators can compare the hashes computed by each
+    /// Aggregator out of band in order to detect configuration drift.
+    ///
+    /// Parameters that are local to an Aggregator (the task's lifetime, the storage precision, and
+    /// the global configuration override) are excluded. The VDAF verification key is excluded as
+    /// well so that the hash can be shared without involving secret material.
+    pub fn config_hash(&self) -> [u8; 32] {
+        #[derive(Serialize)]
+        struct SharedConfig<'a> {
+            version: DapVersion,
+            leader_url: &'a Url,
+            helper_url: &'a Url,
+            time_precision: Duration,
+            min_batch_size: u64,
+            query: &'a DapQueryConfig,
+            vdaf: &'a VdafConfig,
+            collector_hpke_configs: &'a [HpkeConfig],
+        }
+
+        let encoded = serde_json::to_vec(&SharedConfig {
+            version: self.version,
+            leader_url: &self.leader_url,
+            helper_url: &self.helper_url,
+            time_precision: self.time_precision,
+            min_batch_size: self.min_batch_size,
+            query: &self.query,
+            vdaf: &self.vdaf,
+            collector_hpke_configs: &self.collector_hpke_configs,
+        })
+        .expect("failed to encode task config");
+        let digest = ring::digest::digest(&ring::digest::SHA256, &encoded);
+        let mut hash = [0; 32];
+        hash.copy_from_slice(digest.as_ref());
+        hash
+    }
+
+    /// Compare the parameters that the Leader and Helper must agree on with those of `other` and
+    /// return the ones that differ. These are the same parameters covered by
+    /// [`config_hash`](Self::config_hash), so the result is empty if and only if the hashes match.
+    pub fn diff(&self, other: &DapTaskConfig) -> Vec<TaskConfigFieldDiff> {
+        let mut diff = Vec::new();
+        if self.version != other.version {
+            diff.push(TaskConfigFieldDiff::Version);
+        }
+        if self.leader_url != other.leader_url {
+            diff.push(TaskConfigFieldDiff::LeaderUrl);
+        }
+        if self.helper_url != other.helper_url {
+            diff.push(TaskConfigFieldDiff::HelperUrl);
+        }
+        if self.time_precision != other.time_precision {
+            diff.push(TaskConfigFieldDiff::TimePrecision);
+        }
+        if self.min_batch_size != other.min_batch_size {
+            diff.push(TaskConfigFieldDiff::MinBatchSize);
+        }
+        if self.query != other.query {
+            diff.push(TaskConfigFieldDiff::Query);
+        }
+        if self.vdaf != other.vdaf {
+            diff.push(TaskConfigFieldDiff::Vdaf);
+        }
+        if self.collector_hpke_configs != other.collector_hpke_configs {
+            diff.push(TaskConfigFieldDiff::CollectorHpkeConfigs);
+        }
+        diff
+    }
+
     /// Convert at timestamp `now` into an [`Interval`] that contains it. The timestamp is the
     /// numbre of seconds since the beginning of UNIX time.
     #[cfg(test)]
@@ -481,10 +1054,83 @@ impl DapTaskConfig {
         }
     }
 
+    #[cfg(all(test, feature = "roles"))]
     pub(crate) fn truncate_time(&self, time: Time) -> Time {
         time - (time % self.time_precision)
     }
 
+    /// Check that the parameters of the task are consistent with one another. An Aggregator must
+    /// check a task configuration it did not create itself, e.g., one loaded from storage, before
+    /// using it.
+    pub fn validate(&self) -> Result<(), DapError> {
+        if self.time_precision == 0 {
+            return Err(DapError::fatal("time precision is zero"));
+        }
+        if let Some(storage_precision) = self.storage_precision {
+            if storage_precision == 0 || self.time_precision % storage_precision != 0 {
+                return Err(DapError::fatal(
+                    "storage precision is not a divisor of the time precision",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the length of the buckets into which aggregate shares are stored for "time-interval"
+    /// tasks. This is `storage_precision` if set and `time_precision` otherwise.
+    pub fn bucket_precision(&self) -> Duration {
+        self.storage_precision.unwrap_or(self.time_precision)
+    }
+
+    /// Truncate the timestamp to the start of the bucket that contains it. See
+    /// [`Self::bucket_precision`].
+    fn truncate_time_to_bucket(&self, time: Time) -> Time {
+        time - (time % self.bucket_precision())
+    }
+
+    /// Return the start of each batch window (i.e., time window of length `time_precision`)
+    /// covered by the given batch interval, in ascending order. The interval is assumed to be
+    /// aligned to the time precision; see [`Self::validate_collect_req`].
+    pub fn batch_windows(&self, interval: &Interval) -> Vec<Time> {
+        (0..interval.duration / self.time_precision)
+            .map(|i| interval.start + i * self.time_precision)
+            .collect()
+    }
+
+    /// Return the start of each bucket (i.e., time window of length [`Self::bucket_precision`])
+    /// covered by the given batch interval, in ascending order. Like [`Self::batch_windows`], the
+    /// interval is assumed to be aligned to the time precision.
+    fn bucket_windows(&self, interval: &Interval) -> Vec<Time> {
+        let bucket_precision = self.bucket_precision();
+        (0..interval.duration / bucket_precision)
+            .map(|i| interval.start + i * bucket_precision)
+            .collect()
+    }
+
+    /// Compute the bucket to which a report with timestamp `report_time` belongs. For
+    /// time-interval tasks, this is the bucket of length [`Self::bucket_precision`] containing the
+    /// timestamp. For fixed-size tasks, this is the batch currently being filled,
+    /// `current_batch_id`, which must be provided.
+    pub fn batch_bucket_for_report(
+        &self,
+        report_time: Time,
+        current_batch_id: Option<&Id>,
+    ) -> Result<DapBatchBucketOwned, DapError> {
+        match (&self.query, current_batch_id) {
+            (DapQueryConfig::TimeInterval, _) => Ok(DapBatchBucketOwned::TimeInterval {
+                batch_window: self.truncate_time_to_bucket(report_time),
+            }),
+            (DapQueryConfig::FixedSize { .. }, Some(batch_id)) => {
+                Ok(DapBatchBucketOwned::FixedSize {
+                    batch_id: batch_id.clone(),
+                })
+            }
+            (DapQueryConfig::FixedSize { .. }, None) => Err(DapError::fatal(
+                "current batch ID is required for fixed-size tasks",
+            )),
+        }
+    }
+
     /// Compute the "batch span" of a set of output shares and, for each buckent in the span,
     /// aggregate the output shares into an aggregate share.
     pub fn batch_span_for_out_shares<'a>(
@@ -502,7 +1148,7 @@ impl DapTaskConfig {
         for out_share in out_shares.into_iter() {
             let bucket = match part_batch_sel {
                 PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                    batch_window: self.truncate_time(out_share.time),
+                    batch_window: self.truncate_time_to_bucket(out_share.time),
                 },
                 PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
                     DapBatchBucket::FixedSize { batch_id }
@@ -514,6 +1160,8 @@ impl DapTaskConfig {
                 report_count: 1,
                 checksum: out_share.checksum,
                 data: Some(out_share.data),
+                min_time: out_share.time,
+                max_time: out_share.time,
             })?;
         }
 
@@ -531,18 +1179,11 @@ impl DapTaskConfig {
         }
 
         match batch_sel {
-            BatchSelector::TimeInterval {
-                batch_interval: Interval { start, duration },
-            } => {
-                let windows = duration / self.time_precision;
-                let mut span = HashSet::with_capacity(windows as usize);
-                for i in 0..windows {
-                    span.insert(DapBatchBucket::TimeInterval {
-                        batch_window: start + i * self.time_precision,
-                    });
-                }
-                Ok(span)
-            }
+            BatchSelector::TimeInterval { batch_interval } => Ok(self
+                .bucket_windows(batch_interval)
+                .into_iter()
+                .map(|batch_window| DapBatchBucket::TimeInterval { batch_window })
+                .collect()),
             BatchSelector::FixedSizeByBatchId { batch_id } => {
                 Ok(HashSet::from([DapBatchBucket::FixedSize { batch_id }]))
             }
@@ -565,7 +1206,7 @@ impl DapTaskConfig {
         for metadata in report_meta {
             let bucket = match part_batch_sel {
                 PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                    batch_window: self.truncate_time(metadata.time),
+                    batch_window: self.truncate_time_to_bucket(metadata.time),
                 },
                 PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
                     DapBatchBucket::FixedSize { batch_id }
@@ -581,6 +1222,7 @@ impl DapTaskConfig {
 
     /// Check if the batch is ready to aggregate based on the report count. Returns an error if the
     /// report count is too large.
+    #[cfg(feature = "roles")]
     pub(crate) fn is_report_count_compatible(&self, report_count: u64) -> Result<bool, DapAbort> {
         match self.query {
             DapQueryConfig::TimeInterval => (),
@@ -593,6 +1235,126 @@ impl DapTaskConfig {
 
         Ok(report_count >= self.min_batch_size)
     }
+
+    /// Check that the reports in a fixed-size batch fall within `max_batch_duration` of one
+    /// another. This constraint is only enforced if `enforce_fixed_size_batch_duration` is set.
+    /// (For time-interval tasks, the duration is constrained by the batch interval.) An empty
+    /// aggregate share is always compatible.
+    #[cfg(feature = "roles")]
+    pub(crate) fn is_batch_duration_compatible(
+        &self,
+        global_config: &DapGlobalConfig,
+        agg_share: &DapAggregateShare,
+    ) -> bool {
+        match self.query {
+            DapQueryConfig::TimeInterval => true,
+            DapQueryConfig::FixedSize { .. } => {
+                !global_config.enforce_fixed_size_batch_duration
+                    || agg_share.empty()
+                    || agg_share.max_time.saturating_sub(agg_share.min_time)
+                        <= global_config.max_batch_duration
+            }
+        }
+    }
+
+    /// Check that a collect request is valid for this task at time `now`. This runs the checks the
+    /// Leader performs on a collect request that don't depend on its state: the Collector's HPKE
+    /// config and the aggregation parameter must be recognized, the query must match the task's
+    /// query type, and, for time-interval tasks, the batch interval must be aligned and within the
+    /// bounds set by `global_config` (or the task's override, if set). Collectors can use this to
+    /// detect invalid requests before sending them.
+    ///
+    /// Passing these checks doesn't guarantee that the Leader accepts the request. For example,
+    /// the batch may overlap with one that was already collected, or it may not be ready yet.
+    pub fn validate_collect_req(
+        &self,
+        global_config: &DapGlobalConfig,
+        collect_req: &CollectReq,
+        now: Time,
+    ) -> Result<(), DapAbort> {
+        if self
+            .collector_hpke_config(collect_req.collector_hpke_config_id)
+            .is_none()
+        {
+            return Err(DapAbort::BadRequest(
+                "unrecognized collector HPKE config".into(),
+            ));
+        }
+
+        self.vdaf.validate_agg_param(&collect_req.agg_param)?;
+
+        match collect_req.query {
+            // The batch is chosen by the Leader, so there are no batch boundaries to check.
+            Query::FixedSizeCurrentBatch => match self.query {
+                DapQueryConfig::FixedSize { .. } => Ok(()),
+                DapQueryConfig::TimeInterval => Err(DapAbort::QueryMismatch {
+                    expected: QueryKind::from(&self.query),
+                    actual: QueryKind::from(&collect_req.query),
+                }),
+            },
+            _ => self.check_batch_sel(
+                global_config,
+                &BatchSelector::try_from(collect_req.query.clone())?,
+                now,
+            ),
+        }
+    }
+
+    /// Check that the batch selector matches the task's query type and that its boundaries are
+    /// valid at time `now`. The limits on the batch interval are taken from `global_config`,
+    /// unless the task overrides it.
+    pub(crate) fn check_batch_sel(
+        &self,
+        global_config: &DapGlobalConfig,
+        batch_sel: &BatchSelector,
+        now: Time,
+    ) -> Result<(), DapAbort> {
+        let global_config = self.global_config(global_config);
+        match (&self.query, batch_sel) {
+            (
+                DapQueryConfig::TimeInterval { .. },
+                BatchSelector::TimeInterval { batch_interval },
+            ) => {
+                // The interval is chosen by the Collector, so make sure its end can be computed.
+                let batch_interval_end = batch_interval
+                    .start
+                    .checked_add(batch_interval.duration)
+                    .ok_or_else(|| DapAbort::BadRequest("batch interval overflow".to_string()))?;
+
+                if batch_interval.start % self.time_precision != 0
+                    || batch_interval.duration % self.time_precision != 0
+                    || batch_interval.duration < self.time_precision
+                {
+                    return Err(DapAbort::BatchInvalid);
+                }
+
+                if batch_interval.duration > global_config.max_batch_duration {
+                    return Err(DapAbort::BadRequest("batch interval too large".to_string()));
+                }
+
+                if now.abs_diff(batch_interval.start) > global_config.min_batch_interval_start {
+                    return Err(DapAbort::BadRequest(
+                        "batch interval too far into past".to_string(),
+                    ));
+                }
+
+                if now.abs_diff(batch_interval_end) > global_config.max_batch_interval_end {
+                    return Err(DapAbort::BadRequest(
+                        "batch interval too far into future".to_string(),
+                    ));
+                }
+            }
+            (DapQueryConfig::FixedSize { .. }, BatchSelector::FixedSizeByBatchId { .. }) => (),
+            _ => {
+                return Err(DapAbort::QueryMismatch {
+                    expected: QueryKind::from(&self.query),
+                    actual: QueryKind::from(batch_sel),
+                })
+            }
+        };
+
+        Ok(())
+    }
 }
 
 impl AsRef<DapTaskConfig> for DapTaskConfig {
@@ -602,7 +1364,7 @@ impl AsRef<DapTaskConfig> for DapTaskConfig {
 }
 
 /// A measurement from which a Client generates a report.
-#[derive(Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapMeasurement {
     U64(u64),
@@ -615,20 +1377,239 @@ pub enum DapMeasurement {
 pub enum DapAggregateResult {
     U32Vec(Vec<u32>),
     U64(u64),
+    U64Vec(Vec<u64>),
     U128(u128),
     U128Vec(Vec<u128>),
 }
 
+impl DapAggregateResult {
+    /// Combine the aggregate results of two disjoint batches of the same task into the aggregate
+    /// result of their union. This is used to collect a set of fixed-size batches together (see
+    /// `DapLeader::init_collect_jobs_for_batches()`).
+    pub fn merge(self, other: Self) -> Result<Self, DapError> {
+        match (self, other) {
+            (Self::U64(left), Self::U64(right)) => Ok(Self::U64(
+                left.checked_add(right).ok_or_else(merge_overflow)?,
+            )),
+            (Self::U128(left), Self::U128(right)) => Ok(Self::U128(
+                left.checked_add(right).ok_or_else(merge_overflow)?,
+            )),
+            (Self::U32Vec(left), Self::U32Vec(right)) => {
+                Ok(Self::U32Vec(merge_vec(left, right, u32::checked_add)?))
+            }
+            (Self::U64Vec(left), Self::U64Vec(right)) => {
+                Ok(Self::U64Vec(merge_vec(left, right, u64::checked_add)?))
+            }
+            (Self::U128Vec(left), Self::U128Vec(right)) => {
+                Ok(Self::U128Vec(merge_vec(left, right, u128::checked_add)?))
+            }
+            _ => Err(DapError::fatal(
+                "tried to merge aggregate results of different types",
+            )),
+        }
+    }
+}
+
+fn merge_overflow() -> DapError {
+    DapError::fatal("aggregate result overflow")
+}
+
+fn merge_vec<T>(
+    left: Vec<T>,
+    right: Vec<T>,
+    checked_add: impl Fn(T, T) -> Option<T>,
+) -> Result<Vec<T>, DapError> {
+    if left.len() != right.len() {
+        return Err(DapError::fatal(
+            "tried to merge aggregate results of different lengths",
+        ));
+    }
+
+    left.into_iter()
+        .zip(right)
+        .map(|(left, right)| checked_add(left, right).ok_or_else(merge_overflow))
+        .collect()
+}
+
 /// The Leader's state after sending an AggregateInitReq.
 #[derive(Debug)]
 pub struct DapLeaderState {
     pub(crate) seq: Vec<(VdafState, VdafMessage, Time, ReportId)>,
+    /// Reports rejected so far, along with the reason each was rejected.
+    pub(crate) rejected: Vec<(ReportId, TransitionFailure)>,
+}
+
+impl DapLeaderState {
+    /// Encode the Leader state as a byte string.
+    ///
+    /// This method is used by the Leader in order to persist its state between the
+    /// initialization and continuation of an aggregation job, so that the job can be resumed
+    /// after a restart.
+    ///
+    /// Note that the encoding format is not specified by the DAP standard.
+    pub fn get_encoded(&self, vdaf_config: &VdafConfig) -> Result<Vec<u8>, DapError> {
+        let mut bytes = vec![];
+        encode_rejected(&mut bytes, &self.rejected);
+        for (state, message, time, report_id) in self.seq.iter() {
+            let message_data = match (vdaf_config, state) {
+                (VdafConfig::Prio3(prio3_config), _) => {
+                    prio3_append_prepare_state(&mut bytes, prio3_config, state)?;
+                    prio3_encode_prepare_message(message)
+                }
+                (VdafConfig::Prio2 { .. }, VdafState::Prio2(state)) => {
+                    state.encode(&mut bytes);
+                    prio2_encode_prepare_message(message)
+                }
+                _ => return Err(DapError::fatal("VDAF config and prep state mismatch")),
+            };
+            encode_u32_bytes(&mut bytes, &message_data);
+            time.encode(&mut bytes);
+            report_id.encode(&mut bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Decode the Leader state from a byte string.
+    pub fn get_decoded(vdaf_config: &VdafConfig, data: &[u8]) -> Result<Self, DapError> {
+        let mut r = Cursor::new(data);
+        let rejected = decode_rejected(&mut r)?;
+        let mut seq = vec![];
+        while (r.position() as usize) < data.len() {
+            let (state, message) = match vdaf_config {
+                VdafConfig::Prio3(ref prio3_config) => {
+                    let state = prio3_decode_prepare_state(prio3_config, 0, &mut r)?;
+                    let message = prio3_decode_prepare_message(&state, &decode_u32_bytes(&mut r)?)?;
+                    (state, message)
+                }
+                VdafConfig::Prio2 { dimension } => {
+                    let state = prio2_decode_prepare_state(*dimension, 0, &mut r)?;
+                    let message = prio2_decode_prepare_message(&state, &decode_u32_bytes(&mut r)?)?;
+                    (state, message)
+                }
+            };
+            let time = Time::decode(&mut r)?;
+            let report_id = ReportId::decode(&mut r)?;
+            seq.push((state, message, time, report_id))
+        }
+
+        Ok(DapLeaderState { seq, rejected })
+    }
 }
 
 /// The Leader's state after sending an AggregateContReq.
 #[derive(Debug)]
 pub struct DapLeaderUncommitted {
     pub(crate) seq: Vec<(DapOutputShare, ReportId)>,
+    /// Reports rejected so far, along with the reason each was rejected.
+    pub(crate) rejected: Vec<(ReportId, TransitionFailure)>,
+}
+
+impl DapLeaderUncommitted {
+    /// Encode the Leader's uncommitted output shares as a byte string. Like
+    /// [`DapLeaderState::get_encoded`], this is used to persist the Leader's state while it waits
+    /// for the Helper's AggregateResp.
+    ///
+    /// Note that the encoding format is not specified by the DAP standard.
+    pub fn get_encoded(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        encode_rejected(&mut bytes, &self.rejected);
+        for (out_share, report_id) in self.seq.iter() {
+            out_share.time.encode(&mut bytes);
+            bytes.extend_from_slice(&out_share.checksum);
+            encode_u32_bytes(&mut bytes, &out_share.data.get_encoded());
+            report_id.encode(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Decode the Leader's uncommitted output shares from a byte string.
+    pub fn get_decoded(vdaf_config: &VdafConfig, data: &[u8]) -> Result<Self, DapError> {
+        let mut r = Cursor::new(data);
+        let rejected = decode_rejected(&mut r)?;
+        let mut seq = vec![];
+        while (r.position() as usize) < data.len() {
+            let time = Time::decode(&mut r)?;
+            let mut checksum = [0; 32];
+            r.read_exact(&mut checksum).map_err(CodecError::Io)?;
+            let data = vdaf_config.decode_agg_share(&decode_u32_bytes(&mut r)?)?;
+            let report_id = ReportId::decode(&mut r)?;
+            seq.push((
+                DapOutputShare {
+                    time,
+                    checksum,
+                    data,
+                },
+                report_id,
+            ))
+        }
+
+        Ok(DapLeaderUncommitted { seq, rejected })
+    }
+}
+
+/// Encode the reports rejected during an aggregation job, prefixed by their number.
+fn encode_rejected(bytes: &mut Vec<u8>, rejected: &[(ReportId, TransitionFailure)]) {
+    (rejected.len() as u32).encode(bytes);
+    for (report_id, failure) in rejected.iter() {
+        report_id.encode(bytes);
+        failure.encode(bytes);
+    }
+}
+
+/// Decode the reports rejected during an aggregation job.
+fn decode_rejected(
+    r: &mut Cursor<&[u8]>,
+) -> Result<Vec<(ReportId, TransitionFailure)>, CodecError> {
+    let len = u32::decode(r)?;
+    let mut rejected = vec![];
+    for _ in 0..len {
+        rejected.push((ReportId::decode(r)?, TransitionFailure::decode(r)?));
+    }
+    Ok(rejected)
+}
+
+/// The Helper's response to an AggregateInitializeReq carrying an idempotency key. The Helper
+/// stores it so that it can answer a retry of the request without processing the request again.
+/// The response is bound to the request it answers: a request carrying the same key, but for a
+/// different aggregation job or with a different payload, is not a retry.
+///
+/// Note that the encoding format is not specified by the DAP standard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DapStoredAggInitResp {
+    /// ID of the aggregation job initialized by the request.
+    pub agg_job_id: Id,
+
+    /// SHA-256 hash of the request payload.
+    pub req_hash: [u8; 32],
+
+    /// Time at which the response was stored.
+    pub created_at: Time,
+
+    /// The response to the request.
+    pub agg_resp: AggregateResp,
+}
+
+impl Encode for DapStoredAggInitResp {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.agg_job_id.encode(bytes);
+        bytes.extend_from_slice(&self.req_hash);
+        self.created_at.encode(bytes);
+        self.agg_resp.encode(bytes);
+    }
+}
+
+impl Decode for DapStoredAggInitResp {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let agg_job_id = Id::decode(bytes)?;
+        let mut req_hash = [0; 32];
+        bytes.read_exact(&mut req_hash)?;
+        Ok(Self {
+            agg_job_id,
+            req_hash,
+            created_at: Time::decode(bytes)?,
+            agg_resp: AggregateResp::decode(bytes)?,
+        })
+    }
 }
 
 /// The Helper's state during the aggregation flow.
@@ -691,6 +1672,21 @@ impl DapHelperState {
     }
 }
 
+/// Compute the checksum of a batch from the IDs of the reports it contains. The checksum is the
+/// XOR of the SHA-256 hashes of the report IDs; it does not depend on the order of the IDs. This
+/// matches the checksum of the aggregate share computed for the same reports, which allows the
+/// checksum of a batch to be audited out of band.
+pub fn compute_checksum(report_ids: &[ReportId]) -> [u8; 32] {
+    let mut checksum = [0; 32];
+    for report_id in report_ids.iter() {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &report_id.get_encoded());
+        for (x, y) in checksum.iter_mut().zip(digest.as_ref()) {
+            *x ^= y;
+        }
+    }
+    checksum
+}
+
 #[derive(Debug)]
 /// An ouptut share produced by an Aggregator for a single report.
 pub struct DapOutputShare {
@@ -699,12 +1695,31 @@ pub struct DapOutputShare {
     pub(crate) data: VdafAggregateShare,
 }
 
+/// Subtract `right` from `left` element-wise.
+#[cfg(feature = "audit")]
+fn sub_field_vec<F: prio::field::FieldElement>(
+    left: &[F],
+    right: &[F],
+) -> Result<Vec<F>, DapError> {
+    if left.len() != right.len() {
+        return Err(DapError::fatal("aggregate share length mismatch"));
+    }
+    Ok(left.iter().zip(right).map(|(x, y)| *x - *y).collect())
+}
+
+/// Version of the encoding output by [`DapAggregateShare::to_audit_bytes`].
+const AGG_SHARE_AUDIT_FORMAT_VERSION: u8 = 1;
+
 /// An aggregate share computed by combining a set of output shares.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct DapAggregateShare {
     pub(crate) report_count: u64,
     pub(crate) checksum: [u8; 32],
     pub(crate) data: Option<VdafAggregateShare>,
+    #[serde(default)]
+    pub(crate) min_time: Time, // Earliest report timestamp
+    #[serde(default)]
+    pub(crate) max_time: Time, // Latest report timestamp
 }
 
 impl DapAggregateShare {
@@ -712,6 +1727,10 @@ impl DapAggregateShare {
     //
     // TODO Add unit tests.
     pub fn merge(&mut self, other: DapAggregateShare) -> Result<(), DapError> {
+        let self_empty = self.empty();
+        let other_empty = other.empty();
+        let (other_min_time, other_max_time) = (other.min_time, other.max_time);
+
         // Update the aggregate share data.
         match (self.data.as_mut(), other.data) {
             (_, None) => (),
@@ -740,6 +1759,15 @@ impl DapAggregateShare {
             _ => return Err(DapError::fatal("invalid aggregate share merge")),
         };
 
+        // Update the time range of the reports.
+        if self_empty {
+            self.min_time = other_min_time;
+            self.max_time = other_max_time;
+        } else if !other_empty {
+            self.min_time = std::cmp::min(self.min_time, other_min_time);
+            self.max_time = std::cmp::max(self.max_time, other_max_time);
+        }
+
         self.report_count += other.report_count;
         for (x, y) in self.checksum.iter_mut().zip(other.checksum) {
             *x ^= y;
@@ -747,16 +1775,124 @@ impl DapAggregateShare {
         Ok(())
     }
 
+    /// Remove the contribution of a single report from the aggregate share, e.g., to correct a
+    /// batch into which the report was aggregated by mistake. `contribution` is the aggregate
+    /// share of just that report and must match `report_id` under `checksum_algorithm`.
+    ///
+    /// This method can't check that the report was actually aggregated into this share, so it is
+    /// only available with the "audit" feature. The report time range is left unchanged.
+    #[cfg(feature = "audit")]
+    pub fn remove(
+        &mut self,
+        report_id: &ReportId,
+        contribution: &DapAggregateShare,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> Result<(), DapError> {
+        if contribution.report_count != 1
+            || contribution.checksum != checksum_algorithm.compute(std::slice::from_ref(report_id))
+        {
+            return Err(DapError::fatal("contribution does not match report"));
+        }
+
+        if self.empty() {
+            return Err(DapError::fatal("aggregate share is empty"));
+        }
+
+        // Update the aggregate share data.
+        match (self.data.as_mut(), contribution.data.as_ref()) {
+            (Some(VdafAggregateShare::Field64(left)), Some(VdafAggregateShare::Field64(right))) => {
+                *left = sub_field_vec(left.as_ref(), right.as_ref())?.into();
+            }
+            (
+                Some(VdafAggregateShare::Field128(left)),
+                Some(VdafAggregateShare::Field128(right)),
+            ) => {
+                *left = sub_field_vec(left.as_ref(), right.as_ref())?.into();
+            }
+            (
+                Some(VdafAggregateShare::FieldPrio2(left)),
+                Some(VdafAggregateShare::FieldPrio2(right)),
+            ) => {
+                *left = sub_field_vec(left.as_ref(), right.as_ref())?.into();
+            }
+            _ => return Err(DapError::fatal("invalid aggregate share removal")),
+        };
+
+        self.report_count -= 1;
+        for (x, y) in self.checksum.iter_mut().zip(contribution.checksum) {
+            *x ^= y;
+        }
+        if self.empty() {
+            self.reset();
+        }
+        Ok(())
+    }
+
     /// Return `true` if the aggregate share contains no reports.
     pub fn empty(&self) -> bool {
         self.report_count == 0
     }
 
+    /// Encode the aggregate share in a stable, versioned format so that operators can compare the
+    /// aggregate shares computed by the Leader and Helper for the same batch. The encoding
+    /// consists of the format version, the report count, the checksum, the report time range,
+    /// and the VDAF aggregate share, if any.
+    ///
+    /// The encoding is not encrypted and must not leave the Aggregator's trusted boundary. Note
+    /// that the encoding format is not specified by the DAP standard.
+    pub fn to_audit_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![AGG_SHARE_AUDIT_FORMAT_VERSION];
+        self.report_count.encode(&mut bytes);
+        bytes.extend_from_slice(&self.checksum);
+        self.min_time.encode(&mut bytes);
+        self.max_time.encode(&mut bytes);
+        match &self.data {
+            Some(data) => {
+                1_u8.encode(&mut bytes);
+                encode_u32_bytes(&mut bytes, &data.get_encoded());
+            }
+            None => 0_u8.encode(&mut bytes),
+        }
+        bytes
+    }
+
+    /// Decode an aggregate share output by [`to_audit_bytes`](Self::to_audit_bytes).
+    pub fn from_audit_bytes(vdaf_config: &VdafConfig, data: &[u8]) -> Result<Self, DapError> {
+        let mut r = Cursor::new(data);
+        if u8::decode(&mut r)? != AGG_SHARE_AUDIT_FORMAT_VERSION {
+            return Err(DapError::fatal("unsupported audit format version"));
+        }
+        let report_count = u64::decode(&mut r)?;
+        let mut checksum = [0; 32];
+        r.read_exact(&mut checksum).map_err(CodecError::Io)?;
+        let min_time = Time::decode(&mut r)?;
+        let max_time = Time::decode(&mut r)?;
+        let agg_share_data = match u8::decode(&mut r)? {
+            0 => None,
+            1 => Some(vdaf_config.decode_agg_share(&decode_u32_bytes(&mut r)?)?),
+            _ => return Err(CodecError::UnexpectedValue.into()),
+        };
+        let bytes_left = data.len() - r.position() as usize;
+        if bytes_left > 0 {
+            return Err(CodecError::BytesLeftOver(bytes_left).into());
+        }
+
+        Ok(Self {
+            report_count,
+            checksum,
+            data: agg_share_data,
+            min_time,
+            max_time,
+        })
+    }
+
     /// Set the aggregate share to zero.
     pub fn reset(&mut self) {
         self.report_count = 0;
         self.checksum = [0; 32];
         self.data = None;
+        self.min_time = 0;
+        self.max_time = 0;
     }
 
     #[cfg(test)]
@@ -769,6 +1905,8 @@ impl DapAggregateShare {
                 report_count: 1,
                 checksum: out_share.checksum,
                 data: Some(out_share.data),
+                min_time: out_share.time,
+                max_time: out_share.time,
             })?;
         }
         Ok(agg_share)
@@ -785,8 +1923,9 @@ pub enum DapLeaderTransition<M: Debug> {
     /// before committing them.
     Uncommitted(DapLeaderUncommitted, M),
 
-    /// The Leader has completed the aggregation flow without computing an aggregate share.
-    Skip,
+    /// The Leader has completed the aggregation flow without computing an aggregate share. The
+    /// payload is the set of reports that were rejected, along with the reason each was rejected.
+    Skip(Vec<(ReportId, TransitionFailure)>),
 }
 
 /// Helper state transition during the aggregation flow.
@@ -827,6 +1966,10 @@ pub enum Prio3Config {
     /// equal to `0` or `1`.
     Count,
 
+    /// A vector of `length` 64-bit counters. The aggregate is the element-wise sum of the
+    /// measurements, where each element of each measurement is equal to `0` or `1`.
+    CountVec { length: usize },
+
     /// A histogram for estimating the distribution of 64-bit, unsigned integers using pre-defined
     /// bucket boundaries.
     Histogram { buckets: Vec<u64> },
@@ -836,6 +1979,114 @@ pub enum Prio3Config {
     Sum { bits: u32 },
 }
 
+/// Buffer in which the Leader holds uploaded reports before storing them, so that reports can be
+/// written to the report store in batches. The buffer is ready to be flushed once it holds
+/// `max_count` reports or once its oldest report has been held for `max_age` seconds.
+///
+/// Reports that are replayed while in the buffer, or while being flushed, are rejected by the
+/// buffer itself. Only one flush runs at a time: a flush starts with [`Self::flush_if_ready`] and
+/// ends with [`Self::finish_flush`].
+pub struct ReportBuffer {
+    max_count: usize,
+    max_age: Duration,
+    state: Mutex<ReportBufferState>,
+}
+
+#[derive(Default)]
+struct ReportBufferState {
+    reports: Vec<Report>,
+    report_ids: HashSet<(Id, ReportId)>, // IDs of buffered reports and reports being flushed
+    oldest: Time,                        // Time at which the oldest report was pushed
+    flushing: bool,
+}
+
+impl ReportBuffer {
+    /// Create an empty report buffer with the given count and age thresholds.
+    pub fn new(max_count: usize, max_age: Duration) -> Self {
+        Self {
+            max_count,
+            max_age,
+            state: Mutex::new(ReportBufferState::default()),
+        }
+    }
+
+    /// Add a report to the buffer. `now` is the current time. An error is returned if a report
+    /// with the same task ID and report ID is already in the buffer or is being flushed.
+    pub fn push(&self, report: Report, now: Time) -> Result<(), DapError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        if !state
+            .report_ids
+            .insert((report.task_id.clone(), report.metadata.id.clone()))
+        {
+            return Err(DapError::Transition(TransitionFailure::ReportReplayed));
+        }
+
+        if state.reports.is_empty() {
+            state.oldest = now;
+        }
+        state.reports.push(report);
+        Ok(())
+    }
+
+    /// Return the number of reports for the given task that are in the buffer or are being
+    /// flushed.
+    pub fn count(&self, task_id: &Id) -> Result<u64, DapError> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        Ok(state
+            .report_ids
+            .iter()
+            .filter(|(report_task_id, _)| report_task_id == task_id)
+            .count() as u64)
+    }
+
+    /// If the buffer is ready to be flushed at time `now` and no flush is in progress, then start
+    /// a flush by removing and returning the buffered reports. Otherwise return `None`. The caller
+    /// must end the flush with [`Self::finish_flush`].
+    pub fn flush_if_ready(&self, now: Time) -> Result<Option<Vec<Report>>, DapError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        if state.flushing
+            || (state.reports.len() < self.max_count
+                && (state.reports.is_empty() || now < state.oldest.saturating_add(self.max_age)))
+        {
+            return Ok(None);
+        }
+
+        state.flushing = true;
+        Ok(Some(std::mem::take(&mut state.reports)))
+    }
+
+    /// End the flush in progress. `unstored` are the flushed reports that could not be stored.
+    /// They are returned to the buffer ahead of any reports pushed since and are flushed by the
+    /// next call to [`Self::flush_if_ready`] at or after time `now`.
+    pub fn finish_flush(&self, unstored: Vec<Report>, now: Time) -> Result<(), DapError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        if !unstored.is_empty() {
+            state.reports.splice(0..0, unstored);
+            state.oldest = now.saturating_sub(self.max_age);
+        }
+        let report_ids = state
+            .reports
+            .iter()
+            .map(|report| (report.task_id.clone(), report.metadata.id.clone()))
+            .collect();
+        state.report_ids = report_ids;
+        state.flushing = false;
+        Ok(())
+    }
+}
+
 /// DAP request.
 #[derive(Debug)]
 pub struct DapRequest<S> {
@@ -845,9 +2096,24 @@ pub struct DapRequest<S> {
     pub payload: Vec<u8>,
     pub url: Url,
     pub sender_auth: Option<S>,
+    /// Key used by the Helper to recognize a retried AggregateInitializeReq. A retry carrying the
+    /// same key is answered with the response to the original request. This is conveyed by the
+    /// [`HTTP_HEADER_IDEMPOTENCY_KEY`](constants::HTTP_HEADER_IDEMPOTENCY_KEY) header.
+    pub idempotency_key: Option<Id>,
+    /// ID of the Collector's HPKE config under which the Helper is to encrypt its aggregate share.
+    /// If not set, the task's first Collector HPKE config is used. This is conveyed by the
+    /// [`HTTP_HEADER_COLLECTOR_HPKE_CONFIG_ID`](constants::HTTP_HEADER_COLLECTOR_HPKE_CONFIG_ID)
+    /// header.
+    pub collector_hpke_config_id: Option<u8>,
 }
 
 impl<S> DapRequest<S> {
+    /// Create a builder for a request.
+    pub fn builder() -> DapRequestBuilder<S> {
+        DapRequestBuilder::default()
+    }
+
+    #[cfg(feature = "roles")]
     pub(crate) fn task_id(&self) -> Result<&Id, DapAbort> {
         if let Some(ref id) = self.task_id {
             Ok(id)
@@ -858,6 +2124,104 @@ impl<S> DapRequest<S> {
     }
 }
 
+/// Builder for a [`DapRequest`]. The DAP version and URL are required; all other fields are
+/// optional and the payload is empty by default.
+#[derive(Debug)]
+pub struct DapRequestBuilder<S> {
+    version: Option<DapVersion>,
+    media_type: Option<&'static str>,
+    task_id: Option<Id>,
+    payload: Vec<u8>,
+    url: Option<Url>,
+    sender_auth: Option<S>,
+    idempotency_key: Option<Id>,
+    collector_hpke_config_id: Option<u8>,
+}
+
+impl<S> Default for DapRequestBuilder<S> {
+    fn default() -> Self {
+        Self {
+            version: None,
+            media_type: None,
+            task_id: None,
+            payload: Vec::new(),
+            url: None,
+            sender_auth: None,
+            idempotency_key: None,
+            collector_hpke_config_id: None,
+        }
+    }
+}
+
+impl<S> DapRequestBuilder<S> {
+    /// Set the DAP version of the request.
+    pub fn version(mut self, version: DapVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Set the media type of the request.
+    pub fn media_type(mut self, media_type: &'static str) -> Self {
+        self.media_type = Some(media_type);
+        self
+    }
+
+    /// Set the task ID of the request.
+    pub fn task_id(mut self, task_id: Id) -> Self {
+        self.task_id = Some(task_id);
+        self
+    }
+
+    /// Set the payload of the request.
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Set the URL of the request.
+    pub fn url(mut self, url: Url) -> Self {
+        self.url = Some(url);
+        self
+    }
+
+    /// Set the sender authorization of the request.
+    pub fn sender_auth(mut self, sender_auth: S) -> Self {
+        self.sender_auth = Some(sender_auth);
+        self
+    }
+
+    /// Set the idempotency key of the request.
+    pub fn idempotency_key(mut self, idempotency_key: Id) -> Self {
+        self.idempotency_key = Some(idempotency_key);
+        self
+    }
+
+    /// Set the ID of the Collector's HPKE config under which the Helper is to encrypt its
+    /// aggregate share.
+    pub fn collector_hpke_config_id(mut self, collector_hpke_config_id: u8) -> Self {
+        self.collector_hpke_config_id = Some(collector_hpke_config_id);
+        self
+    }
+
+    /// Build the request. Fails if the DAP version or URL were not set.
+    pub fn build(self) -> Result<DapRequest<S>, DapError> {
+        Ok(DapRequest {
+            version: self
+                .version
+                .ok_or_else(|| DapError::fatal("DAP request is missing the DAP version"))?,
+            media_type: self.media_type,
+            task_id: self.task_id,
+            payload: self.payload,
+            url: self
+                .url
+                .ok_or_else(|| DapError::fatal("DAP request is missing the URL"))?,
+            sender_auth: self.sender_auth,
+            idempotency_key: self.idempotency_key,
+            collector_hpke_config_id: self.collector_hpke_config_id,
+        })
+    }
+}
+
 /// DAP response.
 #[derive(Debug)]
 pub struct DapResponse {
@@ -865,12 +2229,46 @@ pub struct DapResponse {
     pub payload: Vec<u8>,
 }
 
+/// Status of a report, as observed by an Aggregator. This is intended for debugging why a report
+/// was not included in an aggregate result.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    /// The report has been stored but not yet aggregated.
+    Pending,
+
+    /// The report was aggregated.
+    Aggregated,
+
+    /// The report was rejected for the given reason.
+    Rejected(TransitionFailure),
+
+    /// The Aggregator has no record of the report.
+    Unknown,
+}
+
 /// Status of a collect job.
 #[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapCollectJob {
-    Done(CollectResp),
-    Pending,
+    Done {
+        collect_resp: CollectResp,
+        created_at: Time,   // Time at which the collect request was received
+        completed_at: Time, // Time at which the collect job was completed
+    },
+    Pending {
+        created_at: Time, // Time at which the collect request was received
+    },
+    /// The collect job was still pending when it expired. See
+    /// [`DapLeader::expire_collect_jobs`](crate::roles::DapLeader::expire_collect_jobs).
+    Expired {
+        created_at: Time, // Time at which the collect request was received
+    },
+    /// The collect job can't be completed, e.g., because the Helper has already collected the
+    /// batch. See [`DapLeader::fail_collect_job`](crate::roles::DapLeader::fail_collect_job).
+    Failed {
+        created_at: Time, // Time at which the collect request was received
+    },
     Unknown,
 }
 
@@ -889,17 +2287,93 @@ pub struct DapLeaderProcessTelemetry {
     pub reports_processed: u64,
 }
 
+/// Outcome of the aggregation jobs run by the Leader for a single task.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DapAggJobReport {
+    /// The number of reports that were aggregated successfully.
+    pub reports_succeeded: u64,
+
+    /// The number of reports that were rejected by the Leader or the Helper.
+    pub reports_failed: u64,
+}
+
 pub mod auth;
 pub mod constants;
 pub mod hpke;
 #[cfg(test)]
 mod hpke_test;
 pub mod messages;
+#[cfg(feature = "roles")]
 pub mod roles;
-#[cfg(test)]
+#[cfg(all(test, feature = "roles"))]
 mod roles_test;
 pub mod taskprov;
 #[cfg(test)]
 mod taskprov_test;
+#[cfg(feature = "roles")]
 pub mod testing;
 pub mod vdaf;
+
+// These are declarative macros which let us generate a test point for
+// each DapVersion given a test which takes a version parameter.
+//
+// E.g. currently
+//
+//     async_test_versions! { something }
+//
+// would generate async tests named
+//
+//     something_draft02
+//
+// and
+//
+//     something_draft03
+//
+// that called something(version) with the appropriate version.
+//
+// We use the "paste" crate to get a macro that can paste tokens and also
+// fiddle case.
+
+#[macro_export]
+macro_rules! test_version {
+    ($fname:ident, $version:ident) => {
+        paste! {
+            #[test]
+            fn [<$fname _ $version:lower>]() {
+                $fname (DapVersion::$version);
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! test_versions {
+    ($($fname:ident),*) => {
+        $(
+            test_version! { $fname, Draft02 }
+            test_version! { $fname, Draft03 }
+        )*
+    };
+}
+
+#[macro_export]
+macro_rules! async_test_version {
+    ($fname:ident, $version:ident) => {
+        paste! {
+            #[tokio::test]
+            async fn [<$fname _ $version:lower>]() {
+                $fname (DapVersion::$version) . await;
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! async_test_versions {
+    ($($fname:ident),*) => {
+        $(
+            async_test_version! { $fname, Draft02 }
+            async_test_version! { $fname, Draft03 }
+        )*
+    };
+}
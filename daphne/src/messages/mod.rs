@@ -3,7 +3,10 @@
 
 //! Messages in the DAP protocol.
 
-use crate::{DapError, DapVersion};
+use crate::{
+    constants::{media_type_for, MediaTypeKind},
+    DapAbort, DapError, DapVersion,
+};
 use prio::codec::{
     decode_u16_items, decode_u32_items, encode_u16_items, encode_u32_items, CodecError, Decode,
     Encode, ParameterizedDecode, ParameterizedEncode,
@@ -13,7 +16,7 @@ use std::{
     collections::HashSet,
     convert::{TryFrom, TryInto},
     fmt::Debug,
-    io::{Cursor, Read},
+    io::{Cursor, ErrorKind, Read, Write},
 };
 
 // Various algorithm constants
@@ -31,7 +34,7 @@ const FIXED_SIZE_QUERY_TYPE_BY_BATCH_ID: u8 = 0x00;
 const FIXED_SIZE_QUERY_TYPE_CURRENT_BATCH: u8 = 0x01;
 
 // Known extension types.
-const EXTENSION_TASKPROV: u16 = 0xff00;
+pub(crate) const EXTENSION_TASKPROV: u16 = 0xff00;
 
 /// The identifier for a DAP task.
 #[derive(Clone, Debug, Default, Deserialize, Hash, PartialEq, Eq, Serialize)]
@@ -116,7 +119,7 @@ pub enum Extension {
 
 impl Extension {
     /// Return the type code associated with the extension
-    fn type_code(&self) -> u16 {
+    pub(crate) fn type_code(&self) -> u16 {
         match self {
             Self::Taskprov { .. } => EXTENSION_TASKPROV,
             Self::Unhandled { typ, .. } => *typ,
@@ -174,16 +177,14 @@ impl Decode for ReportMetadata {
             time: Time::decode(bytes)?,
             extensions: decode_u16_items(&(), bytes)?,
         };
-        // Check for duplicate extensions and unknown extensions.
+        // Check for duplicate extensions. Unrecognized extensions are decoded so that an
+        // Aggregator can reject the report rather than the message carrying it; see
+        // `DapTaskConfig::allows_report_extensions()`.
         let mut seen: HashSet<u16> = HashSet::new();
         for extension in &metadata.extensions {
             if !seen.insert(extension.type_code()) {
                 return Err(CodecError::UnexpectedValue);
             }
-            if matches!(extension, Extension::Unhandled { .. }) {
-                // Unrecognized extensions are an error.
-                return Err(CodecError::UnexpectedValue);
-            }
         }
         Ok(metadata)
     }
@@ -297,6 +298,33 @@ pub enum BatchSelector {
     FixedSizeByBatchId { batch_id: Id },
 }
 
+impl BatchSelector {
+    /// Check whether a report could be in both the batch determined by this selector and the one
+    /// determined by `other`. Time intervals intersect if they overlap (intervals that are merely
+    /// adjacent do not); fixed-size batches intersect if they have the same batch ID. Selectors for
+    /// different query types never intersect.
+    pub fn intersects(&self, other: &BatchSelector) -> bool {
+        match (self, other) {
+            (
+                Self::TimeInterval { batch_interval },
+                Self::TimeInterval {
+                    batch_interval: other_batch_interval,
+                },
+            ) => {
+                batch_interval.start < other_batch_interval.end()
+                    && other_batch_interval.start < batch_interval.end()
+            }
+            (
+                Self::FixedSizeByBatchId { batch_id },
+                Self::FixedSizeByBatchId {
+                    batch_id: other_batch_id,
+                },
+            ) => batch_id == other_batch_id,
+            _ => false,
+        }
+    }
+}
+
 impl Encode for BatchSelector {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match self {
@@ -391,6 +419,161 @@ impl ParameterizedDecode<DapVersion> for AggregateInitializeReq {
     }
 }
 
+impl AggregateInitializeReq {
+    /// Encode the message for the given version and write it to `writer`. The output is the same
+    /// as that of `get_encoded_with_param()`, but the report shares are encoded one at a time, so
+    /// the full encoding is never held in memory.
+    pub fn encode_to_writer(
+        &self,
+        writer: &mut impl Write,
+        version: &DapVersion,
+    ) -> std::io::Result<()> {
+        let mut bytes = Vec::new();
+        self.task_id.encode(&mut bytes);
+        self.agg_job_id.encode(&mut bytes);
+        match version {
+            DapVersion::Draft02 => encode_u16_bytes(&mut bytes, &self.agg_param),
+            DapVersion::Draft03 => encode_u32_bytes(&mut bytes, &self.agg_param),
+            _ => unreachable!("unimplemented version"),
+        };
+        self.part_batch_sel.encode(&mut bytes);
+        writer.write_all(&bytes)?;
+
+        // The report shares are prefixed by the length of their encoding, which we compute by
+        // encoding each report share twice.
+        let mut report_shares_len = 0;
+        for report_share in self.report_shares.iter() {
+            bytes.clear();
+            report_share.encode(&mut bytes);
+            report_shares_len += bytes.len();
+        }
+        bytes.clear();
+        u32::try_from(report_shares_len)
+            .expect("length too large for u32")
+            .encode(&mut bytes);
+        writer.write_all(&bytes)?;
+        for report_share in self.report_shares.iter() {
+            bytes.clear();
+            report_share.encode(&mut bytes);
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Read a message encoded for the given version from `reader` and decode it. This is the
+    /// inverse of [`encode_to_writer`](Self::encode_to_writer): the input is buffered only as
+    /// much as is needed to decode the next report share.
+    pub fn decode_from_reader(
+        reader: &mut impl Read,
+        version: &DapVersion,
+    ) -> Result<Self, CodecError> {
+        let mut decoder = StreamDecoder::new(reader);
+        let task_id = decoder.decode_next(Id::decode)?;
+        let agg_job_id = decoder.decode_next(Id::decode)?;
+        let agg_param = match version {
+            DapVersion::Draft02 => decoder.decode_next(decode_u16_bytes)?,
+            DapVersion::Draft03 => decoder.decode_next(decode_u32_bytes)?,
+            _ => unreachable!("unimplemented version"),
+        };
+        let part_batch_sel = decoder.decode_next(PartialBatchSelector::decode)?;
+
+        let report_shares_len = decoder.decode_next(u32::decode)? as usize;
+        let report_shares_start = decoder.consumed;
+        let mut report_shares = Vec::new();
+        while decoder.consumed - report_shares_start < report_shares_len {
+            report_shares.push(decoder.decode_next(ReportShare::decode)?);
+        }
+        if decoder.consumed - report_shares_start != report_shares_len {
+            return Err(CodecError::UnexpectedValue);
+        }
+
+        if !decoder.is_at_end()? {
+            return Err(CodecError::BytesLeftOver(decoder.buf.len()));
+        }
+
+        Ok(Self {
+            task_id,
+            agg_job_id,
+            agg_param,
+            part_batch_sel,
+            report_shares,
+        })
+    }
+}
+
+// Number of bytes `StreamDecoder` reads at a time, unless more are needed.
+const STREAM_DECODER_CHUNK_LEN: usize = 4096;
+
+// Decodes a sequence of values from a reader, buffering only as much of the input as is needed to
+// decode the next value.
+struct StreamDecoder<'a, R> {
+    reader: &'a mut R,
+    buf: Vec<u8>,
+    consumed: usize,
+}
+
+impl<'a, R: Read> StreamDecoder<'a, R> {
+    fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            consumed: 0,
+        }
+    }
+
+    // Decode the next value from the input. If the buffered input is too short to decode the
+    // value, then read more of the input and try again.
+    fn decode_next<T>(
+        &mut self,
+        decode: impl Fn(&mut Cursor<&[u8]>) -> Result<T, CodecError>,
+    ) -> Result<T, CodecError> {
+        loop {
+            let mut cursor = Cursor::new(self.buf.as_slice());
+            match decode(&mut cursor) {
+                Ok(value) => {
+                    let len = cursor.position() as usize;
+                    self.buf.drain(..len);
+                    self.consumed += len;
+                    return Ok(value);
+                }
+                Err(CodecError::Io(ref e)) if e.kind() == ErrorKind::UnexpectedEof => (),
+                Err(CodecError::LengthPrefixTooBig(..)) => (),
+                Err(e) => return Err(e),
+            }
+
+            if !self.fill()? {
+                return Err(CodecError::Io(ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+
+    // Return true if the input has been consumed in full.
+    fn is_at_end(&mut self) -> Result<bool, CodecError> {
+        Ok(self.buf.is_empty() && !self.fill()?)
+    }
+
+    // Read more of the input into the buffer. Return false if the end of the input was reached.
+    // The amount read grows with the buffer, so that a large value is read in a few steps.
+    fn fill(&mut self) -> Result<bool, CodecError> {
+        let start = self.buf.len();
+        self.buf
+            .resize(start + start.max(STREAM_DECODER_CHUNK_LEN), 0);
+        loop {
+            match self.reader.read(&mut self.buf[start..]) {
+                Ok(len) => {
+                    self.buf.truncate(start + len);
+                    return Ok(len > 0);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(CodecError::Io(e));
+                }
+            }
+        }
+    }
+}
+
 /// Aggregate continuation request.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AggregateContinueReq {
@@ -495,6 +678,39 @@ pub enum TransitionFailure {
     TaskExpired = 7,
 }
 
+impl TransitionFailure {
+    /// Every transition failure, in order of wire-format code.
+    pub const ALL: [Self; 8] = [
+        Self::BatchCollected,
+        Self::ReportReplayed,
+        Self::ReportDropped,
+        Self::HpkeUnknownConfigId,
+        Self::HpkeDecryptError,
+        Self::VdafPrepError,
+        Self::BatchSaturated,
+        Self::TaskExpired,
+    ];
+
+    /// The wire-format code of the failure.
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    /// A human-readable description of the failure, suitable for logging.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::BatchCollected => "batch already collected",
+            Self::ReportReplayed => "report replayed",
+            Self::ReportDropped => "report dropped",
+            Self::HpkeUnknownConfigId => "unknown HPKE config ID",
+            Self::HpkeDecryptError => "HPKE decryption failed",
+            Self::VdafPrepError => "VDAF preparation failed",
+            Self::BatchSaturated => "batch saturated",
+            Self::TaskExpired => "task expired",
+        }
+    }
+}
+
 impl TryFrom<u8> for TransitionFailure {
     type Error = CodecError;
 
@@ -540,8 +756,10 @@ impl std::fmt::Display for TransitionFailure {
     }
 }
 
-/// An aggregate response sent from the Helper to the Leader.
-#[derive(Debug, PartialEq, Eq, Default)]
+/// An aggregate response sent from the Helper to the Leader. The transitions are in the same order
+/// as the report shares (or transitions) of the request to which this is a response, with exactly
+/// one transition per report ID.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
 #[allow(missing_docs)]
 pub struct AggregateResp {
     pub transitions: Vec<Transition>,
@@ -672,6 +890,12 @@ pub struct CollectReq {
     pub task_id: Id,
     pub query: Query,
     pub agg_param: Vec<u8>,
+    /// The ID of the Collector's HPKE config under which the aggregate shares are to be
+    /// encrypted, if the Collector specified one. This is not part of the DAP encoding of the
+    /// message: it is set by the Leader from the `collector_hpke_config_id` query parameter of
+    /// the collect request.
+    #[serde(default)]
+    pub collector_hpke_config_id: Option<u8>,
 }
 
 impl ParameterizedEncode<DapVersion> for CollectReq {
@@ -699,6 +923,7 @@ impl ParameterizedDecode<DapVersion> for CollectReq {
                 DapVersion::Draft03 => decode_u32_bytes(bytes)?,
                 _ => panic!("unimplemented DapVersion"),
             },
+            collector_hpke_config_id: None,
         })
     }
 }
@@ -710,6 +935,9 @@ impl ParameterizedDecode<DapVersion> for CollectReq {
 pub struct CollectResp {
     pub part_batch_sel: PartialBatchSelector,
     pub report_count: u64,
+    /// The aggregate shares, each encrypted to the Collector by one of the Aggregators, with the
+    /// Leader's share first. The Leader does not combine the shares; the Collector may unshard
+    /// them into the aggregate result or decrypt each share individually.
     pub encrypted_agg_shares: Vec<HpkeCiphertext>,
 }
 
@@ -802,6 +1030,71 @@ impl Decode for AggregateShareResp {
     }
 }
 
+/// A DAP message decoded from the payload of an HTTP request or response.
+#[derive(Debug)]
+pub enum DapMessage {
+    HpkeConfig(HpkeConfig),
+    Report(Report),
+    AggregateInitializeReq(AggregateInitializeReq),
+    AggregateContinueReq(AggregateContinueReq),
+    /// An aggregate response. The same message is sent in response to both aggregate
+    /// initialization and aggregate continuation requests.
+    AggregateResp(AggregateResp),
+    AggregateShareReq(AggregateShareReq),
+    AggregateShareResp(AggregateShareResp),
+    CollectReq(CollectReq),
+    CollectResp(CollectResp),
+}
+
+/// Decode the payload of an HTTP request or response with the given media type and DAP version.
+///
+/// The payload is untrusted, so this method never panics: if the media type is unknown or the
+/// payload is malformed, truncated, or has trailing bytes, then it returns
+/// [`DapAbort::UnrecognizedMessage`]. Length-prefixed fields are checked against the number of
+/// remaining bytes before they are read, so the allocation is bounded by the payload length.
+pub fn decode_request_payload(
+    media_type: &str,
+    version: DapVersion,
+    bytes: &[u8],
+) -> Result<DapMessage, DapAbort> {
+    // The decoders of version-dependent messages panic on an unknown version.
+    if version == DapVersion::Unknown {
+        return Err(DapAbort::InvalidProtocolVersion);
+    }
+
+    // Media types may differ between versions, so resolve the kind of message with respect to the
+    // version of the request.
+    let kind = MediaTypeKind::ALL
+        .into_iter()
+        .find(|kind| media_type_for(*kind, version) == media_type)
+        .ok_or(DapAbort::UnrecognizedMessage)?;
+
+    let msg = match kind {
+        MediaTypeKind::HpkeConfig => DapMessage::HpkeConfig(HpkeConfig::get_decoded(bytes)?),
+        MediaTypeKind::Report => DapMessage::Report(Report::get_decoded(bytes)?),
+        MediaTypeKind::AggInitReq => DapMessage::AggregateInitializeReq(
+            AggregateInitializeReq::get_decoded_with_param(&version, bytes)?,
+        ),
+        MediaTypeKind::AggContReq => {
+            DapMessage::AggregateContinueReq(AggregateContinueReq::get_decoded(bytes)?)
+        }
+        MediaTypeKind::AggInitResp | MediaTypeKind::AggContResp => {
+            DapMessage::AggregateResp(AggregateResp::get_decoded(bytes)?)
+        }
+        MediaTypeKind::AggShareReq => DapMessage::AggregateShareReq(
+            AggregateShareReq::get_decoded_with_param(&version, bytes)?,
+        ),
+        MediaTypeKind::AggShareResp => {
+            DapMessage::AggregateShareResp(AggregateShareResp::get_decoded(bytes)?)
+        }
+        MediaTypeKind::CollectReq => {
+            DapMessage::CollectReq(CollectReq::get_decoded_with_param(&version, bytes)?)
+        }
+        MediaTypeKind::CollectResp => DapMessage::CollectResp(CollectResp::get_decoded(bytes)?),
+    };
+    Ok(msg)
+}
+
 /// Codepoint for KEM schemes compatible with HPKE.
 #[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -916,6 +1209,29 @@ pub struct HpkeConfig {
     pub public_key: Vec<u8>,
 }
 
+impl HpkeConfig {
+    /// The serialized KEM public key. This can be used to seal messages with an HPKE
+    /// implementation other than the one used by this crate.
+    pub fn public_key_bytes(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// The KEM algorithm.
+    pub fn kem_id(&self) -> HpkeKemId {
+        self.kem_id
+    }
+
+    /// The KDF algorithm.
+    pub fn kdf_id(&self) -> HpkeKdfId {
+        self.kdf_id
+    }
+
+    /// The AEAD algorithm.
+    pub fn aead_id(&self) -> HpkeAeadId {
+        self.aead_id
+    }
+}
+
 impl AsRef<HpkeConfig> for HpkeConfig {
     fn as_ref(&self) -> &Self {
         self
@@ -997,9 +1313,7 @@ pub(crate) fn encode_u16_bytes(bytes: &mut Vec<u8>, input: &[u8]) {
 
 pub(crate) fn decode_u16_bytes(bytes: &mut Cursor<&[u8]>) -> Result<Vec<u8>, CodecError> {
     let len = u16::decode(bytes)? as usize;
-    let mut out = vec![0; len];
-    bytes.read_exact(&mut out)?;
-    Ok(out)
+    read_bytes(bytes, len)
 }
 
 pub(crate) fn encode_u32_bytes(bytes: &mut Vec<u8>, input: &[u8]) {
@@ -1011,6 +1325,19 @@ pub(crate) fn encode_u32_bytes(bytes: &mut Vec<u8>, input: &[u8]) {
 
 pub(crate) fn decode_u32_bytes(bytes: &mut Cursor<&[u8]>) -> Result<Vec<u8>, CodecError> {
     let len = u32::decode(bytes)? as usize;
+    read_bytes(bytes, len)
+}
+
+// Read `len` bytes from the cursor. The length is checked against the number of remaining bytes
+// before allocating so that a malicious length prefix can't cause a large allocation.
+fn read_bytes(bytes: &mut Cursor<&[u8]>, len: usize) -> Result<Vec<u8>, CodecError> {
+    let remaining = bytes
+        .get_ref()
+        .len()
+        .saturating_sub(bytes.position() as usize);
+    if len > remaining {
+        return Err(CodecError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
     let mut out = vec![0; len];
     bytes.read_exact(&mut out)?;
     Ok(out)
@@ -5,12 +5,24 @@ use crate::messages::taskprov::{
     DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafTypeVar,
 };
 use crate::messages::{
-    AggregateContinueReq, AggregateInitializeReq, AggregateResp, AggregateShareReq, BatchSelector,
+    decode_request_payload, AggregateContinueReq, AggregateInitializeReq, AggregateResp,
+    AggregateShareReq, AggregateShareResp, BatchSelector, CollectReq, CollectResp, DapMessage,
     DapVersion, Extension, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Id,
-    PartialBatchSelector, Report, ReportId, ReportMetadata, ReportShare, Transition, TransitionVar,
+    Interval, PartialBatchSelector, Query, Report, ReportId, ReportMetadata, ReportShare,
+    Transition, TransitionFailure, TransitionVar,
 };
 use crate::taskprov::{compute_task_id, TaskprovVersion};
+use crate::{
+    constants::{
+        MEDIA_TYPE_AGG_CONT_REQ, MEDIA_TYPE_AGG_CONT_RESP, MEDIA_TYPE_AGG_INIT_REQ,
+        MEDIA_TYPE_AGG_INIT_RESP, MEDIA_TYPE_AGG_SHARE_REQ, MEDIA_TYPE_AGG_SHARE_RESP,
+        MEDIA_TYPE_COLLECT_REQ, MEDIA_TYPE_COLLECT_RESP, MEDIA_TYPE_HPKE_CONFIG, MEDIA_TYPE_REPORT,
+    },
+    DapAbort,
+};
+use assert_matches::assert_matches;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
+use std::collections::HashSet;
 
 #[test]
 fn read_report() {
@@ -72,7 +84,8 @@ fn read_report_with_unknown_extensions() {
         ],
     };
 
-    assert!(Report::get_decoded(&report.get_encoded()).is_err());
+    // Unrecognized extensions are decoded. It is up to the Aggregator to reject the report.
+    assert_eq!(Report::get_decoded(&report.get_encoded()).unwrap(), report);
 }
 
 #[test]
@@ -128,6 +141,47 @@ fn read_agg_init_req() {
     assert_eq!(got, want);
 }
 
+#[test]
+fn read_agg_init_req_streaming() {
+    let want = AggregateInitializeReq {
+        task_id: Id([23; 32]),
+        agg_job_id: Id([1; 32]),
+        agg_param: b"this is an aggregation parameter".to_vec(),
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_shares: (0..10_000_u32)
+            .map(|i| ReportShare {
+                metadata: ReportMetadata {
+                    id: ReportId([(i % 256) as u8; 16]),
+                    time: 1637361337 + u64::from(i),
+                    extensions: Vec::default(),
+                },
+                public_share: Vec::default(),
+                encrypted_input_share: HpkeCiphertext {
+                    config_id: 23,
+                    enc: b"encapsulated key".to_vec(),
+                    payload: i.to_be_bytes().repeat(1 + (i % 8) as usize),
+                },
+            })
+            .collect(),
+    };
+
+    for version in [DapVersion::Draft02, DapVersion::Draft03] {
+        let encoded = want.get_encoded_with_param(&version);
+
+        let mut streamed = Vec::new();
+        want.encode_to_writer(&mut streamed, &version).unwrap();
+        assert_eq!(streamed, encoded);
+
+        let got =
+            AggregateInitializeReq::decode_from_reader(&mut encoded.as_slice(), &version).unwrap();
+        assert_eq!(got, want);
+
+        // Truncated input is rejected.
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(AggregateInitializeReq::decode_from_reader(&mut &truncated[..], &version).is_err());
+    }
+}
+
 #[test]
 fn read_agg_cont_req() {
     let want = AggregateContinueReq {
@@ -198,6 +252,186 @@ fn read_agg_resp() {
     assert_eq!(got, want);
 }
 
+// Return an encoded example of each message, along with its media type.
+fn example_payloads(version: DapVersion) -> Vec<(&'static str, Vec<u8>)> {
+    let hpke_ciphertext = HpkeCiphertext {
+        config_id: 23,
+        enc: b"encapsulated key".to_vec(),
+        payload: b"ciphertext".to_vec(),
+    };
+    let batch_interval = Interval {
+        start: 1637361337,
+        duration: 3600,
+    };
+    let agg_resp = AggregateResp {
+        transitions: vec![Transition {
+            report_id: ReportId([22; 16]),
+            var: TransitionVar::Continued(b"VDAF message".to_vec()),
+        }],
+    };
+
+    vec![
+        (
+            MEDIA_TYPE_HPKE_CONFIG,
+            HpkeConfig {
+                id: 23,
+                kem_id: HpkeKemId::X25519HkdfSha256,
+                kdf_id: HpkeKdfId::HkdfSha256,
+                aead_id: HpkeAeadId::Aes128Gcm,
+                public_key: b"this is a public key".to_vec(),
+            }
+            .get_encoded(),
+        ),
+        (
+            MEDIA_TYPE_REPORT,
+            Report {
+                task_id: Id([1; 32]),
+                metadata: ReportMetadata {
+                    id: ReportId([23; 16]),
+                    time: 1637364244,
+                    extensions: vec![],
+                },
+                public_share: b"public share".to_vec(),
+                encrypted_input_shares: vec![hpke_ciphertext.clone(), hpke_ciphertext.clone()],
+            }
+            .get_encoded(),
+        ),
+        (
+            MEDIA_TYPE_AGG_INIT_REQ,
+            AggregateInitializeReq {
+                task_id: Id([1; 32]),
+                agg_job_id: Id([2; 32]),
+                agg_param: b"aggregation parameter".to_vec(),
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                report_shares: vec![ReportShare {
+                    metadata: ReportMetadata {
+                        id: ReportId([99; 16]),
+                        time: 1637361337,
+                        extensions: vec![],
+                    },
+                    public_share: b"public share".to_vec(),
+                    encrypted_input_share: hpke_ciphertext.clone(),
+                }],
+            }
+            .get_encoded_with_param(&version),
+        ),
+        (
+            MEDIA_TYPE_AGG_CONT_REQ,
+            AggregateContinueReq {
+                task_id: Id([1; 32]),
+                agg_job_id: Id([2; 32]),
+                transitions: agg_resp.transitions.clone(),
+            }
+            .get_encoded(),
+        ),
+        (MEDIA_TYPE_AGG_INIT_RESP, agg_resp.get_encoded()),
+        (MEDIA_TYPE_AGG_CONT_RESP, agg_resp.get_encoded()),
+        (
+            MEDIA_TYPE_AGG_SHARE_REQ,
+            AggregateShareReq {
+                task_id: Id([1; 32]),
+                batch_sel: BatchSelector::TimeInterval {
+                    batch_interval: batch_interval.clone(),
+                },
+                agg_param: b"aggregation parameter".to_vec(),
+                report_count: 100,
+                checksum: [0; 32],
+            }
+            .get_encoded_with_param(&version),
+        ),
+        (
+            MEDIA_TYPE_AGG_SHARE_RESP,
+            AggregateShareResp {
+                encrypted_agg_share: hpke_ciphertext.clone(),
+            }
+            .get_encoded(),
+        ),
+        (
+            MEDIA_TYPE_COLLECT_REQ,
+            CollectReq {
+                task_id: Id([1; 32]),
+                query: Query::TimeInterval { batch_interval },
+                agg_param: b"aggregation parameter".to_vec(),
+                collector_hpke_config_id: None,
+            }
+            .get_encoded_with_param(&version),
+        ),
+        (
+            MEDIA_TYPE_COLLECT_RESP,
+            CollectResp {
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                report_count: 100,
+                encrypted_agg_shares: vec![hpke_ciphertext.clone(), hpke_ciphertext],
+            }
+            .get_encoded(),
+        ),
+    ]
+}
+
+#[test]
+fn decode_request_payload_truncated_or_oversized() {
+    for version in [DapVersion::Draft02, DapVersion::Draft03] {
+        for (media_type, payload) in example_payloads(version) {
+            decode_request_payload(media_type, version, &payload).unwrap();
+
+            for len in 0..payload.len() {
+                assert_matches!(
+                    decode_request_payload(media_type, version, &payload[..len]),
+                    Err(DapAbort::UnrecognizedMessage),
+                    "{media_type} truncated to {len} bytes"
+                );
+            }
+
+            let mut oversized = payload;
+            oversized.push(0);
+            assert_matches!(
+                decode_request_payload(media_type, version, &oversized),
+                Err(DapAbort::UnrecognizedMessage),
+                "{media_type} with trailing bytes"
+            );
+        }
+    }
+}
+
+#[test]
+fn decode_request_payload_length_prefix_too_big() {
+    // An aggregate-share request whose aggregation parameter claims to be 4GiB long.
+    let mut payload = Id([1; 32]).get_encoded();
+    payload.extend(
+        BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: 1637361337,
+                duration: 3600,
+            },
+        }
+        .get_encoded(),
+    );
+    payload.extend(u32::MAX.to_be_bytes());
+    payload.extend([0; 40]);
+
+    assert_matches!(
+        decode_request_payload(MEDIA_TYPE_AGG_SHARE_REQ, DapVersion::Draft03, &payload),
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+#[test]
+fn decode_request_payload_unrecognized() {
+    let payload = AggregateResp::default().get_encoded();
+    assert_matches!(
+        decode_request_payload(MEDIA_TYPE_AGG_INIT_RESP, DapVersion::Draft03, &payload),
+        Ok(DapMessage::AggregateResp(agg_resp)) if agg_resp.transitions.is_empty()
+    );
+    assert_matches!(
+        decode_request_payload("application/octet-stream", DapVersion::Draft03, &payload),
+        Err(DapAbort::UnrecognizedMessage)
+    );
+    assert_matches!(
+        decode_request_payload(MEDIA_TYPE_AGG_INIT_RESP, DapVersion::Unknown, &payload),
+        Err(DapAbort::InvalidProtocolVersion)
+    );
+}
+
 #[test]
 fn read_hpke_config() {
     let data = [
@@ -257,6 +491,21 @@ fn read_vdaf_config() {
     );
 }
 
+#[test]
+fn read_vdaf_config_unknown_dp_mechanism() {
+    let data = [0x17, 0x00, 0x00, 0x00, 0x00];
+
+    let vdaf_config = VdafConfig::get_decoded(&data).unwrap();
+    assert_eq!(
+        vdaf_config,
+        VdafConfig {
+            dp_config: DpConfig::NotImplemented(0x17),
+            var: VdafTypeVar::Prio3Aes128Count,
+        }
+    );
+    assert_eq!(vdaf_config.get_encoded(), &data);
+}
+
 #[test]
 fn read_task_config_taskprov_draft02() {
     let data = [
@@ -308,3 +557,53 @@ fn read_task_config_taskprov_draft02() {
         &data
     );
 }
+
+#[test]
+fn batch_selector_intersects() {
+    let time_interval = |start, duration| BatchSelector::TimeInterval {
+        batch_interval: Interval { start, duration },
+    };
+
+    // Overlapping intervals intersect.
+    assert!(time_interval(3600, 7200).intersects(&time_interval(7200, 3600)));
+    assert!(time_interval(7200, 3600).intersects(&time_interval(3600, 7200)));
+    assert!(time_interval(0, 7200).intersects(&time_interval(0, 7200)));
+
+    // Adjacent but disjoint intervals do not.
+    assert!(!time_interval(0, 3600).intersects(&time_interval(3600, 3600)));
+    assert!(!time_interval(3600, 3600).intersects(&time_interval(0, 3600)));
+
+    // Fixed-size batches intersect if they have the same batch ID.
+    let batch_id = BatchSelector::FixedSizeByBatchId {
+        batch_id: Id([1; 32]),
+    };
+    assert!(batch_id.intersects(&batch_id));
+    assert!(!batch_id.intersects(&BatchSelector::FixedSizeByBatchId {
+        batch_id: Id([2; 32])
+    }));
+
+    // Selectors for different query types never intersect.
+    assert!(!time_interval(0, 3600).intersects(&batch_id));
+    assert!(!batch_id.intersects(&time_interval(0, 3600)));
+}
+
+#[test]
+fn transition_failure_descriptions_and_codes() {
+    let mut codes = HashSet::new();
+    for failure in TransitionFailure::ALL {
+        assert!(!failure.description().is_empty(), "{failure:?}");
+        assert!(codes.insert(failure.code()), "{failure:?}");
+        assert_eq!(
+            TransitionFailure::get_decoded(&[failure.code()]).unwrap(),
+            failure
+        );
+    }
+    assert_eq!(TransitionFailure::ReportReplayed.code(), 1);
+
+    // Draft02 and Draft03 define no failure with code 8 (unrecognized_message in later drafts).
+    assert!(TransitionFailure::get_decoded(&[8]).is_err());
+    assert_eq!(
+        TransitionFailure::ReportReplayed.description(),
+        "report replayed"
+    );
+}
@@ -117,12 +117,14 @@ impl From<VdafTypeVar> for VdafType {
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
 pub enum DpConfig {
     None,
+    NotImplemented(u8),
 }
 
 impl Encode for DpConfig {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match self {
             Self::None => DP_MECHANISM_NONE.encode(bytes),
+            Self::NotImplemented(x) => x.encode(bytes),
         }
     }
 }
@@ -131,7 +133,7 @@ impl Decode for DpConfig {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         match u8::decode(bytes)? {
             DP_MECHANISM_NONE => Ok(Self::None),
-            _ => Err(CodecError::UnexpectedValue),
+            x => Ok(Self::NotImplemented(x)),
         }
     }
 }
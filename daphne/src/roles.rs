@@ -5,28 +5,32 @@
 
 use crate::{
     constants::{
-        MEDIA_TYPE_AGG_CONT_REQ, MEDIA_TYPE_AGG_CONT_RESP, MEDIA_TYPE_AGG_INIT_REQ,
-        MEDIA_TYPE_AGG_INIT_RESP, MEDIA_TYPE_AGG_SHARE_REQ, MEDIA_TYPE_AGG_SHARE_RESP,
-        MEDIA_TYPE_HPKE_CONFIG,
+        media_type_for, MediaTypeKind, MEDIA_TYPE_AGGREGATOR_INFO, MEDIA_TYPE_FIXED_SIZE_BATCHES,
     },
     hpke::HpkeDecrypter,
     messages::{
-        constant_time_eq, AggregateContinueReq, AggregateInitializeReq, AggregateResp,
-        AggregateShareReq, AggregateShareResp, BatchSelector, CollectReq, CollectResp, Id,
-        PartialBatchSelector, Query, Report, ReportId, ReportMetadata, Time, TransitionFailure,
-        TransitionVar,
+        constant_time_eq, decode_request_payload, AggregateResp, AggregateShareReq,
+        AggregateShareResp, BatchSelector, CollectReq, CollectResp, DapMessage, Duration,
+        Extension, Id, Interval, PartialBatchSelector, Query, Report, ReportId, ReportMetadata,
+        Time, TransitionFailure, TransitionVar,
     },
-    DapAbort, DapAggregateShare, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapHelperTransition, DapLeaderProcessTelemetry, DapLeaderTransition, DapOutputShare,
-    DapQueryConfig, DapRequest, DapResponse, DapTaskConfig, DapVersion,
+    DapAbort, DapAggJobReport, DapAggregateShare, DapAggregatorInfo, DapCollectJob, DapError,
+    DapGlobalConfig, DapHelperState, DapHelperTransition, DapLeaderProcessTelemetry,
+    DapLeaderTransition, DapOutputShare, DapQueryConfig, DapRequest, DapResponse,
+    DapStoredAggInitResp, DapTaskConfig, DapVersion, HpkeConfigMissingTaskIdPolicy, QueryKind,
+    ReportBuffer, ReportStatus,
 };
 use async_trait::async_trait;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use url::Url;
 
+/// Number of seconds after which a sender whose request was rejected with
+/// [`DapAbort::TooManyRequests`] is asked to retry.
+const TOO_MANY_REQUESTS_RETRY_AFTER: Duration = 60;
+
 /// A party in the DAP protocol who is authorized to send requests to another party.
 #[async_trait(?Send)]
 pub trait DapAuthorizedSender<S> {
@@ -39,6 +43,13 @@ pub trait DapAuthorizedSender<S> {
     ) -> Result<S, DapError>;
 }
 
+/// An HTTP client used by the Leader to send requests to the Helper.
+#[async_trait(?Send)]
+pub trait DapHttpClient<S> {
+    /// Send an HTTP POST request.
+    async fn post(&self, req: DapRequest<S>) -> Result<DapResponse, DapError>;
+}
+
 /// DAP Aggregator functionality.
 #[async_trait(?Send)]
 pub trait DapAggregator<'srv, 'req, S>: HpkeDecrypter<'srv> + Sized
@@ -48,7 +59,11 @@ where
     /// A refernce to a task configuration stored by the Aggregator.
     type WrappedDapTaskConfig: AsRef<DapTaskConfig>;
 
-    /// Decide whether the given DAP request is authorized.
+    /// Decide whether the given DAP request is authorized. This is the only place the roles
+    /// consult to authorize inbound requests, so an implementation may defer the decision to an
+    /// external service. Implementations using bearer tokens can delegate to
+    /// [`BearerTokenProvider`](crate::auth::BearerTokenProvider), which makes the decision per task
+    /// and media type.
     async fn authorized(&self, req: &DapRequest<S>) -> Result<bool, DapError>;
 
     /// Look up the DAP global configuration.
@@ -98,6 +113,22 @@ where
         batch_sel: &BatchSelector,
     ) -> Result<bool, DapError>;
 
+    /// Return the number of times the batch determined by the batch selector may still be
+    /// collected. Each batch may be collected at most once (i.e., `max_batch_query_count` is
+    /// always 1), so the result is 0 if the batch overlaps with a previously collected batch and 1
+    /// otherwise.
+    async fn remaining_batch_queries(
+        &self,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+    ) -> Result<u64, DapError> {
+        if self.is_batch_overlapping(task_id, batch_sel).await? {
+            Ok(0)
+        } else {
+            Ok(1)
+        }
+    }
+
     /// Check whether the given batch ID has been observed before. This is called by the Leader
     /// (resp. Helper) in response to a CollectReq (resp. AggregateShareReq) for fixed-size tasks.
     async fn batch_exists(&self, task_id: &Id, batch_id: &Id) -> Result<bool, DapError>;
@@ -117,6 +148,53 @@ where
         batch_sel: &BatchSelector,
     ) -> Result<DapAggregateShare, DapError>;
 
+    /// Return the number of reports aggregated into each batch window (i.e., time window of length
+    /// `time_precision`) covered by the given time-interval batch selector, in ascending order of
+    /// time. Windows with no reports are omitted. This is intended to help operators analyze the
+    /// composition of a batch, e.g., to detect skew or late-arriving reports.
+    async fn batch_time_distribution(
+        &'srv self,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+    ) -> Result<Vec<(Time, u64)>, DapError> {
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Owned(task_id.clone()))
+            .await?
+            .ok_or_else(|| DapError::fatal("task not found"))?;
+        let task_config = wrapped_task_config.as_ref();
+        if !task_config.query.is_valid_batch_sel(batch_sel) {
+            return Err(DapError::fatal("batch selector not compatible with task"));
+        }
+
+        let batch_interval = match batch_sel {
+            BatchSelector::TimeInterval { batch_interval } => batch_interval,
+            _ => {
+                return Err(DapError::fatal(
+                    "batch time distribution is only defined for time-interval queries",
+                ))
+            }
+        };
+
+        let mut dist = Vec::new();
+        for batch_window in task_config.batch_windows(batch_interval) {
+            let agg_share = self
+                .get_agg_share(
+                    task_id,
+                    &BatchSelector::TimeInterval {
+                        batch_interval: Interval {
+                            start: batch_window,
+                            duration: task_config.time_precision,
+                        },
+                    },
+                )
+                .await?;
+            if agg_share.report_count > 0 {
+                dist.push((batch_window, agg_share.report_count));
+            }
+        }
+        Ok(dist)
+    }
+
     /// Ensure a set of reorts can be aggregated. Return a transition failure for each report
     /// that must be rejected early, due to the repot being replayed, the bucket that contains the
     /// report being collected, etc.
@@ -127,10 +205,116 @@ where
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> Result<HashMap<ReportId, TransitionFailure>, DapError>;
 
-    /// Mark a batch as collected.
+    /// Mark a batch as collected. For a "time-interval" task, this marks each bucket covered by the
+    /// batch interval. Whether the buckets are marked atomically depends on the implementation, so
+    /// if an error is returned, then some of the buckets may have been marked. Marking a bucket is
+    /// idempotent, so the call may be retried.
     async fn mark_collected(&self, task_id: &Id, batch_sel: &BatchSelector)
         -> Result<(), DapError>;
 
+    /// Record the reports that were rejected during the aggregation flow, along with the timestamp
+    /// of each report and the reason it was rejected. This is used to answer
+    /// [`report_status`](Self::report_status); by default, the rejections are not recorded.
+    async fn put_rejected_reports(
+        &self,
+        _task_id: &Id,
+        _rejected: &[(ReportId, Time, TransitionFailure)],
+    ) -> Result<(), DapError> {
+        Ok(())
+    }
+
+    /// Return the status of the report with the given ID. Reports that were processed and not
+    /// recorded as rejected (see [`put_rejected_reports`](Self::put_rejected_reports)) are
+    /// aggregated. By default, the status is not tracked and [`ReportStatus::Unknown`] is
+    /// returned.
+    async fn report_status(
+        &self,
+        _task_id: &Id,
+        _report_id: &ReportId,
+    ) -> Result<ReportStatus, DapError> {
+        Ok(ReportStatus::Unknown)
+    }
+
+    /// Check that this Aggregator can decrypt and decode its input share of the report, i.e., the
+    /// Leader's share if `is_leader` is set and the Helper's share otherwise. The report is
+    /// neither stored nor aggregated, so the same report may still be uploaded afterwards. This is
+    /// intended to let Clients confirm that they seal their reports correctly.
+    ///
+    /// If the report would be rejected by the aggregation flow, then the [`TransitionFailure`] it
+    /// would yield is returned as [`DapError::Transition`]. This covers the task's checks on the
+    /// report, i.e., its expiration, the extension allowlist and
+    /// [`require_latest_hpke_config`](crate::DapTaskConfig::require_latest_hpke_config), as well
+    /// as the consumption of the input share. If the task is not recognized or the report lacks
+    /// this Aggregator's input share, then the corresponding [`DapAbort`] is returned. Other
+    /// errors, e.g., if the task config can't be fetched, are returned as is.
+    ///
+    /// The role is passed in explicitly, since a [`DapAggregator`] is not aware of whether it is
+    /// the Leader or the Helper.
+    async fn probe_report(&'srv self, report: &Report, is_leader: bool) -> Result<(), DapError> {
+        let task_config = self
+            .get_task_config_for(Cow::Owned(report.task_id.clone()))
+            .await?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
+        let task_config = task_config.as_ref();
+
+        let encrypted_input_share = report
+            .encrypted_input_shares
+            .get(if is_leader { 0 } else { 1 })
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedMessage))?;
+
+        if report.metadata.time >= task_config.expiration {
+            return Err(DapError::Transition(TransitionFailure::TaskExpired));
+        }
+
+        // TODO spec: Draft02 and Draft03 have no failure for a report carrying an unrecognized or
+        // disallowed extension, so the report is dropped. See the Helper's aggregation flow.
+        if !task_config.allows_report_extensions(&report.metadata) {
+            return Err(DapError::Transition(TransitionFailure::ReportDropped));
+        }
+
+        if task_config.require_latest_hpke_config {
+            let latest_hpke_config_id = self
+                .get_hpke_config_for(Some(&report.task_id))
+                .await?
+                .as_ref()
+                .id;
+            if encrypted_input_share.config_id != latest_hpke_config_id {
+                return Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId));
+            }
+        }
+
+        task_config
+            .vdaf
+            .consume_report_share(
+                self,
+                is_leader,
+                &task_config.vdaf_verify_key,
+                &report.task_id,
+                &report.metadata,
+                &report.public_share,
+                encrypted_input_share,
+                task_config.version,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Handle HTTP GET to `/aggregator_info`. The response is a JSON document describing the
+    /// Aggregator's capabilities (see [`DapAggregatorInfo`]).
+    async fn http_get_aggregator_info(&self) -> Result<DapResponse, DapAbort> {
+        let global_config = self.get_global_config();
+        let info = DapAggregatorInfo {
+            supported_versions: DapVersion::SUPPORTED.to_vec(),
+            supported_hpke_kems: global_config.supported_hpke_kems.clone(),
+            allow_taskprov: global_config.allow_taskprov,
+        };
+
+        Ok(DapResponse {
+            media_type: Some(MEDIA_TYPE_AGGREGATOR_INFO),
+            payload: serde_json::to_vec(&info).map_err(DapError::from)?,
+        })
+    }
+
     /// Handle HTTP GET to `/hpke_config?task_id=<task_id>`.
     async fn http_get_hpke_config(
         &'srv self,
@@ -158,6 +342,14 @@ where
             id = Some(Id::get_decoded(&bytes)?);
         }
 
+        // Aggregators may abort if the HPKE config request does not specify a task ID.
+        if id.is_none()
+            && self.get_global_config().hpke_config_missing_task_id_policy
+                == HpkeConfigMissingTaskIdPolicy::Abort
+        {
+            return Err(DapAbort::MissingTaskId);
+        }
+
         let hpke_config = self.get_hpke_config_for(id.as_ref()).await?;
 
         if let Some(task_id) = id {
@@ -173,7 +365,7 @@ where
         }
 
         Ok(DapResponse {
-            media_type: Some(MEDIA_TYPE_HPKE_CONFIG),
+            media_type: Some(media_type_for(MediaTypeKind::HpkeConfig, req.version)),
             payload: hpke_config.as_ref().get_encoded(),
         })
     }
@@ -189,26 +381,32 @@ macro_rules! leader_post {
         $path:expr,
         $media_type:expr,
         $req_data:expr
+        $(, $field:ident: $value:expr)*
     ) => {{
         let url = $task_config
             .helper_url
             .join($path)
             .map_err(|e| DapError::Fatal(e.to_string()))?;
-        let req = DapRequest {
+        #[allow(unused_mut)]
+        let mut req = DapRequest {
             version: $task_config.version.clone(),
             media_type: Some($media_type),
             task_id: Some($task_id.clone()),
             payload: $req_data,
             url,
             sender_auth: Some($role.authorize(&$task_id, $media_type, &$req_data).await?),
+            idempotency_key: None,
+            collector_hpke_config_id: None,
         };
-        $role.send_http_post(req).await?
+        $(req.$field = $value;)*
+        $role.post(req).await?
     }};
 }
 
 /// DAP Leader functionality.
 #[async_trait(?Send)]
-pub trait DapLeader<'srv, 'req, S>: DapAuthorizedSender<S> + DapAggregator<'srv, 'req, S>
+pub trait DapLeader<'srv, 'req, S>:
+    DapAuthorizedSender<S> + DapHttpClient<S> + DapAggregator<'srv, 'req, S>
 where
     'srv: 'req,
 {
@@ -220,15 +418,25 @@ where
 
     /// Fetch a sequence of reports to aggregate, grouped by task ID, then by partial batch
     /// selector. The reports returned are removed from persistent storage.
+    ///
+    /// For fixed-size tasks, each report must have been assigned to a batch by the time it is
+    /// returned. A batch is assigned at most `max_batch_size` reports, after which subsequent
+    /// reports are assigned to a new batch. The assignment may be made when the report is stored
+    /// (as [`MockAggregator`](crate::testing::MockAggregator) does) or when it is fetched for
+    /// aggregation (as the worker does).
     async fn get_reports(
         &self,
         selector: &Self::ReportSelector,
     ) -> Result<HashMap<Id, HashMap<PartialBatchSelector, Vec<Report>>>, DapError>;
 
-    /// Create a collect job.
+    /// Create a collect job. `created_at` is the time at which the collect request was received.
     //
     // TODO spec: Figure out if the hostname for the collect URI needs to match the Leader.
-    async fn init_collect_job(&self, collect_req: &CollectReq) -> Result<Url, DapError>;
+    async fn init_collect_job(
+        &self,
+        collect_req: &CollectReq,
+        created_at: Time,
+    ) -> Result<Url, DapError>;
 
     /// Check the status of a collect job.
     async fn poll_collect_job(
@@ -242,15 +450,119 @@ where
     async fn get_pending_collect_jobs(&self) -> Result<Vec<(Id, CollectReq)>, DapError>;
 
     /// Complete a collect job by assigning it the completed [`CollectResp`](crate::messages::CollectResp).
+    /// `completed_at` is the time at which the collect job was completed.
     async fn finish_collect_job(
         &self,
         task_id: &Id,
         collect_id: &Id,
         collect_resp: &CollectResp,
+        completed_at: Time,
     ) -> Result<(), DapError>;
 
-    /// Send an HTTP POST request.
-    async fn send_http_post(&self, req: DapRequest<S>) -> Result<DapResponse, DapError>;
+    /// Mark a pending collect job as failed. A failed job is no longer processed, and polling it
+    /// yields [`DapCollectJob::Failed`].
+    async fn fail_collect_job(&self, task_id: &Id, collect_id: &Id) -> Result<(), DapError>;
+
+    /// Mark each collect job that is still pending and was created more than
+    /// [`collect_job_ttl`](crate::DapGlobalConfig::collect_job_ttl) seconds before `now` as
+    /// expired. An expired job is no longer processed, and polling it yields
+    /// [`DapCollectJob::Expired`]. This is called by [`Self::process`]. By default, collect jobs
+    /// do not expire.
+    async fn expire_collect_jobs(&self, _now: Time) -> Result<(), DapError> {
+        Ok(())
+    }
+
+    /// Fetch the [`CollectResp`](crate::messages::CollectResp) stored for a completed collect job.
+    /// Returns `None` if the collect job is unknown, still pending, or expired.
+    async fn get_collect_resp(
+        &self,
+        task_id: &Id,
+        collect_id: &Id,
+    ) -> Result<Option<CollectResp>, DapError> {
+        match self.poll_collect_job(task_id, collect_id).await? {
+            DapCollectJob::Done { collect_resp, .. } => Ok(Some(collect_resp)),
+            DapCollectJob::Pending { .. }
+            | DapCollectJob::Expired { .. }
+            | DapCollectJob::Failed { .. }
+            | DapCollectJob::Unknown => Ok(None),
+        }
+    }
+
+    /// Return the IDs of the batches of a fixed-size task that are ready to be collected, oldest
+    /// first. A batch is ready once it is full and has not yet been collected. A batch is full once
+    /// `max_batch_size` reports have been aggregated in it: by then, the Leader has stopped
+    /// assigning reports to it (see [`put_report`](Self::put_report)).
+    async fn ready_batches(&self, task_id: &Id) -> Result<Vec<Id>, DapError>;
+
+    /// Check whether any report that has been stored but not yet aggregated falls into the batch
+    /// indicated by `batch_sel`. Collecting such a batch would under-count, so the collect request
+    /// is rejected.
+    async fn has_pending_reports(
+        &self,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError>;
+
+    /// Return the number of reports for the given task that have been stored but not yet
+    /// aggregated. Together with the reports held in the [`report_buffer`](Self::report_buffer),
+    /// this is used to enforce the task's
+    /// [`max_pending_reports`](crate::DapTaskConfig::max_pending_reports) quota.
+    async fn pending_report_count(&self, task_id: &Id) -> Result<u64, DapError>;
+
+    /// Return the buffer in which uploaded reports are held before they are stored, if the Leader
+    /// buffers reports. By default, reports are stored as soon as they are uploaded.
+    fn report_buffer(&self) -> Option<&ReportBuffer> {
+        None
+    }
+
+    /// Store the reports held in the report buffer if the buffer is ready to be flushed at time
+    /// `now`. This is called on each upload, but should also be called periodically so that
+    /// reports are not held indefinitely when uploads stop.
+    ///
+    /// Reports that are rejected by the report store are recorded as rejected (see
+    /// [`put_rejected_reports`](DapAggregator::put_rejected_reports)). If a report can't be
+    /// stored due to an error, then it and the reports after it are returned to the buffer so
+    /// that they are stored by a later flush. Nothing is done if another flush is in progress.
+    async fn flush_report_buffer(&self, now: Time) -> Result<(), DapError> {
+        let report_buffer = match self.report_buffer() {
+            Some(report_buffer) => report_buffer,
+            None => return Ok(()),
+        };
+
+        let mut reports = match report_buffer.flush_if_ready(now)? {
+            Some(reports) => reports.into_iter(),
+            None => return Ok(()),
+        };
+
+        let mut res = Ok(());
+        let mut unstored = Vec::new();
+        let mut rejected: HashMap<Id, Vec<(ReportId, Time, TransitionFailure)>> = HashMap::new();
+        while let Some(report) = reports.next() {
+            match self.put_report(&report).await {
+                Ok(()) => (),
+                // The report was replayed or pertains to a batch that was collected while it was
+                // held in the buffer. The Client can no longer be notified, so drop the report.
+                Err(DapError::Transition(failure_reason)) => {
+                    rejected.entry(report.task_id.clone()).or_default().push((
+                        report.metadata.id.clone(),
+                        report.metadata.time,
+                        failure_reason,
+                    ))
+                }
+                Err(e) => {
+                    unstored = std::iter::once(report).chain(reports).collect();
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+        report_buffer.finish_flush(unstored, now)?;
+
+        for (task_id, rejected) in rejected.iter() {
+            self.put_rejected_reports(task_id, rejected).await?;
+        }
+        res
+    }
 
     /// Handle HTTP POST to `/upload`. The input is the encoded report sent in the body of the HTTP
     /// request.
@@ -261,6 +573,7 @@ where
         }
 
         let report = Report::get_decoded(req.payload.as_ref())?;
+        check_report_extensions(self.get_global_config(), &report.metadata)?;
         let task_config = self
             .get_task_config_considering_taskprov(
                 req.version,
@@ -280,6 +593,24 @@ where
             return Err(DapAbort::UnrecognizedMessage);
         }
 
+        // Check that the report only carries extensions permitted for the task.
+        //
+        // TODO spec: Decide if this behavior should be specified.
+        if !task_config
+            .as_ref()
+            .allows_report_extensions(&report.metadata)
+        {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+
+        // Each Aggregator must get its own input share. Note that the config IDs alone can't be
+        // compared, since the Leader and Helper choose their HPKE config IDs independently.
+        //
+        // TODO spec: Decide if this behavior should be specified.
+        if report.encrypted_input_shares[0] == report.encrypted_input_shares[1] {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+
         // Check that the indicated HpkeConfig is present.
         //
         // TODO spec: It's not clear if this behavior is MUST, SHOULD, or MAY.
@@ -290,15 +621,76 @@ where
             return Err(DapAbort::UnrecognizedHpkeConfig);
         }
 
+        // Check that the task has started.
+        if report.metadata.time < task_config.as_ref().start {
+            return Err(DapAbort::TaskNotStarted);
+        }
+
         // Check that the task has not expired.
         if report.metadata.time >= task_config.as_ref().expiration {
             return Err(DapAbort::ReportTooLate);
         }
 
+        // Check that the report's timestamp is aligned to the task's time precision, if required.
+        //
+        // TODO spec: Decide if this behavior should be specified.
+        if task_config.as_ref().require_aligned_report_time
+            && matches!(task_config.as_ref().query, DapQueryConfig::TimeInterval)
+            && report.metadata.time % task_config.as_ref().time_precision != 0
+        {
+            return Err(DapAbort::BadRequest("report time misaligned".to_string()));
+        }
+
+        // Check that the report falls into the report storage window. Reports outside of the
+        // window can't be checked for replay.
+        if !task_config
+            .as_ref()
+            .global_config(self.get_global_config())
+            .is_report_in_storage_window(self.get_current_time(), report.metadata.time)
+        {
+            return Err(DapAbort::ReportTooLate);
+        }
+
+        // Check that the task's quota of pending reports has not been reached. Reports that are
+        // buffered but not yet stored count towards the quota.
+        if let Some(max_pending_reports) = task_config.as_ref().max_pending_reports {
+            let mut pending_report_count = self.pending_report_count(&report.task_id).await?;
+            if let Some(report_buffer) = self.report_buffer() {
+                pending_report_count += report_buffer.count(&report.task_id)?;
+            }
+            if pending_report_count >= max_pending_reports {
+                return Err(DapAbort::TooManyRequests {
+                    retry_after: TOO_MANY_REQUESTS_RETRY_AFTER,
+                });
+            }
+        }
+
         // Store the report for future processing. At this point, the report may be rejected if
         // the Leader detects that the report was replayed or pertains to a batch that has already
         // been collected.
-        Ok(self.put_report(&report).await?)
+        match self.report_buffer() {
+            Some(report_buffer) => {
+                // The buffer only detects replays of reports it holds, so check the report store
+                // for reports that were already flushed.
+                if self
+                    .report_status(&report.task_id, &report.metadata.id)
+                    .await?
+                    != ReportStatus::Unknown
+                {
+                    return Err(DapAbort::ReplayedReport);
+                }
+
+                let now = self.get_current_time();
+                report_buffer.push(report, now)?;
+
+                // The report is accepted once it is buffered. If it can't be stored yet, then it
+                // is kept in the buffer and stored by a later flush, so the error is not returned
+                // to the Client, who would retry the upload.
+                let _ = self.flush_report_buffer(now).await;
+                Ok(())
+            }
+            None => Ok(self.put_report(&report).await?),
+        }
     }
 
     /// Handle HTTP POST to `/collect`. The input is a [`CollectReq`](crate::messages::CollectReq).
@@ -316,19 +708,31 @@ where
             return Err(DapAbort::UnauthorizedRequest);
         }
 
-        let mut collect_req =
-            CollectReq::get_decoded_with_param(&req.version, req.payload.as_ref())?;
         let wrapped_task_config = self
             .get_task_config_for(Cow::Borrowed(req.task_id()?))
             .await?
             .ok_or(DapAbort::UnrecognizedTask)?;
         let task_config = wrapped_task_config.as_ref();
 
-        // Check whether the DAP version in the request matches the task config.
+        // Check whether the DAP version in the request matches the task config. This is done
+        // before decoding the message, since the encoding of the query depends on the version: a
+        // message encoded for the task's version may not decode under the request's version.
         if task_config.version != req.version {
             return Err(DapAbort::InvalidProtocolVersion);
         }
 
+        let mut collect_req =
+            CollectReq::get_decoded_with_param(&req.version, req.payload.as_ref())?;
+
+        // Check that the task ID in the message matches the task ID of the request.
+        if collect_req.task_id != *req.task_id()? {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+
+        // The Collector may indicate which of the task's Collector HPKE configs the aggregate
+        // shares are to be encrypted under.
+        collect_req.collector_hpke_config_id = collector_hpke_config_id(&req.url)?;
+
         if collect_req.query == Query::FixedSizeCurrentBatch {
             // This is where we assign the current batch, and convert the
             // Query::FixedSizeCurrentBatch into a Query::FixedSizeByBatchId.
@@ -344,20 +748,126 @@ where
             };
         }
 
-        // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
-        // collected batches.
-        let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-        check_batch(
-            self,
-            task_config,
-            &collect_req.task_id,
-            &batch_selector,
-            &collect_req.agg_param,
-            now,
-        )
-        .await?;
+        let pending_collect_reqs = pending_collect_reqs_for(self, &collect_req.task_id).await?;
+        check_collect_req(self, task_config, &collect_req, &pending_collect_reqs, now).await?;
+
+        // Limit the number of collect jobs that may be pending for the task at once. A slot is
+        // freed once one of the pending jobs is completed.
+        if pending_collect_reqs.len() >= self.get_global_config().max_pending_collect_jobs {
+            return Err(DapAbort::TooManyRequests {
+                retry_after: TOO_MANY_REQUESTS_RETRY_AFTER,
+            });
+        }
+
+        Ok(self.init_collect_job(&collect_req, now).await?)
+    }
+
+    /// Initialize a collect job for each of the given batches of a fixed-size task, e.g., in order
+    /// to collect the batches listed by [`ready_batches`](Self::ready_batches) together. Each
+    /// collect job is for a single batch and is run like any other, with its own
+    /// AggregateShareReq, as DAP has no way of collecting a set of batches at once. The Collector
+    /// combines the aggregate results with
+    /// [`DapAggregateResult::merge`](crate::DapAggregateResult::merge).
+    ///
+    /// Each batch is checked in the same way as for a collect request before any collect job is
+    /// initialized, so a batch that can't be collected does not leave collect jobs behind for the
+    /// others. The return value is the collect URI for each batch, in the order given.
+    async fn init_collect_jobs_for_batches(
+        &'srv self,
+        task_id: &Id,
+        batch_ids: &[Id],
+        agg_param: &[u8],
+        collector_hpke_config_id: Option<u8>,
+    ) -> Result<Vec<Url>, DapAbort> {
+        let now = self.get_current_time();
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Owned(task_id.clone()))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+        let task_config = wrapped_task_config.as_ref();
+
+        // Each batch may only be listed once.
+        let mut seen = HashSet::with_capacity(batch_ids.len());
+        if batch_ids.is_empty() || !batch_ids.iter().all(|batch_id| seen.insert(batch_id)) {
+            return Err(DapAbort::BatchInvalid);
+        }
+
+        let collect_reqs: Vec<CollectReq> = batch_ids
+            .iter()
+            .map(|batch_id| CollectReq {
+                task_id: task_id.clone(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: batch_id.clone(),
+                },
+                agg_param: agg_param.to_vec(),
+                collector_hpke_config_id,
+            })
+            .collect();
+
+        let pending_collect_reqs = pending_collect_reqs_for(self, task_id).await?;
+        for collect_req in collect_reqs.iter() {
+            check_collect_req(self, task_config, collect_req, &pending_collect_reqs, now).await?;
+        }
+
+        // Each batch takes up a slot for a pending collect job.
+        if pending_collect_reqs.len() + collect_reqs.len()
+            > self.get_global_config().max_pending_collect_jobs
+        {
+            return Err(DapAbort::TooManyRequests {
+                retry_after: TOO_MANY_REQUESTS_RETRY_AFTER,
+            });
+        }
+
+        let mut collect_uris = Vec::with_capacity(collect_reqs.len());
+        for collect_req in collect_reqs.iter() {
+            collect_uris.push(self.init_collect_job(collect_req, now).await?);
+        }
+        Ok(collect_uris)
+    }
+
+    /// Handle HTTP GET to `/fixed_size_batches`. The response is the JSON-encoded list of IDs of
+    /// the batches of the requested fixed-size task that are ready to be collected (see
+    /// [`ready_batches`](Self::ready_batches)). The request is authorized by the Collector.
+    //
+    // TODO spec: This endpoint is not defined by the DAP spec.
+    async fn http_get_fixed_size_batches(
+        &'srv self,
+        req: &'req DapRequest<S>,
+    ) -> Result<DapResponse, DapAbort> {
+        // Check whether the DAP version indicated by the sender is supported.
+        if req.version == DapVersion::Unknown {
+            return Err(DapAbort::InvalidProtocolVersion);
+        }
+
+        if !self.authorized(req).await? {
+            return Err(DapAbort::UnauthorizedRequest);
+        }
+
+        let task_id = req.task_id()?;
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+        let task_config = wrapped_task_config.as_ref();
+
+        // Check whether the DAP version in the request matches the task config.
+        if task_config.version != req.version {
+            return Err(DapAbort::InvalidProtocolVersion);
+        }
 
-        Ok(self.init_collect_job(&collect_req).await?)
+        let expected = QueryKind::from(&task_config.query);
+        if expected != QueryKind::FixedSize {
+            return Err(DapAbort::QueryMismatch {
+                expected,
+                actual: QueryKind::FixedSize,
+            });
+        }
+
+        let batch_ids = self.ready_batches(task_id).await?;
+        Ok(DapResponse {
+            media_type: Some(MEDIA_TYPE_FIXED_SIZE_BATCHES),
+            payload: serde_json::to_vec(&batch_ids).map_err(DapError::from)?,
+        })
     }
 
     /// Run the aggregation sub-protocol for the given set of reports. Return the number of reports
@@ -368,7 +878,7 @@ where
     // the capacity of this message in the spec. In the meantime, we should at a minimum log this
     // when it happens.
     async fn run_agg_job(
-        &self,
+        &'srv self,
         task_id: &Id,
         task_config: &DapTaskConfig,
         part_batch_sel: &PartialBatchSelector,
@@ -383,22 +893,46 @@ where
         // TODO Add a test similar to http_post_aggregate_init_expired_task() in roles_test.rs that
         // verifies that the Leader properly checks for expiration. This will require extending the
         // test framework to run run_agg_job() directly.
-        let early_rejects = self
+        let mut early_rejects = self
             .check_early_reject(
                 task_id,
                 part_batch_sel,
                 reports.iter().map(|report| &report.metadata),
             )
             .await?;
+
+        // If the task requires it, reject reports that are not sealed to the Leader's latest HPKE
+        // config, even if an older config could still decrypt them.
+        if task_config.require_latest_hpke_config {
+            let latest_hpke_config_id = self.get_hpke_config_for(Some(task_id)).await?.as_ref().id;
+            for report in reports.iter() {
+                let config_id = report
+                    .encrypted_input_shares
+                    .first()
+                    .map(|leader_share| leader_share.config_id);
+                if config_id != Some(latest_hpke_config_id) {
+                    early_rejects
+                        .entry(report.metadata.id.clone())
+                        .or_insert(TransitionFailure::HpkeUnknownConfigId);
+                }
+            }
+        }
+
+        let report_times: HashMap<ReportId, Time> = reports
+            .iter()
+            .map(|report| (report.metadata.id.clone(), report.metadata.time))
+            .collect();
         let reports = reports
             .into_iter()
             .filter(|report| {
-                // Filter the reports, removing any with a timestamps beyond the expiry or that was
-                // early rejected.
-                report.metadata.time < task_config.expiration
+                // Filter the reports, removing any with a timestamps before the start or beyond
+                // the expiry or that was early rejected.
+                report.metadata.time >= task_config.start
+                    && report.metadata.time < task_config.expiration
                     && early_rejects.get(&report.metadata.id).is_none()
             })
             .collect();
+        let mut rejected = with_report_time(early_rejects, &report_times);
 
         // Prepare AggregateInitializeReq.
         let agg_job_id = Id(rng.gen());
@@ -416,32 +950,52 @@ where
             .await?;
         let (state, agg_init_req) = match transition {
             DapLeaderTransition::Continue(state, agg_init_req) => (state, agg_init_req),
-            DapLeaderTransition::Skip => return Ok(0),
+            DapLeaderTransition::Skip(leader_rejected) => {
+                rejected.extend(with_report_time(leader_rejected, &report_times));
+                self.put_rejected_reports(task_id, &rejected).await?;
+                return Ok(0);
+            }
             DapLeaderTransition::Uncommitted(..) => {
                 return Err(DapError::fatal("unexpected state transition (uncommitted)").into())
             }
         };
 
-        // Send AggregateInitializeReq and receive AggregateResp.
+        // Send AggregateInitializeReq and receive AggregateResp. For draft03, the request carries
+        // an idempotency key so that the Helper recognizes it if it is delivered more than once.
+        let idempotency_key = match task_config.version {
+            DapVersion::Draft03 => Some(Id(rng.gen())),
+            _ => None,
+        };
         let resp = leader_post!(
             self,
             task_id,
             task_config,
             "aggregate",
-            MEDIA_TYPE_AGG_INIT_REQ,
-            agg_init_req.get_encoded_with_param(&task_config.version)
+            media_type_for(MediaTypeKind::AggInitReq, task_config.version),
+            agg_init_req.get_encoded_with_param(&task_config.version),
+            idempotency_key: idempotency_key
         );
         let agg_resp = AggregateResp::get_decoded(&resp.payload)?;
 
         // Prepare AggreagteContinueReq.
-        let transition = task_config
-            .vdaf
-            .handle_agg_resp(task_id, &agg_job_id, state, agg_resp)?;
+        let transition = task_config.vdaf.handle_agg_resp(
+            task_id,
+            &agg_job_id,
+            state,
+            agg_resp,
+            task_config
+                .global_config(self.get_global_config())
+                .checksum_algorithm,
+        )?;
         let (uncommited, agg_cont_req) = match transition {
             DapLeaderTransition::Uncommitted(uncommited, agg_cont_req) => {
                 (uncommited, agg_cont_req)
             }
-            DapLeaderTransition::Skip => return Ok(0),
+            DapLeaderTransition::Skip(leader_rejected) => {
+                rejected.extend(with_report_time(leader_rejected, &report_times));
+                self.put_rejected_reports(task_id, &rejected).await?;
+                return Ok(0);
+            }
             DapLeaderTransition::Continue(..) => {
                 return Err(DapError::fatal("unexpected state transition (continue)").into())
             }
@@ -453,18 +1007,20 @@ where
             task_id,
             task_config,
             "aggregate",
-            MEDIA_TYPE_AGG_CONT_REQ,
+            media_type_for(MediaTypeKind::AggContReq, task_config.version),
             agg_cont_req.get_encoded()
         );
         let agg_resp = AggregateResp::get_decoded(&resp.payload)?;
 
         // Commit the output shares.
-        let out_shares = task_config
+        let (out_shares, leader_rejected) = task_config
             .vdaf
             .handle_final_agg_resp(uncommited, agg_resp)?;
         let out_shares_count = out_shares.len() as u64;
         self.put_out_shares(task_id, part_batch_sel, out_shares)
             .await?;
+        rejected.extend(with_report_time(leader_rejected, &report_times));
+        self.put_rejected_reports(task_id, &rejected).await?;
         Ok(out_shares_count)
     }
 
@@ -489,14 +1045,29 @@ where
             return Ok(0);
         }
 
-        let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
+        // Check that the reports in the batch are not too far apart.
+        if !task_config.is_batch_duration_compatible(
+            task_config.global_config(self.get_global_config()),
+            &leader_agg_share,
+        ) {
+            return Err(DapAbort::BatchInvalid);
+        }
 
         // Prepare the Leader's aggregate share.
+        let collector_hpke_config = task_config
+            .collector_hpke_config(collect_req.collector_hpke_config_id)
+            .ok_or_else(|| DapError::fatal("collector HPKE config not found"))?;
         let leader_enc_agg_share = task_config.vdaf.produce_leader_encrypted_agg_share(
-            &task_config.collector_hpke_config,
+            collector_hpke_config,
             &collect_req.task_id,
             &batch_selector,
             &leader_agg_share,
+            task_config
+                .global_config(self.get_global_config())
+                .agg_share_compression,
+            task_config
+                .global_config(self.get_global_config())
+                .noise_split,
             task_config.version,
         )?;
 
@@ -509,14 +1080,16 @@ where
             checksum: leader_agg_share.checksum,
         };
 
-        // Send AggregateShareReq and receive AggregateShareResp.
+        // Send AggregateShareReq and receive AggregateShareResp. The Helper is told which of the
+        // Collector's HPKE configs to use.
         let resp = leader_post!(
             self,
             &collect_req.task_id,
             task_config,
             "aggregate_share",
-            MEDIA_TYPE_AGG_SHARE_REQ,
-            agg_share_req.get_encoded_with_param(&task_config.version)
+            media_type_for(MediaTypeKind::AggShareReq, task_config.version),
+            agg_share_req.get_encoded_with_param(&task_config.version),
+            collector_hpke_config_id: collect_req.collector_hpke_config_id
         );
         let agg_share_resp = AggregateShareResp::get_decoded(&resp.payload)?;
 
@@ -526,8 +1099,13 @@ where
             report_count: leader_agg_share.report_count,
             encrypted_agg_shares: vec![leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
         };
-        self.finish_collect_job(&collect_req.task_id, collect_id, &collect_resp)
-            .await?;
+        self.finish_collect_job(
+            &collect_req.task_id,
+            collect_id,
+            &collect_resp,
+            self.get_current_time(),
+        )
+        .await?;
 
         // Mark reports as collected.
         self.mark_collected(&agg_share_req.task_id, &agg_share_req.batch_sel)
@@ -536,6 +1114,48 @@ where
         Ok(agg_share_req.report_count)
     }
 
+    /// Retry a pending collect job, e.g., after a transient failure to fetch the Helper's
+    /// aggregate share. A collect job is completed only once the Helper's aggregate share is
+    /// received, so a transiently failed attempt leaves the job pending and it can be retried
+    /// again. The return value is the status of the collect job after the attempt.
+    ///
+    /// If the Helper has already collected the batch, then its aggregate share can't be fetched
+    /// again and the job is marked as failed (see [`fail_collect_job`](Self::fail_collect_job)).
+    /// Any other error that is not transient is returned.
+    async fn retry_collect_job(
+        &'srv self,
+        task_id: &Id,
+        collect_id: &Id,
+    ) -> Result<DapCollectJob, DapAbort> {
+        let pending = self
+            .get_pending_collect_jobs()
+            .await?
+            .into_iter()
+            .find(|(id, collect_req)| id == collect_id && collect_req.task_id == *task_id);
+
+        // Only a pending collect job is retried. Otherwise, just report its status.
+        if let Some((_, collect_req)) = pending {
+            let task_config = self
+                .get_task_config_for(Cow::Owned(task_id.clone()))
+                .await?
+                .ok_or(DapAbort::UnrecognizedTask)?;
+
+            match self
+                .run_collect_job(collect_id, task_config.as_ref(), &collect_req)
+                .await
+            {
+                Ok(_) => (),
+                Err(DapAbort::BatchOverlap) => self.fail_collect_job(task_id, collect_id).await?,
+                // The attempt failed transiently, e.g., because the Helper is unavailable. The
+                // collect job remains pending, which is reflected by the status returned below.
+                Err(DapAbort::Internal(..)) | Err(DapAbort::TooManyRequests { .. }) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(self.poll_collect_job(task_id, collect_id).await?)
+    }
+
     /// Fetch a set of reports grouped by task, then run an aggregation job for each task. once all
     /// jobs completed, process the collect job queue. It is not safe to run multiple instances of
     /// this function in parallel.
@@ -573,19 +1193,75 @@ where
         // proceeding to this step. This is to prevent a race condition involving an aggregate
         // share computed during a collect job and any output shares computed during an aggregation
         // job.
+        //
+        // Collect jobs that have been pending for too long are expired first so that they are no
+        // longer processed.
+        self.expire_collect_jobs(self.get_current_time()).await?;
         for (collect_id, collect_req) in self.get_pending_collect_jobs().await? {
             let task_config = self
                 .get_task_config_for(Cow::Owned(collect_req.task_id.clone()))
                 .await?
                 .ok_or(DapAbort::UnrecognizedTask)?;
 
-            telem.reports_collected += self
+            // If the Helper has already collected the batch, e.g., because the response to a
+            // previous attempt was lost, then the collect job can't be completed.
+            match self
                 .run_collect_job(&collect_id, task_config.as_ref(), &collect_req)
-                .await?;
+                .await
+            {
+                Ok(reports_collected) => telem.reports_collected += reports_collected,
+                Err(DapAbort::BatchOverlap) => {
+                    self.fail_collect_job(&collect_req.task_id, &collect_id)
+                        .await?
+                }
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(telem)
     }
+
+    /// Drain the pending reports for the given task and run an aggregation job for each partial
+    /// batch. Requests are sent to the Helper via [`DapHttpClient::post`]. The output shares
+    /// are committed to storage as each job completes.
+    ///
+    /// Unlike [`DapLeader::process`], this method does not process the collect job queue.
+    async fn process_task(&'srv self, task_id: &Id) -> Result<DapAggJobReport, DapAbort>
+    where
+        Self::ReportSelector: From<Id>,
+    {
+        let task_config = self
+            .get_task_config_for(Cow::Owned(task_id.clone()))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+
+        let selector = Self::ReportSelector::from(task_id.clone());
+        let mut agg_job_report = DapAggJobReport::default();
+        loop {
+            let reports = self
+                .get_reports(&selector)
+                .await?
+                .remove(task_id)
+                .unwrap_or_default();
+            if reports.values().all(|reports| reports.is_empty()) {
+                break;
+            }
+
+            for (part_batch_sel, reports) in reports.into_iter() {
+                if reports.is_empty() {
+                    continue;
+                }
+                let reports_processed = reports.len() as u64;
+                let reports_succeeded = self
+                    .run_agg_job(task_id, task_config.as_ref(), &part_batch_sel, reports)
+                    .await?;
+                agg_job_report.reports_succeeded += reports_succeeded;
+                agg_job_report.reports_failed += reports_processed - reports_succeeded;
+            }
+        }
+
+        Ok(agg_job_report)
+    }
 }
 
 /// DAP Helper functionality.
@@ -610,10 +1286,41 @@ where
         agg_job_id: &Id,
     ) -> Result<Option<DapHelperState>, DapError>;
 
+    /// Drop the state of each aggregation job, and each stored response to an
+    /// AggregateInitializeReq, that was created more than
+    /// [`job_ttl`](crate::DapGlobalConfig::job_ttl) seconds before `now`. An expired job can no
+    /// longer be continued. By default, nothing is dropped; this is appropriate for backends that
+    /// expire job state by other means.
+    async fn expire_jobs(&self, _now: Time) -> Result<(), DapError> {
+        Ok(())
+    }
+
+    /// Store the response to an AggregateInitializeReq carrying the given idempotency key. A
+    /// response already stored for the key is replaced.
+    async fn put_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+        stored_agg_init_resp: &DapStoredAggInitResp,
+    ) -> Result<(), DapError>;
+
+    /// Fetch the response to an AggregateInitializeReq carrying the given idempotency key. `None`
+    /// is returned if the Helper has not yet responded to a request with this key. Unlike
+    /// `get_helper_state()`, this does not remove the response.
+    async fn get_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+    ) -> Result<Option<DapStoredAggInitResp>, DapError>;
+
     /// Handle an HTTP POST to `/aggregate`. The input is either an AggregateInitializeReq or
     /// AggregateContinueReq and the response is an AggregateResp.
     ///
-    /// This is called during the Initialization and Continuation phases.
+    /// This is called during the Initialization and Continuation phases. An AggregateInitializeReq
+    /// with no report shares is valid: the aggregation job is registered as usual and the response
+    /// has no transitions.
+    //
+    // TODO spec: Decide if empty aggregation jobs should be allowed.
     async fn http_post_aggregate(
         &'srv self,
         req: &'req DapRequest<S>,
@@ -627,10 +1334,26 @@ where
             return Err(DapAbort::UnauthorizedRequest);
         }
 
-        match req.media_type {
-            Some(MEDIA_TYPE_AGG_INIT_REQ) => {
-                let agg_init_req =
-                    AggregateInitializeReq::get_decoded_with_param(&req.version, &req.payload)?;
+        let media_type = match req.media_type {
+            Some(media_type)
+                if media_type == media_type_for(MediaTypeKind::AggInitReq, req.version)
+                    || media_type == media_type_for(MediaTypeKind::AggContReq, req.version) =>
+            {
+                media_type
+            }
+            //TODO spec: Specify this behavior.
+            _ => return Err(DapAbort::BadRequest("unexpected media type".into())),
+        };
+        match decode_request_payload(media_type, req.version, &req.payload)? {
+            DapMessage::AggregateInitializeReq(agg_init_req) => {
+                // Check that the task ID in the message matches the task ID of the request.
+                if agg_init_req.task_id != *req.task_id()? {
+                    return Err(DapAbort::UnrecognizedMessage);
+                }
+
+                for report_share in agg_init_req.report_shares.iter() {
+                    check_report_extensions(self.get_global_config(), &report_share.metadata)?;
+                }
 
                 let mut first_metadata: Option<&ReportMetadata> = None;
 
@@ -686,6 +1409,51 @@ where
                     &agg_init_req.agg_param,
                 )?;
 
+                // Check that the task has started.
+                if agg_init_req
+                    .report_shares
+                    .iter()
+                    .any(|report_share| report_share.metadata.time < task_config.start)
+                {
+                    return Err(DapAbort::TaskNotStarted);
+                }
+
+                // If the Leader is retrying a request we already responded to, then respond the
+                // same way without processing the request again. The idempotency key is only
+                // recognized for draft03. The stored response expires along with the Helper's
+                // state for the aggregation job.
+                let idempotency_key = match req.version {
+                    DapVersion::Draft03 => req.idempotency_key.as_ref(),
+                    _ => None,
+                };
+                let req_hash = sha256(&req.payload);
+                let now = self.get_current_time();
+                if let Some(idempotency_key) = idempotency_key {
+                    if let Some(stored_agg_init_resp) = self
+                        .get_agg_init_resp(&agg_init_req.task_id, idempotency_key)
+                        .await?
+                    {
+                        let job_ttl = task_config.global_config(self.get_global_config()).job_ttl;
+                        if now.saturating_sub(stored_agg_init_resp.created_at) <= job_ttl {
+                            if stored_agg_init_resp.agg_job_id != agg_init_req.agg_job_id
+                                || stored_agg_init_resp.req_hash != req_hash
+                            {
+                                return Err(DapAbort::BadRequest(
+                                    "idempotency key was used for a different request".into(),
+                                ));
+                            }
+
+                            return Ok(DapResponse {
+                                media_type: Some(media_type_for(
+                                    MediaTypeKind::AggInitResp,
+                                    req.version,
+                                )),
+                                payload: stored_agg_init_resp.agg_resp.get_encoded(),
+                            });
+                        }
+                    }
+                }
+
                 let early_rejects_future = self.check_early_reject(
                     &agg_init_req.task_id,
                     &agg_init_req.part_batch_sel,
@@ -705,14 +1473,52 @@ where
                     )
                     .await?;
 
-                // Check that helper state with task_id and agg_job_id does not exist.
-                if helper_state.await?.is_some() {
+                // Check that helper state with task_id and agg_job_id does not exist. If it does
+                // and the job was initialized for a different batch, then the Leader is attempting
+                // to move the aggregation job to another batch.
+                if let Some(helper_state) = helper_state.await? {
+                    if helper_state.part_batch_sel != agg_init_req.part_batch_sel {
+                        return Err(DapAbort::BatchMismatch);
+                    }
                     // TODO spec: Consider an explicit abort for this case.
                     return Err(DapAbort::BadRequest(
                         "unexpected message for aggregation job (already exists)".into(),
                     ));
                 }
 
+                // Reject reports that the task does not permit: reports carrying extensions that
+                // are unrecognized or not permitted for the task and, if the task requires it,
+                // reports that are not sealed to the Helper's latest HPKE config.
+                //
+                // TODO spec: Draft02 and Draft03 have no failure for a report carrying an
+                // unrecognized or disallowed extension, so the report is dropped. Later drafts
+                // define `unrecognized_message` for this.
+                let latest_hpke_config_id = if task_config.require_latest_hpke_config {
+                    Some(
+                        self.get_hpke_config_for(Some(&agg_init_req.task_id))
+                            .await?
+                            .as_ref()
+                            .id,
+                    )
+                } else {
+                    None
+                };
+                let mut task_rejects = HashMap::new();
+                for report_share in agg_init_req.report_shares.iter() {
+                    if !task_config.allows_report_extensions(&report_share.metadata) {
+                        task_rejects
+                            .insert(&report_share.metadata.id, TransitionFailure::ReportDropped);
+                    } else if latest_hpke_config_id.is_some()
+                        && latest_hpke_config_id
+                            != Some(report_share.encrypted_input_share.config_id)
+                    {
+                        task_rejects.insert(
+                            &report_share.metadata.id,
+                            TransitionFailure::HpkeUnknownConfigId,
+                        );
+                    }
+                }
+
                 // Remove reports that are rejected early.
                 let early_rejects = early_rejects_future.await?;
                 let agg_resp = match transition {
@@ -726,7 +1532,7 @@ where
                             let result = if time >= &task_config.expiration {
                                 Some(&TransitionFailure::TaskExpired)
                             } else {
-                                early_result
+                                task_rejects.get(report_id).or(early_result)
                             };
 
                             // TODO Emit metrics for failure reasons.
@@ -761,17 +1567,48 @@ where
                     }
                 };
 
+                let report_times = agg_init_req
+                    .report_shares
+                    .iter()
+                    .map(|report_share| {
+                        (report_share.metadata.id.clone(), report_share.metadata.time)
+                    })
+                    .collect();
+                self.put_rejected_reports(
+                    &agg_init_req.task_id,
+                    &rejected_reports(&agg_resp, &report_times),
+                )
+                .await?;
+
+                if let Some(idempotency_key) = idempotency_key {
+                    self.put_agg_init_resp(
+                        &agg_init_req.task_id,
+                        idempotency_key,
+                        &DapStoredAggInitResp {
+                            agg_job_id: agg_init_req.agg_job_id.clone(),
+                            req_hash,
+                            created_at: now,
+                            agg_resp: agg_resp.clone(),
+                        },
+                    )
+                    .await?;
+                }
+
                 Ok(DapResponse {
-                    media_type: Some(MEDIA_TYPE_AGG_INIT_RESP),
+                    media_type: Some(media_type_for(MediaTypeKind::AggInitResp, req.version)),
                     payload: agg_resp.get_encoded(),
                 })
             }
-            Some(MEDIA_TYPE_AGG_CONT_REQ) => {
+            DapMessage::AggregateContinueReq(agg_cont_req) => {
                 if !self.authorized(req).await? {
                     return Err(DapAbort::UnauthorizedRequest);
                 }
 
-                let agg_cont_req = AggregateContinueReq::get_decoded(&req.payload)?;
+                // Check that the task ID in the message matches the task ID of the request.
+                if agg_cont_req.task_id != *req.task_id()? {
+                    return Err(DapAbort::UnrecognizedMessage);
+                }
+
                 let wrapped_task_config = self
                     .get_task_config_for(Cow::Borrowed(req.task_id()?))
                     .await?
@@ -787,8 +1624,22 @@ where
                     .get_helper_state(&agg_cont_req.task_id, &agg_cont_req.agg_job_id)
                     .await?
                     .ok_or(DapAbort::UnrecognizedAggregationJob)?;
+
+                // TODO spec: The AggregateContinueReq does not carry the aggregation parameter, so
+                // the Helper can't check that it is the same as in the AggregateInitializeReq.
                 let part_batch_sel = state.part_batch_sel.clone();
-                let transition = task_config.vdaf.handle_agg_cont_req(state, &agg_cont_req)?;
+                let report_times = state
+                    .seq
+                    .iter()
+                    .map(|(_vdaf_state, time, report_id)| (report_id.clone(), *time))
+                    .collect();
+                let transition = task_config.vdaf.handle_agg_cont_req(
+                    state,
+                    &agg_cont_req,
+                    task_config
+                        .global_config(self.get_global_config())
+                        .checksum_algorithm,
+                )?;
 
                 let agg_resp = match transition {
                     DapHelperTransition::Continue(..) => {
@@ -800,13 +1651,17 @@ where
                         agg_resp
                     }
                 };
+                self.put_rejected_reports(
+                    &agg_cont_req.task_id,
+                    &rejected_reports(&agg_resp, &report_times),
+                )
+                .await?;
 
                 Ok(DapResponse {
-                    media_type: Some(MEDIA_TYPE_AGG_CONT_RESP),
+                    media_type: Some(media_type_for(MediaTypeKind::AggContResp, req.version)),
                     payload: agg_resp.get_encoded(),
                 })
             }
-            //TODO spec: Specify this behavior.
             _ => Err(DapAbort::BadRequest("unexpected media type".into())),
         }
     }
@@ -830,18 +1685,25 @@ where
             return Err(DapAbort::UnauthorizedRequest);
         }
 
-        let agg_share_req = AggregateShareReq::get_decoded_with_param(&req.version, &req.payload)?;
         let wrapped_task_config = self
             .get_task_config_for(Cow::Borrowed(req.task_id()?))
             .await?
             .ok_or(DapAbort::UnrecognizedTask)?;
         let task_config = wrapped_task_config.as_ref();
 
-        // Check whether the DAP version in the request matches the task config.
+        // Check whether the DAP version in the request matches the task config. As for collect
+        // requests, this is done before decoding the version-dependent message.
         if task_config.version != req.version {
             return Err(DapAbort::InvalidProtocolVersion);
         }
 
+        let agg_share_req = AggregateShareReq::get_decoded_with_param(&req.version, &req.payload)?;
+
+        // Check that the task ID in the message matches the task ID of the request.
+        if agg_share_req.task_id != *req.task_id()? {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+
         // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
         // collected batches.
         check_batch(
@@ -854,6 +1716,14 @@ where
         )
         .await?;
 
+        // Ensure the batch is small enough for us to aggregate in one go.
+        let num_buckets = task_config
+            .batch_span_for_sel(&agg_share_req.batch_sel)?
+            .len() as u64;
+        if num_buckets > self.get_global_config().max_agg_share_buckets {
+            return Err(DapAbort::AggregateShareTooLarge);
+        }
+
         let agg_share = self
             .get_agg_share(&agg_share_req.task_id, &agg_share_req.batch_sel)
             .await?;
@@ -873,15 +1743,32 @@ where
             return Err(DapAbort::InvalidBatchSize);
         }
 
+        // Check that the reports in the batch are not too far apart.
+        if !task_config.is_batch_duration_compatible(
+            task_config.global_config(self.get_global_config()),
+            &agg_share,
+        ) {
+            return Err(DapAbort::BatchInvalid);
+        }
+
         // Mark each aggregated report as collected.
         self.mark_collected(&agg_share_req.task_id, &agg_share_req.batch_sel)
             .await?;
 
+        let collector_hpke_config = task_config
+            .collector_hpke_config(req.collector_hpke_config_id)
+            .ok_or_else(|| DapAbort::BadRequest("unrecognized collector HPKE config".into()))?;
         let encrypted_agg_share = task_config.vdaf.produce_helper_encrypted_agg_share(
-            &task_config.collector_hpke_config,
+            collector_hpke_config,
             &agg_share_req.task_id,
             &agg_share_req.batch_sel,
             &agg_share,
+            task_config
+                .global_config(self.get_global_config())
+                .agg_share_compression,
+            task_config
+                .global_config(self.get_global_config())
+                .noise_split,
             task_config.version,
         )?;
 
@@ -890,25 +1777,162 @@ where
         };
 
         Ok(DapResponse {
-            media_type: Some(MEDIA_TYPE_AGG_SHARE_RESP),
+            media_type: Some(media_type_for(MediaTypeKind::AggShareResp, req.version)),
             payload: agg_share_resp.get_encoded(),
         })
     }
 }
 
+/// Parse the ID of the Collector's HPKE config from the `collector_hpke_config_id` query parameter
+/// of a collect request, if present.
+fn collector_hpke_config_id(url: &Url) -> Result<Option<u8>, DapAbort> {
+    let mut config_id = None;
+    for (k, v) in url.query_pairs() {
+        if k == "collector_hpke_config_id" {
+            config_id = Some(v.parse().map_err(|_| {
+                DapAbort::BadRequest("failed to parse collector HPKE config ID".into())
+            })?);
+        }
+    }
+    Ok(config_id)
+}
+
+/// Return the reports rejected in an aggregate response, along with the timestamp of each report
+/// (looked up in `report_times`) and the reason it was rejected.
+fn rejected_reports(
+    agg_resp: &AggregateResp,
+    report_times: &HashMap<ReportId, Time>,
+) -> Vec<(ReportId, Time, TransitionFailure)> {
+    with_report_time(
+        agg_resp
+            .transitions
+            .iter()
+            .filter_map(|transition| match transition.var {
+                TransitionVar::Failed(failure_reason) => {
+                    Some((transition.report_id.clone(), failure_reason))
+                }
+                _ => None,
+            }),
+        report_times,
+    )
+}
+
+/// Compute the SHA-256 hash of `data`.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    hash
+}
+
+/// Attach to each rejected report its timestamp, looked up in `report_times`.
+fn with_report_time(
+    rejected: impl IntoIterator<Item = (ReportId, TransitionFailure)>,
+    report_times: &HashMap<ReportId, Time>,
+) -> Vec<(ReportId, Time, TransitionFailure)> {
+    rejected
+        .into_iter()
+        .filter_map(|(report_id, failure_reason)| {
+            let time = *report_times.get(&report_id)?;
+            Some((report_id, time, failure_reason))
+        })
+        .collect()
+}
+
+/// Check that the report's extensions do not exceed the limits set in the global config. This is
+/// done before any extension is processed (e.g., taskprov) in order to bound the work done for
+/// each report.
+fn check_report_extensions(
+    global_config: &DapGlobalConfig,
+    metadata: &ReportMetadata,
+) -> Result<(), DapAbort> {
+    if metadata.extensions.len() > global_config.max_extensions {
+        return Err(DapAbort::UnrecognizedMessage);
+    }
+
+    for extension in metadata.extensions.iter() {
+        let payload = match extension {
+            Extension::Taskprov { payload } | Extension::Unhandled { payload, .. } => payload,
+        };
+        if payload.len() > global_config.max_extension_len {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+    }
+
+    Ok(())
+}
+
 fn check_part_batch(
     task_config: &DapTaskConfig,
     part_batch_sel: &PartialBatchSelector,
     agg_param: &[u8],
 ) -> Result<(), DapAbort> {
-    if !task_config.query.is_valid_part_batch_sel(part_batch_sel) {
-        return Err(DapAbort::QueryMismatch);
+    let expected = QueryKind::from(&task_config.query);
+    let actual = QueryKind::from(part_batch_sel);
+    if expected != actual {
+        return Err(DapAbort::QueryMismatch { expected, actual });
     }
 
     // Check that the aggreation parameter is suitable for the given VDAF.
-    if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::UnrecognizedMessage);
+    task_config.vdaf.validate_agg_param(agg_param)?;
+
+    Ok(())
+}
+
+// Return the collect requests of the pending collect jobs for the given task.
+async fn pending_collect_reqs_for<'srv, 'req, S>(
+    leader: &impl DapLeader<'srv, 'req, S>,
+    task_id: &Id,
+) -> Result<Vec<CollectReq>, DapError>
+where
+    'srv: 'req,
+{
+    Ok(leader
+        .get_pending_collect_jobs()
+        .await?
+        .into_iter()
+        .map(|(_collect_id, pending_req)| pending_req)
+        .filter(|pending_req| pending_req.task_id == *task_id)
+        .collect())
+}
+
+// Check that the batch of a collect request may be collected. The query of `collect_req` must
+// determine the batch (see `BatchSelector::try_from()`). `pending_collect_reqs` are the collect
+// requests of the task's pending collect jobs.
+async fn check_collect_req<'srv, 'req, S>(
+    leader: &impl DapLeader<'srv, 'req, S>,
+    task_config: &DapTaskConfig,
+    collect_req: &CollectReq,
+    pending_collect_reqs: &[CollectReq],
+    now: Time,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+{
+    // Ensure the request is valid for the task, i.e., the Collector's HPKE config and the
+    // aggregation parameter are recognized and the batch boundaries are valid.
+    task_config.validate_collect_req(leader.get_global_config(), collect_req, now)?;
+
+    // Ensure the batch exists and that it doesn't overlap with previously collected batches.
+    let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
+    check_batch_state(leader, &collect_req.task_id, &batch_selector).await?;
+
+    // Ensure that every report in the batch has been aggregated. Otherwise the aggregate shares
+    // would not account for all of the reports.
+    if leader
+        .has_pending_reports(&collect_req.task_id, &batch_selector)
+        .await?
+    {
+        return Err(DapAbort::BatchNotReady);
+    }
+
+    // Ensure the batch doesn't overlap with the batch of a collect job that is still pending.
+    // (Overlap with batches that have already been collected is checked by
+    // `check_batch_state()`.)
+    for pending_req in pending_collect_reqs.iter() {
+        let pending_batch_selector = BatchSelector::try_from(pending_req.query.clone())?;
+        if pending_batch_selector.intersects(&batch_selector) {
+            return Err(DapAbort::BatchOverlap);
+        }
     }
 
     Ok(())
@@ -925,42 +1949,30 @@ async fn check_batch<'srv, 'req, S>(
 where
     'srv: 'req,
 {
-    let global_config = agg.get_global_config();
-    let batch_overlapping = agg.is_batch_overlapping(task_id, batch_sel);
-
     // Check that the aggreation parameter is suitable for the given VDAF.
-    if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::UnrecognizedMessage);
-    }
+    task_config.vdaf.validate_agg_param(agg_param)?;
 
     // Check that the batch boundaries are valid.
-    match (&task_config.query, batch_sel) {
-        (DapQueryConfig::TimeInterval { .. }, BatchSelector::TimeInterval { batch_interval }) => {
-            if batch_interval.start % task_config.time_precision != 0
-                || batch_interval.duration % task_config.time_precision != 0
-                || batch_interval.duration < task_config.time_precision
-            {
-                return Err(DapAbort::BatchInvalid);
-            }
+    task_config.check_batch_sel(agg.get_global_config(), batch_sel, now)?;
 
-            if batch_interval.duration > global_config.max_batch_duration {
-                return Err(DapAbort::BadRequest("batch interval too large".to_string()));
-            }
+    check_batch_state(agg, task_id, batch_sel).await
+}
 
-            if now.abs_diff(batch_interval.start) > global_config.min_batch_interval_start {
-                return Err(DapAbort::BadRequest(
-                    "batch interval too far into past".to_string(),
-                ));
-            }
+/// Check that the batch exists and that it doesn't overlap with a previously collected batch.
+async fn check_batch_state<'srv, 'req, S>(
+    agg: &impl DapAggregator<'srv, 'req, S>,
+    task_id: &Id,
+    batch_sel: &BatchSelector,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+{
+    let batch_overlapping = agg.is_batch_overlapping(task_id, batch_sel);
 
-            if now.abs_diff(batch_interval.end()) > global_config.max_batch_interval_end {
-                return Err(DapAbort::BadRequest(
-                    "batch interval too far into future".to_string(),
-                ));
-            }
-        }
-        (DapQueryConfig::FixedSize { .. }, BatchSelector::FixedSizeByBatchId { batch_id }) => {
+    // Check that the batch exists.
+    match batch_sel {
+        BatchSelector::TimeInterval { .. } => (),
+        BatchSelector::FixedSizeByBatchId { batch_id } => {
             // TODO(cjpatton) The Helper can avoid this callback by first fetching the aggregate
             // share and aborting with "batchInvalid" if the report count is 0. Depending on how we
             // resolve https://github.com/ietf-wg-ppm/draft-ietf-ppm-dap/issues/342, this check may
@@ -971,7 +1983,6 @@ where
                 return Err(DapAbort::BatchInvalid);
             }
         }
-        _ => return Err(DapAbort::QueryMismatch),
     };
 
     // Check that the batch does not overlap with any previously collected batch.
@@ -4,31 +4,42 @@
 use crate::{
     async_test_version, async_test_versions,
     auth::BearerToken,
+    compute_checksum,
     constants::{
+        media_type_for, parse_media_type, MediaTypeKind, MEDIA_TYPE_AGGREGATOR_INFO,
         MEDIA_TYPE_AGG_CONT_REQ, MEDIA_TYPE_AGG_INIT_REQ, MEDIA_TYPE_AGG_SHARE_REQ,
-        MEDIA_TYPE_COLLECT_REQ, MEDIA_TYPE_HPKE_CONFIG, MEDIA_TYPE_REPORT,
+        MEDIA_TYPE_COLLECT_REQ, MEDIA_TYPE_FIXED_SIZE_BATCHES, MEDIA_TYPE_HPKE_CONFIG,
+        MEDIA_TYPE_REPORT,
     },
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
         taskprov, AggregateContinueReq, AggregateInitializeReq, AggregateResp, AggregateShareReq,
-        AggregateShareResp, BatchSelector, CollectReq, CollectResp, Extension, HpkeKemId, Id,
-        Interval, PartialBatchSelector, Query, Report, ReportShare, Time, Transition,
-        TransitionFailure, TransitionVar,
+        BatchSelector, CollectReq, CollectResp, Extension, HpkeCiphertext, HpkeKemId, Id, Interval,
+        PartialBatchSelector, Query, Report, ReportId, ReportShare, Time, Transition,
+        TransitionFailure, TransitionVar, EXTENSION_TASKPROV,
     },
-    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov::TaskprovVersion,
-    testing::{AggStore, DapBatchBucketOwned, MockAggregator, MockAggregatorReportSelector},
-    vdaf::VdafVerifyKey,
-    DapAbort, DapAggregateShare, DapCollectJob, DapGlobalConfig, DapLeaderTransition,
-    DapMeasurement, DapQueryConfig, DapRequest, DapTaskConfig, DapVersion, Prio3Config, VdafConfig,
+    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapHttpClient, DapLeader},
+    test_version, test_versions,
+    testing::{
+        bench_aggregate, bench_produce_reports, AggStore, CollectJobState, DapTaskProvider,
+        InProcessHttpClient, MockAggregator, MockAggregatorReportSelector, TestFixtureBuilder,
+    },
+    vdaf::VdafAggregateShare,
+    AggShareCompression, ChecksumAlgorithm, DapAbort, DapAggregateResult, DapAggregateShare,
+    DapAggregatorInfo, DapBatchBucketOwned, DapCollectJob, DapError, DapGlobalConfig,
+    DapMeasurement, DapOutputShare, DapQueryConfig, DapRequest, DapTaskConfig, DapVersion,
+    HpkeConfigMissingTaskIdPolicy, Prio3Config, QueryKind, ReportBuffer, ReportStatus,
+    TaskConfigFieldDiff, VdafConfig,
 };
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use matchit::Router;
 use paste::paste;
-use prio::codec::{Decode, Encode, ParameterizedEncode};
+use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
 use rand::{thread_rng, Rng};
 use std::{
     borrow::Cow,
+    cell::{Cell, RefCell},
     collections::HashMap,
     sync::{Arc, Mutex},
     time::SystemTime,
@@ -52,6 +63,7 @@ struct Test {
     leader: MockAggregator,
     helper: MockAggregator,
     collector_token: BearerToken,
+    collector_hpke_receiver_config: HpkeReceiverConfig,
     time_interval_task_id: Id,
     fixed_size_task_id: Id,
     expired_task_id: Id,
@@ -60,134 +72,46 @@ struct Test {
 
 impl Test {
     fn new(version: DapVersion) -> Self {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
         let mut rng = thread_rng();
+        let fixture = TestFixtureBuilder::new().version(version).build();
 
-        // Global config. In a real deployment, the Leader and Helper may make different choices
-        // here.
-        let global_config = DapGlobalConfig {
-            report_storage_epoch_duration: 604800, // one week
-            max_batch_duration: 360000,
-            min_batch_interval_start: 259200,
-            max_batch_interval_end: 259200,
-            supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
-            allow_taskprov: true,
-            taskprov_version: TaskprovVersion::Draft02,
-        };
-
-        // Task Parameters that the Leader and Helper must agree on.
-        let vdaf_config = VdafConfig::Prio3(Prio3Config::Count);
-        let leader_url = Url::parse("https://leader.biz/v02/").unwrap();
-        let helper_url = Url::parse("http://helper.com:8788/v02/").unwrap();
-        let time_precision = 3600;
-        let collector_hpke_receiver_config =
-            HpkeReceiverConfig::gen(rng.gen(), HpkeKemId::X25519HkdfSha256).unwrap();
-
-        // Create the task list.
-        let time_interval_task_id = Id(rng.gen());
+        // Add a "fixed-size" task and an expired task to the one generated for the fixture. The
+        // tasks differ from the fixture's only in the query type and expiration respectively.
+        let time_interval_task_id = fixture.task_id;
         let fixed_size_task_id = Id(rng.gen());
         let expired_task_id = Id(rng.gen());
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            time_interval_task_id.clone(),
-            DapTaskConfig {
-                version,
-                collector_hpke_config: collector_hpke_receiver_config.config.clone(),
-                leader_url: leader_url.clone(),
-                helper_url: helper_url.clone(),
-                time_precision,
-                expiration: now + 3600,
-                min_batch_size: 1,
-                query: DapQueryConfig::TimeInterval,
-                vdaf: vdaf_config.clone(),
-                vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
-            },
-        );
-        tasks.insert(
-            fixed_size_task_id.clone(),
-            DapTaskConfig {
-                version,
-                collector_hpke_config: collector_hpke_receiver_config.config.clone(),
-                leader_url: leader_url.clone(),
-                helper_url: helper_url.clone(),
-                time_precision,
-                expiration: now + 3600,
-                min_batch_size: 1,
-                query: DapQueryConfig::FixedSize { max_batch_size: 2 },
-                vdaf: vdaf_config.clone(),
-                vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
-            },
-        );
-        tasks.insert(
-            expired_task_id.clone(),
-            DapTaskConfig {
-                version,
-                collector_hpke_config: collector_hpke_receiver_config.config.clone(),
-                leader_url: leader_url.clone(),
-                helper_url: helper_url.clone(),
-                time_precision,
-                expiration: now, // Expires this second
-                min_batch_size: 1,
-                query: DapQueryConfig::TimeInterval,
-                vdaf: vdaf_config.clone(),
-                vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
-            },
-        );
-
-        // Authorization tokens, used for all tasks.
-        let leader_token = BearerToken::from("this is a bearer token!");
-        let collector_token = BearerToken::from("This is a DIFFERENT token.");
-
-        // taskprov: VDAF verification key.
-        let mut taskprov_vdaf_verify_key_init = vec![0; 32];
-        rng.fill(&mut taskprov_vdaf_verify_key_init[..]);
-
-        let leader_hpke_receiver_config_list = global_config
-            .gen_hpke_receiver_config_list(rng.gen())
-            .collect::<Result<Vec<HpkeReceiverConfig>, _>>()
-            .expect("failed to generate HPKE receiver config");
-        let leader = MockAggregator {
-            now,
-            global_config: global_config.clone(),
-            tasks: Arc::new(Mutex::new(tasks.clone())),
-            hpke_receiver_config_list: leader_hpke_receiver_config_list,
-            leader_token: leader_token.clone(),
-            collector_token: Some(collector_token.clone()),
-            report_store: Arc::new(Mutex::new(HashMap::new())),
-            leader_state_store: Arc::new(Mutex::new(HashMap::new())),
-            helper_state_store: Arc::new(Mutex::new(HashMap::new())),
-            agg_store: Arc::new(Mutex::new(HashMap::new())),
-            collector_hpke_config: collector_hpke_receiver_config.config.clone(),
-            taskprov_vdaf_verify_key_init: taskprov_vdaf_verify_key_init.clone(),
-        };
-
-        let helper_hpke_receiver_config_list = global_config
-            .gen_hpke_receiver_config_list(rng.gen())
-            .collect::<Result<Vec<HpkeReceiverConfig>, _>>()
-            .expect("failed to generate HPKE receiver config");
-        let helper = MockAggregator {
-            now,
-            global_config,
-            tasks: Arc::new(Mutex::new(tasks)),
-            leader_token,
-            collector_token: None,
-            hpke_receiver_config_list: helper_hpke_receiver_config_list,
-            report_store: Arc::new(Mutex::new(HashMap::new())),
-            leader_state_store: Arc::new(Mutex::new(HashMap::new())),
-            helper_state_store: Arc::new(Mutex::new(HashMap::new())),
-            agg_store: Arc::new(Mutex::new(HashMap::new())),
-            collector_hpke_config: collector_hpke_receiver_config.config,
-            taskprov_vdaf_verify_key_init,
-        };
+        let tasks = [
+            (
+                fixed_size_task_id.clone(),
+                DapTaskConfig {
+                    query: DapQueryConfig::FixedSize { max_batch_size: 2 },
+                    vdaf_verify_key: fixture.task_config.vdaf.gen_verify_key(),
+                    ..fixture.task_config.clone()
+                },
+            ),
+            (
+                expired_task_id.clone(),
+                DapTaskConfig {
+                    expiration: fixture.now, // Expires this second
+                    vdaf_verify_key: fixture.task_config.vdaf.gen_verify_key(),
+                    ..fixture.task_config.clone()
+                },
+            ),
+        ];
+        for aggregator in [&fixture.leader, &fixture.helper] {
+            aggregator
+                .tasks
+                .lock()
+                .expect("tasks: failed to lock")
+                .extend(tasks.clone());
+        }
 
         Self {
-            now,
-            leader,
-            helper,
-            collector_token,
+            now: fixture.now,
+            leader: fixture.leader,
+            helper: fixture.helper,
+            collector_token: fixture.collector_token,
+            collector_hpke_receiver_config: fixture.collector_hpke_receiver_config,
             time_interval_task_id,
             fixed_size_task_id,
             expired_task_id,
@@ -206,6 +130,8 @@ impl Test {
             payload: report.get_encoded(),
             url: task_config.leader_url.join("upload").unwrap(),
             sender_auth: None,
+            idempotency_key: None,
+            collector_hpke_config_id: None,
         }
     }
 
@@ -317,9 +243,6 @@ impl Test {
         report
     }
 
-    // TODO Rework the test framework to call DapLeader::run_agg_job() directly. The method here is
-    // basically a re-implementration that allows us to avoid having to mock the HTTP connection.
-    // The (major) downside is that we have to keep the code in-sync.
     async fn run_agg_job(&self, task_id: &Id) -> Result<(), DapAbort> {
         let wrapped = self
             .leader
@@ -328,76 +251,19 @@ impl Test {
             .unwrap();
         let task_config = wrapped.as_ref().unwrap();
 
-        // Leader: Store received report to ReportStore.
-        let report_sel = MockAggregatorReportSelector(task_id.clone());
+        // Leader: Fetch a report from ReportStore.
+        let report_sel = MockAggregatorReportSelector::from(task_id.clone());
         let (task_id, part_batch_sel, reports) = get_reports!(self.leader, &report_sel);
 
-        // Leader: Consume report share.
-        let mut rng = thread_rng();
-        let agg_job_id = Id(rng.gen());
-        let transition = task_config
-            .vdaf
-            .produce_agg_init_req(
-                &self.leader,
-                &task_config.vdaf_verify_key,
-                &task_id,
-                &agg_job_id,
-                &part_batch_sel,
-                reports,
-                task_config.version,
-            )
-            .await?;
-        assert_matches!(transition, DapLeaderTransition::Continue(..));
-        let (leader_state, agg_init_req) = transition.unwrap_continue();
-
-        // Leader: Send aggregate initialization request to Helper and receive response.
-        let version = task_config.version.clone();
-        let req = self
-            .leader_authorized_req_with_version(
-                &task_id,
-                version,
-                MEDIA_TYPE_AGG_INIT_REQ,
-                agg_init_req,
-                task_config.helper_url.join("aggregate").unwrap(),
-            )
-            .await;
-        let res = self.helper.http_post_aggregate(&req).await?;
-        let agg_resp = AggregateResp::get_decoded(&res.payload).unwrap();
-
-        // Leader: Produce Leader output share and prepare aggregate continue request for Helper.
-        let transition =
-            task_config
-                .vdaf
-                .handle_agg_resp(&task_id, &agg_job_id, leader_state, agg_resp)?;
-        assert_matches!(transition, DapLeaderTransition::Uncommitted(..));
-        let (leader_uncommitted, agg_cont_req) = transition.unwrap_uncommitted();
-
-        // Leader: Send aggregate continue request to Helper and receive response.
-        let version = task_config.version.clone();
-        let req = self
-            .leader_authorized_req(
-                &task_id,
-                version,
-                MEDIA_TYPE_AGG_CONT_REQ,
-                agg_cont_req,
-                task_config.helper_url.join("aggregate").unwrap(),
-            )
-            .await;
-        let res = self.helper.http_post_aggregate(&req).await?;
-        let agg_resp = AggregateResp::get_decoded(&res.payload)?;
-
-        // Leader: Commit output shares of Leader and Helper.
-        let out_shares = task_config
-            .vdaf
-            .handle_final_agg_resp(leader_uncommitted, agg_resp)?;
+        // Leader: Run the aggregation job. Requests are routed to the Helper.
         self.leader
-            .put_out_shares(&task_id, &part_batch_sel, out_shares)
+            .run_agg_job(&task_id, task_config, &part_batch_sel, reports)
             .await?;
 
         Ok(())
     }
 
-    async fn run_col_job(&self, task_id: &Id, query: &Query) -> Result<(), DapAbort> {
+    async fn run_col_job(&self, task_id: &Id, query: &Query) -> Result<CollectResp, DapAbort> {
         let wrapped = self
             .leader
             .get_task_config_for(Cow::Owned(task_id.clone()))
@@ -415,6 +281,7 @@ impl Test {
                     task_id: task_id.clone(),
                     query: query.clone(),
                     agg_param: Vec::default(),
+                    collector_hpke_config_id: None,
                 },
                 task_config.helper_url.join("collect").unwrap(),
             )
@@ -425,60 +292,14 @@ impl Test {
         let resp = self.leader.get_pending_collect_jobs().await?;
         let (collect_id, collect_req) = &resp[0];
 
-        // Leader: Handle collect job. First, fetch the aggregate share.
-        let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-        let leader_agg_share = self
-            .leader
-            .get_agg_share(&collect_req.task_id, &batch_selector)
-            .await?;
-        let leader_enc_agg_share = task_config.vdaf.produce_leader_encrypted_agg_share(
-            &task_config.collector_hpke_config,
-            &collect_req.task_id,
-            &batch_selector,
-            &leader_agg_share,
-            task_config.version,
-        )?;
-
-        // Leader->Helper: HTTP POST /aggregate_share
-        let agg_share_req = AggregateShareReq {
-            task_id: collect_req.task_id.clone(),
-            batch_sel: batch_selector.clone(),
-            agg_param: collect_req.agg_param.clone(),
-            report_count: leader_agg_share.report_count,
-            checksum: leader_agg_share.checksum,
-        };
-        let req = self
-            .leader_authorized_req_with_version(
-                &task_id,
-                task_config.version,
-                MEDIA_TYPE_AGG_SHARE_REQ,
-                agg_share_req.clone(),
-                task_config.helper_url.join("aggregate_share").unwrap(),
-            )
-            .await;
-
-        // Helper: Handle request.
-        let res = self.helper.http_post_aggregate_share(&req).await?;
-        let agg_share_resp = AggregateShareResp::get_decoded(&res.payload).unwrap();
-
-        // Leader: Complete the collect job.
-        let collect_resp = CollectResp {
-            part_batch_sel: batch_selector.clone().into(),
-            report_count: leader_agg_share.report_count,
-            encrypted_agg_shares: vec![leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
-        };
-        self.leader
-            .finish_collect_job(task_id, collect_id, &collect_resp)
-            .await?;
+        // Leader: Run the collect job. The AggregateShareReq is routed to the Helper.
         self.leader
-            .mark_collected(task_id, &agg_share_req.batch_sel)
+            .run_collect_job(collect_id, task_config, collect_req)
             .await?;
 
         // Collector: Poll the collect job.
-        let collect_job = self.leader.poll_collect_job(&task_id, &collect_id).await?;
-        assert_matches!(collect_job, DapCollectJob::Done(..));
-
-        Ok(())
+        let collect_job = self.leader.poll_collect_job(task_id, collect_id).await?;
+        assert_matches!(collect_job, DapCollectJob::Done { collect_resp, .. } => Ok(collect_resp))
     }
 
     async fn leader_authorized_req<M: Encode>(
@@ -503,6 +324,8 @@ impl Test {
             payload,
             url,
             sender_auth,
+            idempotency_key: None,
+            collector_hpke_config_id: None,
         }
     }
 
@@ -528,9 +351,32 @@ impl Test {
             payload,
             url,
             sender_auth,
+            idempotency_key: None,
+            collector_hpke_config_id: None,
         }
     }
 
+    async fn get_fixed_size_batches(
+        &self,
+        task_id: &Id,
+        sender_auth: BearerToken,
+    ) -> Result<Vec<Id>, DapAbort> {
+        let task_config = self.leader.unchecked_get_task_config(task_id).await;
+        let req = DapRequest {
+            version: task_config.version,
+            media_type: Some(MEDIA_TYPE_FIXED_SIZE_BATCHES),
+            task_id: Some(task_id.clone()),
+            payload: Vec::default(),
+            url: task_config.leader_url.join("fixed_size_batches").unwrap(),
+            sender_auth: Some(sender_auth),
+            idempotency_key: None,
+            collector_hpke_config_id: None,
+        };
+        let resp = self.leader.http_get_fixed_size_batches(&req).await?;
+        assert_eq!(resp.media_type, Some(MEDIA_TYPE_FIXED_SIZE_BATCHES));
+        Ok(serde_json::from_slice(&resp.payload).unwrap())
+    }
+
     async fn collector_authorized_req<M: ParameterizedEncode<DapVersion>>(
         &self,
         version: DapVersion,
@@ -546,10 +392,31 @@ impl Test {
             payload: msg.get_encoded_with_param(&version),
             url,
             sender_auth: Some(self.collector_token.clone()),
+            idempotency_key: None,
+            collector_hpke_config_id: None,
         }
     }
 }
 
+// Test that the media type for each kind of message is recognized for the given version.
+fn media_type_for_version(version: DapVersion) {
+    for kind in MediaTypeKind::ALL {
+        let media_type = media_type_for(kind, version);
+        assert_eq!(parse_media_type(media_type), Some(media_type));
+    }
+}
+
+test_versions! { media_type_for_version }
+
+#[test]
+fn media_type_for_agg_init_req() {
+    // Draft02 and Draft03 use the same media types. (They were renamed in later drafts.)
+    assert_eq!(
+        media_type_for(MediaTypeKind::AggInitReq, DapVersion::Draft02),
+        media_type_for(MediaTypeKind::AggInitReq, DapVersion::Draft03)
+    );
+}
+
 // Test that the Helper properly handles the batch parameter in the AggregateInitializeReq.
 async fn http_post_aggregate_invalid_batch_sel(version: DapVersion) {
     let mut rng = thread_rng();
@@ -577,7 +444,10 @@ async fn http_post_aggregate_invalid_batch_sel(version: DapVersion) {
         .await;
     assert_matches!(
         t.helper.http_post_aggregate(&req).await.unwrap_err(),
-        DapAbort::QueryMismatch
+        DapAbort::QueryMismatch {
+            expected: QueryKind::TimeInterval,
+            actual: QueryKind::FixedSize,
+        }
     );
 }
 
@@ -631,6 +501,28 @@ async fn http_post_aggregate_init_expired_task(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_init_expired_task }
 
+// Test that the Helper registers an aggregation job initialized without any report shares.
+async fn http_post_aggregate_init_empty(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let req = t.gen_test_agg_init_req(task_id, Vec::default()).await;
+    let agg_job_id = AggregateInitializeReq::get_decoded_with_param(&req.version, &req.payload)
+        .unwrap()
+        .agg_job_id;
+
+    let resp = t.helper.http_post_aggregate(&req).await.unwrap();
+    let agg_resp = AggregateResp::get_decoded(&resp.payload).unwrap();
+    assert!(agg_resp.transitions.is_empty());
+
+    // The aggregation job can be continued.
+    let req = t.gen_test_agg_cont_req(agg_job_id, Vec::default()).await;
+    let resp = t.helper.http_post_aggregate(&req).await.unwrap();
+    let agg_resp = AggregateResp::get_decoded(&resp.payload).unwrap();
+    assert!(agg_resp.transitions.is_empty());
+}
+
+async_test_versions! { http_post_aggregate_init_empty }
+
 async fn http_get_hpke_config_unrecognized_task(version: DapVersion) {
     let t = Test::new(version);
     let mut rng = thread_rng();
@@ -646,6 +538,8 @@ async fn http_get_hpke_config_unrecognized_task(version: DapVersion) {
         ))
         .unwrap(),
         sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
     };
 
     assert_matches!(
@@ -665,19 +559,143 @@ async fn http_get_hpke_config_missing_task_id(version: DapVersion) {
         payload: Vec::new(),
         url: Url::parse("http://aggregator.biz/v02/hpke_config").unwrap(),
         sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
     };
 
-    // An Aggregator is permitted to abort an HPKE config request if the task ID is missing. Note
-    // that Daphne-Workder does not implement this behavior. Instead it returns the HPKE config
-    // used for all tasks.
+    // An Aggregator is permitted to abort an HPKE config request if the task ID is missing.
     assert_matches!(
         t.leader.http_get_hpke_config(&req).await,
         Err(DapAbort::MissingTaskId)
     );
+
+    // Alternatively, it may return the HPKE config used for all tasks. This is what
+    // Daphne-Worker does.
+    let leader = MockAggregator {
+        global_config: DapGlobalConfig {
+            hpke_config_missing_task_id_policy: HpkeConfigMissingTaskIdPolicy::ReturnDefault,
+            ..t.leader.global_config.clone()
+        },
+        ..t.leader.clone()
+    };
+    let resp = leader.http_get_hpke_config(&req).await.unwrap();
+    assert_eq!(
+        resp.payload,
+        leader
+            .get_hpke_config_for(None)
+            .await
+            .unwrap()
+            .get_encoded()
+    );
 }
 
 async_test_versions! { http_get_hpke_config_missing_task_id }
 
+// A task provider that serves the configuration of a single task only once and counts the number
+// of requests it receives.
+struct OneShotTaskProvider {
+    task_id: Id,
+    task_config: RefCell<Option<DapTaskConfig>>,
+    requests: Cell<usize>,
+}
+
+#[async_trait(?Send)]
+impl DapTaskProvider for OneShotTaskProvider {
+    async fn get_task(&self, task_id: &Id) -> Result<Option<DapTaskConfig>, DapError> {
+        self.requests.set(self.requests.get() + 1);
+        if *task_id != self.task_id {
+            return Ok(None);
+        }
+        Ok(self.task_config.borrow_mut().take())
+    }
+}
+
+// Test that an Aggregator loads a task it doesn't know from its task provider and caches it.
+async fn get_task_config_from_task_provider(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t
+        .helper
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .remove(task_id)
+        .unwrap();
+    let task_provider = Arc::new(OneShotTaskProvider {
+        task_id: task_id.clone(),
+        task_config: RefCell::new(Some(task_config.clone())),
+        requests: Cell::new(0),
+    });
+    let helper = MockAggregator {
+        task_provider: Some(task_provider.clone()),
+        ..t.helper.clone()
+    };
+
+    // Expect the task to be fetched from the task provider on the first request.
+    assert_eq!(
+        helper
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await
+            .unwrap()
+            .unwrap()
+            .config_hash(),
+        task_config.config_hash()
+    );
+    assert_eq!(task_provider.requests.get(), 1);
+
+    // Expect the task to be served from the cache subsequently, even though the task provider
+    // would no longer return it.
+    assert_eq!(
+        helper
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await
+            .unwrap()
+            .unwrap()
+            .config_hash(),
+        task_config.config_hash()
+    );
+    assert_eq!(task_provider.requests.get(), 1);
+
+    // Expect the task provider to be consulted for an unrecognized task.
+    assert!(helper
+        .get_task_config_for(Cow::Owned(Id([0; 32])))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(task_provider.requests.get(), 2);
+}
+
+async_test_versions! { get_task_config_from_task_provider }
+
+async fn http_get_aggregator_info(version: DapVersion) {
+    let t = Test::new(version);
+
+    for allow_taskprov in [true, false] {
+        let leader = MockAggregator {
+            global_config: DapGlobalConfig {
+                allow_taskprov,
+                ..t.leader.global_config.clone()
+            },
+            ..t.leader.clone()
+        };
+        let resp = leader.http_get_aggregator_info().await.unwrap();
+        assert_eq!(resp.media_type, Some(MEDIA_TYPE_AGGREGATOR_INFO));
+
+        let info: DapAggregatorInfo = serde_json::from_slice(&resp.payload).unwrap();
+        assert!(info
+            .supported_hpke_kems
+            .contains(&HpkeKemId::X25519HkdfSha256));
+        assert!(info.supported_versions.contains(&version));
+        for supported in info.supported_versions {
+            // Every advertised version is accepted by the version checks.
+            assert_eq!(DapVersion::from(supported.as_ref()), supported);
+        }
+        assert_eq!(info.allow_taskprov, allow_taskprov);
+    }
+}
+
+async_test_versions! { http_get_aggregator_info }
+
 async fn http_post_aggregate_cont_unauthorized_request(version: DapVersion) {
     let t = Test::new(version);
     let mut rng = thread_rng();
@@ -750,7 +768,10 @@ async fn http_post_aggregate_share_invalid_batch_sel(version: DapVersion) {
         .await;
     assert_matches!(
         t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
-        DapAbort::QueryMismatch
+        DapAbort::QueryMismatch {
+            expected: QueryKind::TimeInterval,
+            actual: QueryKind::FixedSize,
+        }
     );
 
     // Leader sends aggregate share request for unrecognized batch ID.
@@ -783,6 +804,97 @@ async fn http_post_aggregate_share_invalid_batch_sel(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_share_invalid_batch_sel }
 
+// Test that the Helper rejects an aggregate share request for a time-interval batch that is not
+// aligned to the task's time precision.
+async fn http_post_aggregate_share_fail_misaligned_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.helper.unchecked_get_task_config(task_id).await;
+    let window_start = task_config.truncate_time(t.now);
+
+    for batch_interval in [
+        // Start not aligned.
+        Interval {
+            start: window_start + 1,
+            duration: task_config.time_precision,
+        },
+        // Duration not aligned.
+        Interval {
+            start: window_start,
+            duration: task_config.time_precision + 1,
+        },
+    ] {
+        let req = t
+            .leader_authorized_req_with_version(
+                task_id,
+                task_config.version,
+                MEDIA_TYPE_AGG_SHARE_REQ,
+                AggregateShareReq {
+                    task_id: task_id.clone(),
+                    batch_sel: BatchSelector::TimeInterval { batch_interval },
+                    agg_param: Vec::default(),
+                    report_count: 0,
+                    checksum: [0; 32],
+                },
+                task_config.helper_url.join("aggregate_share").unwrap(),
+            )
+            .await;
+        assert_matches!(
+            t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+            DapAbort::BatchInvalid
+        );
+    }
+}
+
+async_test_versions! { http_post_aggregate_share_fail_misaligned_batch_interval }
+
+// Test that the Helper refuses to aggregate a batch that spans too many buckets.
+async fn http_post_aggregate_share_fail_too_large(version: DapVersion) {
+    let mut t = Test::new(version);
+    t.helper.global_config.max_agg_share_buckets = 2;
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let window = task_config.truncate_time(t.now);
+
+    let agg_share_req_for = |num_buckets| {
+        t.leader_authorized_req_with_version(
+            task_id,
+            version,
+            MEDIA_TYPE_AGG_SHARE_REQ,
+            AggregateShareReq {
+                task_id: task_id.clone(),
+                batch_sel: BatchSelector::TimeInterval {
+                    batch_interval: Interval {
+                        start: window,
+                        duration: num_buckets * task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+                report_count: 0,
+                checksum: [0; 32],
+            },
+            task_config.helper_url.join("aggregate_share").unwrap(),
+        )
+    };
+
+    // Leader requests an aggregate share for three buckets, but the limit is two.
+    let req = agg_share_req_for(3).await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        DapAbort::AggregateShareTooLarge
+    );
+
+    // Leader requests an aggregate share for two buckets. The request passes the check but is
+    // rejected later because the batch is empty.
+    let req = agg_share_req_for(2).await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        DapAbort::InvalidBatchSize
+    );
+}
+
+async_test_versions! { http_post_aggregate_share_fail_too_large }
+
 async fn http_post_collect_unauthorized_request(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
@@ -795,10 +907,13 @@ async fn http_post_collect_unauthorized_request(version: DapVersion) {
             task_id: task_id.clone(),
             query: Query::default(),
             agg_param: Vec::default(),
+            collector_hpke_config_id: None,
         }
         .get_encoded_with_param(&task_config.version),
         url: task_config.leader_url.join("collect").unwrap(),
         sender_auth: None, // Unauthorized request.
+        idempotency_key: None,
+        collector_hpke_config_id: None,
     };
 
     // Expect failure due to missing bearer token.
@@ -817,670 +932,4250 @@ async fn http_post_collect_unauthorized_request(version: DapVersion) {
 
 async_test_versions! { http_post_collect_unauthorized_request }
 
-async fn http_post_aggregate_failure_hpke_decrypt_error(version: DapVersion) {
+async fn http_post_collect_fail_collector_token_for_other_task(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
+    let task_id_a = &t.time_interval_task_id;
+    let task_id_b = &t.expired_task_id;
+    let token_a = BearerToken::from("collector token for task A");
+    let token_b = BearerToken::from("collector token for task B");
+
+    // Leader: Scope a different Collector to each task.
+    let leader = MockAggregator {
+        task_collector_tokens: HashMap::from([
+            (task_id_a.clone(), token_a.clone()),
+            (task_id_b.clone(), token_b.clone()),
+        ]),
+        ..t.leader.clone()
+    };
 
-    let report = t.gen_test_report(task_id).await;
-    let (metadata, public_share, mut encrypted_input_share) = (
-        report.metadata,
-        report.public_share,
-        report.encrypted_input_shares[1].clone(),
-    );
-    encrypted_input_share.payload[0] ^= 0xff; // Cause decryption to fail
-    let report_shares = vec![ReportShare {
-        metadata,
-        public_share,
-        encrypted_input_share,
-    }];
-    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    let task_config = leader.unchecked_get_task_config(task_id_a).await;
+    let collect_req = |task_id: &Id, sender_auth: &BearerToken| DapRequest {
+        version: task_config.version,
+        media_type: Some(MEDIA_TYPE_COLLECT_REQ),
+        task_id: Some(task_id.clone()),
+        payload: CollectReq {
+            task_id: task_id.clone(),
+            query: task_config.query_for_current_batch_window(t.now),
+            agg_param: Vec::default(),
+            collector_hpke_config_id: None,
+        }
+        .get_encoded_with_param(&task_config.version),
+        url: task_config.leader_url.join("collect").unwrap(),
+        sender_auth: Some(sender_auth.clone()),
+        idempotency_key: None,
+        collector_hpke_config_id: None,
+    };
 
-    // Get AggregateResp and then extract the transition data from inside.
-    let agg_resp =
-        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
-            .unwrap();
-    let transition = &agg_resp.transitions[0];
+    // Expect failure due to using a token that is only valid for the other task.
+    assert_matches!(
+        leader
+            .http_post_collect(&collect_req(task_id_b, &token_a))
+            .await,
+        Err(DapAbort::UnauthorizedRequest)
+    );
+    assert_matches!(
+        leader
+            .http_post_collect(&collect_req(task_id_a, &token_b))
+            .await,
+        Err(DapAbort::UnauthorizedRequest)
+    );
 
-    // Expect failure due to invalid ciphertext.
+    // Expect failure due to using the default token for a task with a scoped Collector.
     assert_matches!(
-        transition.var,
-        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+        leader
+            .http_post_collect(&collect_req(task_id_a, &t.collector_token))
+            .await,
+        Err(DapAbort::UnauthorizedRequest)
     );
+
+    // Expect success when the token is valid for the task.
+    leader
+        .http_post_collect(&collect_req(task_id_a, &token_a))
+        .await
+        .unwrap();
 }
 
-async_test_versions! { http_post_aggregate_failure_hpke_decrypt_error }
+async_test_versions! { http_post_collect_fail_collector_token_for_other_task }
 
-async fn http_post_aggregate_transition_continue(version: DapVersion) {
+// Test that the Leader rejects a collect request whose task ID does not match the request's.
+async fn http_post_collect_fail_task_id_mismatch(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        metadata: report.metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
-    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
-
-    // Get AggregateResp and then extract the transition data from inside.
-    let agg_resp =
-        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
-            .unwrap();
-    let transition = &agg_resp.transitions[0];
-
-    // Expect success due to valid ciphertext.
-    assert_matches!(transition.var, TransitionVar::Continued(_));
-}
+    let mut req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    req.task_id = Some(t.fixed_size_task_id.clone());
 
-async_test_versions! { http_post_aggregate_transition_continue }
+    assert_matches!(
+        t.leader.http_post_collect(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
 
-async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
+async_test_versions! { http_post_collect_fail_task_id_mismatch }
+
+// Test that the Helper rejects aggregate requests whose task ID does not match the request's.
+async fn http_post_aggregate_fail_task_id_mismatch(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
 
-    let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        metadata: report.metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
-    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    let mut req = t.gen_test_agg_init_req(task_id, Vec::default()).await;
+    req.task_id = Some(t.fixed_size_task_id.clone());
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
 
-    // Add dummy data to report store backend. This is done in a new scope so that the lock on the
-    // report store is released before running the test.
-    {
-        let mut guard = t
-            .helper
-            .report_store
+    let mut req = t
+        .gen_test_agg_cont_req(Id(thread_rng().gen()), Vec::default())
+        .await;
+    req.task_id = Some(t.fixed_size_task_id.clone());
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_versions! { http_post_aggregate_fail_task_id_mismatch }
+
+// Test that the Helper rejects an aggregation job whose aggregation parameter is not valid for the
+// VDAF. Prio3 does not take an aggregation parameter.
+async fn http_post_aggregate_fail_agg_param(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let req = t
+        .leader_authorized_req_with_version(
+            task_id,
+            version,
+            MEDIA_TYPE_AGG_INIT_REQ,
+            AggregateInitializeReq {
+                task_id: task_id.clone(),
+                agg_job_id: Id(thread_rng().gen()),
+                agg_param: b"bad agg param".to_vec(),
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                report_shares: Vec::default(),
+            },
+            task_config.helper_url.join("aggregate").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_versions! { http_post_aggregate_fail_agg_param }
+
+// Test that the Aggregators reject reports carrying extensions that are not permitted for the task.
+async fn http_post_fail_disallowed_extension(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
             .lock()
-            .expect("report_store: failed to lock");
-        let report_store = guard.entry(task_id.clone()).or_default();
-        report_store.processed.insert(report.metadata.id.clone());
+            .expect("tasks: failed to lock")
+            .get_mut(task_id)
+            .unwrap()
+            .allowed_extension_types = Some(vec![EXTENSION_TASKPROV]);
     }
 
-    // Get AggregateResp and then extract the transition data from inside.
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    let bad_report = VdafConfig::Prio3(Prio3Config::Count)
+        .produce_report_with_extensions(
+            &hpke_config_list,
+            t.now,
+            task_id,
+            DapMeasurement::U64(1),
+            vec![Extension::Unhandled {
+                typ: 0xffff,
+                payload: b"fingerprint".to_vec(),
+            }],
+            version,
+        )
+        .unwrap();
+    let good_report = t.gen_test_report(task_id).await;
+
+    // Client: Expect the Leader to reject the report with the disallowed extension.
+    let req = t.gen_test_upload_req(bad_report.clone()).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+    let req = t.gen_test_upload_req(good_report.clone()).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Expect the Helper to reject the report with the disallowed extension.
+    let report_shares = [bad_report, good_report]
+        .into_iter()
+        .map(|report| ReportShare {
+            metadata: report.metadata,
+            public_share: report.public_share,
+            encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        })
+        .collect();
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
     let agg_resp =
         AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
             .unwrap();
-    let transition = &agg_resp.transitions[0];
+    assert_eq!(agg_resp.transitions.len(), 2);
+    assert_matches!(
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::ReportDropped)
+    );
+    assert_matches!(agg_resp.transitions[1].var, TransitionVar::Continued(_));
+}
 
-    // Expect failure due to report store marked as collected.
+async_test_versions! { http_post_fail_disallowed_extension }
+
+// Test that the Helper rejects an aggregate share request whose task ID does not match the
+// request's.
+async fn http_post_aggregate_share_fail_task_id_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+
+    let mut req = t.gen_test_agg_share_req(0, [0; 32]).await;
+    req.task_id = Some(t.fixed_size_task_id.clone());
     assert_matches!(
-        transition.var,
-        TransitionVar::Failed(TransitionFailure::ReportReplayed)
+        t.helper.http_post_aggregate_share(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
     );
 }
 
-async_test_versions! { http_post_aggregate_failure_report_replayed }
+async_test_versions! { http_post_aggregate_share_fail_task_id_mismatch }
 
-async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
+async fn http_post_aggregate_failure_invalid_public_share(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.helper.unchecked_get_task_config(task_id).await;
 
+    // Give the report a public share that is inconsistent with its input shares.
     let report = t.gen_test_report(task_id).await;
     let report_shares = vec![ReportShare {
-        metadata: report.metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        metadata: report.metadata,
+        public_share: b"some other public share".to_vec(),
         encrypted_input_share: report.encrypted_input_shares[1].clone(),
     }];
     let req = t.gen_test_agg_init_req(task_id, report_shares).await;
 
-    // Add mock data to the aggreagte store backend. This is done in its own scope so that the lock
-    // is released before running the test. Otherwise the test will deadlock.
-    {
-        let mut guard = t
-            .helper
-            .agg_store
-            .lock()
-            .expect("agg_store: failed to lock");
-        let agg_store = guard.entry(task_id.clone()).or_default();
-
-        agg_store.insert(
-            DapBatchBucketOwned::TimeInterval {
-                batch_window: task_config.truncate_time(t.now),
-            },
-            AggStore {
-                agg_share: DapAggregateShare::default(),
-                collected: true,
-            },
-        );
-    }
-
-    // Get AggregateResp and then extract the transition data from inside.
     let agg_resp =
         AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
             .unwrap();
-    let transition = &agg_resp.transitions[0];
-
-    // Expect failure due to report store marked as collected.
+    assert_eq!(agg_resp.transitions.len(), 1);
     assert_matches!(
-        transition.var,
-        TransitionVar::Failed(TransitionFailure::BatchCollected)
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::VdafPrepError)
     );
 }
 
-async_test_versions! { http_post_aggregate_failure_batch_collected }
+async_test_versions! { http_post_aggregate_failure_invalid_public_share }
 
-async fn http_post_aggregate_abort_helper_state_overwritten(version: DapVersion) {
+async fn http_post_aggregate_failure_hpke_decrypt_error(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
 
     let report = t.gen_test_report(task_id).await;
+    let (metadata, public_share, mut encrypted_input_share) = (
+        report.metadata,
+        report.public_share,
+        report.encrypted_input_shares[1].clone(),
+    );
+    encrypted_input_share.payload[0] ^= 0xff; // Cause decryption to fail
     let report_shares = vec![ReportShare {
-        metadata: report.metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        metadata,
+        public_share,
+        encrypted_input_share,
     }];
     let req = t.gen_test_agg_init_req(task_id, report_shares).await;
 
-    // Send aggregate request.
-    let _ = t.helper.http_post_aggregate(&req).await;
-
-    // Send another aggregate request.
-    let err = t.helper.http_post_aggregate(&req).await.unwrap_err();
+    // Get AggregateResp and then extract the transition data from inside.
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    let transition = &agg_resp.transitions[0];
 
-    // Expect failure due to overwriting existing helper state.
-    assert_matches!(err, DapAbort::BadRequest(e) =>
-        assert_eq!(e, "unexpected message for aggregation job (already exists)")
+    // Expect failure due to invalid ciphertext.
+    assert_matches!(
+        transition.var,
+        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
     );
 }
 
-async_test_versions! { http_post_aggregate_abort_helper_state_overwritten }
+async_test_versions! { http_post_aggregate_failure_hpke_decrypt_error }
 
-async fn http_post_aggregate_fail_send_cont_req(version: DapVersion) {
+// Test that a report whose Helper input share fails to decrypt is rejected by both Aggregators,
+// even though the Leader's input share decrypts.
+async fn e2e_helper_hpke_decrypt_error(version: DapVersion) {
     let t = Test::new(version);
-    let mut rng = thread_rng();
-    let req = t.gen_test_agg_cont_req(Id(rng.gen()), Vec::default()).await;
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Send aggregate continue request to helper.
-    let err = t.helper.http_post_aggregate(&req).await.unwrap_err();
+    let mut report = t.gen_test_report(task_id).await;
+    report.encrypted_input_shares[1].payload[0] ^= 0xff; // Cause decryption to fail
 
-    // Expect failure due to sending continue request before initialization request.
-    assert_matches!(err, DapAbort::UnrecognizedAggregationJob);
+    // Leader: The Leader's input share decrypts.
+    task_config
+        .vdaf
+        .consume_report_share(
+            &t.leader,
+            true, // is_leader
+            &task_config.vdaf_verify_key,
+            task_id,
+            &report.metadata,
+            &report.public_share,
+            &report.encrypted_input_shares[0],
+            version,
+        )
+        .await
+        .unwrap();
+
+    // Helper: Expect failure due to invalid ciphertext.
+    let req = t
+        .gen_test_agg_init_req(
+            task_id,
+            vec![ReportShare {
+                metadata: report.metadata.clone(),
+                public_share: report.public_share.clone(),
+                encrypted_input_share: report.encrypted_input_shares[1].clone(),
+            }],
+        )
+        .await;
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+    );
+
+    // Client: Send upload request to Leader.
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Run the aggregation job. The Leader drops the report after the Helper rejects it.
+    let agg_job_report = t.leader.process_task(task_id).await.unwrap();
+    assert_eq!(agg_job_report.reports_succeeded, 0);
+    assert_eq!(agg_job_report.reports_failed, 1);
+
+    // Leader and Helper: Neither Aggregator commits an output share for the report.
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let report_count: u64 = guard
+            .get(task_id)
+            .map(|agg_store| {
+                agg_store
+                    .values()
+                    .map(|agg_store| agg_store.agg_share.report_count)
+                    .sum()
+            })
+            .unwrap_or_default();
+        assert_eq!(report_count, 0);
+    }
 }
 
-async_test_versions! { http_post_aggregate_fail_send_cont_req }
+async_test_versions! { e2e_helper_hpke_decrypt_error }
 
-async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
+// Test that an Aggregator probes a report by decrypting its own input share, without storing or
+// aggregating the report.
+async fn probe_report(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Construct a report payload with an invalid task ID.
-    let mut report_invalid_task_id = t.gen_test_report(task_id).await;
-    report_invalid_task_id.task_id = Id([0; 32]);
-    let req = DapRequest {
-        version: task_config.version,
-        media_type: Some(MEDIA_TYPE_REPORT),
-        task_id: Some(report_invalid_task_id.task_id.clone()),
-        payload: report_invalid_task_id.get_encoded(),
-        url: task_config.leader_url.join("upload").unwrap(),
-        sender_auth: None,
-    };
+    // Expect a well-formed report to pass the probe of either Aggregator.
+    let report = t.gen_test_report(task_id).await;
+    t.leader.probe_report(&report, true).await.unwrap();
+    t.helper.probe_report(&report, false).await.unwrap();
+    assert!(t
+        .leader
+        .report_store
+        .lock()
+        .expect("report_store: failed to lock")
+        .get(task_id)
+        .is_none());
+
+    // Expect a report whose Leader input share is corrupted to fail the Leader's probe only.
+    let mut corrupted_report = report.clone();
+    corrupted_report.encrypted_input_shares[0].payload[0] ^= 0xff; // Cause decryption to fail
+    assert_matches!(
+        t.leader.probe_report(&corrupted_report, true).await,
+        Err(DapError::Transition(TransitionFailure::HpkeDecryptError))
+    );
+    t.helper
+        .probe_report(&corrupted_report, false)
+        .await
+        .unwrap();
 
-    // Expect failure due to invalid task ID in report.
+    // Expect a report for an unrecognized task to be rejected as such.
+    let mut unrecognized_report = report.clone();
+    unrecognized_report.task_id = Id(thread_rng().gen());
     assert_matches!(
-        t.leader.http_post_upload(&req).await,
-        Err(DapAbort::UnrecognizedTask)
+        t.leader.probe_report(&unrecognized_report, true).await,
+        Err(DapError::Abort(DapAbort::UnrecognizedTask))
     );
 
-    // Construct an invalid report payload that only has one input share.
-    let mut report_one_input_share = t.gen_test_report(task_id).await;
-    report_one_input_share.encrypted_input_shares =
-        vec![report_one_input_share.encrypted_input_shares[0].clone()];
-    let req = t.gen_test_upload_req(report_one_input_share).await;
+    // Expect a report that is past the task's expiration to be rejected.
+    let mut expired_report = report.clone();
+    expired_report.metadata.time = t.leader.unchecked_get_task_config(task_id).await.expiration;
+    assert_matches!(
+        t.leader.probe_report(&expired_report, true).await,
+        Err(DapError::Transition(TransitionFailure::TaskExpired))
+    );
+
+    // Expect a report carrying an unrecognized extension to be dropped.
+    let mut extended_report = report.clone();
+    extended_report.metadata.extensions = vec![Extension::Unhandled {
+        typ: 0xffff,
+        payload: b"fingerprint".to_vec(),
+    }];
+    assert_matches!(
+        t.leader.probe_report(&extended_report, true).await,
+        Err(DapError::Transition(TransitionFailure::ReportDropped))
+    );
+
+    // Expect a report that is not sealed to the latest HPKE config to be rejected if the task
+    // requires it.
+    let old_config_id = t.leader.hpke_receiver_config_list[0].config.id;
+    let mut hpke_receiver_config_list =
+        vec![
+            HpkeReceiverConfig::gen(old_config_id.wrapping_add(1), HpkeKemId::X25519HkdfSha256)
+                .unwrap(),
+        ];
+    hpke_receiver_config_list.extend(t.leader.hpke_receiver_config_list.iter().cloned());
+    let leader = MockAggregator {
+        hpke_receiver_config_list,
+        tasks: Arc::new(Mutex::new(
+            t.leader
+                .tasks
+                .lock()
+                .expect("tasks: failed to lock")
+                .clone(),
+        )),
+        ..t.leader.clone()
+    };
+    leader.probe_report(&report, true).await.unwrap();
+    leader
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .get_mut(task_id)
+        .unwrap()
+        .require_latest_hpke_config = true;
+    assert_matches!(
+        leader.probe_report(&report, true).await,
+        Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
+    );
+
+    // Expect the probed report to be accepted for upload.
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_versions! { probe_report }
+
+async fn http_post_aggregate_failure_empty_ciphertext(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let (metadata, public_share, mut encrypted_input_share) = (
+        report.metadata,
+        report.public_share,
+        report.encrypted_input_shares[1].clone(),
+    );
+    encrypted_input_share.payload.clear();
+    let report_shares = vec![ReportShare {
+        metadata,
+        public_share,
+        encrypted_input_share,
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+    );
+}
+
+async_test_versions! { http_post_aggregate_failure_empty_ciphertext }
+
+async fn http_post_aggregate_transition_continue(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+
+    // Get AggregateResp and then extract the transition data from inside.
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    let transition = &agg_resp.transitions[0];
+
+    // Expect success due to valid ciphertext.
+    assert_matches!(transition.var, TransitionVar::Continued(_));
+}
+
+async_test_versions! { http_post_aggregate_transition_continue }
+
+async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+
+    // Add dummy data to report store backend. This is done in a new scope so that the lock on the
+    // report store is released before running the test.
+    {
+        let mut guard = t
+            .helper
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = guard.entry(task_id.clone()).or_default();
+        report_store
+            .processed
+            .entry(
+                t.helper
+                    .global_config
+                    .report_storage_epoch(report.metadata.time),
+            )
+            .or_default()
+            .insert(report.metadata.id.clone());
+    }
+
+    // Get AggregateResp and then extract the transition data from inside.
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    let transition = &agg_resp.transitions[0];
+
+    // Expect failure due to report store marked as collected.
+    assert_matches!(
+        transition.var,
+        TransitionVar::Failed(TransitionFailure::ReportReplayed)
+    );
+}
+
+async_test_versions! { http_post_aggregate_failure_report_replayed }
+
+// Test that the Helper protects against replay within the report storage window and evicts the
+// replay protection state once the report's epoch falls out of the window.
+async fn http_post_aggregate_report_replay_window(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let epoch_duration = t.helper.global_config.report_storage_epoch_duration;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+
+    // The first attempt succeeds.
+    let req = t
+        .gen_test_agg_init_req(task_id, report_shares.clone())
+        .await;
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(agg_resp.transitions[0].var, TransitionVar::Continued(_));
+
+    // The report is replayed within the window.
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::ReportReplayed)
+    );
+
+    let epoch = t
+        .helper
+        .global_config
+        .report_storage_epoch(report.metadata.time);
+    let is_processed = || {
+        let guard = t
+            .helper
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        guard[task_id]
+            .processed
+            .values()
+            .any(|report_ids| report_ids.contains(&report.metadata.id))
+    };
+
+    // The report's epoch is still the previous epoch, so the report is retained.
+    t.helper.purge(epoch + 2 * epoch_duration - 1);
+    assert!(is_processed());
+
+    // At the start of the next epoch, the report's epoch falls out of the window.
+    t.helper.purge(epoch + 2 * epoch_duration);
+    assert!(!is_processed());
+}
+
+async_test_versions! { http_post_aggregate_report_replay_window }
+
+// Test that the Aggregator refuses to store the output share of a report more than once.
+async fn put_out_shares_fail_double_commit(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.helper.unchecked_get_task_config(task_id).await;
+    let report_id = ReportId(thread_rng().gen());
+    let out_share = || DapOutputShare {
+        time: t.now,
+        checksum: compute_checksum(std::slice::from_ref(&report_id)),
+        data: VdafAggregateShare::Field64(vec![1.into()].into()),
+    };
+
+    t.helper
+        .put_out_shares(
+            task_id,
+            &PartialBatchSelector::TimeInterval,
+            vec![out_share()],
+        )
+        .await
+        .unwrap();
+
+    // Committing the same report again is rejected.
+    assert_matches!(
+        t.helper
+            .put_out_shares(
+                task_id,
+                &PartialBatchSelector::TimeInterval,
+                vec![out_share()]
+            )
+            .await,
+        Err(DapError::Fatal(..))
+    );
+
+    // Expect the aggregate share to reflect only the first commit.
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.truncate_time(t.now),
+            duration: task_config.time_precision,
+        },
+    };
+    let agg_share = t.helper.get_agg_share(task_id, &batch_sel).await.unwrap();
+    assert_eq!(agg_share.report_count, 1);
+}
+
+async_test_versions! { put_out_shares_fail_double_commit }
+
+async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+
+    // Add mock data to the aggreagte store backend. This is done in its own scope so that the lock
+    // is released before running the test. Otherwise the test will deadlock.
+    {
+        let mut guard = t
+            .helper
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let agg_store = guard.entry(task_id.clone()).or_default();
+
+        agg_store.insert(
+            DapBatchBucketOwned::TimeInterval {
+                batch_window: task_config.truncate_time(t.now),
+            },
+            AggStore {
+                agg_share: DapAggregateShare::default(),
+                collected: true,
+            },
+        );
+    }
+
+    // Get AggregateResp and then extract the transition data from inside.
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    let transition = &agg_resp.transitions[0];
+
+    // Expect failure due to report store marked as collected.
+    assert_matches!(
+        transition.var,
+        TransitionVar::Failed(TransitionFailure::BatchCollected)
+    );
+}
+
+async_test_versions! { http_post_aggregate_failure_batch_collected }
+
+async fn http_post_aggregate_abort_helper_state_overwritten(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+
+    // Send aggregate request.
+    let _ = t.helper.http_post_aggregate(&req).await;
+
+    // Send another aggregate request.
+    let err = t.helper.http_post_aggregate(&req).await.unwrap_err();
+
+    // Expect failure due to overwriting existing helper state.
+    assert_matches!(err, DapAbort::BadRequest(e) =>
+        assert_eq!(e, "unexpected message for aggregation job (already exists)")
+    );
+}
+
+async_test_versions! { http_post_aggregate_abort_helper_state_overwritten }
+
+async fn http_post_aggregate_abort_batch_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+
+    // Initialize the aggregation job for batch A.
+    let mut req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    t.helper.http_post_aggregate(&req).await.unwrap();
+
+    // Re-send the initialization request for the same aggregation job, but for batch B.
+    let mut agg_init_req =
+        AggregateInitializeReq::get_decoded_with_param(&version, &req.payload).unwrap();
+    agg_init_req.part_batch_sel = PartialBatchSelector::FixedSizeByBatchId {
+        batch_id: Id(thread_rng().gen()),
+    };
+    req.payload = agg_init_req.get_encoded_with_param(&version);
+
+    // Expect failure due to the aggregation job being moved to a different batch.
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        DapAbort::BatchMismatch
+    );
+}
+
+async_test_versions! { http_post_aggregate_abort_batch_mismatch }
+
+// Test that the Helper answers a retried aggregate initialization request with the same
+// idempotency key using the original response.
+async fn http_post_aggregate_idempotency_key(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata.clone(),
+        public_share: report.public_share,
+        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let mut req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    req.idempotency_key = Some(Id(thread_rng().gen()));
+
+    let resp = t.helper.http_post_aggregate(&req).await.unwrap();
+    let retry_resp = t.helper.http_post_aggregate(&req).await.unwrap();
+    assert_eq!(retry_resp.media_type, resp.media_type);
+    assert_eq!(retry_resp.payload, resp.payload);
+
+    // Expect the aggregation job to have been initialized only once.
+    assert_eq!(
+        t.helper
+            .helper_state_store
+            .lock()
+            .expect("helper_state_store: failed to lock")
+            .len(),
+        1
+    );
+}
+
+async_test_version! { http_post_aggregate_idempotency_key, Draft03 }
+
+// Test that the Helper rejects an aggregate initialization request that carries the idempotency key
+// of a different request.
+async fn http_post_aggregate_idempotency_key_reused(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let idempotency_key = Id(thread_rng().gen());
+
+    let mut req = t.gen_test_agg_init_req(task_id, Vec::default()).await;
+    req.idempotency_key = Some(idempotency_key.clone());
+    t.helper.http_post_aggregate(&req).await.unwrap();
+
+    // Helper: A request for another aggregation job is not a retry.
+    let mut req = t.gen_test_agg_init_req(task_id, Vec::default()).await;
+    req.idempotency_key = Some(idempotency_key);
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        DapAbort::BadRequest(..)
+    );
+}
+
+async_test_version! { http_post_aggregate_idempotency_key_reused, Draft03 }
+
+// Test that the Helper drops the stored response to an aggregate initialization request once its
+// time to live has elapsed.
+async fn expire_jobs_agg_init_resp(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let mut req = t.gen_test_agg_init_req(task_id, Vec::default()).await;
+    req.idempotency_key = Some(Id(thread_rng().gen()));
+    t.helper.http_post_aggregate(&req).await.unwrap();
+    let agg_init_resp_count = || {
+        t.helper
+            .agg_init_resp_store
+            .lock()
+            .expect("agg_init_resp_store: failed to lock")
+            .len()
+    };
+
+    // The response is retained until its time to live has elapsed.
+    let now = t.helper.get_current_time();
+    t.helper.expire_jobs(now).await.unwrap();
+    assert_eq!(agg_init_resp_count(), 1);
+
+    t.helper
+        .expire_jobs(now + t.helper.global_config.job_ttl + 1)
+        .await
+        .unwrap();
+    assert_eq!(agg_init_resp_count(), 0);
+}
+
+async_test_version! { expire_jobs_agg_init_resp, Draft03 }
+
+// Test that the Leader sends an idempotency key with each aggregate initialization request.
+async fn run_agg_job_sends_idempotency_key(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let req = t
+        .gen_test_upload_req(t.gen_test_report(task_id).await)
+        .await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    assert_eq!(
+        t.helper
+            .agg_init_resp_store
+            .lock()
+            .expect("agg_init_resp_store: failed to lock")
+            .len(),
+        1
+    );
+}
+
+async_test_version! { run_agg_job_sends_idempotency_key, Draft03 }
+
+async fn http_post_aggregate_fail_send_cont_req(version: DapVersion) {
+    let t = Test::new(version);
+    let mut rng = thread_rng();
+    let req = t.gen_test_agg_cont_req(Id(rng.gen()), Vec::default()).await;
+
+    // Send aggregate continue request to helper.
+    let err = t.helper.http_post_aggregate(&req).await.unwrap_err();
+
+    // Expect failure due to sending continue request before initialization request.
+    assert_matches!(err, DapAbort::UnrecognizedAggregationJob);
+}
+
+async_test_versions! { http_post_aggregate_fail_send_cont_req }
+
+// Test that an aggregation job can't be continued once the Helper's state for it has expired.
+async fn http_post_aggregate_cont_abort_job_expired(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_shares = vec![ReportShare {
+        metadata: report.metadata,
+        public_share: report.public_share,
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    }];
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    let agg_job_id = AggregateInitializeReq::get_decoded_with_param(&version, &req.payload)
+        .unwrap()
+        .agg_job_id;
+    t.helper.http_post_aggregate(&req).await.unwrap();
+
+    // The job is retained until its time to live has elapsed.
+    let now = t.helper.get_current_time();
+    t.helper.expire_jobs(now).await.unwrap();
+    assert_eq!(
+        t.helper
+            .helper_state_store
+            .lock()
+            .expect("helper_state_store: failed to lock")
+            .len(),
+        1
+    );
+
+    t.helper
+        .expire_jobs(now + t.helper.global_config.job_ttl + 1)
+        .await
+        .unwrap();
+    assert!(t
+        .helper
+        .helper_state_store
+        .lock()
+        .expect("helper_state_store: failed to lock")
+        .is_empty());
+
+    // Expect failure due to the job having expired.
+    let req = t.gen_test_agg_cont_req(agg_job_id, Vec::default()).await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedAggregationJob)
+    );
+}
+
+async_test_versions! { http_post_aggregate_cont_abort_job_expired }
+
+// Test that the Helper aborts the aggregation job if the Leader's continue request includes a
+// transition for a report the Helper did not see during initialization.
+async fn http_post_aggregate_cont_abort_unrecognized_report_id(version: DapVersion) {
+    let mut rng = thread_rng();
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let agg_job_id = Id(rng.gen());
+
+    // Leader: Initialize the aggregation job with a single report.
+    let report = t.gen_test_report(task_id).await;
+    let (leader_state, agg_init_req) = task_config
+        .vdaf
+        .produce_agg_init_req(
+            &t.leader,
+            &task_config.vdaf_verify_key,
+            task_id,
+            &agg_job_id,
+            &PartialBatchSelector::TimeInterval,
+            vec![report],
+            version,
+        )
+        .await
+        .unwrap()
+        .unwrap_continue();
+    let req = t
+        .leader_authorized_req_with_version(
+            task_id,
+            version,
+            MEDIA_TYPE_AGG_INIT_REQ,
+            agg_init_req,
+            task_config.helper_url.join("aggregate").unwrap(),
+        )
+        .await;
+    let agg_resp =
+        AggregateResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+
+    // Leader: Continue with the valid transition followed by one for a fabricated report ID.
+    let (_leader_uncommitted, mut agg_cont_req) = task_config
+        .vdaf
+        .handle_agg_resp(
+            task_id,
+            &agg_job_id,
+            leader_state,
+            agg_resp,
+            ChecksumAlgorithm::Sha256Xor,
+        )
+        .unwrap()
+        .unwrap_uncommitted();
+    assert_eq!(agg_cont_req.transitions.len(), 1);
+    agg_cont_req.transitions.push(Transition {
+        report_id: ReportId(rng.gen()),
+        var: agg_cont_req.transitions[0].var.clone(),
+    });
+    let req = t
+        .gen_test_agg_cont_req(agg_job_id, agg_cont_req.transitions)
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+
+    // Helper: Nothing was aggregated.
+    let guard = t
+        .helper
+        .agg_store
+        .lock()
+        .expect("agg_store: failed to lock");
+    for agg_store in guard
+        .get(task_id)
+        .into_iter()
+        .flat_map(|inner| inner.values())
+    {
+        assert_eq!(agg_store.agg_share.report_count, 0);
+    }
+}
+
+async_test_versions! { http_post_aggregate_cont_abort_unrecognized_report_id }
+
+async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Construct a report payload with an invalid task ID.
+    let mut report_invalid_task_id = t.gen_test_report(task_id).await;
+    report_invalid_task_id.task_id = Id([0; 32]);
+    let req = DapRequest {
+        version: task_config.version,
+        media_type: Some(MEDIA_TYPE_REPORT),
+        task_id: Some(report_invalid_task_id.task_id.clone()),
+        payload: report_invalid_task_id.get_encoded(),
+        url: task_config.leader_url.join("upload").unwrap(),
+        sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
+    };
+
+    // Expect failure due to invalid task ID in report.
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedTask)
+    );
+
+    // Construct an invalid report payload that only has one input share.
+    let mut report_one_input_share = t.gen_test_report(task_id).await;
+    report_one_input_share.encrypted_input_shares =
+        vec![report_one_input_share.encrypted_input_shares[0].clone()];
+    let req = t.gen_test_upload_req(report_one_input_share).await;
+
+    // Expect failure due to incorrect number of input shares
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+
+    // Construct an invalid report payload whose input shares both target the Leader.
+    let mut report_duplicate_input_share = t.gen_test_report(task_id).await;
+    report_duplicate_input_share.encrypted_input_shares[1] =
+        report_duplicate_input_share.encrypted_input_shares[0].clone();
+    let req = t.gen_test_upload_req(report_duplicate_input_share).await;
+
+    // Expect failure due to duplicate input shares.
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_versions! { http_post_upload_fail_send_invalid_report }
+
+// Test that the Leader rejects reports past the expiration date.
+async fn http_post_upload_task_expired(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.expired_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = DapRequest {
+        version: task_config.version,
+        media_type: Some(MEDIA_TYPE_REPORT),
+        task_id: Some(task_id.clone()),
+        payload: report.get_encoded(),
+        url: task_config.leader_url.join("upload").unwrap(),
+        sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
+    };
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportTooLate
+    );
+}
+
+async_test_versions! { http_post_upload_task_expired }
+
+// Test that the Leader holds uploaded reports in its report buffer until the buffer is flushed.
+async fn http_post_upload_report_buffer(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let leader = MockAggregator {
+        report_buffer: Some(Arc::new(ReportBuffer::new(3, 3600))),
+        ..t.leader.clone()
+    };
+    let pending_report_count = || -> usize {
+        leader
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock")
+            .get(task_id)
+            .map(|report_store| report_store.pending.values().map(|queue| queue.len()).sum())
+            .unwrap_or(0)
+    };
+
+    // Client: Upload two reports, which is below the count threshold.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone()).await;
+    leader.http_post_upload(&req).await.unwrap();
+    let req = t
+        .gen_test_upload_req(t.gen_test_report(task_id).await)
+        .await;
+    leader.http_post_upload(&req).await.unwrap();
+    assert_eq!(pending_report_count(), 0);
+
+    // Client: Replaying a buffered report is rejected.
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        leader.http_post_upload(&req).await,
+        Err(DapAbort::ReplayedReport)
+    );
+    assert_eq!(pending_report_count(), 0);
+
+    // Leader: The buffer is not flushed before the age threshold is reached.
+    leader.flush_report_buffer(t.now).await.unwrap();
+    assert_eq!(pending_report_count(), 0);
+
+    // Leader: The buffer is flushed once the age threshold is reached.
+    leader.flush_report_buffer(t.now + 3600).await.unwrap();
+    assert_eq!(pending_report_count(), 2);
+
+    // Client: Replaying a report that was flushed is rejected.
+    assert_matches!(
+        leader.http_post_upload(&req).await,
+        Err(DapAbort::ReplayedReport)
+    );
+    assert_eq!(pending_report_count(), 2);
+}
+
+async_test_versions! { http_post_upload_report_buffer }
+
+// Test that a buffered report that is rejected when the buffer is flushed is recorded as rejected.
+async fn flush_report_buffer_records_rejected_reports(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let leader = MockAggregator {
+        report_buffer: Some(Arc::new(ReportBuffer::new(3, 3600))),
+        ..t.leader.clone()
+    };
+
+    // Client: Upload a report, which is held in the buffer.
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.metadata.id.clone();
+    let req = t.gen_test_upload_req(report).await;
+    leader.http_post_upload(&req).await.unwrap();
+
+    // Collector: Collect the batch window containing the buffered report.
+    let req = t
+        .gen_test_upload_req(t.gen_test_report(task_id).await)
+        .await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    // Leader: Expect the buffered report to be rejected once the buffer is flushed.
+    leader.flush_report_buffer(t.now + 3600).await.unwrap();
+    assert_eq!(
+        leader.report_status(task_id, &report_id).await.unwrap(),
+        ReportStatus::Rejected(TransitionFailure::BatchCollected)
+    );
+}
+
+async_test_versions! { flush_report_buffer_records_rejected_reports }
+
+// Test that only one flush of the report buffer runs at a time and that reports that could not be
+// stored are flushed again, ahead of reports pushed since, without waiting for the buffer's
+// thresholds.
+#[tokio::test]
+async fn report_buffer_finish_flush() {
+    let t = Test::new(DapVersion::Draft02);
+    let task_id = &t.time_interval_task_id;
+    let report_buffer = ReportBuffer::new(3, 3600);
+    let reports = [
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+    ];
+
+    for report in reports[..3].iter() {
+        report_buffer.push(report.clone(), t.now).unwrap();
+    }
+    let flushed = report_buffer.flush_if_ready(t.now).unwrap().unwrap();
+    assert_eq!(flushed.len(), 3);
+    report_buffer.push(reports[3].clone(), t.now).unwrap();
+
+    // Reports being flushed are still checked for replay, and no other flush can start.
+    assert_matches!(
+        report_buffer.push(reports[0].clone(), t.now),
+        Err(DapError::Transition(TransitionFailure::ReportReplayed))
+    );
+    assert_eq!(report_buffer.flush_if_ready(t.now + 3600).unwrap(), None);
+
+    // Only the first of the flushed reports was stored.
+    report_buffer
+        .finish_flush(flushed[1..].to_vec(), t.now)
+        .unwrap();
+    assert_matches!(
+        report_buffer.push(reports[1].clone(), t.now),
+        Err(DapError::Transition(TransitionFailure::ReportReplayed))
+    );
+    assert_eq!(
+        report_buffer.flush_if_ready(t.now).unwrap(),
+        Some(reports[1..].to_vec())
+    );
+}
+
+// Test that the Leader rejects reports with too many extensions or with an extension that is too
+// large.
+async fn http_post_upload_fail_extension_limits(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let global_config = t.leader.get_global_config();
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.metadata.extensions = (0..global_config.max_extensions + 1)
+        .map(|i| Extension::Unhandled {
+            typ: 0xff00 + i as u16,
+            payload: Vec::new(),
+        })
+        .collect();
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::UnrecognizedMessage
+    );
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.metadata.extensions = vec![Extension::Unhandled {
+        typ: 0xff00,
+        payload: vec![0; global_config.max_extension_len + 1],
+    }];
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::UnrecognizedMessage
+    );
+}
+
+async_test_versions! { http_post_upload_fail_extension_limits }
+
+// Test that the Aggregators reject reports for a task that has not yet started.
+async fn http_post_upload_task_not_started(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = Id(thread_rng().gen());
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    task_config.start = t.now + 3600;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+
+    // The Leader rejects the report.
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report.clone()).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::TaskNotStarted
+    );
+
+    // The Helper rejects the report share.
+    let report_share = ReportShare {
+        metadata: report.metadata,
+        public_share: report.public_share,
+        encrypted_input_share: report.encrypted_input_shares[1].clone(),
+    };
+    let req = t.gen_test_agg_init_req(&task_id, vec![report_share]).await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        DapAbort::TaskNotStarted
+    );
+}
+
+async_test_versions! { http_post_upload_task_not_started }
+
+// Test that the Leader rejects uploads once the task's quota of pending reports is reached and
+// accepts them again once the pending reports have been aggregated.
+async fn http_post_upload_fail_max_pending_reports(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = Id(thread_rng().gen());
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    task_config.max_pending_reports = Some(1);
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+
+    // The first report fills the quota.
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::TooManyRequests { .. }
+    );
+
+    // Aggregating the pending report frees up the quota.
+    t.run_agg_job(&task_id).await.unwrap();
+    t.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_upload_fail_max_pending_reports }
+
+// Test that reports held in the Leader's report buffer count towards the task's quota of pending
+// reports.
+async fn http_post_upload_fail_max_pending_reports_buffered(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = Id(thread_rng().gen());
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    task_config.max_pending_reports = Some(2);
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+    let leader = MockAggregator {
+        report_buffer: Some(Arc::new(ReportBuffer::new(3, 3600))),
+        ..t.leader.clone()
+    };
+
+    // The first two reports are buffered and fill the quota.
+    for _ in 0..2 {
+        let report = t.gen_test_report(&task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        leader.http_post_upload(&req).await.unwrap();
+    }
+
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::TooManyRequests { .. }
+    );
+
+    // Storing the buffered reports does not free up the quota.
+    leader.flush_report_buffer(t.now + 3600).await.unwrap();
+    assert_matches!(
+        leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::TooManyRequests { .. }
+    );
+}
+
+async_test_versions! { http_post_upload_fail_max_pending_reports_buffered }
+
+// Test that a report uploaded before the task expired is still aggregated after the task expires.
+// Expiration is checked against the report's timestamp, not the current time, so only new uploads
+// are rejected.
+async fn aggregate_after_task_expiration(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = Id(thread_rng().gen());
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+
+    // Client: Upload a report two hours ago, before the task expires.
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(&task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(&task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    let report = task_config
+        .vdaf
+        .produce_report(
+            &hpke_config_list,
+            t.now - 7200,
+            &task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // The task expires an hour ago.
+    task_config.expiration = t.now - 3600;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+
+    // Leader: Run aggregation job. Both Aggregators aggregate the report.
+    t.run_agg_job(&task_id).await.unwrap();
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let mut agg_share = DapAggregateShare::default();
+        for agg_store in guard.get(&task_id).unwrap().values() {
+            agg_share.merge(agg_store.agg_share.clone()).unwrap();
+        }
+        assert_eq!(agg_share.report_count, 1);
+    }
+
+    // Client: New reports are rejected.
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportTooLate
+    );
+}
+
+async_test_versions! { aggregate_after_task_expiration }
+
+// Test that a fixture built with `TestFixtureBuilder` accepts uploads.
+async fn test_fixture_upload(version: DapVersion) {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let fixture = TestFixtureBuilder::new()
+        .version(version)
+        .query(DapQueryConfig::FixedSize { max_batch_size: 2 })
+        .vdaf(VdafConfig::Prio3(Prio3Config::Sum { bits: 8 }))
+        .expiration(now + 86400)
+        .build();
+
+    let report = fixture.gen_report(DapMeasurement::U64(23)).unwrap();
+    let req = fixture.gen_upload_req(&report);
+    fixture.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_versions! { test_fixture_upload }
+
+// Smoke test for the benchmark helpers.
+#[tokio::test]
+async fn bench_helpers() {
+    for vdaf in [
+        VdafConfig::Prio3(Prio3Config::Count),
+        VdafConfig::Prio3(Prio3Config::CountVec { length: 3 }),
+        VdafConfig::Prio3(Prio3Config::Sum { bits: 8 }),
+        VdafConfig::Prio2 { dimension: 3 },
+    ] {
+        bench_produce_reports(&vdaf, 3);
+    }
+    bench_aggregate(3).await;
+}
+
+async fn e2e_count_vec(version: DapVersion) {
+    let fixture = TestFixtureBuilder::new()
+        .version(version)
+        .vdaf(VdafConfig::Prio3(Prio3Config::CountVec { length: 3 }))
+        .build();
+    let task_id = &fixture.task_id;
+    let task_config = &fixture.task_config;
+
+    // Client: Send upload requests to Leader.
+    for measurement in [vec![1, 0, 1], vec![1, 1, 0]] {
+        let report = fixture
+            .gen_report(DapMeasurement::U32Vec(measurement))
+            .unwrap();
+        let req = fixture.gen_upload_req(&report);
+        fixture.leader.http_post_upload(&req).await.unwrap();
+    }
+
+    // Leader: Aggregate the reports.
+    fixture.leader.process_task(task_id).await.unwrap();
+
+    // Collector: Create a collect job.
+    let query = task_config.query_for_current_batch_window(fixture.now);
+    let req = DapRequest {
+        version,
+        media_type: Some(MEDIA_TYPE_COLLECT_REQ),
+        task_id: Some(task_id.clone()),
+        payload: CollectReq {
+            task_id: task_id.clone(),
+            query: query.clone(),
+            agg_param: Vec::default(),
+            collector_hpke_config_id: None,
+        }
+        .get_encoded_with_param(&version),
+        url: task_config.leader_url.join("collect").unwrap(),
+        sender_auth: Some(fixture.collector_token.clone()),
+        idempotency_key: None,
+        collector_hpke_config_id: None,
+    };
+    fixture.leader.http_post_collect(&req).await.unwrap();
+    let resp = fixture.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+
+    // Leader: Run the collect job.
+    let telem = fixture
+        .leader
+        .process(&MockAggregatorReportSelector::from(task_id.clone()))
+        .await
+        .unwrap();
+    assert_eq!(telem.reports_collected, 2);
+
+    // Collector: Poll the collect job and unshard the aggregate result.
+    let collect_resp = assert_matches!(
+        fixture.leader.poll_collect_job(task_id, collect_id).await.unwrap(),
+        DapCollectJob::Done { collect_resp, .. } => collect_resp
+    );
+    let agg_res = task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &fixture.collector_hpke_receiver_config,
+            task_id,
+            &BatchSelector::try_from(query).unwrap(),
+            collect_resp.report_count,
+            collect_resp.encrypted_agg_shares,
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_res, DapAggregateResult::U64Vec(vec![2, 1, 1]));
+}
+
+async_test_versions! { e2e_count_vec }
+
+async fn batch_bucket_for_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let hour = task_config.truncate_time(t.now);
+
+    // Reports in the same hour map to the same bucket.
+    let bucket = task_config.batch_bucket_for_report(hour, None).unwrap();
+    assert_eq!(
+        bucket,
+        DapBatchBucketOwned::TimeInterval { batch_window: hour }
+    );
+    assert_eq!(
+        task_config
+            .batch_bucket_for_report(hour + task_config.time_precision - 1, None)
+            .unwrap(),
+        bucket
+    );
+
+    // Reports in different hours map to different buckets.
+    assert_ne!(
+        task_config
+            .batch_bucket_for_report(hour + task_config.time_precision, None)
+            .unwrap(),
+        bucket
+    );
+
+    // For fixed-size tasks, the bucket is the current batch.
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    let batch_id = Id(thread_rng().gen());
+    assert_eq!(
+        task_config
+            .batch_bucket_for_report(t.now, Some(&batch_id))
+            .unwrap(),
+        DapBatchBucketOwned::FixedSize { batch_id }
+    );
+    assert!(task_config.batch_bucket_for_report(t.now, None).is_err());
+}
+
+async_test_versions! { batch_bucket_for_report }
+
+async fn task_config_validate_storage_precision(version: DapVersion) {
+    let t = Test::new(version);
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    assert!(task_config.validate().is_ok());
+
+    task_config.storage_precision = Some(task_config.time_precision / 4);
+    assert!(task_config.validate().is_ok());
+
+    // The storage precision must be a non-zero divisor of the time precision.
+    task_config.storage_precision = Some(0);
+    assert!(task_config.validate().is_err());
+    task_config.storage_precision = Some(task_config.time_precision - 1);
+    assert!(task_config.validate().is_err());
+
+    // The Aggregator refuses to use an invalid task configuration.
+    t.leader
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .insert(t.time_interval_task_id.clone(), task_config);
+    assert!(t
+        .leader
+        .get_task_config_for(Cow::Borrowed(&t.time_interval_task_id))
+        .await
+        .is_err());
+}
+
+async_test_versions! { task_config_validate_storage_precision }
+
+async fn batch_time_distribution(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let hour = task_config.truncate_time(t.now);
+
+    // Client: Upload one report two hours ago and two reports in the current hour.
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    for time in [t.now - 7200, t.now, t.now] {
+        let report = task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                time,
+                task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    // Leader and Helper: The reports are distributed over two of the hours of a day-long batch.
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: hour - 43200,
+            duration: 86400,
+        },
+    };
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(
+            aggregator
+                .batch_time_distribution(task_id, &batch_sel)
+                .await
+                .unwrap(),
+            vec![(hour - 7200, 1), (hour, 2)]
+        );
+    }
+
+    // The distribution is not defined for fixed-size batches.
+    assert_matches!(
+        t.leader
+            .batch_time_distribution(
+                &t.fixed_size_task_id,
+                &BatchSelector::FixedSizeByBatchId {
+                    batch_id: Id([0; 32])
+                },
+            )
+            .await,
+        Err(DapError::Fatal(..))
+    );
+}
+
+async_test_versions! { batch_time_distribution }
+
+// Test that the Leader rejects reports that fall outside of the report storage window.
+async fn http_post_upload_fail_report_outside_storage_window(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let epoch_duration = t.leader.global_config.report_storage_epoch_duration;
+
+    for time in [t.now - 2 * epoch_duration, t.now + 2 * epoch_duration] {
+        let mut report = t.gen_test_report(task_id).await;
+        report.metadata.time = time;
+        let req = t.gen_test_upload_req(report).await;
+        assert_matches!(
+            t.leader.http_post_upload(&req).await.unwrap_err(),
+            DapAbort::ReportTooLate
+        );
+    }
+}
+
+async_test_versions! { http_post_upload_fail_report_outside_storage_window }
+
+// Test that the Leader rejects a report whose time is not aligned to the time precision if the
+// task requires it.
+async fn http_post_upload_fail_misaligned_report_time(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    t.leader
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .get_mut(task_id)
+        .unwrap()
+        .require_aligned_report_time = true;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert_eq!(task_config.time_precision, 3600);
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.metadata.time = task_config.truncate_time(t.now) + 30;
+    let req = t.gen_test_upload_req(report).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::BadRequest(s) => assert_eq!(s, "report time misaligned")
+    );
+}
+
+async_test_versions! { http_post_upload_fail_misaligned_report_time }
+
+// Test that, by default, the Leader accepts a report whose time is not aligned to the time
+// precision and assigns it to the batch window containing its time.
+async fn http_post_upload_misaligned_report_time(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert!(!task_config.require_aligned_report_time);
+    assert_eq!(task_config.time_precision, 3600);
+    let batch_window = task_config.truncate_time(t.now);
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.metadata.time = batch_window + 30;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    let guard = t
+        .leader
+        .report_store
+        .lock()
+        .expect("report_store: failed to lock");
+    let pending = &guard.get(task_id).unwrap().pending;
+    assert_eq!(
+        pending
+            .get(&DapBatchBucketOwned::TimeInterval { batch_window })
+            .map(|queue| queue.len()),
+        Some(1)
+    );
+}
+
+async_test_versions! { http_post_upload_misaligned_report_time }
+
+async fn get_reports_empty_response(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone()).await;
+
+    // Upload report.
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+
+    // Get one report. This should return with the report that was uploaded earlier.
+    // We also check that the task ID associated to the report is the same one we
+    // requested.
+    let report_sel = MockAggregatorReportSelector::from(task_id.clone());
+    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(&returned_task_id, task_id);
+
+    // Try to get another report. This should not return an error, but simply
+    // an empty vector, as we drained the ReportStore above. The task ID
+    // associated to the report should be the same one we requested.
+    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert_eq!(reports.len(), 0);
+    assert_eq!(&returned_task_id, task_id);
+}
+
+async_test_versions! { get_reports_empty_response }
+
+async fn poll_collect_job_test_results(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq.
+    let version = task_config.version.clone();
+    let req = t
+        .collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    t.leader.http_post_collect(&req).await.unwrap();
+
+    // Expect DapCollectJob::Unknown due to invalid collect ID.
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, &Id::default())
+            .await
+            .unwrap(),
+        DapCollectJob::Unknown
+    );
+
+    // Leader: Get pending collect job to obtain collect_id
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+    let collect_resp = CollectResp {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 0,
+        encrypted_agg_shares: Vec::default(),
+    };
+
+    // Expect DapCollectJob::Pending due to pending collect job.
+    let created_at = assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Pending { created_at } => created_at
+    );
+
+    // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
+    let completed_at = created_at + 1;
+    t.leader
+        .finish_collect_job(&task_id, &collect_id, &collect_resp, completed_at)
+        .await
+        .unwrap();
+
+    // Expect DapCollectJob::Done due to processed collect job.
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Done {
+            collect_resp,
+            created_at,
+            completed_at,
+        }
+    );
+}
+
+async_test_versions! { poll_collect_job_test_results }
+
+// Test that a collect job that is still pending once its TTL has elapsed is expired by the sweep
+// and that polling it then yields `Expired` rather than `Pending` or `Unknown`.
+async fn poll_collect_job_expired(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let collect_job_ttl = t.leader.global_config.collect_job_ttl;
+
+    let req = t
+        .collector_authorized_req(
+            task_config.version.clone(),
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    t.leader.http_post_collect(&req).await.unwrap();
+    let (collect_id, _collect_req) = t.leader.get_pending_collect_jobs().await.unwrap()[0].clone();
+    let created_at = assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Pending { created_at } => created_at
+    );
+
+    // Expect the collect job to remain pending until its TTL has elapsed.
+    t.leader
+        .expire_collect_jobs(created_at + collect_job_ttl)
+        .await
+        .unwrap();
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Pending { .. }
+    );
+
+    // Expect the collect job to be expired and removed from the collect job queue afterwards.
+    t.leader
+        .expire_collect_jobs(created_at + collect_job_ttl + 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Expired { created_at }
+    );
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(t
+        .leader
+        .get_collect_resp(task_id, &collect_id)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async_test_versions! { poll_collect_job_expired }
+
+// Test that the Leader expires collect jobs whose TTL has elapsed when it processes the collect
+// job queue.
+async fn process_expires_collect_jobs(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let collect_job_ttl = t.leader.global_config.collect_job_ttl;
+
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    t.leader.http_post_collect(&req).await.unwrap();
+    let (collect_id, _collect_req) = t.leader.get_pending_collect_jobs().await.unwrap()[0].clone();
+
+    // Pretend that the collect request was received longer ago than the TTL.
+    let created_at = t.leader.get_current_time() - collect_job_ttl - 1;
+    match t
+        .leader
+        .leader_state_store
+        .lock()
+        .expect("leader_state_store: failed to lock")
+        .get_mut(task_id)
+        .unwrap()
+        .collect_jobs
+        .get_mut(&collect_id)
+        .unwrap()
+    {
+        CollectJobState::Pending(_, ref mut pending_created_at) => {
+            *pending_created_at = created_at;
+        }
+        _ => panic!("collect job is not pending"),
+    }
+
+    // Leader: Process the collect job queue. Expect the collect job to be expired rather than run.
+    let telem = t
+        .leader
+        .process(&MockAggregatorReportSelector::from(task_id.clone()))
+        .await
+        .unwrap();
+    assert_eq!(telem.reports_collected, 0);
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Expired { created_at }
+    );
+}
+
+async_test_versions! { process_expires_collect_jobs }
+
+async fn get_collect_resp(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let req = t
+        .collector_authorized_req(
+            task_config.version.clone(),
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+
+    // Unknown collect ID.
+    assert_eq!(
+        t.leader
+            .get_collect_resp(task_id, &Id::default())
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Pending collect job.
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+    assert_eq!(
+        t.leader
+            .get_collect_resp(task_id, collect_id)
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Completed collect job.
+    let collect_resp = CollectResp {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 23,
+        encrypted_agg_shares: vec![
+            HpkeCiphertext {
+                config_id: 1,
+                enc: b"leader enc".to_vec(),
+                payload: b"leader payload".to_vec(),
+            },
+            HpkeCiphertext {
+                config_id: 1,
+                enc: b"helper enc".to_vec(),
+                payload: b"helper payload".to_vec(),
+            },
+        ],
+    };
+    t.leader
+        .finish_collect_job(task_id, collect_id, &collect_resp, t.now)
+        .await
+        .unwrap();
+    assert_eq!(
+        t.leader
+            .get_collect_resp(task_id, collect_id)
+            .await
+            .unwrap(),
+        Some(collect_resp)
+    );
+}
+
+async_test_versions! { get_collect_resp }
+
+async fn poll_collect_job_created_at(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a collect job.
+    let req = t
+        .collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+
+    // Collector: Poll the collect job. The creation time is the time at which the Leader received
+    // the collect request.
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+    let created_at = assert_matches!(
+        t.leader.poll_collect_job(task_id, collect_id).await.unwrap(),
+        DapCollectJob::Pending { created_at } => created_at
+    );
+    assert!(created_at.abs_diff(t.now) <= 1);
+}
+
+async_test_versions! { poll_collect_job_created_at }
+
+// Test that a collect job that fails because the Helper is unavailable remains pending and can be
+// retried to completion once the Helper is available again.
+async fn retry_collect_job(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // A Leader that can't reach the Helper. It shares its storage with the Leader.
+    let leader_with_unavailable_helper = MockAggregator {
+        peer: None,
+        ..t.leader.clone()
+    };
+
+    // Client: Send upload request to Leader and aggregate it.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector: Create collection job.
+    let req = t
+        .collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+
+    // Leader: Expect the collect job to remain pending while the Helper is unavailable.
+    for _ in 0..2 {
+        assert_matches!(
+            leader_with_unavailable_helper
+                .retry_collect_job(task_id, collect_id)
+                .await
+                .unwrap(),
+            DapCollectJob::Pending { .. }
+        );
+        assert_matches!(
+            t.leader
+                .poll_collect_job(task_id, collect_id)
+                .await
+                .unwrap(),
+            DapCollectJob::Pending { .. }
+        );
+    }
+
+    // Leader: Expect the collect job to complete once the Helper is available.
+    let collect_resp = assert_matches!(
+        t.leader.retry_collect_job(task_id, collect_id).await.unwrap(),
+        DapCollectJob::Done { collect_resp, .. } => collect_resp
+    );
+    assert_eq!(collect_resp.report_count, 1);
+
+    // Leader: Retrying a completed collect job does nothing.
+    assert_matches!(
+        t.leader
+            .retry_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Done { .. }
+    );
+}
+
+async_test_versions! { retry_collect_job }
+
+// Test that a collect job fails, rather than remaining pending, if the Helper has already collected
+// the batch, e.g., because its response to a previous attempt was lost.
+async fn retry_collect_job_batch_collected_by_helper(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let query = task_config.query_for_current_batch_window(t.now);
+
+    // Client: Send upload request to Leader and aggregate it.
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.metadata.id.clone();
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector: Create collection job.
+    let req = t
+        .collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: query.clone(),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, _collect_req) = &resp[0];
+
+    // Helper: Handle an aggregate-share request whose response never reaches the Leader.
+    let req = t
+        .leader_authorized_req_with_version(
+            task_id,
+            version,
+            MEDIA_TYPE_AGG_SHARE_REQ,
+            AggregateShareReq {
+                task_id: task_id.clone(),
+                batch_sel: BatchSelector::try_from(query).unwrap(),
+                agg_param: Vec::default(),
+                report_count: 1,
+                checksum: compute_checksum(&[report_id]),
+            },
+            task_config.helper_url.join("aggregate_share").unwrap(),
+        )
+        .await;
+    t.helper.http_post_aggregate_share(&req).await.unwrap();
+
+    // Leader: Expect the collect job to fail and to no longer be processed.
+    assert_matches!(
+        t.leader
+            .retry_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Failed { .. }
+    );
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async_test_versions! { retry_collect_job_batch_collected_by_helper }
+
+// Test that the status of a report is tracked from upload through aggregation.
+async fn report_status(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // Client: Upload a valid report and a report whose Helper share is corrupted.
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.metadata.id.clone();
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    let mut corrupted_report = t.gen_test_report(task_id).await;
+    corrupted_report.encrypted_input_shares[1].payload[0] ^= 1;
+    let corrupted_report_id = corrupted_report.metadata.id.clone();
+    let req = t.gen_test_upload_req(corrupted_report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    assert_eq!(
+        t.leader.report_status(task_id, &report_id).await.unwrap(),
+        ReportStatus::Pending
+    );
+    assert_eq!(
+        t.leader
+            .report_status(task_id, &ReportId([1; 16]))
+            .await
+            .unwrap(),
+        ReportStatus::Unknown
+    );
+
+    // Leader: Run an aggregation job for each report.
+    t.run_agg_job(task_id).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(
+            aggregator.report_status(task_id, &report_id).await.unwrap(),
+            ReportStatus::Aggregated
+        );
+        assert_eq!(
+            aggregator
+                .report_status(task_id, &corrupted_report_id)
+                .await
+                .unwrap(),
+            ReportStatus::Rejected(TransitionFailure::HpkeDecryptError)
+        );
+    }
+}
+
+async_test_versions! { report_status }
+
+// Test that a report sealed to an HPKE config that has since been removed is rejected with
+// hpke-unknown-config-id.
+async fn report_status_hpke_unknown_config_id(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Client: Upload a report sealed to the Leader's current HPKE config.
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.metadata.id.clone();
+    let config_id = report.encrypted_input_shares[0].config_id;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Remove the HPKE config, then run the aggregation job.
+    let leader = MockAggregator {
+        hpke_receiver_config_list: t
+            .leader
+            .hpke_receiver_config_list
+            .iter()
+            .filter(|hpke_receiver_config| hpke_receiver_config.config.id != config_id)
+            .cloned()
+            .collect(),
+        ..t.leader.clone()
+    };
+    let report_sel = MockAggregatorReportSelector::from(task_id.clone());
+    let (task_id, part_batch_sel, reports) = get_reports!(leader, &report_sel);
+    assert_eq!(
+        leader
+            .run_agg_job(&task_id, &task_config, &part_batch_sel, reports)
+            .await
+            .unwrap(),
+        0
+    );
+
+    assert_eq!(
+        leader.report_status(&task_id, &report_id).await.unwrap(),
+        ReportStatus::Rejected(TransitionFailure::HpkeUnknownConfigId)
+    );
+}
+
+async_test_versions! { report_status_hpke_unknown_config_id }
+
+// Test that if the task requires it, a report sealed to an HPKE config that has been superseded is
+// rejected with hpke-unknown-config-id, even though the Aggregator can still decrypt it.
+async fn require_latest_hpke_config(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .get_mut(task_id)
+            .unwrap()
+            .require_latest_hpke_config = true;
+    }
+
+    // Rotate an Aggregator's HPKE config: the new config is advertised, but the old one is kept
+    // so that reports sealed to it can still be decrypted.
+    let rotate = |aggregator: &MockAggregator| {
+        let old_config_id = aggregator.hpke_receiver_config_list[0].config.id;
+        let new_hpke_receiver_config =
+            HpkeReceiverConfig::gen(old_config_id.wrapping_add(1), HpkeKemId::X25519HkdfSha256)
+                .unwrap();
+        let mut hpke_receiver_config_list = vec![new_hpke_receiver_config];
+        hpke_receiver_config_list.extend(aggregator.hpke_receiver_config_list.iter().cloned());
+        MockAggregator {
+            hpke_receiver_config_list,
+            ..aggregator.clone()
+        }
+    };
+
+    // Leader: Upload a report sealed to the old config and a report sealed to the new config.
+    let old_report = t.gen_test_report(task_id).await;
+    let leader = rotate(&t.leader);
+    let new_report = VdafConfig::Prio3(Prio3Config::Count)
+        .produce_report(
+            &[
+                leader
+                    .get_hpke_config_for(Some(task_id))
+                    .await
+                    .unwrap()
+                    .clone(),
+                t.helper
+                    .get_hpke_config_for(Some(task_id))
+                    .await
+                    .unwrap()
+                    .clone(),
+            ],
+            t.now,
+            task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+    for report in [&old_report, &new_report] {
+        let req = t.gen_test_upload_req(report.clone()).await;
+        leader.http_post_upload(&req).await.unwrap();
+    }
+
+    // Leader: Run the aggregation jobs. Only the report sealed to the new config is aggregated.
+    let agg_job_report = leader.process_task(task_id).await.unwrap();
+    assert_eq!(agg_job_report.reports_succeeded, 1);
+    assert_eq!(agg_job_report.reports_failed, 1);
+    assert_eq!(
+        leader
+            .report_status(task_id, &old_report.metadata.id)
+            .await
+            .unwrap(),
+        ReportStatus::Rejected(TransitionFailure::HpkeUnknownConfigId)
+    );
+    assert_eq!(
+        leader
+            .report_status(task_id, &new_report.metadata.id)
+            .await
+            .unwrap(),
+        ReportStatus::Aggregated
+    );
+
+    // Helper: Expect the report share sealed to the old config to be rejected after rotation.
+    let helper = rotate(&t.helper);
+    let old_report = t.gen_test_report(task_id).await;
+    let new_report = VdafConfig::Prio3(Prio3Config::Count)
+        .produce_report(
+            &[
+                t.leader
+                    .get_hpke_config_for(Some(task_id))
+                    .await
+                    .unwrap()
+                    .clone(),
+                helper
+                    .get_hpke_config_for(Some(task_id))
+                    .await
+                    .unwrap()
+                    .clone(),
+            ],
+            t.now,
+            task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+    let report_shares = [old_report, new_report]
+        .into_iter()
+        .map(|report| ReportShare {
+            metadata: report.metadata,
+            public_share: report.public_share,
+            encrypted_input_share: report.encrypted_input_shares[1].clone(),
+        })
+        .collect();
+    let req = t.gen_test_agg_init_req(task_id, report_shares).await;
+    let agg_resp =
+        AggregateResp::get_decoded(&helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::HpkeUnknownConfigId)
+    );
+    assert_matches!(agg_resp.transitions[1].var, TransitionVar::Continued(_));
+}
+
+async_test_versions! { require_latest_hpke_config }
+
+// Test that the task-config hash only depends on the parameters the Aggregators must agree on.
+async fn task_config_hash(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let hash = task_config.config_hash();
+    assert_eq!(
+        t.helper
+            .unchecked_get_task_config(task_id)
+            .await
+            .config_hash(),
+        hash
+    );
+
+    // Local-only parameters and the VDAF verification key do not affect the hash.
+    let mut other = task_config.clone();
+    other.expiration += 1;
+    other.vdaf_verify_key = other.vdaf.gen_verify_key();
+    assert_eq!(other.config_hash(), hash);
+
+    // Shared parameters do affect the hash.
+    let mut other = task_config.clone();
+    other.min_batch_size += 1;
+    assert_ne!(other.config_hash(), hash);
+
+    let mut other = task_config.clone();
+    other.time_precision += 1;
+    assert_ne!(other.config_hash(), hash);
+
+    let mut other = task_config.clone();
+    other.helper_url = Url::parse("https://other-helper.org/").unwrap();
+    assert_ne!(other.config_hash(), hash);
+
+    let mut other = task_config;
+    other.collector_hpke_configs[0].id = other.collector_hpke_configs[0].id.wrapping_add(1);
+    assert_ne!(other.config_hash(), hash);
+}
+
+async_test_versions! { task_config_hash }
+
+async fn task_config_batch_windows(version: DapVersion) {
+    let t = Test::new(version);
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    task_config.time_precision = 3600;
+    let start = task_config.truncate_time(t.now);
+
+    assert_eq!(
+        task_config.batch_windows(&Interval {
+            start,
+            duration: 3 * 3600,
+        }),
+        vec![start, start + 3600, start + 2 * 3600]
+    );
+    assert_eq!(
+        task_config.batch_windows(&Interval { start, duration: 0 }),
+        Vec::<Time>::new()
+    );
+}
+
+async_test_versions! { task_config_batch_windows }
+
+async fn task_config_diff(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert_eq!(
+        task_config.diff(&t.helper.unchecked_get_task_config(task_id).await),
+        vec![]
+    );
+
+    // Only the shared parameters that differ are reported.
+    let mut other = task_config.clone();
+    other.min_batch_size += 1;
+    other.time_precision += 1;
+    other.expiration += 1;
+    other.vdaf_verify_key = other.vdaf.gen_verify_key();
+    other.collector_hpke_configs[0].id = other.collector_hpke_configs[0].id.wrapping_add(1);
+    assert_eq!(
+        task_config.diff(&other),
+        vec![
+            TaskConfigFieldDiff::TimePrecision,
+            TaskConfigFieldDiff::MinBatchSize,
+            TaskConfigFieldDiff::CollectorHpkeConfigs,
+        ]
+    );
+}
+
+async_test_versions! { task_config_diff }
+
+#[test]
+fn abort_retry_after() {
+    assert_eq!(
+        DapAbort::TooManyRequests { retry_after: 30 }.retry_after(),
+        Some(30)
+    );
+    assert_eq!(
+        DapAbort::QueryMismatch {
+            expected: QueryKind::TimeInterval,
+            actual: QueryKind::FixedSize,
+        }
+        .retry_after(),
+        None
+    );
+}
+
+#[test]
+fn collect_job_serialization() {
+    for collect_job in [
+        DapCollectJob::Unknown,
+        DapCollectJob::Pending {
+            created_at: 1637359200,
+        },
+        DapCollectJob::Expired {
+            created_at: 1637359200,
+        },
+        DapCollectJob::Done {
+            collect_resp: CollectResp {
+                part_batch_sel: PartialBatchSelector::FixedSizeByBatchId {
+                    batch_id: Id([23; 32]),
+                },
+                report_count: 42,
+                encrypted_agg_shares: vec![
+                    HpkeCiphertext {
+                        config_id: 1,
+                        enc: b"leader encapsulated key".to_vec(),
+                        payload: b"leader ciphertext".to_vec(),
+                    },
+                    HpkeCiphertext {
+                        config_id: 2,
+                        enc: b"helper encapsulated key".to_vec(),
+                        payload: b"helper ciphertext".to_vec(),
+                    },
+                ],
+            },
+            created_at: 1637359200,
+            completed_at: 1637359260,
+        },
+    ] {
+        let encoded = serde_json::to_string(&collect_job).unwrap();
+        let decoded: DapCollectJob = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, collect_job);
+    }
+}
+
+// Test that a global config that predates the optional parameters deserializes and leaves them at
+// their defaults.
+#[test]
+fn global_config_defaults() {
+    let global_config: DapGlobalConfig = serde_json::from_str(
+        r#"{
+            "report_storage_epoch_duration": 604800,
+            "max_batch_duration": 360000,
+            "min_batch_interval_start": 259200,
+            "max_batch_interval_end": 259200,
+            "supported_hpke_kems": ["x25519_hkdf_sha256"],
+            "allow_taskprov": true,
+            "taskprov_version": "v02"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(global_config.max_extensions, usize::MAX);
+    assert_eq!(global_config.max_extension_len, usize::MAX);
+    assert_eq!(global_config.min_time_precision, 0);
+    assert_eq!(global_config.max_batch_buckets, u64::MAX);
+    assert_eq!(global_config.max_pending_collect_jobs, usize::MAX);
+    assert_eq!(global_config.max_agg_share_buckets, u64::MAX);
+    assert_eq!(global_config.job_ttl, u64::MAX);
+    assert_eq!(global_config.collect_job_ttl, u64::MAX);
+}
+
+// Test that a task config with a single Collector HPKE config, stored under the name it had before
+// a task could have several, deserializes.
+async fn task_config_single_collector_hpke_config(version: DapVersion) {
+    let t = Test::new(version);
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+
+    let mut json = serde_json::to_value(&task_config).unwrap();
+    let json_object = json.as_object_mut().unwrap();
+    let collector_hpke_configs = json_object.remove("collector_hpke_configs").unwrap();
+    json_object.insert(
+        "collector_hpke_config".to_string(),
+        collector_hpke_configs[0].clone(),
+    );
+
+    let decoded: DapTaskConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        decoded.collector_hpke_configs,
+        task_config.collector_hpke_configs
+    );
+}
+
+async_test_versions! { task_config_single_collector_hpke_config }
+
+async fn http_post_collect_fail_invalid_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a very large batch interval.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now - (t.now % task_config.time_precision),
+                        duration: t.leader.global_config.max_batch_duration
+                            + task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too large.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too large".to_string()));
+
+    // Collector: Create a CollectReq with a batch interval in the past.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now
+                            - (t.now % task_config.time_precision)
+                            - t.leader.global_config.min_batch_interval_start
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too far into the past.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into past".to_string()));
+
+    // Collector: Create a CollectReq with a batch interval in the future.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now - (t.now % task_config.time_precision)
+                            + t.leader.global_config.max_batch_interval_end
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too far into the future.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into future".to_string()));
+}
+
+async_test_versions! { http_post_collect_fail_invalid_batch_interval }
+
+async fn http_post_collect_fail_batch_interval_overflow(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a batch interval whose end overflows.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now - (t.now % task_config.time_precision),
+                        duration: u64::MAX,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval overflow"));
+}
+
+async_test_versions! { http_post_collect_fail_batch_interval_overflow }
+
+// Test that a collect request can be validated against the task without sending it to the Leader.
+async fn validate_collect_req(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let global_config = &t.leader.global_config;
+    let window = task_config.truncate_time(t.now);
+
+    let collect_req_for = |query| CollectReq {
+        task_id: task_id.clone(),
+        query,
+        agg_param: Vec::default(),
+        collector_hpke_config_id: None,
+    };
+    let validate_interval = |start, duration| {
+        task_config.validate_collect_req(
+            global_config,
+            &collect_req_for(Query::TimeInterval {
+                batch_interval: Interval { start, duration },
+            }),
+            t.now,
+        )
+    };
+
+    // The current batch window is valid.
+    assert_matches!(
+        validate_interval(window, task_config.time_precision),
+        Ok(())
+    );
+
+    // The batch interval is too large.
+    assert_matches!(
+        validate_interval(
+            window,
+            global_config.max_batch_duration + task_config.time_precision
+        ),
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too large")
+    );
+
+    // The batch interval is too far into the past.
+    assert_matches!(
+        validate_interval(
+            window - global_config.min_batch_interval_start - task_config.time_precision,
+            task_config.time_precision * 2,
+        ),
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into past")
+    );
+
+    // The batch interval is too far into the future.
+    assert_matches!(
+        validate_interval(
+            window + global_config.max_batch_interval_end - task_config.time_precision,
+            task_config.time_precision * 2,
+        ),
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into future")
+    );
+
+    // The end of the batch interval overflows.
+    assert_matches!(
+        validate_interval(window, u64::MAX),
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval overflow")
+    );
+
+    // The batch interval is not aligned with the time precision.
+    assert_matches!(
+        validate_interval(window + 1, task_config.time_precision),
+        Err(DapAbort::BatchInvalid)
+    );
+
+    // The query doesn't match the task's query type.
+    assert_matches!(
+        task_config.validate_collect_req(
+            global_config,
+            &collect_req_for(Query::FixedSizeCurrentBatch),
+            t.now
+        ),
+        Err(DapAbort::QueryMismatch {
+            expected: QueryKind::TimeInterval,
+            actual: QueryKind::FixedSize,
+        })
+    );
+
+    // The Collector's HPKE config is not recognized.
+    let mut collect_req = collect_req_for(task_config.query_for_current_batch_window(t.now));
+    collect_req.collector_hpke_config_id =
+        Some(task_config.collector_hpke_configs[0].id.wrapping_add(1));
+    assert_matches!(
+        task_config.validate_collect_req(global_config, &collect_req, t.now),
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "unrecognized collector HPKE config")
+    );
+
+    // For fixed-size tasks, the current batch may be requested.
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    assert_matches!(
+        task_config.validate_collect_req(
+            global_config,
+            &collect_req_for(Query::FixedSizeCurrentBatch),
+            t.now
+        ),
+        Ok(())
+    );
+}
+
+async_test_versions! { validate_collect_req }
+
+async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a very large batch interval.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now
+                            - (t.now % task_config.time_precision)
+                            - t.leader.global_config.max_batch_duration / 2,
+                        duration: t.leader.global_config.max_batch_duration,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let _collect_uri = t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_succeed_max_batch_interval }
+
+async fn http_post_collect_fail_task_global_config_override(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // Override the global config for the task with a smaller maximum batch duration.
+    let global_config = t.leader.get_global_config();
+    let mut global_config_override = global_config.clone();
+    global_config_override.max_batch_duration = global_config.max_batch_duration / 2;
+    t.leader
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .get_mut(task_id)
+        .unwrap()
+        .global_config_override = Some(global_config_override);
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a batch interval that is permitted by the Leader's
+    // global config, but not by the task's override.
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: t.now
+                            - (t.now % task_config.time_precision)
+                            - global_config.max_batch_duration / 2,
+                        duration: global_config.max_batch_duration,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too large for the task.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too large".to_string()));
+}
+
+async_test_versions! { http_post_collect_fail_task_global_config_override }
+
+// Send a collect request with an overlapping batch interval.
+async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Create a report.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone()).await;
+
+    // Client: Send upload request to Leader.
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Run aggregation job.
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Run first collect job (expect success).
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    // run a second collect job (expect failure due to overlapping batch).
+    assert_matches!(
+        t.run_col_job(task_id, &query).await.unwrap_err(),
+        DapAbort::BatchOverlap
+    );
+}
+
+async_test_versions! { http_post_collect_fail_overlapping_batch_interval }
+
+// Test a successful collect request submission.
+// This checks that the Leader reponds with the collect ID with the ID associated to the request.
+async fn http_post_collect_success(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq.
+    let collector_collect_req = CollectReq {
+        task_id: task_id.clone(),
+        query: task_config.query_for_current_batch_window(t.now),
+        agg_param: Vec::default(),
+        collector_hpke_config_id: None,
+    };
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            collector_collect_req.clone(),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let url = t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (leader_collect_id, leader_collect_req) = &resp[0];
+
+    // Check that the CollectReq sent by Collector is the same that is received by Leader.
+    assert_eq!(&collector_collect_req, leader_collect_req);
+
+    // Check that the collect_id included in the URI is the same with the one received
+    // by Leader.
+    let path = url.path().to_string();
+    let mut router = Router::new();
+    router
+        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+        .unwrap();
+    let url_match = router.at(&path).unwrap();
+    let collector_collect_id = url_match.params.get("collect_id").unwrap();
+    assert_eq!(
+        collector_collect_id.to_string(),
+        leader_collect_id.to_base64url()
+    );
+}
+
+async_test_versions! { http_post_collect_success }
+
+// Test that the Leader only accepts a collect request with an empty aggregation parameter for a
+// Prio3 task.
+async fn http_post_collect_agg_param(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let collect_req_with = |agg_param: Vec<u8>| {
+        t.collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param,
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+    };
+
+    // Expect failure if the aggregation parameter is not empty.
+    let req = collect_req_with(b"bad agg param".to_vec()).await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Expect success if the aggregation parameter is empty.
+    let req = collect_req_with(Vec::default()).await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    assert_eq!(t.leader.get_pending_collect_jobs().await.unwrap().len(), 1);
+}
+
+async_test_versions! { http_post_collect_agg_param }
+
+// Test that the Leader rejects a collect request for a batch with reports that have not yet been
+// aggregated.
+async fn http_post_collect_fail_batch_not_ready(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let query = task_config.query_for_current_batch_window(t.now);
+
+    // Client: Send upload request to Leader.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Collector: Collecting the batch before the report is aggregated fails.
+    assert_matches!(
+        t.run_col_job(task_id, &query).await.unwrap_err(),
+        DapAbort::BatchNotReady
+    );
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Leader: Aggregate the report. The batch can now be collected.
+    t.run_agg_job(task_id).await.unwrap();
+    let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(collect_resp.report_count, 1);
+}
+
+async_test_versions! { http_post_collect_fail_batch_not_ready }
+
+// Test that the Leader limits the number of pending collect jobs per task.
+async fn http_post_collect_fail_too_many_pending_jobs(version: DapVersion) {
+    let mut t = Test::new(version);
+    t.leader.global_config.max_pending_collect_jobs = 2;
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let window = task_config.truncate_time(t.now);
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector: Request collection of the current batch window and the `i`-th preceding one.
+    let collect_req_for = |i| {
+        t.collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: window - i * task_config.time_precision,
+                        duration: task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+    };
+
+    // Collector: Create collect jobs up to the limit.
+    for i in 0..2 {
+        let req = collect_req_for(i).await;
+        t.leader.http_post_collect(&req).await.unwrap();
+    }
+
+    // Collector: The next collect job is rejected.
+    let req = collect_req_for(2).await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::TooManyRequests { .. }
+    );
+
+    // Leader: Complete the collect job for the current batch window.
+    let pending = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, collect_req) = pending
+        .iter()
+        .find(|(_collect_id, collect_req)| {
+            collect_req.query == task_config.query_for_current_batch_window(t.now)
+        })
+        .unwrap();
+    t.leader
+        .run_collect_job(collect_id, &task_config, collect_req)
+        .await
+        .unwrap();
+
+    // Collector: Now that a slot has been freed, the collect job is accepted.
+    t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_fail_too_many_pending_jobs }
+
+// Test that the Leader rejects a collect request whose batch overlaps with the batch of a pending
+// collect job.
+async fn http_post_collect_fail_overlapping_pending_job(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let window = task_config.truncate_time(t.now);
+
+    let collect_req_for = |start, duration| {
+        t.collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::TimeInterval {
+                    batch_interval: Interval { start, duration },
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+    };
+
+    let req = collect_req_for(window - 3600, 3600).await;
+    t.leader.http_post_collect(&req).await.unwrap();
+
+    // Collector: A batch that overlaps with the pending one is rejected.
+    let req = collect_req_for(window - 7200, 7200).await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::BatchOverlap
+    );
+
+    // Collector: An adjacent batch is accepted.
+    let req = collect_req_for(window, 3600).await;
+    t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_fail_overlapping_pending_job }
+
+// Test that the Collector can choose which of the task's HPKE configs the aggregate shares are
+// encrypted under.
+async fn http_post_collect_collector_hpke_config_id(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // Add a second Collector HPKE config to the task.
+    let other_collector_hpke_receiver_config = HpkeReceiverConfig::gen(
+        t.collector_hpke_receiver_config.config.id.wrapping_add(1),
+        HpkeKemId::X25519HkdfSha256,
+    )
+    .unwrap();
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .collector_hpke_configs
+            .push(other_collector_hpke_receiver_config.config.clone());
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let query = task_config.query_for_current_batch_window(t.now);
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    let collect_req_for = |path: &str| {
+        t.collector_authorized_req(
+            version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: query.clone(),
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join(path).unwrap(),
+        )
+    };
+
+    // Collector: Requests for unknown or malformed config IDs are rejected.
+    let unknown_config_id = other_collector_hpke_receiver_config
+        .config
+        .id
+        .wrapping_add(1);
+    for path in [
+        format!("collect?collector_hpke_config_id={unknown_config_id}"),
+        "collect?collector_hpke_config_id=nope".to_string(),
+    ] {
+        let req = collect_req_for(&path).await;
+        assert_matches!(
+            t.leader.http_post_collect(&req).await.unwrap_err(),
+            DapAbort::BadRequest(..)
+        );
+    }
+
+    // Collector: Request the aggregate shares under the second config.
+    let req = collect_req_for(&format!(
+        "collect?collector_hpke_config_id={}",
+        other_collector_hpke_receiver_config.config.id
+    ))
+    .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (collect_id, collect_req) = &resp[0];
+    assert_eq!(
+        collect_req.collector_hpke_config_id,
+        Some(other_collector_hpke_receiver_config.config.id)
+    );
+    t.leader
+        .run_collect_job(collect_id, &task_config, collect_req)
+        .await
+        .unwrap();
+    let collect_resp = assert_matches!(
+        t.leader.poll_collect_job(task_id, collect_id).await.unwrap(),
+        DapCollectJob::Done { collect_resp, .. } => collect_resp
+    );
+
+    // Collector: Both aggregate shares are encrypted under the second config.
+    let batch_sel = BatchSelector::try_from(query.clone()).unwrap();
+    assert!(task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
+            task_id,
+            &batch_sel,
+            collect_resp.report_count,
+            collect_resp.encrypted_agg_shares.clone(),
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .is_err());
+    let agg_res = task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &other_collector_hpke_receiver_config,
+            task_id,
+            &batch_sel,
+            collect_resp.report_count,
+            collect_resp.encrypted_agg_shares,
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_res, DapAggregateResult::U64(1));
+}
+
+async_test_versions! { http_post_collect_collector_hpke_config_id }
+
+// Test that the Leader handles queries from the Collector properly.
+async fn http_post_collect_invalid_query(version: DapVersion) {
+    let mut rng = thread_rng();
+    let t = Test::new(version);
+
+    // Leader expects "time_interval" query, but Collector sent "fixed_size".
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            &t.time_interval_task_id,
+            CollectReq {
+                task_id: t.time_interval_task_id.clone(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: Id(rng.gen()),
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::QueryMismatch {
+            expected: QueryKind::TimeInterval,
+            actual: QueryKind::FixedSize,
+        }
+    );
+
+    // Collector indicates unrecognized batch ID.
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            &t.fixed_size_task_id,
+            CollectReq {
+                task_id: t.fixed_size_task_id.clone(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: Id(rng.gen()), // Unrecognized batch ID
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::BatchInvalid
+    );
+}
+
+async_test_versions! { http_post_collect_invalid_query }
+
+// Test HTTP POST requests with a wrong DAP version.
+async fn http_post_fail_wrong_dap_version(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Send a request with the wrong DAP version.
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report).await;
+    req.version = DapVersion::Unknown;
+    req.url = task_config.leader_url.join("upload").unwrap();
+
+    let err = t.leader.http_post_upload(&req).await.unwrap_err();
+    assert_matches!(err, DapAbort::InvalidProtocolVersion);
+}
+
+async_test_versions! { http_post_fail_wrong_dap_version }
+
+// Test that the Leader rejects a collect request whose version doesn't match the task's, even if
+// the message was encoded for the task's version and doesn't decode under the request's version.
+async fn http_post_collect_fail_version_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq encoded for the task's version, but send it under another
+    // version. The encoding of fixed-size queries differs between the versions.
+    let mut req = t
+        .collector_authorized_req(
+            task_config.version,
+            MEDIA_TYPE_COLLECT_REQ,
+            task_id,
+            CollectReq {
+                task_id: task_id.clone(),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: Id([0; 32]),
+                },
+                agg_param: Vec::default(),
+                collector_hpke_config_id: None,
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    req.version = match version {
+        DapVersion::Draft02 => DapVersion::Draft03,
+        _ => DapVersion::Draft02,
+    };
+    assert!(CollectReq::get_decoded_with_param(&req.version, &req.payload).is_err());
 
-    // Expect failure due to incorrect number of input shares
     assert_matches!(
-        t.leader.http_post_upload(&req).await,
-        Err(DapAbort::UnrecognizedMessage)
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::InvalidProtocolVersion
     );
 }
 
-async_test_versions! { http_post_upload_fail_send_invalid_report }
+async_test_versions! { http_post_collect_fail_version_mismatch }
 
-// Test that the Leader rejects reports past the expiration date.
-async fn http_post_upload_task_expired(version: DapVersion) {
+async fn http_post_upload(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.expired_task_id;
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+}
+
+async_test_versions! { http_post_upload }
+
+// Test that an upload request constructed with the request builder is accepted.
+async fn http_post_upload_request_builder(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
-    let req = DapRequest {
-        version: task_config.version,
-        media_type: Some(MEDIA_TYPE_REPORT),
-        task_id: Some(task_id.clone()),
-        payload: report.get_encoded(),
-        url: task_config.leader_url.join("upload").unwrap(),
-        sender_auth: None,
-    };
+    let report_id = report.metadata.id.clone();
+    let req = DapRequest::<BearerToken>::builder()
+        .version(version)
+        .media_type(MEDIA_TYPE_REPORT)
+        .task_id(task_id.clone())
+        .payload(report.get_encoded())
+        .url(task_config.leader_url.join("upload").unwrap())
+        .build()
+        .unwrap();
+
+    t.leader.http_post_upload(&req).await.unwrap();
+    assert_eq!(
+        t.leader.report_status(task_id, &report_id).await.unwrap(),
+        ReportStatus::Pending
+    );
 
+    // The DAP version and URL are required.
     assert_matches!(
-        t.leader.http_post_upload(&req).await.unwrap_err(),
-        DapAbort::ReportTooLate
+        DapRequest::<BearerToken>::builder()
+            .url(task_config.leader_url.clone())
+            .build(),
+        Err(DapError::Fatal(..))
+    );
+    assert_matches!(
+        DapRequest::<BearerToken>::builder()
+            .version(version)
+            .build(),
+        Err(DapError::Fatal(..))
     );
 }
 
-async_test_versions! { http_post_upload_task_expired }
+async_test_versions! { http_post_upload_request_builder }
 
-async fn get_reports_empty_response(version: DapVersion) {
+async fn http_post_upload_fixed_size_rollover(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+
+    // Client: Send three upload requests to Leader. The maximum batch size is 2, so the third
+    // report is assigned to a new batch.
+    for _ in 0..3 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+
+    let mut batch_sizes: Vec<usize> = t
+        .leader
+        .report_store
+        .lock()
+        .expect("report_store: failed to lock")
+        .get(task_id)
+        .unwrap()
+        .pending
+        .iter()
+        .map(|(bucket, queue)| match bucket {
+            DapBatchBucketOwned::FixedSize { .. } => queue.len(),
+            DapBatchBucketOwned::TimeInterval { .. } => panic!("unexpected bucket"),
+        })
+        .collect();
+    batch_sizes.sort();
+    assert_eq!(batch_sizes, vec![1, 2]);
+}
+
+async_test_versions! { http_post_upload_fixed_size_rollover }
+
+async fn e2e_time_interval(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
-    let req = t.gen_test_upload_req(report.clone()).await;
+    let req = t.gen_test_upload_req(report).await;
 
-    // Upload report.
-    t.leader
-        .http_post_upload(&req)
-        .await
-        .expect("upload failed unexpectedly");
+    // Client: Send upload request to Leader.
+    t.leader.http_post_upload(&req).await.unwrap();
 
-    // Get one report. This should return with the report that was uploaded earlier.
-    // We also check that the task ID associated to the report is the same one we
-    // requested.
-    let report_sel = MockAggregatorReportSelector(task_id.clone());
-    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
-    assert_eq!(reports.len(), 1);
-    assert_eq!(&returned_task_id, task_id);
+    // Leader: Run aggregation job.
+    t.run_agg_job(task_id).await.unwrap();
 
-    // Try to get another report. This should not return an error, but simply
-    // an empty vector, as we drained the ReportStore above. The task ID
-    // associated to the report should be the same one we requested.
-    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
-    assert_eq!(reports.len(), 0);
-    assert_eq!(&returned_task_id, task_id);
+    // Collector: Create collection job and poll result.
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
 }
 
-async_test_versions! { get_reports_empty_response }
+async_test_versions! { e2e_time_interval }
 
-async fn poll_collect_job_test_results(version: DapVersion) {
+// Test that a single collect response lets the Collector recover both each Aggregator's aggregate
+// share and the aggregate result, and that the two are consistent.
+async fn e2e_time_interval_raw_and_combined(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq.
-    let version = task_config.version.clone();
-    let req = t
-        .collector_authorized_req(
-            version,
-            MEDIA_TYPE_COLLECT_REQ,
+    // Client: Send upload requests to Leader. Leader: Run an aggregation job for each report.
+    for _ in 0..3 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    // Collector: Create collection job and poll result.
+    let query = task_config.query_for_current_batch_window(t.now);
+    let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+    let batch_sel = BatchSelector::try_from(query).unwrap();
+
+    // Collector: Get the combined result.
+    let agg_res = task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
             task_id,
-            CollectReq {
-                task_id: task_id.clone(),
-                query: task_config.query_for_current_batch_window(t.now),
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
+            &batch_sel,
+            collect_resp.report_count,
+            collect_resp.encrypted_agg_shares.clone(),
+            AggShareCompression::None,
+            version,
         )
-        .await;
+        .await
+        .unwrap();
 
-    // Leader: Handle the CollectReq received from Collector.
-    t.leader.http_post_collect(&req).await.unwrap();
+    // Collector: Get the raw shares from the same response, one per Aggregator.
+    let agg_shares = task_config
+        .vdaf
+        .decrypt_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
+            task_id,
+            &batch_sel,
+            collect_resp.encrypted_agg_shares,
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_shares.len(), 2);
 
-    // Expect DapCollectJob::Unknown due to invalid collect ID.
+    // Collector: Unsharding the raw shares yields the combined result.
     assert_eq!(
-        t.leader
-            .poll_collect_job(task_id, &Id::default())
-            .await
+        task_config
+            .vdaf
+            .unshard_agg_shares(collect_resp.report_count, agg_shares)
             .unwrap(),
-        DapCollectJob::Unknown
+        agg_res
     );
+}
 
-    // Leader: Get pending collect job to obtain collect_id
-    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
-    let (collect_id, _collect_req) = &resp[0];
-    let collect_resp = CollectResp {
-        part_batch_sel: PartialBatchSelector::TimeInterval,
-        report_count: 0,
-        encrypted_agg_shares: Vec::default(),
-    };
+async_test_versions! { e2e_time_interval_raw_and_combined }
 
-    // Expect DapCollectJob::Pending due to pending collect job.
-    assert_eq!(
+// Test that a report uploaded after its batch window has started aggregating, but with an earlier
+// timestamp than the reports already aggregated, is folded into the window's aggregate share, and
+// that a late report for a collected window is rejected.
+async fn e2e_time_interval_late_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let window_start = task_config.truncate_time(t.now);
+    let hpke_config_list = [
         t.leader
-            .poll_collect_job(task_id, &collect_id)
+            .get_hpke_config_for(Some(task_id))
             .await
-            .unwrap(),
-        DapCollectJob::Pending
-    );
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    let late_report = || {
+        task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                window_start,
+                task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap()
+    };
 
-    // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
-    t.leader
-        .finish_collect_job(&task_id, &collect_id, &collect_resp)
-        .await
-        .unwrap();
+    // Client: Send upload request to Leader and aggregate it.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
 
-    // Expect DapCollectJob::Done due to processed collect job.
+    // Client: Send a report with an earlier timestamp in the same, uncollected window and
+    // aggregate it.
+    let req = t.gen_test_upload_req(late_report()).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector: Expect both reports to be included in the batch.
+    let query = task_config.query_for_current_batch_window(t.now);
+    let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(collect_resp.report_count, 2);
+
+    // Client: Expect a late report for the collected window to be rejected.
+    let req = t.gen_test_upload_req(late_report()).await;
     assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::StaleReport
+    );
+}
+
+async_test_versions! { e2e_time_interval_late_report }
+
+// Test that, if the storage precision is finer than the time precision, aggregate shares are
+// stored in sub-buckets of the batch window and are summed when the batch window is collected.
+async fn e2e_time_interval_storage_precision(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .get_mut(task_id)
+            .unwrap()
+            .storage_precision = Some(900);
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert_eq!(task_config.time_precision, 3600);
+    assert_eq!(task_config.bucket_precision(), 900);
+    let window_start = task_config.truncate_time(t.now);
+    let hpke_config_list = [
         t.leader
-            .poll_collect_job(task_id, &collect_id)
+            .get_hpke_config_for(Some(task_id))
             .await
-            .unwrap(),
-        DapCollectJob::Done(..)
-    );
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+
+    // Client: Send upload requests to Leader for reports in the first two 15-minute sub-buckets of
+    // the batch window.
+    for time in [window_start, window_start + 60, window_start + 900] {
+        let report = task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                time,
+                task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    // Expect the Aggregators to store the aggregate shares in two sub-buckets.
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let agg_store = guard.get(task_id).unwrap();
+        assert_eq!(agg_store.len(), 2);
+        for (batch_window, report_count) in [(window_start, 2), (window_start + 900, 1)] {
+            assert_eq!(
+                agg_store
+                    .get(&DapBatchBucketOwned::TimeInterval { batch_window })
+                    .unwrap()
+                    .agg_share
+                    .report_count,
+                report_count
+            );
+        }
+    }
+
+    // Collector: Expect the collected batch window to include the reports from both sub-buckets.
+    let query = task_config.query_for_current_batch_window(t.now);
+    let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(collect_resp.report_count, 3);
 }
 
-async_test_versions! { poll_collect_job_test_results }
+async_test_versions! { e2e_time_interval_storage_precision }
 
-async fn http_post_collect_fail_invalid_batch_interval(version: DapVersion) {
+// Test that collecting a batch interval that spans several batch windows sums the aggregate
+// shares of each window and marks each of them as collected by both Aggregators.
+async fn e2e_time_interval_multi_bucket_collect(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let batch_windows = [
+        task_config.truncate_time(t.now) - 2 * task_config.time_precision,
+        task_config.truncate_time(t.now) - task_config.time_precision,
+        task_config.truncate_time(t.now),
+    ];
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(task_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
 
-    // Collector: Create a CollectReq with a very large batch interval.
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            task_id,
-            CollectReq {
-                task_id: task_id.clone(),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: t.now - (t.now % task_config.time_precision),
-                        duration: t.leader.global_config.max_batch_duration
-                            + task_config.time_precision,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
-        )
-        .await;
+    // Client: Send upload requests to Leader for a report in each of three batch windows.
+    for batch_window in batch_windows {
+        let report = task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                batch_window,
+                task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    // Collector: Expect the batch interval spanning the three windows to include each report.
+    let query = Query::TimeInterval {
+        batch_interval: Interval {
+            start: batch_windows[0],
+            duration: 3 * task_config.time_precision,
+        },
+    };
+    let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(collect_resp.report_count, 3);
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+    // Expect each batch window to have been marked as collected.
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let agg_store = guard.get(task_id).unwrap();
+        for batch_window in batch_windows {
+            assert!(
+                agg_store
+                    .get(&DapBatchBucketOwned::TimeInterval { batch_window })
+                    .unwrap()
+                    .collected
+            );
+        }
+    }
+}
 
-    // Fails because the requested batch interval is too large.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too large".to_string()));
+async_test_versions! { e2e_time_interval_multi_bucket_collect }
 
-    // Collector: Create a CollectReq with a batch interval in the past.
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            task_id,
-            CollectReq {
-                task_id: task_id.clone(),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: t.now
-                            - (t.now % task_config.time_precision)
-                            - t.leader.global_config.min_batch_interval_start
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
-        )
-        .await;
+// Test that the checksum computed from the report IDs matches the checksum of the aggregate share
+// produced by the aggregation flow for the same reports.
+async fn e2e_compute_checksum(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+    // Client: Send upload requests to Leader. Leader: Run an aggregation job for each report.
+    let mut report_ids = Vec::new();
+    for _ in 0..3 {
+        let report = t.gen_test_report(task_id).await;
+        report_ids.push(report.metadata.id.clone());
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
 
-    // Fails because the requested batch interval is too far into the past.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into past".to_string()));
+    let expected_checksum = compute_checksum(&report_ids);
+    assert_ne!(expected_checksum, [0; 32]);
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let mut agg_share = DapAggregateShare::default();
+        for agg_store in guard.get(task_id).unwrap().values() {
+            agg_share.merge(agg_store.agg_share.clone()).unwrap();
+        }
+        assert_eq!(agg_share.report_count, 3);
+        assert_eq!(agg_share.checksum, expected_checksum);
+    }
+}
 
-    // Collector: Create a CollectReq with a batch interval in the future.
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            task_id,
-            CollectReq {
-                task_id: task_id.clone(),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: t.now - (t.now % task_config.time_precision)
-                            + t.leader.global_config.max_batch_interval_end
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
+async_test_versions! { e2e_compute_checksum }
+
+// Test that a Leader and Helper configured with the same checksum algorithm compute the same
+// checksum for a batch and complete the collection.
+async fn e2e_checksum_algorithm(version: DapVersion) {
+    for checksum_algorithm in [ChecksumAlgorithm::Sha256Xor] {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        for aggregator in [&t.leader, &t.helper] {
+            aggregator
+                .tasks
+                .lock()
+                .expect("tasks: failed to lock")
+                .get_mut(task_id)
+                .unwrap()
+                .global_config_override = Some(DapGlobalConfig {
+                checksum_algorithm,
+                ..aggregator.global_config.clone()
+            });
+        }
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Client: Send upload requests to Leader. Leader: Run an aggregation job for each report.
+        let mut report_ids = Vec::new();
+        for _ in 0..3 {
+            let report = t.gen_test_report(task_id).await;
+            report_ids.push(report.metadata.id.clone());
+            let req = t.gen_test_upload_req(report).await;
+            t.leader.http_post_upload(&req).await.unwrap();
+            t.run_agg_job(task_id).await.unwrap();
+        }
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+        // Expect both Aggregators to have computed the checksum with the configured algorithm.
+        let expected_checksum = checksum_algorithm.compute(&report_ids);
+        for aggregator in [&t.leader, &t.helper] {
+            let guard = aggregator
+                .agg_store
+                .lock()
+                .expect("agg_store: failed to lock");
+            let mut agg_share = DapAggregateShare::default();
+            for agg_store in guard.get(task_id).unwrap().values() {
+                agg_share.merge(agg_store.agg_share.clone()).unwrap();
+            }
+            assert_eq!(agg_share.checksum, expected_checksum);
+        }
 
-    // Fails because the requested batch interval is too far into the future.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into future".to_string()));
+        // Collector: Expect the Helper to accept the Leader's checksum.
+        let query = task_config.query_for_current_batch_window(t.now);
+        let collect_resp = t.run_col_job(task_id, &query).await.unwrap();
+        assert_eq!(collect_resp.report_count, 3);
+    }
 }
 
-async_test_versions! { http_post_collect_fail_invalid_batch_interval }
+async_test_versions! { e2e_checksum_algorithm }
 
-async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
+// Test that the Helper aborts if the report count in the aggregate share request doesn't match the
+// number of reports it aggregated, even if the checksum matches.
+async fn http_post_aggregate_share_abort_report_count_mismatch(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq with a very large batch interval.
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
+    // Client: Send upload requests to Leader. Leader: Run an aggregation job for each report.
+    let mut report_ids = Vec::new();
+    for _ in 0..3 {
+        let report = t.gen_test_report(task_id).await;
+        report_ids.push(report.metadata.id.clone());
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    let agg_share_req_for = |report_count| {
+        t.leader_authorized_req_with_version(
             task_id,
-            CollectReq {
+            version,
+            MEDIA_TYPE_AGG_SHARE_REQ,
+            AggregateShareReq {
                 task_id: task_id.clone(),
-                query: Query::TimeInterval {
+                batch_sel: BatchSelector::TimeInterval {
                     batch_interval: Interval {
-                        start: t.now
-                            - (t.now % task_config.time_precision)
-                            - t.leader.global_config.max_batch_duration / 2,
-                        duration: t.leader.global_config.max_batch_duration,
+                        start: task_config.truncate_time(t.now),
+                        duration: task_config.time_precision,
                     },
                 },
                 agg_param: Vec::default(),
+                report_count,
+                checksum: compute_checksum(&report_ids),
             },
-            task_config.leader_url.join("collect").unwrap(),
+            task_config.helper_url.join("aggregate_share").unwrap(),
         )
-        .await;
+    };
 
-    // Leader: Handle the CollectReq received from Collector.
-    let _collect_uri = t.leader.http_post_collect(&req).await.unwrap();
+    // Leader claims fewer reports than were folded into the checksum.
+    let req = agg_share_req_for(2).await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        DapAbort::BatchMismatch
+    );
+
+    // Leader claims more reports than were folded into the checksum.
+    let req = agg_share_req_for(4).await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await.unwrap_err(),
+        DapAbort::BatchMismatch
+    );
+
+    // The request succeeds once the report count is consistent with the checksum.
+    let req = agg_share_req_for(3).await;
+    t.helper.http_post_aggregate_share(&req).await.unwrap();
 }
 
-async_test_versions! { http_post_collect_succeed_max_batch_interval }
+async_test_versions! { http_post_aggregate_share_abort_report_count_mismatch }
 
-// Send a collect request with an overlapping batch interval.
-async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion) {
+async fn remaining_batch_queries(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Create a report.
     let report = t.gen_test_report(task_id).await;
-    let req = t.gen_test_upload_req(report.clone()).await;
-
-    // Client: Send upload request to Leader.
+    let req = t.gen_test_upload_req(report).await;
     t.leader.http_post_upload(&req).await.unwrap();
-
-    // Leader: Run aggregation job.
     t.run_agg_job(task_id).await.unwrap();
 
-    // Run first collect job (expect success).
+    // Leader and Helper: The batch has not been collected yet.
     let query = task_config.query_for_current_batch_window(t.now);
+    let batch_sel = BatchSelector::try_from(query.clone()).unwrap();
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(
+            aggregator
+                .remaining_batch_queries(task_id, &batch_sel)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    // Collector: Collect the batch.
     t.run_col_job(task_id, &query).await.unwrap();
 
-    // run a second collect job (expect failure due to overlapping batch).
-    assert_matches!(
-        t.run_col_job(task_id, &query).await.unwrap_err(),
-        DapAbort::BatchOverlap
-    );
+    // Leader and Helper: The batch may not be collected again.
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(
+            aggregator
+                .remaining_batch_queries(task_id, &batch_sel)
+                .await
+                .unwrap(),
+            0
+        );
+    }
 }
 
-async_test_versions! { http_post_collect_fail_overlapping_batch_interval }
+async_test_versions! { remaining_batch_queries }
 
-// Test a successful collect request submission.
-// This checks that the Leader reponds with the collect ID with the ID associated to the request.
-async fn http_post_collect_success(version: DapVersion) {
+async fn leader_process_task(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
-
-    // Collector: Create a CollectReq.
-    let collector_collect_req = CollectReq {
-        task_id: task_id.clone(),
-        query: task_config.query_for_current_batch_window(t.now),
-        agg_param: Vec::default(),
-    };
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            task_id,
-            collector_collect_req.clone(),
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
-
-    // Leader: Handle the CollectReq received from Collector.
-    let url = t.leader.http_post_collect(&req).await.unwrap();
-    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
-    let (leader_collect_id, leader_collect_req) = &resp[0];
-
-    // Check that the CollectReq sent by Collector is the same that is received by Leader.
-    assert_eq!(&collector_collect_req, leader_collect_req);
-
-    // Check that the collect_id included in the URI is the same with the one received
-    // by Leader.
-    let path = url.path().to_string();
-    let mut router = Router::new();
-    router
-        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
-        .unwrap();
-    let url_match = router.at(&path).unwrap();
-    let collector_collect_id = url_match.params.get("collect_id").unwrap();
-    assert_eq!(
-        collector_collect_id.to_string(),
-        leader_collect_id.to_base64url()
-    );
-}
 
-async_test_versions! { http_post_collect_success }
+    // Client: Send upload requests to Leader.
+    for _ in 0..3 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
 
-// Test that the Leader handles queries from the Collector properly.
-async fn http_post_collect_invalid_query(version: DapVersion) {
-    let mut rng = thread_rng();
-    let t = Test::new(version);
+    // Leader: Run aggregation jobs for the task. Requests are routed to the Helper.
+    let agg_job_report = t.leader.process_task(task_id).await.unwrap();
+    assert_eq!(agg_job_report.reports_succeeded, 3);
+    assert_eq!(agg_job_report.reports_failed, 0);
 
-    // Leader expects "time_interval" query, but Collector sent "fixed_size".
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.time_interval_task_id)
-        .await;
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            &t.time_interval_task_id,
-            CollectReq {
-                task_id: t.time_interval_task_id.clone(),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: Id(rng.gen()),
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
-    assert_matches!(
-        t.leader.http_post_collect(&req).await.unwrap_err(),
-        DapAbort::QueryMismatch
-    );
+    // Leader and Helper: Check that the output shares were committed.
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let report_count: u64 = guard
+            .get(task_id)
+            .unwrap()
+            .values()
+            .map(|agg_store| agg_store.agg_share.report_count)
+            .sum();
+        assert_eq!(report_count, 3);
+    }
 
-    // Collector indicates unrecognized batch ID.
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.fixed_size_task_id)
-        .await;
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            MEDIA_TYPE_COLLECT_REQ,
-            &t.fixed_size_task_id,
-            CollectReq {
-                task_id: t.fixed_size_task_id.clone(),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: Id(rng.gen()), // Unrecognized batch ID
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
-    assert_matches!(
-        t.leader.http_post_collect(&req).await.unwrap_err(),
-        DapAbort::BatchInvalid
-    );
+    // Leader: There are no more reports to process.
+    let agg_job_report = t.leader.process_task(task_id).await.unwrap();
+    assert_eq!(agg_job_report.reports_succeeded, 0);
+    assert_eq!(agg_job_report.reports_failed, 0);
 }
 
-async_test_versions! { http_post_collect_invalid_query }
+async_test_versions! { leader_process_task }
 
-// Test HTTP POST requests with a wrong DAP version.
-async fn http_post_fail_wrong_dap_version(version: DapVersion) {
+// Test that the aggregation job report counts the reports that fail aggregation.
+async fn leader_process_task_report_failed(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Send a request with the wrong DAP version.
+    // Client: Send a valid report and a report whose Helper input share is corrupted.
     let report = t.gen_test_report(task_id).await;
-    let mut req = t.gen_test_upload_req(report).await;
-    req.version = DapVersion::Unknown;
-    req.url = task_config.leader_url.join("upload").unwrap();
-
-    let err = t.leader.http_post_upload(&req).await.unwrap_err();
-    assert_matches!(err, DapAbort::InvalidProtocolVersion);
-}
-
-async_test_versions! { http_post_fail_wrong_dap_version }
-
-async fn http_post_upload(version: DapVersion) {
-    let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
+    let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
 
-    let report = t.gen_test_report(task_id).await;
+    let mut report = t.gen_test_report(task_id).await;
+    let corrupted_report_id = report.metadata.id.clone();
+    report.encrypted_input_shares[1].payload[0] ^= 1;
     let req = t.gen_test_upload_req(report).await;
+    t.leader.http_post_upload(&req).await.unwrap();
 
-    t.leader
-        .http_post_upload(&req)
-        .await
-        .expect("upload failed unexpectedly");
+    // Leader: Run aggregation jobs for the task. The Helper fails to decrypt the corrupted report.
+    let agg_job_report = t.leader.process_task(task_id).await.unwrap();
+    assert_eq!(agg_job_report.reports_succeeded, 1);
+    assert_eq!(agg_job_report.reports_failed, 1);
+    assert_eq!(
+        t.leader
+            .report_status(task_id, &corrupted_report_id)
+            .await
+            .unwrap(),
+        ReportStatus::Rejected(TransitionFailure::HpkeDecryptError)
+    );
 }
 
-async_test_versions! { http_post_upload }
+async_test_versions! { leader_process_task_report_failed }
 
-async fn e2e_time_interval(version: DapVersion) {
+async fn leader_run_agg_job_in_process_helper(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
+    let task_id = &t.fixed_size_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
+    // Client: Send upload request to Leader.
     let report = t.gen_test_report(task_id).await;
     let req = t.gen_test_upload_req(report).await;
-
-    // Client: Send upload request to Leader.
     t.leader.http_post_upload(&req).await.unwrap();
 
-    // Leader: Run aggregation job.
-    t.run_agg_job(task_id).await.unwrap();
+    // Leader: Wire a Leader to the Helper via the in-process client and run an aggregation job.
+    let leader = MockAggregator {
+        peer: Some(InProcessHttpClient::new(Arc::new(t.helper.clone()))),
+        ..t.leader.clone()
+    };
+    let report_sel = MockAggregatorReportSelector::from(task_id.clone());
+    let (task_id, part_batch_sel, reports) = get_reports!(leader, &report_sel);
+    assert_eq!(
+        leader
+            .run_agg_job(&task_id, &task_config, &part_batch_sel, reports)
+            .await
+            .unwrap(),
+        1
+    );
 
-    // Collector: Create collection job and poll result.
-    let query = task_config.query_for_current_batch_window(t.now);
-    t.run_col_job(task_id, &query).await.unwrap();
+    // Leader and Helper: Check that the output shares were committed to the same batch.
+    let bucket = DapBatchBucketOwned::FixedSize {
+        batch_id: t.leader.current_batch_id(&task_id, &task_config).unwrap(),
+    };
+    for aggregator in [&t.leader, &t.helper] {
+        let guard = aggregator
+            .agg_store
+            .lock()
+            .expect("agg_store: failed to lock");
+        let agg_store = guard.get(&task_id).unwrap().get(&bucket).unwrap();
+        assert_eq!(agg_store.agg_share.report_count, 1);
+    }
+
+    // The in-process client only routes requests for the Helper's endpoints.
+    let req: DapRequest<BearerToken> = DapRequest {
+        version,
+        media_type: Some(MEDIA_TYPE_COLLECT_REQ),
+        task_id: Some(task_id.clone()),
+        payload: Vec::default(),
+        url: task_config.helper_url.join("collect").unwrap(),
+        sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
+    };
+    assert_matches!(
+        leader.peer.as_ref().unwrap().post(req).await,
+        Err(DapError::Fatal(..))
+    );
 }
 
-async_test_versions! { e2e_time_interval }
+async_test_versions! { leader_run_agg_job_in_process_helper }
 
 async fn e2e_fixed_size(version: DapVersion) {
     let t = Test::new(version);
@@ -1505,6 +5200,322 @@ async fn e2e_fixed_size(version: DapVersion) {
 
 async_test_versions! { e2e_fixed_size }
 
+async fn leader_ready_batches(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+
+    // Client: Send upload requests to Leader. The maximum batch size is 2, so two batches are
+    // filled and a third batch is started.
+    for _ in 0..5 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+
+    // No batch is ready before the reports are aggregated.
+    assert_eq!(t.leader.ready_batches(task_id).await.unwrap(), vec![]);
+
+    // Leader: Aggregate all pending reports. The third batch is not full, so it is not ready, even
+    // though it has reached the minimum batch size.
+    t.leader.process_task(task_id).await.unwrap();
+    let ready_batch_ids = t.leader.ready_batches(task_id).await.unwrap();
+    assert_eq!(ready_batch_ids.len(), 2);
+
+    // Collector: Collect the first batch. Only the second batch remains ready.
+    let query = Query::FixedSizeByBatchId {
+        batch_id: ready_batch_ids[0].clone(),
+    };
+    t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(
+        t.leader.ready_batches(task_id).await.unwrap(),
+        vec![ready_batch_ids[1].clone()]
+    );
+}
+
+async_test_versions! { leader_ready_batches }
+
+// Test that a report selector with a batch filter only selects reports from the requested batch.
+async fn leader_get_reports_with_batch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+
+    // Client: Send upload requests to Leader. The maximum batch size is 2, so the reports are
+    // assigned to two batches.
+    for _ in 0..4 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+    let pending_report_ids = |bucket: &DapBatchBucketOwned| -> Vec<ReportId> {
+        let guard = t
+            .leader
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        guard[task_id]
+            .pending
+            .get(bucket)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .map(|report| report.metadata.id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let buckets: Vec<DapBatchBucketOwned> = t
+        .leader
+        .report_store
+        .lock()
+        .expect("report_store: failed to lock")[task_id]
+        .pending
+        .keys()
+        .cloned()
+        .collect();
+    assert_eq!(buckets.len(), 2);
+    let other_report_ids = pending_report_ids(&buckets[1]);
+
+    // Leader: Select the reports of the first batch one at a time.
+    let report_sel = MockAggregatorReportSelector::with_batch(task_id.clone(), buckets[0].clone());
+    let part_batch_sel = PartialBatchSelector::from(buckets[0].clone());
+    for report_id in pending_report_ids(&buckets[0]) {
+        let reports = t.leader.get_reports(&report_sel).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[task_id].len(), 1);
+        let reports = &reports[task_id][&part_batch_sel];
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].metadata.id, report_id);
+    }
+
+    // The first batch is drained and the reports of the second batch are still pending.
+    assert!(t.leader.get_reports(&report_sel).await.unwrap().is_empty());
+    assert_eq!(pending_report_ids(&buckets[1]), other_report_ids);
+    assert_eq!(other_report_ids.len(), 2);
+}
+
+async_test_versions! { leader_get_reports_with_batch }
+
+async fn http_get_fixed_size_batches(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+
+    // Client: Send upload requests to Leader. The maximum batch size is 2, so two batches are
+    // closed.
+    for _ in 0..4 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+    t.leader.process_task(task_id).await.unwrap();
+    let batch_ids = t
+        .get_fixed_size_batches(task_id, t.collector_token.clone())
+        .await
+        .unwrap();
+    assert_eq!(batch_ids.len(), 2);
+
+    // Client: Send more upload requests. The reports are assigned to a new batch, which is not
+    // listed until it is full and its reports are aggregated.
+    for _ in 0..2 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        assert_eq!(
+            t.get_fixed_size_batches(task_id, t.collector_token.clone())
+                .await
+                .unwrap(),
+            batch_ids
+        );
+    }
+
+    t.leader.process_task(task_id).await.unwrap();
+    let new_batch_ids = t
+        .get_fixed_size_batches(task_id, t.collector_token.clone())
+        .await
+        .unwrap();
+    assert_eq!(new_batch_ids.len(), 3);
+    assert_eq!(new_batch_ids[..2], batch_ids);
+
+    // Expect failure if the request is not authorized by the Collector.
+    assert_matches!(
+        t.get_fixed_size_batches(
+            task_id,
+            BearerToken::from("incorrect auth token!".to_string())
+        )
+        .await,
+        Err(DapAbort::UnauthorizedRequest)
+    );
+}
+
+async_test_versions! { http_get_fixed_size_batches }
+
+// Test that the Leader collects a set of fixed-size batches by running a collect job for each
+// batch, and that the Collector can combine the results.
+async fn leader_collect_batches(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Fill and aggregate two batches. The maximum batch size for the task is 2, so every two
+    // reports fill a batch.
+    for _ in 0..4 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+    let batch_ids = t.leader.ready_batches(task_id).await.unwrap();
+    assert_eq!(batch_ids.len(), 2);
+
+    // Leader: Expect failure if a batch is listed twice or is not recognized. No collect job is
+    // initialized for the other batches.
+    assert_matches!(
+        t.leader
+            .init_collect_jobs_for_batches(
+                task_id,
+                &[batch_ids[0].clone(), batch_ids[0].clone()],
+                &[],
+                None
+            )
+            .await,
+        Err(DapAbort::BatchInvalid)
+    );
+    assert_matches!(
+        t.leader
+            .init_collect_jobs_for_batches(
+                task_id,
+                &[batch_ids[0].clone(), Id(thread_rng().gen())],
+                &[],
+                None
+            )
+            .await,
+        Err(DapAbort::BatchInvalid)
+    );
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Leader: Initialize and run a collect job for each batch.
+    let collect_uris = t
+        .leader
+        .init_collect_jobs_for_batches(task_id, &batch_ids, &[], None)
+        .await
+        .unwrap();
+    assert_eq!(collect_uris.len(), 2);
+    let pending = t.leader.get_pending_collect_jobs().await.unwrap();
+    assert_eq!(pending.len(), 2);
+    for (collect_id, collect_req) in pending.iter() {
+        t.leader
+            .run_collect_job(collect_id, &task_config, collect_req)
+            .await
+            .unwrap();
+    }
+
+    // Collector: Combine the results of the collect jobs.
+    let mut report_count = 0;
+    let mut agg_res: Option<DapAggregateResult> = None;
+    for (collect_id, collect_req) in pending.into_iter() {
+        let collect_resp = assert_matches!(
+            t.leader.poll_collect_job(task_id, &collect_id).await.unwrap(),
+            DapCollectJob::Done { collect_resp, .. } => collect_resp
+        );
+        let batch_agg_res = task_config
+            .vdaf
+            .consume_encrypted_agg_shares(
+                &t.collector_hpke_receiver_config,
+                task_id,
+                &BatchSelector::try_from(collect_req.query).unwrap(),
+                collect_resp.report_count,
+                collect_resp.encrypted_agg_shares,
+                AggShareCompression::None,
+                version,
+            )
+            .await
+            .unwrap();
+        report_count += collect_resp.report_count;
+        agg_res = Some(match agg_res {
+            Some(agg_res) => agg_res.merge(batch_agg_res).unwrap(),
+            None => batch_agg_res,
+        });
+    }
+    assert_eq!(report_count, 4);
+    assert_eq!(agg_res, Some(DapAggregateResult::U64(4)));
+
+    // Collector: Each of the batches has now been collected.
+    for batch_id in batch_ids.into_iter() {
+        let query = Query::FixedSizeByBatchId { batch_id };
+        assert_matches!(
+            t.run_col_job(task_id, &query).await,
+            Err(DapAbort::BatchOverlap)
+        );
+    }
+}
+
+async_test_versions! { leader_collect_batches }
+
+// Test that collecting a fixed-size batch fails if the reports in the batch are too far apart.
+async fn e2e_fixed_size_batch_duration_exceeded(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = Id(thread_rng().gen());
+    let mut task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    task_config.min_batch_size = 2;
+    let mut global_config = t.leader.global_config.clone();
+    global_config.max_batch_duration = 3600;
+    global_config.enforce_fixed_size_batch_duration = true;
+    task_config.global_config_override = Some(global_config);
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .expect("tasks: failed to lock")
+            .insert(task_id.clone(), task_config.clone());
+    }
+
+    // Upload two reports that are two hours apart. The maximum batch size is 2, so both reports
+    // are assigned to the same batch.
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(&task_id))
+            .await
+            .unwrap()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(&task_id))
+            .await
+            .unwrap()
+            .clone(),
+    ];
+    for time in [t.now - 7200, t.now] {
+        let report = task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                time,
+                &task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+        let req = t.gen_test_upload_req(report).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(&task_id).await.unwrap();
+    }
+
+    let batch_id = t.leader.current_batch_id(&task_id, &task_config).unwrap();
+    let query = Query::FixedSizeByBatchId { batch_id };
+    assert_matches!(
+        t.run_col_job(&task_id, &query).await,
+        Err(DapAbort::BatchInvalid)
+    );
+}
+
+async_test_versions! { e2e_fixed_size_batch_duration_exceeded }
+
 async fn e2e_taskprov(version: DapVersion) {
     let t = Test::new(version);
     let vdaf = VdafConfig::Prio3(Prio3Config::Count);
@@ -1575,6 +5586,8 @@ async fn e2e_taskprov(version: DapVersion) {
         payload: report.get_encoded(),
         url: Url::parse("https://cool.biz/upload").unwrap(),
         sender_auth: None,
+        idempotency_key: None,
+        collector_hpke_config_id: None,
     };
     t.leader.http_post_upload(&req).await.unwrap();
 
@@ -1592,3 +5605,147 @@ async fn e2e_taskprov(version: DapVersion) {
 }
 
 async_test_versions! { e2e_taskprov }
+
+// Send the Helper an AggregateInitializeReq for a taskprov task whose config is derived from a
+// valid one by `mutate`, and check that the Helper opts out of the task.
+async fn http_post_aggregate_taskprov_opt_out(
+    t: &Test,
+    version: DapVersion,
+    mutate: impl FnOnce(&mut taskprov::TaskConfig),
+) {
+    let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+    let mut rng = thread_rng();
+
+    let mut taskprov_config = taskprov::TaskConfig {
+        task_info: "cool task".as_bytes().to_vec(),
+        aggregator_endpoints: vec![
+            taskprov::UrlBytes {
+                bytes: b"https://cool.biz/".to_vec(),
+            },
+            taskprov::UrlBytes {
+                bytes: b"http://cool.com:8788/".to_vec(),
+            },
+        ],
+        query_config: taskprov::QueryConfig {
+            time_precision: t.helper.global_config.min_time_precision,
+            max_batch_query_count: 1,
+            min_batch_size: 1,
+            var: taskprov::QueryConfigVar::TimeInterval,
+        },
+        task_expiration: t.now + 86400 * 14,
+        vdaf_config: taskprov::VdafConfig {
+            dp_config: taskprov::DpConfig::None,
+            var: taskprov::VdafTypeVar::Prio3Aes128Count,
+        },
+    };
+    mutate(&mut taskprov_config);
+
+    let part_batch_sel = match taskprov_config.query_config.var {
+        taskprov::QueryConfigVar::TimeInterval => PartialBatchSelector::TimeInterval,
+        _ => PartialBatchSelector::FixedSizeByBatchId {
+            batch_id: Id(rng.gen()),
+        },
+    };
+    let taskprov_ext_payload =
+        taskprov_config.get_encoded_with_param(&t.helper.global_config.taskprov_version);
+    let taskprov_id = crate::taskprov::compute_task_id(
+        t.helper.global_config.taskprov_version,
+        &taskprov_ext_payload,
+    )
+    .unwrap();
+
+    let hpke_config_list = [
+        t.leader
+            .get_hpke_config_for(Some(&taskprov_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+        t.helper
+            .get_hpke_config_for(Some(&taskprov_id))
+            .await
+            .unwrap()
+            .as_ref()
+            .clone(),
+    ];
+    let report = vdaf
+        .produce_report_with_extensions(
+            &hpke_config_list,
+            t.now,
+            &taskprov_id,
+            DapMeasurement::U64(1),
+            vec![Extension::Taskprov {
+                payload: taskprov_ext_payload,
+            }],
+            version,
+        )
+        .unwrap();
+
+    let req = t
+        .leader_authorized_req_with_version(
+            &taskprov_id,
+            version,
+            MEDIA_TYPE_AGG_INIT_REQ,
+            AggregateInitializeReq {
+                task_id: taskprov_id.clone(),
+                agg_job_id: Id(rng.gen()),
+                agg_param: Vec::default(),
+                part_batch_sel,
+                report_shares: vec![ReportShare {
+                    metadata: report.metadata.clone(),
+                    public_share: report.public_share,
+                    encrypted_input_share: report.encrypted_input_shares[1].clone(),
+                }],
+            },
+            Url::parse("http://cool.com:8788/aggregate").unwrap(),
+        )
+        .await;
+
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await.unwrap_err(),
+        DapAbort::InvalidTask
+    );
+
+    // Expect the Helper to have opted out of the task.
+    assert!(!t
+        .helper
+        .tasks
+        .lock()
+        .expect("tasks: failed to lock")
+        .contains_key(&taskprov_id));
+}
+
+// Test that the Helper opts out of a taskprov task whose time precision is below the minimum.
+async fn http_post_aggregate_taskprov_fail_time_precision(version: DapVersion) {
+    let t = Test::new(version);
+    let min_time_precision = t.helper.global_config.min_time_precision;
+    http_post_aggregate_taskprov_opt_out(&t, version, |taskprov_config| {
+        taskprov_config.query_config.time_precision = min_time_precision - 1;
+        taskprov_config.query_config.var =
+            taskprov::QueryConfigVar::FixedSize { max_batch_size: 2 };
+    })
+    .await;
+}
+
+async_test_versions! { http_post_aggregate_taskprov_fail_time_precision }
+
+// Test that the Helper opts out of a taskprov task whose query type is not allowed.
+async fn http_post_aggregate_taskprov_fail_query_type(version: DapVersion) {
+    let mut t = Test::new(version);
+    t.helper.global_config.allowed_query_types = Some(vec![QueryKind::FixedSize]);
+    http_post_aggregate_taskprov_opt_out(&t, version, |_| ()).await;
+}
+
+async_test_versions! { http_post_aggregate_taskprov_fail_query_type }
+
+// Test that the Helper opts out of a taskprov task with an unsupported differential privacy
+// mechanism.
+async fn http_post_aggregate_taskprov_fail_dp_config(version: DapVersion) {
+    let t = Test::new(version);
+    http_post_aggregate_taskprov_opt_out(&t, version, |taskprov_config| {
+        taskprov_config.vdaf_config.dp_config = taskprov::DpConfig::NotImplemented(0xff);
+    })
+    .await;
+}
+
+async_test_versions! { http_post_aggregate_taskprov_fail_dp_config }
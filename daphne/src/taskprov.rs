@@ -3,7 +3,7 @@
 
 use crate::{
     messages::{
-        taskprov::{QueryConfigVar, TaskConfig, VdafType, VdafTypeVar},
+        taskprov::{DpConfig, QueryConfigVar, TaskConfig, VdafType, VdafTypeVar},
         Extension, HpkeConfig, Id, ReportMetadata,
     },
     vdaf::VdafVerifyKey,
@@ -194,12 +194,19 @@ impl DapTaskConfig {
         if task_config.aggregator_endpoints.len() != 2 {
             return Err(bad_request("number of aggregator endpoints is not 2"));
         }
+        // Refuse to opt in to a task whose differential privacy mechanism we don't implement,
+        // rather than ignoring the privacy parameter.
+        if task_config.vdaf_config.dp_config != DpConfig::None {
+            return Err(DapError::Abort(DapAbort::InvalidTask));
+        }
         let vdaf_type = VdafType::from(task_config.vdaf_config.var.clone());
-        Ok(DapTaskConfig {
+        let task_config = DapTaskConfig {
             version: dap_version,
             leader_url: url_from_bytes(&task_config.aggregator_endpoints[0].bytes)?,
             helper_url: url_from_bytes(&task_config.aggregator_endpoints[1].bytes)?,
             time_precision: task_config.query_config.time_precision,
+            storage_precision: None,
+            start: 0,
             expiration: task_config.task_expiration,
             min_batch_size: task_config.query_config.min_batch_size.into(),
             query: DapQueryConfig::from(task_config.query_config.var),
@@ -210,8 +217,19 @@ impl DapTaskConfig {
                 task_id,
                 vdaf_type,
             ),
-            collector_hpke_config: collector_hpke_config.clone(),
-        })
+            collector_hpke_configs: vec![collector_hpke_config.clone()],
+            max_pending_reports: None,
+            allowed_extension_types: None,
+            require_latest_hpke_config: false,
+            require_aligned_report_time: false,
+            global_config_override: None,
+        };
+
+        // The task parameters are chosen by a peer, so check that they are consistent.
+        task_config
+            .validate()
+            .map_err(|_| DapError::Abort(DapAbort::InvalidTask))?;
+        Ok(task_config)
     }
 }
 
@@ -5,77 +5,86 @@
 
 use crate::{
     auth::{BearerToken, BearerTokenProvider},
+    constants::MEDIA_TYPE_REPORT,
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
+    messages::HpkeKemId,
     messages::{
         BatchSelector, CollectReq, CollectResp, HpkeCiphertext, HpkeConfig, Id,
         PartialBatchSelector, Report, ReportId, ReportMetadata, Time, TransitionFailure,
     },
-    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov, DapAbort, DapAggregateShare, DapBatchBucket, DapCollectJob, DapError,
-    DapGlobalConfig, DapHelperState, DapOutputShare, DapQueryConfig, DapRequest, DapResponse,
-    DapTaskConfig, DapVersion,
+    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapHttpClient, DapLeader},
+    taskprov::{self, TaskprovVersion},
+    AggShareCompression, ChecksumAlgorithm, DapAbort, DapAggregateShare, DapBatchBucketOwned,
+    DapCollectJob, DapError, DapGlobalConfig, DapHelperState, DapMeasurement, DapOutputShare,
+    DapQueryConfig, DapRequest, DapResponse, DapStoredAggInitResp, DapTaskConfig, DapVersion,
+    HpkeConfigMissingTaskIdPolicy, NoiseSplit, Prio3Config, ReportBuffer, ReportStatus, VdafConfig,
 };
 use assert_matches::assert_matches;
 use async_trait::async_trait;
+use prio::codec::Encode;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::{Borrow, Cow},
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     hash::Hash,
     ops::DerefMut,
     sync::{Arc, Mutex},
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 use url::Url;
 
-#[derive(Eq, Hash, PartialEq)]
-pub(crate) enum DapBatchBucketOwned {
-    FixedSize { batch_id: Id },
-    TimeInterval { batch_window: Time },
+/// A source of task configurations from which the [`MockAggregator`] loads the tasks it doesn't
+/// hold in memory, e.g., to simulate loading tasks on demand from an external store.
+#[async_trait(?Send)]
+pub trait DapTaskProvider {
+    /// Fetch the configuration of the given task, if it exists.
+    async fn get_task(&self, task_id: &Id) -> Result<Option<DapTaskConfig>, DapError>;
 }
 
-impl From<DapBatchBucketOwned> for PartialBatchSelector {
-    fn from(bucket: DapBatchBucketOwned) -> Self {
-        match bucket {
-            DapBatchBucketOwned::FixedSize { batch_id } => Self::FixedSizeByBatchId { batch_id },
-            DapBatchBucketOwned::TimeInterval { .. } => Self::TimeInterval,
-        }
+/// Selects the pending reports of a task to aggregate, optionally restricted to a single batch
+/// bucket (i.e., a batch window for time-interval tasks or a batch ID for fixed-size tasks).
+pub struct MockAggregatorReportSelector(pub Id, pub Option<DapBatchBucketOwned>);
+
+impl MockAggregatorReportSelector {
+    /// Select only the pending reports of the task that belong to the given batch bucket.
+    pub fn with_batch(task_id: Id, bucket: DapBatchBucketOwned) -> Self {
+        Self(task_id, Some(bucket))
     }
 }
 
-impl<'a> DapBatchBucket<'a> {
-    // TODO(cjpatton) Figure out how to use `ToOwned` properly. The lifetime parameter causes
-    // confusion for the compiler for implementing `Borrow`. The goal is to avoid cloning the
-    // bucket each time we need to check if it exists in the set.
-    pub(crate) fn to_owned_bucket(&self) -> DapBatchBucketOwned {
-        match self {
-            Self::FixedSize { batch_id } => DapBatchBucketOwned::FixedSize {
-                batch_id: (*batch_id).clone(),
-            },
-            Self::TimeInterval { batch_window } => DapBatchBucketOwned::TimeInterval {
-                batch_window: *batch_window,
-            },
-        }
+impl From<Id> for MockAggregatorReportSelector {
+    fn from(task_id: Id) -> Self {
+        Self(task_id, None)
     }
 }
 
-pub(crate) struct MockAggregatorReportSelector(pub(crate) Id);
-
 #[allow(dead_code)]
-pub(crate) struct MockAggregator {
+#[derive(Clone)]
+pub struct MockAggregator {
     pub(crate) now: Time,
     pub(crate) global_config: DapGlobalConfig,
     pub(crate) tasks: Arc<Mutex<HashMap<Id, DapTaskConfig>>>,
     pub(crate) hpke_receiver_config_list: Vec<HpkeReceiverConfig>,
     pub(crate) leader_token: BearerToken,
     pub(crate) collector_token: Option<BearerToken>, // Not set by Helper
+    pub(crate) task_collector_tokens: HashMap<Id, BearerToken>, // Overrides collector_token
     pub(crate) report_store: Arc<Mutex<HashMap<Id, ReportStore>>>,
     pub(crate) leader_state_store: Arc<Mutex<HashMap<Id, LeaderState>>>,
-    pub(crate) helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, DapHelperState>>>,
+    /// The Helper's state for each aggregation job, along with the time at which it was stored.
+    pub(crate) helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, (DapHelperState, Time)>>>,
+    pub(crate) agg_init_resp_store: Arc<Mutex<HashMap<IdempotencyKeyInfo, DapStoredAggInitResp>>>,
     pub(crate) agg_store: Arc<Mutex<HashMap<Id, HashMap<DapBatchBucketOwned, AggStore>>>>,
     pub(crate) collector_hpke_config: HpkeConfig,
     pub(crate) taskprov_vdaf_verify_key_init: Vec<u8>,
+    pub(crate) report_buffer: Option<Arc<ReportBuffer>>, // Not set by Helper
+
+    /// Source of the configurations of tasks not in `tasks`. Tasks fetched from it are cached in
+    /// `tasks`.
+    pub(crate) task_provider: Option<Arc<dyn DapTaskProvider>>,
+
+    // The Helper to which the Leader's HTTP requests are routed. Not set by Helper.
+    pub(crate) peer: Option<InProcessHttpClient<MockAggregator>>,
 }
 
 #[allow(dead_code)]
@@ -103,13 +112,38 @@ impl MockAggregator {
             .lock()
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
-        if report_store.processed.contains(&metadata.id) {
+        if report_store
+            .processed
+            .values()
+            .any(|report_ids| report_ids.contains(&metadata.id))
+        {
             return Some(TransitionFailure::ReportReplayed);
         }
 
         None
     }
 
+    /// Evict the replay protection state for each report storage epoch that has fallen out of the
+    /// report storage window at time `now`.
+    pub(crate) fn purge(&self, now: Time) {
+        let tasks = self.tasks.lock().expect("tasks: failed to lock");
+        let mut guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        for (task_id, report_store) in guard.iter_mut() {
+            let global_config = tasks
+                .get(task_id)
+                .map_or(&self.global_config, |task_config| {
+                    task_config.global_config(&self.global_config)
+                });
+            let oldest_epoch = global_config
+                .report_storage_epoch(now)
+                .saturating_sub(global_config.report_storage_epoch_duration);
+            report_store.processed = report_store.processed.split_off(&oldest_epoch);
+        }
+    }
+
     fn get_hpke_receiver_config_for(&self, hpke_config_id: u8) -> Option<&HpkeReceiverConfig> {
         self.hpke_receiver_config_list
             .iter()
@@ -129,16 +163,17 @@ impl MockAggregator {
 
         match task_config.query {
             // For fixed-size queries, the bucket corresponds to a single batch.
-            DapQueryConfig::FixedSize { .. } => {
+            DapQueryConfig::FixedSize { max_batch_size } => {
                 let mut guard = self
                     .leader_state_store
                     .lock()
                     .expect("leader_state_store: failed to lock");
                 let leader_state_store = guard.entry(report.task_id.clone()).or_default();
 
-                // Assign the report to the first unsaturated batch.
+                // Assign the report to the first batch that is not yet full. A batch is full once
+                // it has been assigned `max_batch_size` reports.
                 for (batch_id, report_count) in leader_state_store.batch_queue.iter_mut() {
-                    if *report_count < task_config.min_batch_size {
+                    if *report_count < max_batch_size {
                         *report_count += 1;
                         return Some(DapBatchBucketOwned::FixedSize {
                             batch_id: batch_id.clone(),
@@ -146,7 +181,7 @@ impl MockAggregator {
                     }
                 }
 
-                // No unsaturated batch exists, so create a new batch.
+                // Every batch is full, so roll over to a new batch.
                 let batch_id = Id(rng.gen());
                 leader_state_store
                     .batch_queue
@@ -156,9 +191,9 @@ impl MockAggregator {
 
             // For time-interval queries, the bucket is the batch window computed by truncating the
             // report timestamp.
-            DapQueryConfig::TimeInterval => Some(DapBatchBucketOwned::TimeInterval {
-                batch_window: task_config.truncate_time(report.metadata.time),
-            }),
+            DapQueryConfig::TimeInterval => task_config
+                .batch_bucket_for_report(report.metadata.time, None)
+                .ok(),
         }
     }
 
@@ -204,9 +239,11 @@ impl<'a> BearerTokenProvider<'a> for MockAggregator {
 
     async fn get_collector_bearer_token_for(
         &'a self,
-        _task_id: &'a Id,
+        task_id: &'a Id,
     ) -> Result<Option<&'a BearerToken>, DapError> {
-        if let Some(ref collector_token) = self.collector_token {
+        if let Some(collector_token) = self.task_collector_tokens.get(task_id) {
+            Ok(Some(collector_token))
+        } else if let Some(ref collector_token) = self.collector_token {
             Ok(Some(collector_token))
         } else {
             Err(DapError::fatal(
@@ -236,22 +273,16 @@ impl<'a> HpkeDecrypter<'a> for MockAggregator {
 
     async fn get_hpke_config_for(
         &'a self,
-        task_id: Option<&Id>,
+        _task_id: Option<&Id>,
     ) -> Result<&'a HpkeConfig, DapError> {
         if self.hpke_receiver_config_list.is_empty() {
             return Err(DapError::fatal("emtpy HPKE receiver config list"));
         }
 
-        // Aggregators MAY abort if the HPKE config request does not specify a task ID. While not
-        // required for MockAggregator, we simulate this behavior for testing purposes.
-        //
         // TODO(cjpatton) To make this clearer, have MockAggregator store a map from task IDs to
         // HPKE receiver configs.
-        if task_id.is_none() {
-            return Err(DapError::Abort(DapAbort::MissingTaskId));
-        }
-
-        // Always advertise the first HPKE config in the list.
+        //
+        // Always advertise the first HPKE config in the list, regardless of the task.
         Ok(&self.hpke_receiver_config_list[0].config)
     }
 
@@ -343,6 +374,8 @@ where
                 let mut tasks = self.tasks.lock().expect("tasks: lock failed");
                 if tasks.get(task_id.as_ref()).is_none() {
                     // Decide whether to opt-in to the task.
+                    self.global_config
+                        .check_taskprov_task_config(&task_config, self.get_current_time())?;
                     if !self.taskprov_opt_in_decision(&task_config)? {
                         return Err(DapError::Abort(DapAbort::InvalidTask));
                     }
@@ -356,8 +389,29 @@ where
             }
         }
 
-        let tasks = self.tasks.lock().expect("tasks: lock failed");
-        Ok(tasks.get(task_id.as_ref()).cloned())
+        if let Some(task_config) = self
+            .tasks
+            .lock()
+            .expect("tasks: lock failed")
+            .get(task_id.as_ref())
+        {
+            task_config.validate()?;
+            return Ok(Some(task_config.clone()));
+        }
+
+        // Fetch the task from the task provider, if any, and cache it.
+        if let Some(ref task_provider) = self.task_provider {
+            if let Some(task_config) = task_provider.get_task(task_id.as_ref()).await? {
+                task_config.validate()?;
+                self.tasks
+                    .lock()
+                    .expect("tasks: lock failed")
+                    .insert(task_id.into_owned(), task_config.clone());
+                return Ok(Some(task_config));
+            }
+        }
+
+        Ok(None)
     }
 
     fn get_current_time(&self) -> Time {
@@ -421,6 +475,23 @@ where
 
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = guard.entry(task_id.clone()).or_default();
+
+        // Refuse to store the output share of a report more than once. By the time output shares
+        // are stored, replays have already been rejected, so hitting this indicates a bug.
+        let mut report_store_guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = report_store_guard.entry(task_id.clone()).or_default();
+        let mut checksums = HashSet::with_capacity(out_shares.len());
+        for out_share in out_shares.iter() {
+            if report_store.committed.contains(&out_share.checksum)
+                || !checksums.insert(out_share.checksum)
+            {
+                return Err(DapError::fatal("output share committed more than once"));
+            }
+        }
+
         for (bucket, agg_share_delta) in task_config
             .batch_span_for_out_shares(part_batch_sel, out_shares)?
             .into_iter()
@@ -428,6 +499,7 @@ where
             let inner_agg_store = agg_store.entry(bucket.to_owned_bucket()).or_default();
             inner_agg_store.agg_share.merge(agg_share_delta)?;
         }
+        report_store.committed.extend(checksums);
 
         Ok(())
     }
@@ -471,10 +543,18 @@ where
             .await
             .unwrap()
             .expect("tasks: unrecognized task");
+        let global_config = task_config.global_config(&self.global_config);
+        let now = self.get_current_time();
         let span = task_config.batch_span_for_meta(part_batch_sel, report_meta)?;
         let mut early_fails = HashMap::new();
         for (bucket, report_meta) in span.iter() {
             for metadata in report_meta.iter() {
+                // Reports outside of the report storage window can't be checked for replay.
+                if !global_config.is_report_in_storage_window(now, metadata.time) {
+                    early_fails.insert(metadata.id.clone(), TransitionFailure::ReportDropped);
+                    continue;
+                }
+
                 // Check whether Report has been collected or replayed.
                 if let Some(transition_failure) = self
                     .check_report_early_fail(task_id, &bucket.to_owned_bucket(), metadata)
@@ -489,13 +569,73 @@ where
                     .lock()
                     .expect("report_store: failed to lock");
                 let report_store = guard.entry(task_id.clone()).or_default();
-                report_store.processed.insert(metadata.id.clone());
+                report_store
+                    .processed
+                    .entry(global_config.report_storage_epoch(metadata.time))
+                    .or_default()
+                    .insert(metadata.id.clone());
             }
         }
 
         Ok(early_fails)
     }
 
+    async fn put_rejected_reports(
+        &self,
+        task_id: &Id,
+        rejected: &[(ReportId, Time, TransitionFailure)],
+    ) -> Result<(), DapError> {
+        let mut guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = guard.entry(task_id.clone()).or_default();
+        for (report_id, _time, failure_reason) in rejected.iter() {
+            // A replay does not change the status of the original report.
+            if *failure_reason == TransitionFailure::ReportReplayed {
+                continue;
+            }
+            report_store
+                .rejected
+                .insert(report_id.clone(), *failure_reason);
+        }
+        Ok(())
+    }
+
+    async fn report_status(
+        &self,
+        task_id: &Id,
+        report_id: &ReportId,
+    ) -> Result<ReportStatus, DapError> {
+        let guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = match guard.get(task_id) {
+            Some(report_store) => report_store,
+            None => return Ok(ReportStatus::Unknown),
+        };
+
+        if let Some(failure_reason) = report_store.rejected.get(report_id) {
+            Ok(ReportStatus::Rejected(*failure_reason))
+        } else if report_store
+            .processed
+            .values()
+            .any(|report_ids| report_ids.contains(report_id))
+        {
+            Ok(ReportStatus::Aggregated)
+        } else if report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| &report.metadata.id == report_id)
+        {
+            Ok(ReportStatus::Pending)
+        } else {
+            Ok(ReportStatus::Unknown)
+        }
+    }
+
     async fn mark_collected(
         &self,
         task_id: &Id,
@@ -557,7 +697,10 @@ where
 
         // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
         // For VDAFs with more rounds, the helper state blob will need to be updated here.
-        helper_state_store.insert(helper_state_info, helper_state.clone());
+        helper_state_store.insert(
+            helper_state_info,
+            (helper_state.clone(), self.get_current_time()),
+        );
 
         Ok(())
     }
@@ -582,13 +725,68 @@ where
         // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
         // For VDAFs with more rounds, the helper state blob will need to be updated here.
         if helper_state_store.contains_key(&helper_state_info) {
-            let helper_state = helper_state_store.remove(&helper_state_info);
+            let helper_state = helper_state_store
+                .remove(&helper_state_info)
+                .map(|(helper_state, _created_at)| helper_state);
 
             return Ok(helper_state);
         }
 
         Ok(None)
     }
+
+    async fn expire_jobs(&self, now: Time) -> Result<(), DapError> {
+        let job_ttl = self.global_config.job_ttl;
+        self.helper_state_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .retain(|_helper_state_info, (_helper_state, created_at)| {
+                now.saturating_sub(*created_at) <= job_ttl
+            });
+        self.agg_init_resp_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .retain(|_idempotency_key_info, stored_agg_init_resp| {
+                now.saturating_sub(stored_agg_init_resp.created_at) <= job_ttl
+            });
+        Ok(())
+    }
+
+    async fn put_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+        stored_agg_init_resp: &DapStoredAggInitResp,
+    ) -> Result<(), DapError> {
+        let idempotency_key_info = IdempotencyKeyInfo {
+            task_id: task_id.clone(),
+            idempotency_key: idempotency_key.clone(),
+        };
+
+        let mut agg_init_resp_store = self
+            .agg_init_resp_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        agg_init_resp_store.insert(idempotency_key_info, stored_agg_init_resp.clone());
+        Ok(())
+    }
+
+    async fn get_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+    ) -> Result<Option<DapStoredAggInitResp>, DapError> {
+        let idempotency_key_info = IdempotencyKeyInfo {
+            task_id: task_id.clone(),
+            idempotency_key: idempotency_key.clone(),
+        };
+
+        let agg_init_resp_store = self
+            .agg_init_resp_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        Ok(agg_init_resp_store.get(&idempotency_key_info).cloned())
+    }
 }
 
 #[async_trait(?Send)]
@@ -598,6 +796,10 @@ where
 {
     type ReportSelector = MockAggregatorReportSelector;
 
+    fn report_buffer(&self) -> Option<&ReportBuffer> {
+        self.report_buffer.as_deref()
+    }
+
     async fn put_report(&self, report: &Report) -> Result<(), DapError> {
         let bucket = self
             .assign_report_to_bucket(report)
@@ -639,6 +841,22 @@ where
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
 
+        // If the report selector names a batch bucket, choose a single report from that bucket.
+        if let Some(ref bucket) = report_sel.1 {
+            let part_batch_sel = PartialBatchSelector::from(bucket.clone());
+            if !task_config.query.is_valid_part_batch_sel(&part_batch_sel) {
+                return Err(DapError::fatal("batch bucket not compatible with task"));
+            }
+
+            return Ok(match report_store.pending.get_mut(bucket) {
+                Some(queue) if !queue.is_empty() => HashMap::from([(
+                    task_id.clone(),
+                    HashMap::from([(part_batch_sel, queue.drain(..1).collect())]),
+                )]),
+                _ => HashMap::default(),
+            });
+        }
+
         // For the task indicated by the report selector, choose a single report to aggregate.
         match task_config.query {
             DapQueryConfig::TimeInterval { .. } => {
@@ -656,29 +874,45 @@ where
                 )]));
             }
             DapQueryConfig::FixedSize { .. } => {
-                // Drain the batch that is being filled.
-
-                let bucket = if let Some(batch_id) = self.current_batch_id(task_id, &task_config) {
-                    DapBatchBucketOwned::FixedSize { batch_id }
-                } else {
-                    return Ok(HashMap::default());
-                };
+                // Drain the oldest batch that has pending reports.
+                let batch_ids: Vec<Id> = self
+                    .leader_state_store
+                    .lock()
+                    .expect("leader_state_store: failed to lock")
+                    .get(task_id)
+                    .map(|leader_state| {
+                        leader_state
+                            .batch_queue
+                            .iter()
+                            .map(|(batch_id, _report_count)| batch_id.clone())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                for batch_id in batch_ids.into_iter() {
+                    let bucket = DapBatchBucketOwned::FixedSize { batch_id };
+                    if let Some(queue) = report_store.pending.get_mut(&bucket) {
+                        if !queue.is_empty() {
+                            let reports = queue.drain(..1).collect();
+                            return Ok(HashMap::from([(
+                                task_id.clone(),
+                                HashMap::from([(bucket.into(), reports)]),
+                            )]));
+                        }
+                    }
+                }
 
-                let queue = report_store
-                    .pending
-                    .get_mut(&bucket)
-                    .expect("report_store: unknown bucket");
-                let reports = queue.drain(..1).collect();
-                return Ok(HashMap::from([(
-                    task_id.clone(),
-                    HashMap::from([(bucket.into(), reports)]),
-                )]));
+                return Ok(HashMap::default());
             }
         }
     }
 
     // Called after receiving a CollectReq from Collector.
-    async fn init_collect_job(&self, collect_req: &CollectReq) -> Result<Url, DapError> {
+    async fn init_collect_job(
+        &self,
+        collect_req: &CollectReq,
+        created_at: Time,
+    ) -> Result<Url, DapError> {
         let mut rng = thread_rng();
         let task_config = self
             .get_task_config_for(Cow::Borrowed(&collect_req.task_id))
@@ -707,7 +941,7 @@ where
             .entry(collect_req.task_id.clone())
             .or_default();
         leader_state.collect_ids.push_back(collect_id.clone());
-        let collect_job_state = CollectJobState::Pending(collect_req.clone());
+        let collect_job_state = CollectJobState::Pending(collect_req.clone(), created_at);
         leader_state
             .collect_jobs
             .insert(collect_id, collect_job_state);
@@ -732,8 +966,22 @@ where
             .ok_or_else(|| DapError::fatal("collect job not found for task_id"))?;
         if let Some(collect_job_state) = leader_state.collect_jobs.get(collect_id) {
             match collect_job_state {
-                CollectJobState::Pending(_) => Ok(DapCollectJob::Pending),
-                CollectJobState::Processed(resp) => Ok(DapCollectJob::Done(resp.clone())),
+                CollectJobState::Pending(_, created_at) => Ok(DapCollectJob::Pending {
+                    created_at: *created_at,
+                }),
+                CollectJobState::Processed(resp, created_at, completed_at) => {
+                    Ok(DapCollectJob::Done {
+                        collect_resp: resp.clone(),
+                        created_at: *created_at,
+                        completed_at: *completed_at,
+                    })
+                }
+                CollectJobState::Expired(created_at) => Ok(DapCollectJob::Expired {
+                    created_at: *created_at,
+                }),
+                CollectJobState::Failed(created_at) => Ok(DapCollectJob::Failed {
+                    created_at: *created_at,
+                }),
             }
         } else {
             Ok(DapCollectJob::Unknown)
@@ -752,7 +1000,7 @@ where
         for (_task_id, leader_state) in leader_state_store.iter() {
             // Iterate over collect IDs and copy them and their associated requests to the response.
             for collect_id in leader_state.collect_ids.iter() {
-                if let CollectJobState::Pending(collect_req) =
+                if let CollectJobState::Pending(collect_req, _created_at) =
                     leader_state.collect_jobs.get(collect_id).unwrap()
                 {
                     res.push((collect_id.clone(), collect_req.clone()));
@@ -767,6 +1015,7 @@ where
         task_id: &Id,
         collect_id: &Id,
         collect_resp: &CollectResp,
+        completed_at: Time,
     ) -> Result<(), DapError> {
         let mut leader_state_store_mutex_guard = self
             .leader_state_store
@@ -792,9 +1041,10 @@ where
         }
 
         match collect_job {
-            CollectJobState::Pending(_) => {
+            CollectJobState::Pending(_, created_at) => {
                 // Mark collect job as Processed.
-                *collect_job = CollectJobState::Processed(collect_resp.clone());
+                *collect_job =
+                    CollectJobState::Processed(collect_resp.clone(), *created_at, completed_at);
 
                 // Remove collect ID from queue.
                 let index = leader_state
@@ -806,15 +1056,461 @@ where
 
                 Ok(())
             }
-            CollectJobState::Processed(_) => {
+            CollectJobState::Processed(..) => {
                 Err(DapError::fatal("tried to overwrite collect response"))
             }
+            CollectJobState::Expired(..) => Err(DapError::fatal("collect job expired")),
+            CollectJobState::Failed(..) => Err(DapError::fatal("collect job failed")),
         }
     }
 
-    async fn send_http_post(&self, _req: DapRequest<BearerToken>) -> Result<DapResponse, DapError> {
-        unreachable!("not implemented");
+    async fn fail_collect_job(&self, task_id: &Id, collect_id: &Id) -> Result<(), DapError> {
+        let mut leader_state_store_mutex_guard = self
+            .leader_state_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let leader_state_store = leader_state_store_mutex_guard.deref_mut();
+
+        let leader_state = leader_state_store
+            .get_mut(task_id)
+            .ok_or_else(|| DapError::fatal("collect job not found for task_id"))?;
+        let collect_job = leader_state
+            .collect_jobs
+            .get_mut(collect_id)
+            .ok_or_else(|| DapError::fatal("collect job not found for collect_id"))?;
+
+        if let CollectJobState::Pending(_, created_at) = collect_job {
+            // Mark collect job as Failed and remove collect ID from queue.
+            *collect_job = CollectJobState::Failed(*created_at);
+            leader_state.collect_ids.retain(|id| id != collect_id);
+        }
+        Ok(())
     }
+
+    async fn expire_collect_jobs(&self, now: Time) -> Result<(), DapError> {
+        let collect_job_ttl = self.global_config.collect_job_ttl;
+        let mut leader_state_store_mutex_guard = self
+            .leader_state_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        for leader_state in leader_state_store_mutex_guard.values_mut() {
+            let LeaderState {
+                collect_ids,
+                collect_jobs,
+                ..
+            } = leader_state;
+            collect_ids.retain(|collect_id| {
+                let collect_job = collect_jobs.get_mut(collect_id).unwrap();
+                match *collect_job {
+                    CollectJobState::Pending(_, created_at)
+                        if now.saturating_sub(created_at) > collect_job_ttl =>
+                    {
+                        // Mark collect job as Expired and remove collect ID from queue.
+                        *collect_job = CollectJobState::Expired(created_at);
+                        false
+                    }
+                    _ => true,
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn ready_batches(&self, task_id: &Id) -> Result<Vec<Id>, DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let max_batch_size = match task_config.query {
+            DapQueryConfig::FixedSize { max_batch_size } => max_batch_size,
+            DapQueryConfig::TimeInterval => return Err(DapError::fatal("query type mismatch")),
+        };
+
+        let batch_ids: Vec<Id> = self
+            .leader_state_store
+            .lock()
+            .expect("leader_state_store: failed to lock")
+            .get(task_id)
+            .map(|leader_state| {
+                leader_state
+                    .batch_queue
+                    .iter()
+                    .map(|(batch_id, _report_count)| batch_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let guard = self.agg_store.lock().expect("agg_store: failed to lock");
+        let agg_store = match guard.get(task_id) {
+            Some(agg_store) => agg_store,
+            None => return Ok(Vec::new()),
+        };
+        Ok(batch_ids
+            .into_iter()
+            .filter(|batch_id| {
+                let bucket = DapBatchBucketOwned::FixedSize {
+                    batch_id: batch_id.clone(),
+                };
+                agg_store.get(&bucket).map_or(false, |inner_agg_store| {
+                    !inner_agg_store.collected
+                        && inner_agg_store.agg_share.report_count >= max_batch_size
+                })
+            })
+            .collect())
+    }
+
+    async fn has_pending_reports(
+        &self,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = match guard.get(task_id) {
+            Some(report_store) => report_store,
+            None => return Ok(false),
+        };
+
+        for bucket in task_config.batch_span_for_sel(batch_sel)? {
+            if let Some(queue) = report_store.pending.get(&bucket.to_owned_bucket()) {
+                if !queue.is_empty() {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    async fn pending_report_count(&self, task_id: &Id) -> Result<u64, DapError> {
+        let guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        Ok(guard.get(task_id).map_or(0, |report_store| {
+            report_store
+                .pending
+                .values()
+                .map(|queue| queue.len() as u64)
+                .sum()
+        }))
+    }
+}
+
+#[async_trait(?Send)]
+impl DapHttpClient<BearerToken> for MockAggregator {
+    async fn post(&self, req: DapRequest<BearerToken>) -> Result<DapResponse, DapError> {
+        self.peer
+            .as_ref()
+            .ok_or_else(|| DapError::fatal("helper cannot send HTTP requests"))?
+            .post(req)
+            .await
+    }
+}
+
+/// An HTTP client that dispatches requests directly to a Helper running in the same process.
+pub struct InProcessHttpClient<H> {
+    helper: Arc<H>,
+}
+
+impl<H> InProcessHttpClient<H> {
+    pub fn new(helper: Arc<H>) -> Self {
+        Self { helper }
+    }
+}
+
+impl<H> Clone for InProcessHttpClient<H> {
+    fn clone(&self) -> Self {
+        Self {
+            helper: self.helper.clone(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<S, H> DapHttpClient<S> for InProcessHttpClient<H>
+where
+    S: 'static,
+    H: for<'a> DapHelper<'a, 'a, S>,
+{
+    async fn post(&self, req: DapRequest<S>) -> Result<DapResponse, DapError> {
+        // Route the request to the Helper's handler for the request path.
+        let path = req.url.path();
+        if path.ends_with("/aggregate") {
+            self.helper
+                .http_post_aggregate(&req)
+                .await
+                .map_err(DapError::Abort)
+        } else if path.ends_with("/aggregate_share") {
+            self.helper
+                .http_post_aggregate_share(&req)
+                .await
+                .map_err(DapError::Abort)
+        } else {
+            Err(DapError::Fatal(format!("unexpected path: {}", path)))
+        }
+    }
+}
+
+/// Builder for a [`TestFixture`], a Leader and Helper configured with a common task. This is
+/// intended for testing crates that depend on this one.
+pub struct TestFixtureBuilder {
+    version: DapVersion,
+    query: DapQueryConfig,
+    vdaf: VdafConfig,
+    expiration: Option<Time>,
+}
+
+impl Default for TestFixtureBuilder {
+    fn default() -> Self {
+        Self {
+            version: DapVersion::Draft02,
+            query: DapQueryConfig::TimeInterval,
+            vdaf: VdafConfig::Prio3(Prio3Config::Count),
+            expiration: None,
+        }
+    }
+}
+
+impl TestFixtureBuilder {
+    /// Create a builder for a "time-interval" task with the Prio3Count VDAF.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the DAP version of the task.
+    pub fn version(mut self, version: DapVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set the query configuration of the task.
+    pub fn query(mut self, query: DapQueryConfig) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Set the VDAF of the task.
+    pub fn vdaf(mut self, vdaf: VdafConfig) -> Self {
+        self.vdaf = vdaf;
+        self
+    }
+
+    /// Set the expiration time of the task. By default, the task expires an hour from now.
+    pub fn expiration(mut self, expiration: Time) -> Self {
+        self.expiration = Some(expiration);
+        self
+    }
+
+    /// Generate the task and configure the Leader and Helper.
+    pub fn build(self) -> TestFixture {
+        let mut rng = thread_rng();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let global_config = DapGlobalConfig {
+            report_storage_epoch_duration: 604800, // one week
+            max_batch_duration: 360000,
+            enforce_fixed_size_batch_duration: false,
+            min_batch_interval_start: 259200,
+            max_batch_interval_end: 259200,
+            supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            max_extensions: 16,
+            max_extension_len: 4096,
+            allow_taskprov: true,
+            taskprov_version: TaskprovVersion::Draft02,
+            min_time_precision: 3600,
+            max_batch_buckets: 8760,
+            allowed_query_types: None,
+            max_pending_collect_jobs: 100,
+            max_agg_share_buckets: 8760,
+            job_ttl: 3600,
+            collect_job_ttl: 3600,
+            hpke_config_missing_task_id_policy: HpkeConfigMissingTaskIdPolicy::Abort,
+            agg_share_compression: AggShareCompression::None,
+            noise_split: NoiseSplit::None,
+            checksum_algorithm: ChecksumAlgorithm::Sha256Xor,
+        };
+
+        let collector_hpke_receiver_config =
+            HpkeReceiverConfig::gen(rng.gen(), HpkeKemId::X25519HkdfSha256)
+                .expect("failed to generate HPKE receiver config");
+        let task_id = Id(rng.gen());
+        let task_config = DapTaskConfig {
+            version: self.version,
+            leader_url: Url::parse("https://leader.biz/v02/").unwrap(),
+            helper_url: Url::parse("http://helper.com:8788/v02/").unwrap(),
+            time_precision: 3600,
+            storage_precision: None,
+            start: 0,
+            expiration: self.expiration.unwrap_or(now + 3600),
+            min_batch_size: 1,
+            query: self.query,
+            vdaf_verify_key: self.vdaf.gen_verify_key(),
+            vdaf: self.vdaf,
+            collector_hpke_configs: vec![collector_hpke_receiver_config.config.clone()],
+            max_pending_reports: None,
+            allowed_extension_types: None,
+            require_latest_hpke_config: false,
+            require_aligned_report_time: false,
+            global_config_override: None,
+        };
+        let tasks = HashMap::from([(task_id.clone(), task_config.clone())]);
+
+        let leader_token = BearerToken::from("this is a bearer token!");
+        let collector_token = BearerToken::from("This is a DIFFERENT token.");
+        let mut taskprov_vdaf_verify_key_init = vec![0; 32];
+        rng.fill(&mut taskprov_vdaf_verify_key_init[..]);
+
+        let mock_aggregator = |collector_token| MockAggregator {
+            now,
+            global_config: global_config.clone(),
+            tasks: Arc::new(Mutex::new(tasks.clone())),
+            hpke_receiver_config_list: global_config
+                .gen_hpke_receiver_config_list(thread_rng().gen())
+                .collect::<Result<Vec<HpkeReceiverConfig>, _>>()
+                .expect("failed to generate HPKE receiver config"),
+            leader_token: leader_token.clone(),
+            collector_token,
+            task_collector_tokens: HashMap::new(),
+            report_store: Arc::new(Mutex::new(HashMap::new())),
+            leader_state_store: Arc::new(Mutex::new(HashMap::new())),
+            helper_state_store: Arc::new(Mutex::new(HashMap::new())),
+            agg_init_resp_store: Arc::new(Mutex::new(HashMap::new())),
+            agg_store: Arc::new(Mutex::new(HashMap::new())),
+            collector_hpke_config: collector_hpke_receiver_config.config.clone(),
+            taskprov_vdaf_verify_key_init: taskprov_vdaf_verify_key_init.clone(),
+            report_buffer: None,
+            task_provider: None,
+            peer: None,
+        };
+        let helper = mock_aggregator(None);
+        let leader = MockAggregator {
+            peer: Some(InProcessHttpClient::new(Arc::new(helper.clone()))),
+            ..mock_aggregator(Some(collector_token.clone()))
+        };
+
+        TestFixture {
+            now,
+            task_id,
+            task_config,
+            leader,
+            helper,
+            leader_token,
+            collector_token,
+            collector_hpke_receiver_config,
+        }
+    }
+}
+
+/// A Leader and Helper configured with a common task. Constructed by [`TestFixtureBuilder`].
+pub struct TestFixture {
+    /// The time at which the fixture was built.
+    pub now: Time,
+
+    /// The ID of the task.
+    pub task_id: Id,
+
+    /// The configuration of the task.
+    pub task_config: DapTaskConfig,
+
+    /// The Leader.
+    pub leader: MockAggregator,
+
+    /// The Helper.
+    pub helper: MockAggregator,
+
+    /// The bearer token used by the Leader to authenticate to the Helper.
+    pub leader_token: BearerToken,
+
+    /// The bearer token used by the Collector to authenticate to the Leader.
+    pub collector_token: BearerToken,
+
+    /// The Collector's HPKE receiver configuration, used to decrypt aggregate shares.
+    pub collector_hpke_receiver_config: HpkeReceiverConfig,
+}
+
+impl TestFixture {
+    /// Generate a report for the task with the current time.
+    pub fn gen_report(&self, measurement: DapMeasurement) -> Result<Report, DapError> {
+        let hpke_config_list = [
+            self.leader.hpke_receiver_config_list[0].config.clone(),
+            self.helper.hpke_receiver_config_list[0].config.clone(),
+        ];
+        self.task_config.vdaf.produce_report(
+            &hpke_config_list,
+            self.now,
+            &self.task_id,
+            measurement,
+            self.task_config.version,
+        )
+    }
+
+    /// Generate an upload request for the given report.
+    pub fn gen_upload_req(&self, report: &Report) -> DapRequest<BearerToken> {
+        DapRequest {
+            version: self.task_config.version,
+            media_type: Some(MEDIA_TYPE_REPORT),
+            task_id: Some(report.task_id.clone()),
+            payload: report.get_encoded(),
+            url: self.task_config.leader_url.join("upload").unwrap(),
+            sender_auth: None,
+            idempotency_key: None,
+            collector_hpke_config_id: None,
+        }
+    }
+}
+
+/// A valid measurement for the given VDAF, used to generate reports for benchmarks.
+fn bench_measurement(vdaf: &VdafConfig) -> DapMeasurement {
+    match vdaf {
+        VdafConfig::Prio3(Prio3Config::CountVec { length }) => {
+            DapMeasurement::U32Vec(vec![1; *length])
+        }
+        VdafConfig::Prio2 { dimension } => DapMeasurement::U32Vec(vec![1; *dimension as usize]),
+        VdafConfig::Prio3(..) => DapMeasurement::U64(1),
+    }
+}
+
+/// Benchmark helper: generate `n` reports for a task with the given VDAF and return the time it
+/// took. Setting up the task is not included in the measurement.
+pub fn bench_produce_reports(vdaf: &VdafConfig, n: usize) -> Duration {
+    let fixture = TestFixtureBuilder::new().vdaf(vdaf.clone()).build();
+
+    let start = Instant::now();
+    for _ in 0..n {
+        fixture
+            .gen_report(bench_measurement(vdaf))
+            .expect("failed to generate report");
+    }
+    start.elapsed()
+}
+
+/// Benchmark helper: run a single aggregation job over `n` reports for a "time-interval" task
+/// with the Prio3Count VDAF and return the time it took. The Helper runs in process. Generating
+/// the reports is not included in the measurement.
+pub async fn bench_aggregate(n: usize) -> Duration {
+    let fixture = TestFixtureBuilder::new().build();
+    let reports = (0..n)
+        .map(|_| fixture.gen_report(DapMeasurement::U64(1)))
+        .collect::<Result<Vec<Report>, _>>()
+        .expect("failed to generate report");
+
+    let start = Instant::now();
+    let reports_aggregated = fixture
+        .leader
+        .run_agg_job(
+            &fixture.task_id,
+            &fixture.task_config,
+            &PartialBatchSelector::TimeInterval,
+            reports,
+        )
+        .await
+        .expect("aggregation job failed");
+    let elapsed = start.elapsed();
+    assert_eq!(reports_aggregated, n as u64);
+    elapsed
 }
 
 /// Information associated to a certain helper state for a given task ID and aggregate job ID.
@@ -824,17 +1520,29 @@ pub(crate) struct HelperStateInfo {
     agg_job_id: Id,
 }
 
+/// Identifies the response to an aggregate initialization request for a given task ID and
+/// idempotency key.
+#[derive(Clone, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub(crate) struct IdempotencyKeyInfo {
+    task_id: Id,
+    idempotency_key: Id,
+}
+
 /// Stores the reports received from Clients.
 #[derive(Default)]
 pub(crate) struct ReportStore {
     pub(crate) pending: HashMap<DapBatchBucketOwned, VecDeque<Report>>,
-    pub(crate) processed: HashSet<ReportId>,
+    pub(crate) processed: BTreeMap<Time, HashSet<ReportId>>, // Report storage epoch, report IDs
+    pub(crate) rejected: HashMap<ReportId, TransitionFailure>,
+    pub(crate) committed: HashSet<[u8; 32]>, // Checksums of reports whose output shares were stored
 }
 
 /// Stores the state of the collect job.
 pub(crate) enum CollectJobState {
-    Pending(CollectReq),
-    Processed(CollectResp),
+    Pending(CollectReq, Time),          // Collect request, creation time
+    Processed(CollectResp, Time, Time), // Collect response, creation time, completion time
+    Expired(Time),                      // Creation time
+    Failed(Time),                       // Creation time
 }
 
 /// LeaderState keeps track of the following:
@@ -843,7 +1551,7 @@ pub(crate) enum CollectJobState {
 #[derive(Default)]
 pub(crate) struct LeaderState {
     collect_ids: VecDeque<Id>,
-    collect_jobs: HashMap<Id, CollectJobState>,
+    pub(crate) collect_jobs: HashMap<Id, CollectJobState>,
     batch_queue: VecDeque<(Id, u64)>, // Batch ID, batch size
 }
 
@@ -855,67 +1563,3 @@ pub(crate) struct AggStore {
     pub(crate) agg_share: DapAggregateShare,
     pub(crate) collected: bool,
 }
-
-// These are declarative macros which let us generate a test point for
-// each DapVersion given a test which takes a version parameter.
-//
-// E.g. currently
-//
-//     async_test_versions! { something }
-//
-// would generate async tests named
-//
-//     something_draft02
-//
-// and
-//
-//     something_draft03
-//
-// that called something(version) with the appropriate version.
-//
-// We use the "paste" crate to get a macro that can paste tokens and also
-// fiddle case.
-
-#[macro_export]
-macro_rules! test_version {
-    ($fname:ident, $version:ident) => {
-        paste! {
-            #[test]
-            fn [<$fname _ $version:lower>]() {
-                $fname (DapVersion::$version);
-            }
-        }
-    };
-}
-
-#[macro_export]
-macro_rules! test_versions {
-    ($($fname:ident),*) => {
-        $(
-            test_version! { $fname, Draft02 }
-            test_version! { $fname, Draft03 }
-        )*
-    };
-}
-
-#[macro_export]
-macro_rules! async_test_version {
-    ($fname:ident, $version:ident) => {
-        paste! {
-            #[tokio::test]
-            async fn [<$fname _ $version:lower>]() {
-                $fname (DapVersion::$version) . await;
-            }
-        }
-    };
-}
-
-#[macro_export]
-macro_rules! async_test_versions {
-    ($($fname:ident),*) => {
-        $(
-            async_test_version! { $fname, Draft02 }
-            async_test_version! { $fname, Draft03 }
-        )*
-    };
-}
@@ -5,46 +5,54 @@
 //! ([VDAFs](https://datatracker.ietf.org/doc/draft-irtf-cfrg-vdaf/)).
 
 use crate::{
-    hpke::HpkeDecrypter,
+    hpke::{agg_share_info, report_info, HpkeDecrypter},
     messages::{
-        encode_u32_bytes, AggregateContinueReq, AggregateInitializeReq, AggregateResp,
-        BatchSelector, Extension, HpkeCiphertext, HpkeConfig, Id, PartialBatchSelector, Report,
-        ReportId, ReportMetadata, ReportShare, Time, Transition, TransitionFailure, TransitionVar,
+        encode_u32_bytes, BatchSelector, Extension, HpkeCiphertext, HpkeConfig, Id, Report,
+        ReportId, ReportMetadata, Time,
+    },
+    vdaf::{
+        prio2::{prio2_shard, prio2_unshard},
+        prio3::{prio3_shard, prio3_unshard},
+    },
+    AggShareCompression, DapAbort, DapAggregateResult, DapError, DapMeasurement, DapVersion,
+    Prio3Config, VdafConfig,
+};
+#[cfg(any(feature = "roles", test))]
+use crate::{
+    messages::{
+        AggregateContinueReq, AggregateInitializeReq, AggregateResp, PartialBatchSelector,
+        ReportShare, Transition, TransitionFailure, TransitionVar,
     },
     vdaf::{
         prio2::{
             prio2_encode_prepare_message, prio2_helper_prepare_finish, prio2_leader_prepare_finish,
-            prio2_prepare_init, prio2_shard, prio2_unshard,
+            prio2_prepare_init,
         },
         prio3::{
             prio3_encode_prepare_message, prio3_helper_prepare_finish, prio3_leader_prepare_finish,
-            prio3_prepare_init, prio3_shard, prio3_unshard,
+            prio3_prepare_init,
         },
     },
-    DapAbort, DapAggregateResult, DapAggregateShare, DapError, DapHelperState, DapHelperTransition,
-    DapLeaderState, DapLeaderTransition, DapLeaderUncommitted, DapMeasurement, DapOutputShare,
-    DapVersion, VdafConfig,
+    ChecksumAlgorithm, DapAggregateShare, DapHelperState, DapHelperTransition, DapLeaderState,
+    DapLeaderTransition, DapLeaderUncommitted, DapOutputShare, NoiseSplit,
 };
+use flate2::read::GzDecoder;
+#[cfg(any(feature = "roles", test))]
+use flate2::{write::GzEncoder, Compression};
 use prio::{
     codec::{CodecError, Encode},
-    field::{Field128, Field64, FieldPrio2},
+    field::{Field128, Field64, FieldElement, FieldPrio2},
     vdaf::{
         prio2::{Prio2PrepareShare, Prio2PrepareState},
         prio3::{Prio3PrepareShare, Prio3PrepareState},
+        AggregateShare,
     },
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, convert::TryInto};
-
-const CTX_INPUT_SHARE_DRAFT02: &[u8] = b"dap-02 input share";
-const CTX_INPUT_SHARE_DRAFT03: &[u8] = b"dap-03 input share";
-const CTX_AGG_SHARE_DRAFT02: &[u8] = b"dap-02 aggregate share";
-const CTX_AGG_SHARE_DRAFT03: &[u8] = b"dap-03 aggregate share";
-const CTX_ROLE_COLLECTOR: u8 = 0;
-const CTX_ROLE_CLIENT: u8 = 1;
-const CTX_ROLE_LEADER: u8 = 2;
-const CTX_ROLE_HELPER: u8 = 3;
+use std::io::Read;
+#[cfg(any(feature = "roles", test))]
+use std::{collections::HashSet, io::Write};
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum VdafError {
@@ -103,14 +111,6 @@ impl Encode for VdafAggregateShare {
     }
 }
 
-fn unimplemented_version_abort() -> DapAbort {
-    DapAbort::BadRequest("unimplemented version".to_string())
-}
-
-fn unimplemented_version() -> DapError {
-    DapError::Abort(unimplemented_version_abort())
-}
-
 impl VdafConfig {
     /// Parse a verification key from raw bytes.
     pub fn get_decoded_verify_key(&self, bytes: &[u8]) -> Result<VdafVerifyKey, DapError> {
@@ -124,6 +124,14 @@ impl VdafConfig {
         }
     }
 
+    /// Checks if the provided public share is valid for the underlying VDAF being executed.
+    pub fn is_valid_public_share(&self, public_share: &[u8]) -> bool {
+        match self {
+            // Neither Prio3 nor Prio2 has a public share.
+            Self::Prio3(..) | Self::Prio2 { .. } => public_share.is_empty(),
+        }
+    }
+
     /// Checks if the provided aggregation parameter is valid for the underling VDAF being
     /// executed.
     pub fn is_valid_agg_param(&self, agg_param: &[u8]) -> bool {
@@ -132,6 +140,34 @@ impl VdafConfig {
         }
     }
 
+    /// Check that the provided aggregation parameter is valid for the underlying VDAF, aborting
+    /// with [`DapAbort::UnrecognizedMessage`] if not. Neither Prio3 nor Prio2 takes an
+    /// aggregation parameter, so it must be empty.
+    //
+    // TODO spec: Define this behavior.
+    pub fn validate_agg_param(&self, agg_param: &[u8]) -> Result<(), DapAbort> {
+        if !self.is_valid_agg_param(agg_param) {
+            return Err(DapAbort::UnrecognizedMessage);
+        }
+        Ok(())
+    }
+
+    /// Decode an aggregate share for this VDAF from `bytes`.
+    pub(crate) fn decode_agg_share(&self, bytes: &[u8]) -> Result<VdafAggregateShare, DapError> {
+        let agg_share = match self {
+            Self::Prio3(Prio3Config::Count) => VdafAggregateShare::Field64(
+                AggregateShare::try_from(bytes).map_err(|e| CodecError::Other(Box::new(e)))?,
+            ),
+            Self::Prio3(..) => VdafAggregateShare::Field128(
+                AggregateShare::try_from(bytes).map_err(|e| CodecError::Other(Box::new(e)))?,
+            ),
+            Self::Prio2 { .. } => VdafAggregateShare::FieldPrio2(
+                AggregateShare::try_from(bytes).map_err(|e| CodecError::Other(Box::new(e)))?,
+            ),
+        };
+        Ok(agg_share)
+    }
+
     /// Generate the Aggregators' shared verification parameters.
     pub fn gen_verify_key(&self) -> VdafVerifyKey {
         let mut rng = thread_rng();
@@ -141,6 +177,60 @@ impl VdafConfig {
         }
     }
 
+    /// Parse a measurement for the VDAF from a JSON value. This allows a Client to accept
+    /// measurements without knowing which `DapMeasurement` variant the VDAF expects.
+    ///
+    /// Prio3Count, Prio3Sum, and Prio3Histogram expect a non-negative integer. Prio3CountVec
+    /// expects an array of `length` integers and Prio2 expects an array of `dimension` integers,
+    /// each of which is either `0` or `1`.
+    pub fn measurement_from_json(
+        &self,
+        value: serde_json::Value,
+    ) -> Result<DapMeasurement, DapError> {
+        let bad_measurement = |reason: &str| {
+            DapError::Abort(DapAbort::BadRequest(format!("measurement: {}", reason)))
+        };
+        let bit_vec = |len: usize| {
+            let values = value
+                .as_array()
+                .ok_or_else(|| bad_measurement("expected an array"))?;
+            if values.len() != len {
+                return Err(bad_measurement(&format!(
+                    "expected an array of length {}; got {}",
+                    len,
+                    values.len()
+                )));
+            }
+            values
+                .iter()
+                .map(|v| match v.as_u64() {
+                    Some(b @ (0 | 1)) => Ok(b as u32),
+                    _ => Err(bad_measurement("expected each element to be 0 or 1")),
+                })
+                .collect::<Result<Vec<u32>, DapError>>()
+                .map(DapMeasurement::U32Vec)
+        };
+
+        match self {
+            Self::Prio3(Prio3Config::CountVec { length }) => bit_vec(*length),
+            Self::Prio3(prio3_config) => {
+                let measurement = value
+                    .as_u64()
+                    .ok_or_else(|| bad_measurement("expected a non-negative integer"))?;
+                match prio3_config {
+                    Prio3Config::Count if measurement > 1 => {
+                        Err(bad_measurement("expected 0 or 1"))
+                    }
+                    Prio3Config::Sum { bits } if *bits < 64 && measurement >> *bits != 0 => Err(
+                        bad_measurement(&format!("value does not fit in {} bits", bits)),
+                    ),
+                    _ => Ok(DapMeasurement::U64(measurement)),
+                }
+            }
+            Self::Prio2 { dimension } => bit_vec(*dimension as usize),
+        }
+    }
+
     /// Generate a report for a measurement. This method is run by the Client.
     ///
     /// # Inputs
@@ -171,67 +261,75 @@ impl VdafConfig {
         extensions: Vec<Extension>,
         version: DapVersion,
     ) -> Result<Report, DapError> {
-        let mut rng = thread_rng();
+        self.produce_report_with_rng(
+            hpke_config_list,
+            time,
+            task_id,
+            measurement,
+            extensions,
+            version,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Generate a report for the given measurement and extensions, using `rng` to generate the
+    /// report ID. This method is run by the Client. The inputs are the same as for
+    /// [`produce_report_with_extensions`](Self::produce_report_with_extensions).
+    ///
+    /// Note that a seeded `rng` does not make the report byte-stable: the VDAF sharding and HPKE
+    /// encapsulation draw their randomness from the underlying libraries, which don't yet accept
+    /// an RNG.
+    #[allow(clippy::too_many_arguments)]
+    pub fn produce_report_with_rng(
+        &self,
+        hpke_config_list: &[HpkeConfig],
+        time: Time,
+        task_id: &Id,
+        measurement: DapMeasurement,
+        extensions: Vec<Extension>,
+        version: DapVersion,
+        rng: &mut impl RngCore,
+    ) -> Result<Report, DapError> {
         let metadata = ReportMetadata {
             id: ReportId(rng.gen()),
             time,
             extensions,
         };
 
-        let public_share = Vec::new();
         let encoded_input_shares = match self {
             Self::Prio3(prio3_config) => prio3_shard(prio3_config, measurement)?,
             Self::Prio2 { dimension } => prio2_shard(*dimension, measurement)?,
         };
 
-        if hpke_config_list.len() != encoded_input_shares.len() {
-            return Err(DapError::Fatal("unexpected number of HPKE configs".into()));
-        }
+        seal_report(
+            hpke_config_list,
+            task_id,
+            metadata,
+            encoded_input_shares,
+            version,
+        )
+    }
 
-        let input_share_text = match version {
-            DapVersion::Draft02 => CTX_INPUT_SHARE_DRAFT02,
-            DapVersion::Draft03 => CTX_INPUT_SHARE_DRAFT03,
-            _ => return Err(unimplemented_version()),
+    /// Generate a report from input shares that have already been produced by sharding a
+    /// measurement for this VDAF. This is useful for testing, e.g., for encrypting the input shares
+    /// of a test vector. The inputs are the same as for [`produce_report`](Self::produce_report),
+    /// except that `input_shares` is the sequence of encoded input shares, the first for the
+    /// Leader and the remainder for the Helpers.
+    pub fn produce_report_from_shares(
+        &self,
+        hpke_config_list: &[HpkeConfig],
+        time: Time,
+        task_id: &Id,
+        input_shares: Vec<Vec<u8>>,
+        version: DapVersion,
+    ) -> Result<Report, DapError> {
+        let metadata = ReportMetadata {
+            id: ReportId(thread_rng().gen()),
+            time,
+            extensions: Vec::new(),
         };
-        let n: usize = input_share_text.len();
-        let mut info = Vec::new();
-        info.reserve(n + 2);
-        info.extend_from_slice(input_share_text);
-        info.push(CTX_ROLE_CLIENT); // Sender role
-        info.push(CTX_ROLE_LEADER); // Receiver role placeholder; updated below.
-
-        let mut aad = Vec::with_capacity(58);
-        task_id.encode(&mut aad);
-        metadata.encode(&mut aad);
-        // TODO spec: Consider folding the public share into a field called "header".
-        encode_u32_bytes(&mut aad, &public_share);
-
-        let mut encrypted_input_shares = Vec::with_capacity(encoded_input_shares.len());
-        for (i, (hpke_config, input_share_data)) in hpke_config_list
-            .iter()
-            .zip(encoded_input_shares)
-            .enumerate()
-        {
-            info[n + 1] = if i == 0 {
-                CTX_ROLE_LEADER
-            } else {
-                CTX_ROLE_HELPER
-            }; // Receiver role
-            let (enc, payload) = hpke_config.encrypt(&info, &aad, &input_share_data)?;
-
-            encrypted_input_shares.push(HpkeCiphertext {
-                config_id: hpke_config.id,
-                enc,
-                payload,
-            });
-        }
 
-        Ok(Report {
-            task_id: task_id.clone(),
-            metadata,
-            public_share,
-            encrypted_input_shares,
-        })
+        seal_report(hpke_config_list, task_id, metadata, input_shares, version)
     }
 
     /// Generate a report for a measurement. This method is run by the Client.
@@ -286,6 +384,7 @@ impl VdafConfig {
     ///
     /// * `version` is the DapVersion to use.
     #[allow(clippy::too_many_arguments)]
+    #[cfg(any(feature = "roles", test))]
     pub(crate) async fn consume_report_share(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
@@ -297,21 +396,7 @@ impl VdafConfig {
         encrypted_input_share: &HpkeCiphertext,
         version: DapVersion,
     ) -> Result<(VdafState, VdafMessage), DapError> {
-        let input_share_text = match version {
-            DapVersion::Draft02 => CTX_INPUT_SHARE_DRAFT02,
-            DapVersion::Draft03 => CTX_INPUT_SHARE_DRAFT03,
-            _ => return Err(unimplemented_version()),
-        };
-        let n: usize = input_share_text.len();
-        let mut info = Vec::new();
-        info.reserve(n + 2);
-        info.extend_from_slice(input_share_text);
-        info.push(CTX_ROLE_CLIENT); // Sender role (receiver role set below)
-        info.push(if is_leader {
-            CTX_ROLE_LEADER
-        } else {
-            CTX_ROLE_HELPER
-        }); // Receiver role
+        let info = report_info(version, is_leader)?;
 
         let mut aad = Vec::with_capacity(58);
         task_id.encode(&mut aad);
@@ -319,10 +404,35 @@ impl VdafConfig {
         // TODO spec: Consider folding the public share into a field called "header".
         encode_u32_bytes(&mut aad, public_share);
 
+        // A ciphertext sealed to an HPKE config that is no longer in use can't be decrypted, so
+        // reject it with the specific failure rather than attempting decryption.
+        if !decrypter
+            .can_hpke_decrypt(task_id, encrypted_input_share.config_id)
+            .await?
+        {
+            return Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId));
+        }
+
+        // An empty ciphertext can't be valid, so reject it without calling into the HPKE library.
+        if encrypted_input_share.payload.is_empty() {
+            return Err(DapError::Transition(TransitionFailure::HpkeDecryptError));
+        }
+
+        // The public share is bound to the input share by the AAD, so a public share that is
+        // inconsistent with the input share would otherwise surface as a decryption failure.
+        // Reject a public share that can't be valid for the VDAF explicitly.
+        //
+        // TODO spec: Decide if this behavior should be specified.
+        if !self.is_valid_public_share(public_share) {
+            return Err(DapError::Transition(TransitionFailure::VdafPrepError));
+        }
+
         let input_share_data = decrypter
             .hpke_decrypt(task_id, &info, &aad, encrypted_input_share)
             .await?;
 
+        // The input share is decoded using the VDAF's parameters and must be consumed in full, so a
+        // plaintext that is too short or too long results in a `VdafPrepError`.
         let agg_id = if is_leader { 0 } else { 1 };
         match (self, verify_key) {
             (Self::Prio3(ref prio3_config), VdafVerifyKey::Prio3(ref verify_key)) => {
@@ -366,6 +476,7 @@ impl VdafConfig {
     ///
     /// * `version` is the DapVersion to use.
     #[allow(clippy::too_many_arguments)]
+    #[cfg(any(feature = "roles", test))]
     pub(crate) async fn produce_agg_init_req(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
@@ -379,6 +490,7 @@ impl VdafConfig {
         let mut processed = HashSet::with_capacity(reports.len());
         let mut states = Vec::with_capacity(reports.len());
         let mut seq = Vec::with_capacity(reports.len());
+        let mut rejected = Vec::new();
         for report in reports.into_iter() {
             if processed.contains(&report.metadata.id) {
                 return Err(DapError::fatal(
@@ -429,18 +541,23 @@ impl VdafConfig {
                 // Skip report that can't be processed any further.
                 //
                 // TODO Emit metric for failure reason
-                Err(DapError::Transition(..)) => (),
+                Err(DapError::Transition(failure_reason)) => {
+                    rejected.push((report.metadata.id, failure_reason));
+                }
 
                 Err(e) => return Err(DapAbort::Internal(Box::new(e))),
             };
         }
 
         if seq.is_empty() {
-            return Ok(DapLeaderTransition::Skip);
+            return Ok(DapLeaderTransition::Skip(rejected));
         }
 
         Ok(DapLeaderTransition::Continue(
-            DapLeaderState { seq: states },
+            DapLeaderState {
+                seq: states,
+                rejected,
+            },
             AggregateInitializeReq {
                 task_id: task_id.clone(),
                 agg_job_id: agg_job_id.clone(),
@@ -476,6 +593,10 @@ impl VdafConfig {
     /// the transition failure the Helper is to transmit.
     ///
     /// * `version` is the DapVersion to use.
+    ///
+    /// The transitions in the aggregate response are in the same order as the report shares in
+    /// `agg_init_req`.
+    #[cfg(any(feature = "roles", test))]
     pub(crate) async fn handle_agg_init_req(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
@@ -550,13 +671,19 @@ impl VdafConfig {
     ///
     /// * `state` is the Leader's current state.
     ///
-    /// * `agg_resp` is the previous aggregate response sent by the Helper.
+    /// * `agg_resp` is the previous aggregate response sent by the Helper. The response must
+    /// contain exactly one transition for each report in `state`, in the same order; otherwise
+    /// the Leader aborts with [`DapAbort::UnrecognizedMessage`].
+    ///
+    /// * `checksum_algorithm` is the algorithm used to compute the checksum of each output share.
+    #[cfg(any(feature = "roles", test))]
     pub(crate) fn handle_agg_resp(
         &self,
         task_id: &Id,
         agg_job_id: &Id,
         state: DapLeaderState,
         agg_resp: AggregateResp,
+        checksum_algorithm: ChecksumAlgorithm,
     ) -> Result<DapLeaderTransition<AggregateContinueReq>, DapAbort> {
         if agg_resp.transitions.len() != state.seq.len() {
             return Err(DapAbort::UnrecognizedMessage);
@@ -564,6 +691,7 @@ impl VdafConfig {
 
         let mut seq = Vec::with_capacity(state.seq.len());
         let mut states = Vec::with_capacity(state.seq.len());
+        let mut rejected = state.rejected;
         for (helper, (leader_step, leader_message, leader_time, leader_report_id)) in
             agg_resp.transitions.into_iter().zip(state.seq.into_iter())
         {
@@ -578,7 +706,10 @@ impl VdafConfig {
                 // Skip report that can't be processed any further.
                 //
                 // TODO Log the reason the report was skipped.
-                TransitionVar::Failed(..) => continue,
+                TransitionVar::Failed(failure_reason) => {
+                    rejected.push((leader_report_id, *failure_reason));
+                    continue;
+                }
 
                 // TODO Log the fact that the helper sent an unexpected message.
                 TransitionVar::Finished => return Err(DapAbort::UnrecognizedMessage),
@@ -601,15 +732,11 @@ impl VdafConfig {
 
             match res {
                 Ok((data, message)) => {
-                    let checksum = ring::digest::digest(
-                        &ring::digest::SHA256,
-                        &leader_report_id.get_encoded(),
-                    );
-
                     states.push((
                         DapOutputShare {
                             time: leader_time,
-                            checksum: checksum.as_ref().try_into().unwrap(),
+                            checksum: checksum_algorithm
+                                .compute(std::slice::from_ref(&leader_report_id)),
                             data,
                         },
                         leader_report_id.clone(),
@@ -624,16 +751,21 @@ impl VdafConfig {
                 // Skip report that can't be processed any further.
                 //
                 // TODO Log the reason the report was skipped.
-                Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => (),
+                Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => {
+                    rejected.push((leader_report_id, TransitionFailure::VdafPrepError));
+                }
             };
         }
 
         if seq.is_empty() {
-            return Ok(DapLeaderTransition::Skip);
+            return Ok(DapLeaderTransition::Skip(rejected));
         }
 
         Ok(DapLeaderTransition::Uncommitted(
-            DapLeaderUncommitted { seq: states },
+            DapLeaderUncommitted {
+                seq: states,
+                rejected,
+            },
             AggregateContinueReq {
                 task_id: task_id.clone(),
                 agg_job_id: agg_job_id.clone(),
@@ -647,15 +779,25 @@ impl VdafConfig {
     /// Note: This method does not compute the message authentication tag. It is up to the caller
     /// to do so.
     ///
+    /// If the request includes a transition for a report ID that was not part of the
+    /// initialization phase, or that appears more than once or out of order, then the Helper
+    /// aborts the aggregation job with [`DapAbort::UnrecognizedMessage`]. Draft02 and Draft03 do
+    /// not define a per-report failure for this case. (Later drafts fail the report with
+    /// `unrecognized_message`, which would need to be gated on the version.)
+    ///
     /// # Inputs
     ///
     /// * `state` is the helper's current state.
     ///
     /// * `agg_cont_req` is the aggregate request sent by the Leader.
+    ///
+    /// * `checksum_algorithm` is the algorithm used to compute the checksum of each output share.
+    #[cfg(any(feature = "roles", test))]
     pub(crate) fn handle_agg_cont_req(
         &self,
         state: DapHelperState,
         agg_cont_req: &AggregateContinueReq,
+        checksum_algorithm: ChecksumAlgorithm,
     ) -> Result<DapHelperTransition<AggregateResp>, DapAbort> {
         let mut processed = HashSet::with_capacity(state.seq.len());
         let mut recognized = HashSet::with_capacity(state.seq.len());
@@ -706,14 +848,10 @@ impl VdafConfig {
 
                 let var = match res {
                     Ok(data) => {
-                        let checksum = ring::digest::digest(
-                            &ring::digest::SHA256,
-                            &helper_report_id.get_encoded(),
-                        );
-
                         out_shares.push(DapOutputShare {
                             time: helper_time,
-                            checksum: checksum.as_ref().try_into().unwrap(),
+                            checksum: checksum_algorithm
+                                .compute(std::slice::from_ref(&helper_report_id)),
                             data,
                         });
                         TransitionVar::Finished
@@ -752,16 +890,22 @@ impl VdafConfig {
     /// the previous round that have not yet been commmitted to.
     ///
     /// * `agg_resp` is the previous aggregate response sent by the Helper.
+    ///
+    /// The output is the set of output shares to commit to, along with the set of reports that
+    /// were rejected during the aggregation flow and the reason each was rejected.
+    #[allow(clippy::type_complexity)]
+    #[cfg(any(feature = "roles", test))]
     pub(crate) fn handle_final_agg_resp(
         &self,
         uncommitted: DapLeaderUncommitted,
         agg_resp: AggregateResp,
-    ) -> Result<Vec<DapOutputShare>, DapAbort> {
+    ) -> Result<(Vec<DapOutputShare>, Vec<(ReportId, TransitionFailure)>), DapAbort> {
         if agg_resp.transitions.len() != uncommitted.seq.len() {
             return Err(DapAbort::UnrecognizedMessage);
         }
 
         let mut out_shares = Vec::with_capacity(uncommitted.seq.len());
+        let mut rejected = uncommitted.rejected;
         for (helper, (out_share, leader_report_id)) in agg_resp
             .transitions
             .into_iter()
@@ -779,13 +923,15 @@ impl VdafConfig {
                 // Skip report that can't be processed any further.
                 //
                 // TODO Log the reason the report was skipped.
-                TransitionVar::Failed(..) => continue,
+                TransitionVar::Failed(failure_reason) => {
+                    rejected.push((leader_report_id, *failure_reason))
+                }
 
                 TransitionVar::Finished => out_shares.push(out_share),
             };
         }
 
-        Ok(out_shares)
+        Ok((out_shares, rejected))
     }
 
     /// Encrypt an aggregate share under the Collector's public key. This method is run by the
@@ -801,35 +947,65 @@ impl VdafConfig {
     ///
     /// * `agg_share` is the aggregate share.
     ///
+    /// * `compression` is the compression applied to the aggregate share before encryption.
+    ///
+    /// * `noise_split` determines the differential privacy noise added to the aggregate share.
+    ///
     /// * `version` is the DapVersion to use.
+    #[cfg(any(feature = "roles", test))]
     pub(crate) fn produce_leader_encrypted_agg_share(
         &self,
         hpke_config: &HpkeConfig,
         task_id: &Id,
         batch_sel: &BatchSelector,
         agg_share: &DapAggregateShare,
+        compression: AggShareCompression,
+        noise_split: NoiseSplit,
         version: DapVersion,
     ) -> Result<HpkeCiphertext, DapAbort> {
-        produce_encrypted_agg_share(true, hpke_config, task_id, batch_sel, agg_share, version)
+        produce_encrypted_agg_share(
+            true,
+            hpke_config,
+            task_id,
+            batch_sel,
+            agg_share,
+            compression,
+            noise_split,
+            version,
+        )
     }
 
     /// Like [`produce_leader_encrypted_agg_share`] but run by the Helper in response to an
     /// aggregate-share request.
     ///
     /// * `version` is the DapVersion to use.
+    #[cfg(any(feature = "roles", test))]
     pub(crate) fn produce_helper_encrypted_agg_share(
         &self,
         hpke_config: &HpkeConfig,
         task_id: &Id,
         batch_sel: &BatchSelector,
         agg_share: &DapAggregateShare,
+        compression: AggShareCompression,
+        noise_split: NoiseSplit,
         version: DapVersion,
     ) -> Result<HpkeCiphertext, DapAbort> {
-        produce_encrypted_agg_share(false, hpke_config, task_id, batch_sel, agg_share, version)
+        produce_encrypted_agg_share(
+            false,
+            hpke_config,
+            task_id,
+            batch_sel,
+            agg_share,
+            compression,
+            noise_split,
+            version,
+        )
     }
 
     /// Decrypt and unshard a sequence of aggregate shares. This method is run by the Collector
-    /// after completing a collect request.
+    /// after completing a collect request. It is equivalent to calling
+    /// [`decrypt_encrypted_agg_shares`](Self::decrypt_encrypted_agg_shares) followed by
+    /// [`unshard_agg_shares`](Self::unshard_agg_shares).
     ///
     /// # Inputs
     ///
@@ -839,12 +1015,17 @@ impl VdafConfig {
     ///
     /// * `batch_interval` is the batch interval for the aggregate share.
     ///
+    /// * `report_count` is the number of reports aggregated into the aggregate shares.
+    ///
     /// * `encrypted_agg_shares` is the set of encrypted aggregate shares produced by the
     /// Aggregators. The first encrypted aggregate shares must be the Leader's.
     ///
+    /// * `compression` is the compression the Aggregators are configured to apply to aggregate
+    ///   shares (see [`DapGlobalConfig::agg_share_compression`](crate::DapGlobalConfig)). It is
+    ///   not indicated in the aggregate shares themselves.
+    ///
     /// * `version` is the DapVersion to use.
-    //
-    // TODO spec: Allow the collector to have multiple HPKE public keys (the way Aggregators do).
+    #[allow(clippy::too_many_arguments)]
     pub async fn consume_encrypted_agg_shares(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
@@ -852,19 +1033,55 @@ impl VdafConfig {
         batch_sel: &BatchSelector,
         report_count: u64,
         encrypted_agg_shares: Vec<HpkeCiphertext>,
+        compression: AggShareCompression,
         version: DapVersion,
     ) -> Result<DapAggregateResult, DapError> {
-        let agg_share_text = match version {
-            DapVersion::Draft02 => CTX_AGG_SHARE_DRAFT02,
-            DapVersion::Draft03 => CTX_AGG_SHARE_DRAFT03,
-            _ => return Err(unimplemented_version()),
-        };
-        let n: usize = agg_share_text.len();
-        let mut info = Vec::new();
-        info.reserve(n + 2);
-        info.extend_from_slice(agg_share_text);
-        info.push(CTX_ROLE_LEADER); // Sender role placeholder
-        info.push(CTX_ROLE_COLLECTOR); // Receiver role
+        let agg_shares = self
+            .decrypt_encrypted_agg_shares(
+                decrypter,
+                task_id,
+                batch_sel,
+                encrypted_agg_shares,
+                compression,
+                version,
+            )
+            .await?;
+        self.unshard_agg_shares(report_count, agg_shares)
+    }
+
+    /// Decrypt a sequence of aggregate shares without unsharding them. This method is run by a
+    /// Collector that wants to inspect or verify each Aggregator's share independently. The
+    /// output is the sequence of encoded aggregate shares, in the same order as the input.
+    /// Aggregate shares are decompressed according to `compression`.
+    ///
+    /// # Inputs
+    ///
+    /// * `decrypter` is used to decrypt the aggregate shares.
+    ///
+    /// * `task_id` is the DAP task ID.
+    ///
+    /// * `batch_interval` is the batch interval for the aggregate share.
+    ///
+    /// * `encrypted_agg_shares` is the set of encrypted aggregate shares produced by the
+    /// Aggregators. The first encrypted aggregate shares must be the Leader's.
+    ///
+    /// * `compression` is the compression the Aggregators are configured to apply to aggregate
+    ///   shares.
+    ///
+    /// * `version` is the DapVersion to use.
+    //
+    // TODO spec: Allow the collector to have multiple HPKE public keys (the way Aggregators do).
+    pub async fn decrypt_encrypted_agg_shares(
+        &self,
+        decrypter: &impl HpkeDecrypter<'_>,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+        encrypted_agg_shares: Vec<HpkeCiphertext>,
+        compression: AggShareCompression,
+        version: DapVersion,
+    ) -> Result<Vec<Vec<u8>>, DapError> {
+        let leader_info = agg_share_info(version, true)?;
+        let helper_info = agg_share_info(version, false)?;
 
         let mut aad = Vec::with_capacity(40);
         task_id.encode(&mut aad);
@@ -872,16 +1089,16 @@ impl VdafConfig {
 
         let mut agg_shares = Vec::with_capacity(encrypted_agg_shares.len());
         for (i, agg_share_ciphertext) in encrypted_agg_shares.iter().enumerate() {
-            info[n] = if i == 0 {
-                CTX_ROLE_LEADER
-            } else {
-                CTX_ROLE_HELPER
-            };
+            let info = if i == 0 { &leader_info } else { &helper_info };
 
             let agg_share_data = decrypter
-                .hpke_decrypt(task_id, &info, &aad, agg_share_ciphertext)
+                .hpke_decrypt(task_id, info, &aad, agg_share_ciphertext)
                 .await?;
-            agg_shares.push(agg_share_data);
+            agg_shares.push(decompress_agg_share(
+                compression,
+                agg_share_data,
+                self.agg_share_len(),
+            )?);
         }
 
         if agg_shares.len() != encrypted_agg_shares.len() {
@@ -890,6 +1107,30 @@ impl VdafConfig {
             ));
         }
 
+        Ok(agg_shares)
+    }
+
+    /// Return the length in bytes of an encoded aggregate share.
+    fn agg_share_len(&self) -> usize {
+        match self {
+            Self::Prio3(Prio3Config::Count) => Field64::ENCODED_SIZE,
+            Self::Prio3(Prio3Config::CountVec { length }) => length * Field128::ENCODED_SIZE,
+            Self::Prio3(Prio3Config::Histogram { buckets }) => {
+                (buckets.len() + 1) * Field128::ENCODED_SIZE
+            }
+            Self::Prio3(Prio3Config::Sum { .. }) => Field128::ENCODED_SIZE,
+            Self::Prio2 { dimension } => *dimension as usize * FieldPrio2::ENCODED_SIZE,
+        }
+    }
+
+    /// Unshard a sequence of encoded aggregate shares, as output by
+    /// [`decrypt_encrypted_agg_shares`](Self::decrypt_encrypted_agg_shares), into the aggregate
+    /// result. The first aggregate share must be the Leader's.
+    pub fn unshard_agg_shares(
+        &self,
+        report_count: u64,
+        agg_shares: Vec<Vec<u8>>,
+    ) -> Result<DapAggregateResult, DapError> {
         let num_measurements = usize::try_from(report_count).unwrap();
         match self {
             Self::Prio3(prio3_config) => {
@@ -902,36 +1143,85 @@ impl VdafConfig {
     }
 }
 
+/// Encrypt the input shares of a report to the Aggregators.
+fn seal_report(
+    hpke_config_list: &[HpkeConfig],
+    task_id: &Id,
+    metadata: ReportMetadata,
+    encoded_input_shares: Vec<Vec<u8>>,
+    version: DapVersion,
+) -> Result<Report, DapError> {
+    // Neither Prio3 nor Prio2 has a public share.
+    let public_share = Vec::new();
+
+    if hpke_config_list.len() != encoded_input_shares.len() {
+        return Err(DapError::Fatal("unexpected number of HPKE configs".into()));
+    }
+
+    let leader_info = report_info(version, true)?;
+    let helper_info = report_info(version, false)?;
+
+    let mut aad = Vec::with_capacity(58);
+    task_id.encode(&mut aad);
+    metadata.encode(&mut aad);
+    // TODO spec: Consider folding the public share into a field called "header".
+    encode_u32_bytes(&mut aad, &public_share);
+
+    let mut encrypted_input_shares = Vec::with_capacity(encoded_input_shares.len());
+    for (i, (hpke_config, input_share_data)) in hpke_config_list
+        .iter()
+        .zip(encoded_input_shares)
+        .enumerate()
+    {
+        let info = if i == 0 { &leader_info } else { &helper_info };
+        let (enc, payload) = hpke_config.encrypt(info, &aad, &input_share_data)?;
+
+        encrypted_input_shares.push(HpkeCiphertext {
+            config_id: hpke_config.id,
+            enc,
+            payload,
+        });
+    }
+
+    Ok(Report {
+        task_id: task_id.clone(),
+        metadata,
+        public_share,
+        encrypted_input_shares,
+    })
+}
+
+#[cfg(any(feature = "roles", test))]
 fn produce_encrypted_agg_share(
     is_leader: bool,
     hpke_config: &HpkeConfig,
     task_id: &Id,
     batch_sel: &BatchSelector,
     agg_share: &DapAggregateShare,
+    compression: AggShareCompression,
+    noise_split: NoiseSplit,
     version: DapVersion,
 ) -> Result<HpkeCiphertext, DapAbort> {
     let agg_share_data = agg_share
         .data
         .as_ref()
-        .ok_or_else(|| DapError::fatal("empty aggregate share"))?
-        .get_encoded();
-
-    let agg_share_text = match version {
-        DapVersion::Draft02 => CTX_AGG_SHARE_DRAFT02,
-        DapVersion::Draft03 => CTX_AGG_SHARE_DRAFT03,
-        _ => return Err(unimplemented_version_abort()),
-    };
-    let n: usize = agg_share_text.len();
-    let mut info = Vec::new();
-    info.reserve(n + 2);
-    info.extend_from_slice(agg_share_text);
-    info.push(if is_leader {
-        CTX_ROLE_LEADER
-    } else {
-        CTX_ROLE_HELPER
-    }); // Sender role
-    info.push(CTX_ROLE_COLLECTOR); // Receiver role
+        .ok_or_else(|| DapError::fatal("empty aggregate share"))?;
+    let agg_share_data = compress_agg_share(
+        compression,
+        match noise_split {
+            NoiseSplit::None => agg_share_data.get_encoded(),
+            // Each Aggregator adds half of the variance.
+            NoiseSplit::Even { variance } => {
+                add_noise(agg_share_data, variance, &mut thread_rng())?.get_encoded()
+            }
+        },
+    )?;
+
+    let info = agg_share_info(version, is_leader)?;
 
+    // The AAD binds the aggregate share to the task and batch, so the Collector can't decrypt it
+    // in the context of any other task or batch.
+    //
     // TODO spec: Consider adding agg param to AAD.
     let mut aad = Vec::with_capacity(40);
     task_id.encode(&mut aad);
@@ -947,6 +1237,113 @@ fn produce_encrypted_agg_share(
     })
 }
 
+/// Add noise to each element of an aggregate share. The noise is sampled from a centered binomial
+/// distribution with variance `trials / 2`.
+#[cfg(any(feature = "roles", test))]
+fn add_noise(
+    agg_share: &VdafAggregateShare,
+    trials: u64,
+    rng: &mut impl RngCore,
+) -> Result<VdafAggregateShare, DapError> {
+    Ok(match agg_share {
+        VdafAggregateShare::Field64(data) => {
+            VdafAggregateShare::Field64(add_noise_to_field_vec(data.as_ref(), trials, rng)?.into())
+        }
+        VdafAggregateShare::Field128(data) => {
+            VdafAggregateShare::Field128(add_noise_to_field_vec(data.as_ref(), trials, rng)?.into())
+        }
+        VdafAggregateShare::FieldPrio2(data) => VdafAggregateShare::FieldPrio2(
+            add_noise_to_field_vec(data.as_ref(), trials, rng)?.into(),
+        ),
+    })
+}
+
+#[cfg(any(feature = "roles", test))]
+fn add_noise_to_field_vec<F: FieldElement>(
+    data: &[F],
+    trials: u64,
+    rng: &mut impl RngCore,
+) -> Result<Vec<F>, DapError> {
+    data.iter()
+        .map(|x| {
+            let noise = sample_centered_binomial(trials, rng)?;
+            let magnitude = usize::try_from(noise.unsigned_abs())
+                .ok()
+                .and_then(|magnitude| F::Integer::try_from(magnitude).ok())
+                .ok_or_else(|| DapError::fatal("noise out of range"))?;
+            if noise < 0 {
+                Ok(*x - F::from(magnitude))
+            } else {
+                Ok(*x + F::from(magnitude))
+            }
+        })
+        .collect()
+}
+
+/// Sample from the binomial distribution with `2 * trials` trials and success probability 1/2,
+/// centered at zero. The variance of the distribution is `trials / 2`.
+#[cfg(any(feature = "roles", test))]
+fn sample_centered_binomial(trials: u64, rng: &mut impl RngCore) -> Result<i64, DapError> {
+    let offset = i64::try_from(trials).map_err(|_| DapError::fatal("noise variance too large"))?;
+    let mut ones = 0_i64;
+    let mut remaining = trials
+        .checked_mul(2)
+        .ok_or_else(|| DapError::fatal("noise variance too large"))?;
+    while remaining > 0 {
+        let mut bits = rng.next_u64();
+        if remaining < 64 {
+            bits &= (1 << remaining) - 1;
+        }
+        ones += i64::from(bits.count_ones());
+        remaining = remaining.saturating_sub(64);
+    }
+    Ok(ones - offset)
+}
+
+/// Compress an encoded aggregate share. The compressed aggregate share carries no indication of
+/// the codec used: the Collector must be configured with the same compression as the Aggregators.
+#[cfg(any(feature = "roles", test))]
+fn compress_agg_share(
+    compression: AggShareCompression,
+    agg_share_data: Vec<u8>,
+) -> Result<Vec<u8>, DapError> {
+    match compression {
+        AggShareCompression::None => Ok(agg_share_data),
+        AggShareCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(&agg_share_data)
+                .map_err(|e| DapError::Fatal(format!("gzip: {}", e)))?;
+            encoder
+                .finish()
+                .map_err(|e| DapError::Fatal(format!("gzip: {}", e)))
+        }
+    }
+}
+
+/// Decompress an aggregate share output by [`compress_agg_share`]. `max_len` is the length of the
+/// encoded aggregate share: Decompression stops with an error as soon as the output exceeds it.
+fn decompress_agg_share(
+    compression: AggShareCompression,
+    agg_share_data: Vec<u8>,
+    max_len: usize,
+) -> Result<Vec<u8>, DapError> {
+    match compression {
+        AggShareCompression::None => Ok(agg_share_data),
+        AggShareCompression::Gzip => {
+            let mut bytes = Vec::new();
+            GzDecoder::new(agg_share_data.as_slice())
+                .take(max_len as u64 + 1)
+                .read_to_end(&mut bytes)
+                .map_err(|e| DapError::Fatal(format!("gzip: {}", e)))?;
+            if bytes.len() > max_len {
+                return Err(DapError::fatal("decompressed aggregate share is too long"));
+            }
+            Ok(bytes)
+        }
+    }
+}
+
 #[cfg(test)]
 mod mod_test;
 pub mod prio2;
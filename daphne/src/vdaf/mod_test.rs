@@ -1,6 +1,7 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
+use super::{compress_agg_share, decompress_agg_share, sample_centered_binomial};
 use crate::{
     async_test_version, async_test_versions,
     hpke::HpkeReceiverConfig,
@@ -9,16 +10,21 @@ use crate::{
         HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Id, Interval, PartialBatchSelector,
         Report, ReportId, Transition, TransitionFailure, TransitionVar,
     },
-    test_version, test_versions, DapAbort, DapAggregateResult, DapAggregateShare, DapError,
-    DapHelperState, DapHelperTransition, DapLeaderState, DapLeaderTransition, DapLeaderUncommitted,
-    DapMeasurement, DapOutputShare, DapVersion, Prio3Config, VdafAggregateShare, VdafConfig,
-    VdafMessage, VdafState, VdafVerifyKey,
+    test_version, test_versions, AggShareCompression, ChecksumAlgorithm, DapAbort,
+    DapAggregateResult, DapAggregateShare, DapError, DapHelperState, DapHelperTransition,
+    DapLeaderState, DapLeaderTransition, DapLeaderUncommitted, DapMeasurement, DapOutputShare,
+    DapVersion, NoiseSplit, Prio3Config, VdafAggregateShare, VdafConfig, VdafMessage, VdafState,
+    VdafVerifyKey,
 };
 use assert_matches::assert_matches;
 use paste::paste;
-use prio::vdaf::{
-    prio3::Prio3, Aggregatable, Aggregator as VdafAggregator, Collector as VdafCollector,
-    PrepareTransition,
+use prio::{
+    codec::{Decode, Encode},
+    field::{Field128, FieldElement},
+    vdaf::{
+        prio3::Prio3, Aggregatable, Aggregator as VdafAggregator, Client as VdafClient,
+        Collector as VdafCollector, PrepareTransition,
+    },
 };
 use rand::prelude::*;
 use std::{collections::HashMap, fmt::Debug, time::SystemTime};
@@ -145,6 +151,97 @@ async fn roundtrip_report(version: DapVersion) {
 
 async_test_versions! { roundtrip_report }
 
+// Test that each input share is bound to the Aggregator it is encrypted to, so that a Client that
+// gets the order of the HPKE configs wrong produces a report that is rejected by both Aggregators.
+async fn roundtrip_report_fail_aggregator_order(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let leader_hpke_receiver_config =
+        HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256).unwrap();
+    let helper_hpke_receiver_config =
+        HpkeReceiverConfig::gen(2, HpkeKemId::X25519HkdfSha256).unwrap();
+
+    // Client swaps the Leader's and Helper's HPKE configs.
+    let report = t
+        .vdaf
+        .produce_report(
+            &[
+                helper_hpke_receiver_config.config.clone(),
+                leader_hpke_receiver_config.config.clone(),
+            ],
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+    for (decrypter, is_leader, encrypted_input_share) in [
+        (
+            &leader_hpke_receiver_config,
+            true,
+            &report.encrypted_input_shares[0],
+        ),
+        (
+            &helper_hpke_receiver_config,
+            false,
+            &report.encrypted_input_shares[1],
+        ),
+    ] {
+        assert_matches!(
+            TEST_VDAF
+                .consume_report_share(
+                    decrypter,
+                    is_leader,
+                    &t.vdaf_verify_key,
+                    &t.task_id,
+                    &report.metadata,
+                    &report.public_share,
+                    encrypted_input_share,
+                    version,
+                )
+                .await,
+            Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
+        );
+    }
+
+    // The receiver's role is part of the HPKE context. Thus, even if the config IDs match, an
+    // input share can only be decrypted by the Aggregator in the position it was encrypted for.
+    let report = t
+        .vdaf
+        .produce_report(
+            &[
+                leader_hpke_receiver_config.config.clone(),
+                leader_hpke_receiver_config.config.clone(),
+            ],
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+    for (is_leader, encrypted_input_share) in [
+        (false, &report.encrypted_input_shares[0]),
+        (true, &report.encrypted_input_shares[1]),
+    ] {
+        assert_matches!(
+            TEST_VDAF
+                .consume_report_share(
+                    &leader_hpke_receiver_config,
+                    is_leader,
+                    &t.vdaf_verify_key,
+                    &t.task_id,
+                    &report.metadata,
+                    &report.public_share,
+                    encrypted_input_share,
+                    version,
+                )
+                .await,
+            Err(DapError::Transition(TransitionFailure::HpkeDecryptError))
+        );
+    }
+}
+
+async_test_versions! { roundtrip_report_fail_aggregator_order }
+
 fn roundtrip_report_unsupported_hpke_suite(version: DapVersion) {
     let t = Test::new(TEST_VDAF, version);
 
@@ -172,6 +269,118 @@ fn roundtrip_report_unsupported_hpke_suite(version: DapVersion) {
 
 test_versions! { roundtrip_report_unsupported_hpke_suite }
 
+// Test that a Client can produce a report and encode it for upload. This only depends on the
+// message codec and the VDAF, so it is also run when the `roles` feature is disabled.
+fn report_codec_roundtrip(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let report = t
+        .vdaf
+        .produce_report(
+            &t.client_hpke_config_list,
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+
+    assert_eq!(Report::get_decoded(&report.get_encoded()).unwrap(), report);
+}
+
+test_versions! { report_codec_roundtrip }
+
+// Test that the report metadata is determined by the RNG supplied by the Client.
+fn produce_report_with_rng(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let produce_report = |seed| {
+        t.vdaf
+            .produce_report_with_rng(
+                &t.client_hpke_config_list,
+                t.now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                Vec::new(),
+                version,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap()
+    };
+
+    assert_eq!(produce_report(1).metadata, produce_report(1).metadata);
+    assert_ne!(produce_report(1).metadata, produce_report(2).metadata);
+}
+
+test_versions! { produce_report_with_rng }
+
+// Test that a report produced from pre-sharded input shares aggregates to the sharded measurement.
+async fn produce_report_from_shares(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let vdaf = Prio3::new_aes128_count(2).unwrap();
+    let reports = [1, 0, 1]
+        .iter()
+        .map(|measurement| {
+            let (_public_share, input_shares) = vdaf.shard(measurement).unwrap();
+            t.vdaf
+                .produce_report_from_shares(
+                    &t.client_hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    input_shares
+                        .iter()
+                        .map(|input_share| input_share.get_encoded())
+                        .collect(),
+                    version,
+                )
+                .unwrap()
+        })
+        .collect();
+
+    assert_eq!(
+        t.roundtrip_reports(reports).await,
+        DapAggregateResult::U64(2)
+    );
+}
+
+async_test_versions! { produce_report_from_shares }
+
+#[test]
+fn measurement_from_json_count() {
+    let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!(1)),
+        Ok(DapMeasurement::U64(1))
+    );
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!(2)),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!([1])),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+}
+
+#[test]
+fn measurement_from_json_vec() {
+    let vdaf = VdafConfig::Prio2 { dimension: 3 };
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!([1, 0, 1])),
+        Ok(DapMeasurement::U32Vec(v)) => assert_eq!(v, vec![1, 0, 1])
+    );
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!([1, 0])),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!([1, 0, 2])),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+    assert_matches!(
+        vdaf.measurement_from_json(serde_json::json!(1)),
+        Err(DapError::Abort(DapAbort::BadRequest(..)))
+    );
+}
+
 async fn agg_init_req(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![
@@ -210,8 +419,9 @@ async fn agg_init_req_fail_hpke_decrypt_err(version: DapVersion) {
     reports[0].encrypted_input_shares[0].payload[0] ^= 1;
 
     assert_matches!(
-        t.produce_agg_init_req(reports).await,
-        DapLeaderTransition::Skip
+        t.produce_agg_init_req(reports.clone()).await,
+        DapLeaderTransition::Skip(rejected)
+            if rejected == vec![(reports[0].metadata.id.clone(), TransitionFailure::HpkeDecryptError)]
     );
 }
 
@@ -225,8 +435,9 @@ async fn agg_init_req_fail_hpke_decrypt_err_wrong_config_id(version: DapVersion)
     reports[0].encrypted_input_shares[0].config_id ^= 1;
 
     assert_matches!(
-        t.produce_agg_init_req(reports).await,
-        DapLeaderTransition::Skip
+        t.produce_agg_init_req(reports.clone()).await,
+        DapLeaderTransition::Skip(rejected)
+            if rejected == vec![(reports[0].metadata.id.clone(), TransitionFailure::HpkeUnknownConfigId)]
     );
 }
 
@@ -276,6 +487,57 @@ async fn agg_resp_fail_hpke_decrypt_err_wrong_id(version: DapVersion) {
 
 async_test_versions! { agg_resp_fail_hpke_decrypt_err_wrong_id }
 
+// Test that an input share that is too short or too long for the VDAF is rejected with a VDAF
+// preparation error by the Aggregator it is addressed to.
+async fn agg_init_req_fail_input_share_len(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let vdaf = Prio3::new_aes128_count(2).unwrap();
+    let resizers: [fn(&mut Vec<u8>); 2] = [
+        |input_share| input_share.truncate(input_share.len() - 1),
+        |input_share| input_share.push(0),
+    ];
+
+    for agg_id in 0..2 {
+        for resize in resizers {
+            let (_public_share, input_shares) = vdaf.shard(&1).unwrap();
+            let mut input_shares: Vec<Vec<u8>> = input_shares
+                .iter()
+                .map(|input_share| input_share.get_encoded())
+                .collect();
+            resize(&mut input_shares[agg_id]);
+            let report = t
+                .vdaf
+                .produce_report_from_shares(
+                    &t.client_hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    input_shares,
+                    version,
+                )
+                .unwrap();
+            let report_id = report.metadata.id.clone();
+
+            if agg_id == 0 {
+                assert_matches!(
+                    t.produce_agg_init_req(vec![report]).await,
+                    DapLeaderTransition::Skip(rejected)
+                        if rejected == vec![(report_id, TransitionFailure::VdafPrepError)]
+                );
+            } else {
+                let (_, agg_req) = t.produce_agg_init_req(vec![report]).await.unwrap_continue();
+                let (_, agg_resp) = t.handle_agg_init_req(agg_req).await.unwrap_continue();
+                assert_eq!(agg_resp.transitions.len(), 1);
+                assert_matches!(
+                    agg_resp.transitions[0].var,
+                    TransitionVar::Failed(TransitionFailure::VdafPrepError)
+                );
+            }
+        }
+    }
+}
+
+async_test_versions! { agg_init_req_fail_input_share_len }
+
 async fn agg_resp_abort_transition_out_of_order(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
@@ -313,6 +575,48 @@ async fn agg_resp_abort_report_id_repeated(version: DapVersion) {
 
 async_test_versions! { agg_resp_abort_report_id_repeated }
 
+async fn agg_resp_abort_report_id_repeated_in_place(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (_, mut agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+
+    // Helper sends a transition twice in place of another.
+    agg_resp.transitions[2] = agg_resp.transitions[0].clone();
+
+    assert_matches!(
+        t.handle_agg_resp_expect_err(leader_state, agg_resp),
+        DapAbort::UnrecognizedMessage
+    );
+}
+
+async_test_versions! { agg_resp_abort_report_id_repeated_in_place }
+
+async fn agg_resp_abort_transition_missing(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (_, mut agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+
+    // Helper omits a transition.
+    agg_resp.transitions.remove(1);
+
+    assert_matches!(
+        t.handle_agg_resp_expect_err(leader_state, agg_resp),
+        DapAbort::UnrecognizedMessage
+    );
+}
+
+async_test_versions! { agg_resp_abort_transition_missing }
+
 async fn agg_resp_abort_unrecognized_report_id(version: DapVersion) {
     let mut rng = thread_rng();
     let mut t = Test::new(TEST_VDAF, version);
@@ -351,6 +655,56 @@ async fn agg_resp_abort_invalid_transition(version: DapVersion) {
 
 async_test_versions! { agg_resp_abort_invalid_transition }
 
+// Test that removing a report from an aggregate share of two reports yields the aggregate share of
+// the other report.
+#[cfg(feature = "audit")]
+async fn agg_share_remove(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
+    let report_ids: Vec<ReportId> = reports
+        .iter()
+        .map(|report| report.metadata.id.clone())
+        .collect();
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (helper_state, agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+    let (leader_uncommitted, agg_cont_req) = t
+        .handle_agg_resp(leader_state, agg_resp)
+        .unwrap_uncommitted();
+    let (_helper_out_shares, agg_resp) = t
+        .handle_agg_cont_req(helper_state, &agg_cont_req)
+        .unwrap_finish();
+    let out_shares = t.handle_final_agg_resp(leader_uncommitted, agg_resp);
+    assert_eq!(out_shares.len(), 2);
+
+    let single = |out_share: &DapOutputShare| {
+        DapAggregateShare::try_from_out_shares([DapOutputShare {
+            time: out_share.time,
+            checksum: out_share.checksum,
+            data: out_share.data.clone(),
+        }])
+        .unwrap()
+    };
+    let expected = single(&out_shares[0]);
+    let contribution = single(&out_shares[1]);
+
+    let mut agg_share = DapAggregateShare::try_from_out_shares(out_shares).unwrap();
+    assert_eq!(agg_share.report_count, 2);
+
+    // Expect the contribution to be rejected if it doesn't match the report ID.
+    assert_matches!(
+        agg_share.remove(&report_ids[0], &contribution, t.checksum_algorithm),
+        Err(DapError::Fatal(..))
+    );
+
+    agg_share
+        .remove(&report_ids[1], &contribution, t.checksum_algorithm)
+        .unwrap();
+    assert_eq!(agg_share.to_audit_bytes(), expected.to_audit_bytes());
+}
+
+#[cfg(feature = "audit")]
+async_test_versions! { agg_share_remove }
+
 async fn agg_cont_req(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![
@@ -525,11 +879,15 @@ async fn encrypted_agg_share(version: DapVersion) {
         report_count: 50,
         checksum: [0; 32],
         data: Some(VdafAggregateShare::Field64(vec![23.into()].into())),
+        min_time: 1637359200,
+        max_time: 1637359200,
     };
     let helper_agg_share = DapAggregateShare {
         report_count: 50,
         checksum: [0; 32],
         data: Some(VdafAggregateShare::Field64(vec![9.into()].into())),
+        min_time: 1637359200,
+        max_time: 1637359200,
     };
 
     let batch_selector = BatchSelector::TimeInterval {
@@ -555,6 +913,333 @@ async fn encrypted_agg_share(version: DapVersion) {
 
 async_test_versions! { encrypted_agg_share }
 
+async fn encrypted_agg_share_raw(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (helper_state, agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+    let (uncommitted, agg_cont_req) = t
+        .handle_agg_resp(leader_state, agg_resp)
+        .unwrap_uncommitted();
+    let (helper_out_shares, agg_resp) = t
+        .handle_agg_cont_req(helper_state, &agg_cont_req)
+        .unwrap_finish();
+    let leader_out_shares = t.handle_final_agg_resp(uncommitted, agg_resp);
+    let leader_agg_share = DapAggregateShare::try_from_out_shares(leader_out_shares).unwrap();
+    let helper_agg_share = DapAggregateShare::try_from_out_shares(helper_out_shares).unwrap();
+
+    let batch_selector = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: t.now,
+            duration: 3600,
+        },
+    };
+    let encrypted_agg_shares = vec![
+        t.produce_leader_encrypted_agg_share(&batch_selector, &leader_agg_share),
+        t.produce_helper_encrypted_agg_share(&batch_selector, &helper_agg_share),
+    ];
+
+    // Collector: Get the combined result.
+    let agg_res = t
+        .consume_encrypted_agg_shares(&batch_selector, 3, encrypted_agg_shares.clone())
+        .await;
+    assert_eq!(agg_res, DapAggregateResult::U64(2));
+
+    // Collector: Get the raw shares, which match what each Aggregator produced.
+    let agg_shares = t
+        .vdaf
+        .decrypt_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
+            &t.task_id,
+            &batch_selector,
+            encrypted_agg_shares,
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        agg_shares,
+        vec![
+            leader_agg_share.data.as_ref().unwrap().get_encoded(),
+            helper_agg_share.data.as_ref().unwrap().get_encoded(),
+        ]
+    );
+
+    // Collector: Unshard the raw shares on demand.
+    assert_eq!(
+        t.vdaf.unshard_agg_shares(3, agg_shares).unwrap(),
+        DapAggregateResult::U64(2)
+    );
+}
+
+async_test_versions! { encrypted_agg_share_raw }
+
+#[test]
+fn sample_centered_binomial_trials_too_large() {
+    assert_matches!(
+        sample_centered_binomial(u64::MAX, &mut thread_rng()),
+        Err(DapError::Fatal(..))
+    );
+    assert_eq!(sample_centered_binomial(0, &mut thread_rng()).unwrap(), 0);
+}
+
+#[test]
+fn agg_share_audit_bytes_roundtrip() {
+    let agg_share = DapAggregateShare {
+        report_count: 50,
+        checksum: [1; 32],
+        data: Some(VdafAggregateShare::Field64(vec![23.into()].into())),
+        min_time: 1637359200,
+        max_time: 1637362800,
+    };
+
+    for agg_share in [agg_share, DapAggregateShare::default()] {
+        let bytes = agg_share.to_audit_bytes();
+        let got = DapAggregateShare::from_audit_bytes(TEST_VDAF, &bytes).unwrap();
+        assert_eq!(got.report_count, agg_share.report_count);
+        assert_eq!(got.checksum, agg_share.checksum);
+        assert_eq!(got.min_time, agg_share.min_time);
+        assert_eq!(got.max_time, agg_share.max_time);
+        assert_eq!(
+            got.data.map(|data| data.get_encoded()),
+            agg_share.data.map(|data| data.get_encoded())
+        );
+    }
+
+    let mut bytes = DapAggregateShare::default().to_audit_bytes();
+    bytes[0] += 1;
+    assert!(DapAggregateShare::from_audit_bytes(TEST_VDAF, &bytes).is_err());
+
+    let mut bytes = DapAggregateShare::default().to_audit_bytes();
+    bytes.push(0);
+    assert!(DapAggregateShare::from_audit_bytes(TEST_VDAF, &bytes).is_err());
+}
+
+// Test that the Leader's and Helper's aggregate shares for the same batch can be reconciled.
+async fn agg_share_audit_bytes_reconcile(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (helper_state, agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+    let (uncommitted, agg_cont_req) = t
+        .handle_agg_resp(leader_state, agg_resp)
+        .unwrap_uncommitted();
+    let (helper_out_shares, agg_resp) = t
+        .handle_agg_cont_req(helper_state, &agg_cont_req)
+        .unwrap_finish();
+    let leader_out_shares = t.handle_final_agg_resp(uncommitted, agg_resp);
+
+    // Each Aggregator exports its aggregate share.
+    let leader_audit_bytes = DapAggregateShare::try_from_out_shares(leader_out_shares)
+        .unwrap()
+        .to_audit_bytes();
+    let helper_audit_bytes = DapAggregateShare::try_from_out_shares(helper_out_shares)
+        .unwrap()
+        .to_audit_bytes();
+
+    // The auditor imports the aggregate shares and compares them.
+    let leader_agg_share =
+        DapAggregateShare::from_audit_bytes(TEST_VDAF, &leader_audit_bytes).unwrap();
+    let helper_agg_share =
+        DapAggregateShare::from_audit_bytes(TEST_VDAF, &helper_audit_bytes).unwrap();
+    assert_eq!(leader_agg_share.report_count, 3);
+    assert_eq!(leader_agg_share.report_count, helper_agg_share.report_count);
+    assert_eq!(leader_agg_share.checksum, helper_agg_share.checksum);
+}
+
+async_test_versions! { agg_share_audit_bytes_reconcile }
+
+async fn encrypted_agg_share_bound_to_task_and_batch(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let agg_share = DapAggregateShare {
+        report_count: 50,
+        checksum: [0; 32],
+        data: Some(VdafAggregateShare::Field64(vec![23.into()].into())),
+        min_time: 1637359200,
+        max_time: 1637359200,
+    };
+
+    let batch_selector = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637359200,
+            duration: 7200,
+        },
+    };
+    let other_batch_selector = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637366400,
+            duration: 7200,
+        },
+    };
+    let leader_encrypted_agg_share =
+        t.produce_leader_encrypted_agg_share(&batch_selector, &agg_share);
+    let helper_encrypted_agg_share =
+        t.produce_helper_encrypted_agg_share(&batch_selector, &agg_share);
+
+    // Decryption fails if the Collector claims the shares are for a different batch.
+    assert!(t
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
+            &t.task_id,
+            &other_batch_selector,
+            50,
+            vec![
+                leader_encrypted_agg_share.clone(),
+                helper_encrypted_agg_share.clone(),
+            ],
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .is_err());
+
+    // Decryption fails if the Collector claims the shares are for a different task.
+    assert!(t
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver_config,
+            &Id([1; 32]),
+            &batch_selector,
+            50,
+            vec![leader_encrypted_agg_share, helper_encrypted_agg_share],
+            AggShareCompression::None,
+            version,
+        )
+        .await
+        .is_err());
+}
+
+async_test_versions! { encrypted_agg_share_bound_to_task_and_batch }
+
+async fn encrypted_agg_share_compression(version: DapVersion) {
+    let vdaf = VdafConfig::Prio3(Prio3Config::Histogram {
+        buckets: (0..1000).map(|i| 10 * i).collect(),
+    });
+    let mut expected = vec![0; 1001];
+    expected[0] = 1;
+    expected[1] = 2;
+
+    for compression in [AggShareCompression::None, AggShareCompression::Gzip] {
+        let mut t = Test::new(&vdaf, version);
+        t.agg_share_compression = compression;
+        let agg_res = t
+            .roundtrip(vec![
+                DapMeasurement::U64(0),
+                DapMeasurement::U64(5),
+                DapMeasurement::U64(5),
+            ])
+            .await;
+        assert_eq!(agg_res, DapAggregateResult::U128Vec(expected.clone()));
+    }
+}
+
+async_test_versions! { encrypted_agg_share_compression }
+
+// Test that the Collector rejects compressed aggregate shares unless it is configured for the
+// compression and that decompression is bounded by the length of the aggregate share.
+async fn encrypted_agg_share_compression_mismatch(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    t.agg_share_compression = AggShareCompression::Gzip;
+    let agg_share = DapAggregateShare {
+        report_count: 50,
+        checksum: [0; 32],
+        data: Some(VdafAggregateShare::Field64(vec![23.into()].into())),
+        min_time: 1637359200,
+        max_time: 1637359200,
+    };
+    let batch_selector = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637359200,
+            duration: 7200,
+        },
+    };
+    let encrypted_agg_share = t.produce_leader_encrypted_agg_share(&batch_selector, &agg_share);
+
+    assert_matches!(
+        t.vdaf
+            .decrypt_encrypted_agg_shares(
+                &t.collector_hpke_receiver_config,
+                &t.task_id,
+                &batch_selector,
+                vec![encrypted_agg_share],
+                AggShareCompression::None,
+                version,
+            )
+            .await,
+        Ok(agg_shares) if agg_shares[0] != agg_share.data.as_ref().unwrap().get_encoded()
+    );
+
+    // A compressed aggregate share that is longer than expected is rejected.
+    let long_agg_share_data = vec![0; 100];
+    let compressed =
+        compress_agg_share(AggShareCompression::Gzip, long_agg_share_data.clone()).unwrap();
+    assert_matches!(
+        decompress_agg_share(AggShareCompression::Gzip, compressed.clone(), 99),
+        Err(DapError::Fatal(..))
+    );
+    assert_eq!(
+        decompress_agg_share(AggShareCompression::Gzip, compressed, 100).unwrap(),
+        long_agg_share_data
+    );
+}
+
+async_test_versions! { encrypted_agg_share_compression_mismatch }
+
+async fn encrypted_agg_share_noise(version: DapVersion) {
+    let vdaf = VdafConfig::Prio3(Prio3Config::Histogram {
+        buckets: (0..1000).map(|i| 10 * i).collect(),
+    });
+    let variance = 1000;
+    let mut t = Test::new(&vdaf, version);
+    t.noise_split = NoiseSplit::Even { variance };
+    let agg_res = t
+        .roundtrip(vec![DapMeasurement::U64(0), DapMeasurement::U64(5)])
+        .await;
+
+    // Each bucket of the result is the sum of the Leader's and Helper's noise. Recover the noise
+    // by removing the true counts and interpreting elements close to the modulus as negative.
+    let modulus = Field128::modulus();
+    let noise: Vec<f64> = assert_matches!(agg_res, DapAggregateResult::U128Vec(counts) => counts)
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let want = u128::from(i < 2);
+            let count = if count >= want {
+                count - want
+            } else {
+                modulus - (want - count)
+            };
+            if count > modulus / 2 {
+                -((modulus - count) as f64)
+            } else {
+                count as f64
+            }
+        })
+        .collect();
+
+    let n = noise.len() as f64;
+    let mean = noise.iter().sum::<f64>() / n;
+    let sample_variance = noise.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    assert!(
+        (sample_variance - variance as f64).abs() < 0.2 * variance as f64,
+        "sample variance {} is too far from {}",
+        sample_variance,
+        variance
+    );
+}
+
+async_test_versions! { encrypted_agg_share_noise }
+
 async fn helper_state_serialization(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![
@@ -576,6 +1261,64 @@ async fn helper_state_serialization(version: DapVersion) {
 
 async_test_versions! { helper_state_serialization }
 
+// Test that the Leader can complete an aggregation job from its serialized state, as it would
+// after a restart.
+async fn leader_state_serialization(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(1),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(0),
+        DapMeasurement::U64(1),
+    ]);
+    let (leader_state, agg_init_req) = t.produce_agg_init_req(reports).await.unwrap_continue();
+    let (helper_state, agg_resp) = t.handle_agg_init_req(agg_init_req).await.unwrap_continue();
+
+    let leader_state =
+        DapLeaderState::get_decoded(TEST_VDAF, &leader_state.get_encoded(TEST_VDAF).unwrap())
+            .unwrap();
+    let (leader_uncommitted, agg_cont_req) = t
+        .handle_agg_resp(leader_state, agg_resp)
+        .unwrap_uncommitted();
+    let (helper_out_shares, agg_resp) = t
+        .handle_agg_cont_req(helper_state, &agg_cont_req)
+        .unwrap_finish();
+
+    let leader_uncommitted =
+        DapLeaderUncommitted::get_decoded(TEST_VDAF, &leader_uncommitted.get_encoded()).unwrap();
+    let leader_out_shares = t.handle_final_agg_resp(leader_uncommitted, agg_resp);
+    assert_eq!(leader_out_shares.len(), 5);
+    let num_measurements = leader_out_shares.len();
+
+    let [leader_agg_share, helper_agg_share] =
+        [leader_out_shares, helper_out_shares].map(|out_shares| {
+            out_shares
+                .into_iter()
+                .map(|out_share| match out_share.data {
+                    VdafAggregateShare::Field64(data) => data,
+                    _ => panic!("unexpected aggregate share varaint"),
+                })
+                .reduce(|mut left, right| {
+                    left.merge(&right).unwrap();
+                    left
+                })
+                .unwrap()
+        });
+
+    let vdaf = Prio3::new_aes128_count(2).unwrap();
+    assert_eq!(
+        vdaf.unshard(&(), [leader_agg_share, helper_agg_share], num_measurements)
+            .unwrap(),
+        3,
+    );
+
+    assert!(DapLeaderState::get_decoded(TEST_VDAF, b"invalid leader state").is_err());
+    assert!(DapLeaderUncommitted::get_decoded(TEST_VDAF, b"invalid leader state").is_err());
+}
+
+async_test_versions! { leader_state_serialization }
+
 pub(crate) struct Test<'a> {
     now: u64,
     vdaf: &'a VdafConfig,
@@ -585,6 +1328,9 @@ pub(crate) struct Test<'a> {
     leader_hpke_receiver_config: HpkeReceiverConfig,
     helper_hpke_receiver_config: HpkeReceiverConfig,
     early_rejects: HashMap<ReportId, TransitionFailure>,
+    agg_share_compression: AggShareCompression,
+    noise_split: NoiseSplit,
+    checksum_algorithm: ChecksumAlgorithm,
     client_hpke_config_list: Vec<HpkeConfig>,
     collector_hpke_config: HpkeConfig,
     collector_hpke_receiver_config: HpkeReceiverConfig,
@@ -620,6 +1366,9 @@ impl<'a> Test<'a> {
             leader_hpke_receiver_config,
             helper_hpke_receiver_config,
             early_rejects: HashMap::default(),
+            agg_share_compression: AggShareCompression::None,
+            noise_split: NoiseSplit::None,
+            checksum_algorithm: ChecksumAlgorithm::Sha256Xor,
             client_hpke_config_list: vec![leader_hpke_config, helper_hpke_config],
             collector_hpke_config,
             collector_hpke_receiver_config,
@@ -696,7 +1445,13 @@ impl<'a> Test<'a> {
         agg_resp: AggregateResp,
     ) -> DapLeaderTransition<AggregateContinueReq> {
         self.vdaf
-            .handle_agg_resp(&self.task_id, &self.agg_job_id, leader_state, agg_resp)
+            .handle_agg_resp(
+                &self.task_id,
+                &self.agg_job_id,
+                leader_state,
+                agg_resp,
+                self.checksum_algorithm,
+            )
             .unwrap()
     }
 
@@ -706,7 +1461,13 @@ impl<'a> Test<'a> {
         agg_resp: AggregateResp,
     ) -> DapAbort {
         self.vdaf
-            .handle_agg_resp(&self.task_id, &self.agg_job_id, leader_state, agg_resp)
+            .handle_agg_resp(
+                &self.task_id,
+                &self.agg_job_id,
+                leader_state,
+                agg_resp,
+                self.checksum_algorithm,
+            )
             .err()
             .expect("handle_agg_resp() succeeded; expected failure")
     }
@@ -717,7 +1478,7 @@ impl<'a> Test<'a> {
         agg_cont_req: &AggregateContinueReq,
     ) -> DapHelperTransition<AggregateResp> {
         self.vdaf
-            .handle_agg_cont_req(helper_state, agg_cont_req)
+            .handle_agg_cont_req(helper_state, agg_cont_req, self.checksum_algorithm)
             .unwrap()
     }
 
@@ -727,7 +1488,7 @@ impl<'a> Test<'a> {
         agg_cont_req: &AggregateContinueReq,
     ) -> DapAbort {
         self.vdaf
-            .handle_agg_cont_req(helper_state, agg_cont_req)
+            .handle_agg_cont_req(helper_state, agg_cont_req, self.checksum_algorithm)
             .err()
             .expect("handle_agg_cont_req() succeeded; expected failure")
     }
@@ -737,9 +1498,11 @@ impl<'a> Test<'a> {
         leader_uncommitted: DapLeaderUncommitted,
         agg_resp: AggregateResp,
     ) -> Vec<DapOutputShare> {
-        self.vdaf
+        let (out_shares, _rejected) = self
+            .vdaf
             .handle_final_agg_resp(leader_uncommitted, agg_resp)
-            .unwrap()
+            .unwrap();
+        out_shares
     }
 
     fn produce_leader_encrypted_agg_share(
@@ -753,6 +1516,8 @@ impl<'a> Test<'a> {
                 &self.task_id,
                 batch_selector,
                 agg_share,
+                self.agg_share_compression,
+                self.noise_split,
                 self.version,
             )
             .unwrap()
@@ -769,6 +1534,8 @@ impl<'a> Test<'a> {
                 &self.task_id,
                 batch_selector,
                 agg_share,
+                self.agg_share_compression,
+                self.noise_split,
                 self.version,
             )
             .unwrap()
@@ -787,6 +1554,7 @@ impl<'a> Test<'a> {
                 batch_selector,
                 report_count,
                 enc_agg_shares,
+                self.agg_share_compression,
                 self.version,
             )
             .await
@@ -797,6 +1565,13 @@ impl<'a> Test<'a> {
         &mut self,
         measurements: Vec<DapMeasurement>,
     ) -> DapAggregateResult {
+        // Clients: Shard
+        let reports = self.produce_reports(measurements);
+
+        self.roundtrip_reports(reports).await
+    }
+
+    pub(crate) async fn roundtrip_reports(&mut self, reports: Vec<Report>) -> DapAggregateResult {
         let batch_selector = BatchSelector::TimeInterval {
             batch_interval: Interval {
                 start: self.now,
@@ -804,9 +1579,6 @@ impl<'a> Test<'a> {
             },
         };
 
-        // Clients: Shard
-        let reports = self.produce_reports(measurements);
-
         // Aggregators: Preparation
         let (leader_state, agg_init) = self.produce_agg_init_req(reports).await.unwrap_continue();
         let (helper_state, agg_resp) = self.handle_agg_init_req(agg_init).await.unwrap_continue();
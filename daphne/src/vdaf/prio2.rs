@@ -4,15 +4,20 @@
 //! Prio2, the Prio-based construction used in ENPA. This is not a standard
 //! [VDAF](https://datatracker.ietf.org/doc/draft-patton-cfrg-vdaf/).
 
-use crate::{
-    vdaf::VdafError, DapAggregateResult, DapMeasurement, VdafAggregateShare, VdafMessage, VdafState,
-};
+#[cfg(any(feature = "roles", test))]
+use crate::VdafAggregateShare;
+use crate::{vdaf::VdafError, DapAggregateResult, DapMeasurement, VdafMessage, VdafState};
+#[cfg(any(feature = "roles", test))]
 use prio::{
-    codec::{CodecError, Decode, Encode, ParameterizedDecode},
+    codec::Decode,
     field::FieldPrio2,
+    vdaf::{Aggregator, PrepareTransition, Share},
+};
+use prio::{
+    codec::{CodecError, Encode, ParameterizedDecode},
     vdaf::{
         prio2::{Prio2, Prio2PrepareShare, Prio2PrepareState},
-        AggregateShare, Aggregator, Client, Collector, PrepareTransition, Share, Vdaf,
+        AggregateShare, Client, Collector, Vdaf,
     },
 };
 use std::{convert::TryFrom, io::Cursor};
@@ -35,6 +40,7 @@ pub(crate) fn prio2_shard(
 }
 
 /// Consume an input share and return the corresponding VDAF step and message.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio2_prepare_init(
     dimension: u32,
     verify_key: &[u8; 32],
@@ -51,6 +57,7 @@ pub(crate) fn prio2_prepare_init(
 }
 
 /// Consume the verifier shares and return the output share and serialized outbound message.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio2_leader_prepare_finish(
     dimension: u32,
     leader_state: VdafState,
@@ -77,6 +84,7 @@ pub(crate) fn prio2_leader_prepare_finish(
 }
 
 /// Consume the peer's prepare message and return an output share.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio2_helper_prepare_finish(
     dimension: u32,
     helper_state: VdafState,
@@ -118,6 +126,19 @@ pub(crate) fn prio2_encode_prepare_message(message: &VdafMessage) -> Vec<u8> {
     }
 }
 
+/// Decode a prio2 prepare message from `data` whose type is compatible with `state`.
+pub(crate) fn prio2_decode_prepare_message(
+    state: &VdafState,
+    data: &[u8],
+) -> Result<VdafMessage, VdafError> {
+    match state {
+        VdafState::Prio2(state) => Ok(VdafMessage::Prio2Share(
+            Prio2PrepareShare::get_decoded_with_param(state, data)?,
+        )),
+        _ => panic!("prio2_decode_prepare_message: unexpected state type"),
+    }
+}
+
 /// Interpret `encoded_agg_shares` as a sequence of encoded aggregate shares and unshard them.
 pub(crate) fn prio2_unshard<M: IntoIterator<Item = Vec<u8>>>(
     dimension: u32,
@@ -3,21 +3,26 @@
 
 //! Parameters for the [Prio3 VDAF](https://datatracker.ietf.org/doc/draft-patton-cfrg-vdaf/).
 
+#[cfg(any(feature = "roles", test))]
+use crate::VdafAggregateShare;
 use crate::{
-    vdaf::VdafError, DapAggregateResult, DapMeasurement, Prio3Config, VdafAggregateShare,
-    VdafMessage, VdafState,
+    vdaf::VdafError, DapAggregateResult, DapMeasurement, Prio3Config, VdafMessage, VdafState,
+};
+#[cfg(any(feature = "roles", test))]
+use prio::vdaf::{
+    prio3::{Prio3InputShare, Prio3PrepareMessage},
+    Aggregator, PrepareTransition,
 };
 use prio::{
     codec::{CodecError, Encode, ParameterizedDecode},
     vdaf::{
-        prio3::{
-            Prio3, Prio3InputShare, Prio3PrepareMessage, Prio3PrepareShare, Prio3PrepareState,
-        },
-        AggregateShare, Aggregator, Client, Collector, PrepareTransition, Vdaf,
+        prio3::{Prio3, Prio3PrepareShare, Prio3PrepareState},
+        AggregateShare, Client, Collector, Vdaf,
     },
 };
 use std::{convert::TryFrom, io::Cursor};
 
+#[cfg(any(feature = "roles", test))]
 const ERR_EXPECT_FINISH: &str = "unexpected transition (continued)";
 const ERR_FIELD_TYPE: &str = "unexpected field type for step or message";
 
@@ -47,6 +52,11 @@ pub(crate) fn prio3_shard(
             let vdaf = Prio3::new_aes128_count(2)?;
             Ok(shard!(vdaf, &measurement))
         }
+        (Prio3Config::CountVec { length }, DapMeasurement::U32Vec(measurement)) => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            let measurement: Vec<u128> = measurement.into_iter().map(u128::from).collect();
+            Ok(shard!(vdaf, &measurement))
+        }
         (Prio3Config::Histogram { buckets }, DapMeasurement::U64(measurement)) => {
             let vdaf = Prio3::new_aes128_histogram(2, buckets)?;
             Ok(shard!(vdaf, &(measurement as u128)))
@@ -59,6 +69,7 @@ pub(crate) fn prio3_shard(
     }
 }
 
+#[cfg(any(feature = "roles", test))]
 macro_rules! prep_init {
     (
         $vdaf:ident,
@@ -77,6 +88,7 @@ macro_rules! prep_init {
 }
 
 /// Consume an input share and return the corresponding VDAF step and message.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio3_prepare_init(
     config: &Prio3Config,
     verify_key: &[u8; 16],
@@ -93,6 +105,14 @@ pub(crate) fn prio3_prepare_init(
                 VdafMessage::Prio3ShareField64(share),
             ))
         }
+        Prio3Config::CountVec { length } => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            let (state, share) = prep_init!(vdaf, verify_key, agg_id, nonce_data, input_share_data);
+            Ok((
+                VdafState::Prio3Field128(state),
+                VdafMessage::Prio3ShareField128(share),
+            ))
+        }
         Prio3Config::Histogram { buckets } => {
             let vdaf = Prio3::new_aes128_histogram(2, buckets)?;
             let (state, share) = prep_init!(vdaf, verify_key, agg_id, nonce_data, input_share_data);
@@ -112,6 +132,7 @@ pub(crate) fn prio3_prepare_init(
     }
 }
 
+#[cfg(any(feature = "roles", test))]
 macro_rules! leader_prep_fin {
     (
         $vdaf:ident,
@@ -138,6 +159,7 @@ macro_rules! leader_prep_fin {
 }
 
 /// Consume the verifier shares and return the output share and serialized outbound message.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio3_leader_prepare_finish(
     config: &Prio3Config,
     leader_state: VdafState,
@@ -155,6 +177,16 @@ pub(crate) fn prio3_leader_prepare_finish(
             let agg_share = VdafAggregateShare::Field64(vdaf.aggregate(&(), [out_share])?);
             (agg_share, outbound)
         }
+        (
+            Prio3Config::CountVec { length },
+            VdafState::Prio3Field128(state),
+            VdafMessage::Prio3ShareField128(share),
+        ) => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            let (out_share, outbound) = leader_prep_fin!(vdaf, state, share, helper_share_data);
+            let agg_share = VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?);
+            (agg_share, outbound)
+        }
         (
             Prio3Config::Histogram { buckets },
             VdafState::Prio3Field128(state),
@@ -181,6 +213,7 @@ pub(crate) fn prio3_leader_prepare_finish(
     Ok((agg_share, outbound))
 }
 
+#[cfg(any(feature = "roles", test))]
 macro_rules! helper_prep_fin {
     (
         $vdaf:ident,
@@ -203,6 +236,7 @@ macro_rules! helper_prep_fin {
 }
 
 /// Consume the peer's prepare message and return an output share.
+#[cfg(any(feature = "roles", test))]
 pub(crate) fn prio3_helper_prepare_finish(
     config: &Prio3Config,
     state: VdafState,
@@ -214,6 +248,11 @@ pub(crate) fn prio3_helper_prepare_finish(
             let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
             VdafAggregateShare::Field64(vdaf.aggregate(&(), [out_share])?)
         }
+        (Prio3Config::CountVec { length }, VdafState::Prio3Field128(state)) => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
+            VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?)
+        }
         (Prio3Config::Histogram { buckets }, VdafState::Prio3Field128(state)) => {
             let vdaf = Prio3::new_aes128_histogram(2, buckets)?;
             let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
@@ -241,7 +280,8 @@ pub(crate) fn prio3_append_prepare_state(
         (Prio3Config::Count, VdafState::Prio3Field64(state)) => {
             state.encode(bytes);
         }
-        (Prio3Config::Histogram { buckets: _ }, VdafState::Prio3Field128(state))
+        (Prio3Config::CountVec { length: _ }, VdafState::Prio3Field128(state))
+        | (Prio3Config::Histogram { buckets: _ }, VdafState::Prio3Field128(state))
         | (Prio3Config::Sum { bits: _ }, VdafState::Prio3Field128(state)) => {
             state.encode(bytes);
         }
@@ -263,6 +303,12 @@ pub(crate) fn prio3_decode_prepare_state(
                 Prio3PrepareState::decode_with_param(&(&vdaf, agg_id), bytes)?,
             ))
         }
+        Prio3Config::CountVec { length } => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            Ok(VdafState::Prio3Field128(
+                Prio3PrepareState::decode_with_param(&(&vdaf, agg_id), bytes)?,
+            ))
+        }
         Prio3Config::Histogram { buckets } => {
             let vdaf = Prio3::new_aes128_histogram(2, buckets)?;
             Ok(VdafState::Prio3Field128(
@@ -287,6 +333,22 @@ pub(crate) fn prio3_encode_prepare_message(message: &VdafMessage) -> Vec<u8> {
     }
 }
 
+/// Decode a prio3 prepare message from `data` whose type is compatible with `state`.
+pub(crate) fn prio3_decode_prepare_message(
+    state: &VdafState,
+    data: &[u8],
+) -> Result<VdafMessage, VdafError> {
+    match state {
+        VdafState::Prio3Field64(state) => Ok(VdafMessage::Prio3ShareField64(
+            Prio3PrepareShare::get_decoded_with_param(state, data)?,
+        )),
+        VdafState::Prio3Field128(state) => Ok(VdafMessage::Prio3ShareField128(
+            Prio3PrepareShare::get_decoded_with_param(state, data)?,
+        )),
+        _ => panic!("prio3_decode_prepare_message: {}", ERR_FIELD_TYPE),
+    }
+}
+
 macro_rules! unshard {
     (
         $vdaf:ident,
@@ -315,6 +377,16 @@ pub(crate) fn prio3_unshard<M: IntoIterator<Item = Vec<u8>>>(
             let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
             Ok(DapAggregateResult::U64(agg_res))
         }
+        Prio3Config::CountVec { length } => {
+            let vdaf = Prio3::new_aes128_count_vec(2, *length)?;
+            let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
+            let agg_res = agg_res
+                .into_iter()
+                .map(u64::try_from)
+                .collect::<Result<Vec<u64>, _>>()
+                .map_err(|e| CodecError::Other(Box::new(e)))?;
+            Ok(DapAggregateResult::U64Vec(agg_res))
+        }
         Prio3Config::Histogram { buckets } => {
             let vdaf = Prio3::new_aes128_histogram(2, buckets)?;
             let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
@@ -24,6 +24,16 @@ fn prepare_count() {
     .unwrap();
 }
 
+#[test]
+fn prepare_count_vec() {
+    test_prepare(
+        &Prio3Config::CountVec { length: 3 },
+        DapMeasurement::U32Vec(vec![1, 0, 1]),
+        DapAggregateResult::U64Vec(vec![1, 0, 1]),
+    )
+    .unwrap();
+}
+
 #[test]
 fn prepare_sum() {
     test_prepare(
@@ -10,19 +10,21 @@ use crate::{
     durable::{
         durable_name_report_store, durable_name_task,
         leader_batch_queue::{LeaderBatchQueueResult, DURABLE_LEADER_BATCH_QUEUE_CURRENT},
+        reports_pending::DURABLE_REPORTS_PENDING_COUNT,
         DurableConnector, BINDING_DAP_GARBAGE_COLLECTOR, BINDING_DAP_LEADER_BATCH_QUEUE,
-        DURABLE_DELETE_ALL,
+        BINDING_DAP_REPORTS_PENDING, DURABLE_DELETE_ALL,
     },
-    int_err, InternalTestAddTask, InternalTestEndpointForTask, InternalTestRole,
+    int_err, now, InternalTestAddTask, InternalTestEndpointForTask, InternalTestRole,
 };
 use daphne::{
     auth::BearerToken,
     constants,
     hpke::HpkeReceiverConfig,
-    messages::{HpkeConfig, Id, ReportMetadata},
+    messages::{HpkeConfig, Id, Interval, ReportId, Time},
     DapAbort, DapError, DapGlobalConfig, DapQueryConfig, DapRequest, DapTaskConfig, DapVersion,
     Prio3Config, VdafConfig,
 };
+use futures::future::try_join_all;
 use matchit::Router;
 use prio::{
     codec::Decode,
@@ -232,21 +234,64 @@ impl<D> DaphneWorkerConfig<D> {
         })
     }
 
-    /// Derive the batch name for a report for the given task and with the given report ID.
+    /// Derive the batch name for a report for the given task and with the given report ID and
+    /// timestamp.
     pub(crate) fn durable_name_report_store(
         &self,
         task_config: &DapTaskConfig,
         task_id_hex: &str,
-        metadata: &ReportMetadata,
+        report_id: &ReportId,
+        time: Time,
     ) -> String {
         let mut shard_seed = [0; 8];
-        PrgAes128::seed_stream(&self.report_shard_key, metadata.id.as_ref()).fill(&mut shard_seed);
+        PrgAes128::seed_stream(&self.report_shard_key, report_id.as_ref()).fill(&mut shard_seed);
         let shard = u64::from_be_bytes(shard_seed) % self.report_shard_count;
-        let epoch =
-            metadata.time - (metadata.time % self.global_config.report_storage_epoch_duration);
+        let epoch = self.global_config.report_storage_epoch(time);
         durable_name_report_store(&task_config.version, task_id_hex, epoch, shard)
     }
 
+    /// Return the epochs of the report storage window, i.e., the previous, current, and next
+    /// epoch. Reports outside of this window are not stored.
+    pub(crate) fn report_storage_window_epochs(&self) -> Vec<Time> {
+        let epoch_duration = self.global_config.report_storage_epoch_duration;
+        let current_epoch = self.global_config.report_storage_epoch(now());
+        let mut epochs = vec![
+            current_epoch.saturating_sub(epoch_duration),
+            current_epoch,
+            current_epoch.saturating_add(epoch_duration),
+        ];
+        epochs.dedup();
+        epochs
+    }
+
+    /// Count the reports for the given task that are stored in the given epochs and are waiting
+    /// to be processed. If `interval` is set, then only reports whose timestamp falls into it are
+    /// counted.
+    pub(crate) async fn count_pending_reports(
+        &self,
+        task_config: &DapTaskConfig,
+        task_id: &Id,
+        epochs: &[Time],
+        interval: Option<&Interval>,
+    ) -> std::result::Result<u64, DapError> {
+        let task_id_hex = task_id.to_hex();
+        let durable = self.durable();
+        let mut requests = Vec::new();
+        for epoch in epochs {
+            for shard in 0..self.report_shard_count {
+                requests.push(durable.post(
+                    BINDING_DAP_REPORTS_PENDING,
+                    DURABLE_REPORTS_PENDING_COUNT,
+                    durable_name_report_store(&task_config.version, &task_id_hex, *epoch, shard),
+                    interval,
+                ));
+            }
+        }
+
+        let counts: Vec<u64> = try_join_all(requests).await.map_err(dap_err)?;
+        Ok(counts.into_iter().sum())
+    }
+
     pub(crate) fn durable(&self) -> DurableConnector<'_> {
         DurableConnector::new(&self.ctx.as_ref().expect("no route context configured").env)
     }
@@ -288,6 +333,24 @@ impl<D> DaphneWorkerConfig<D> {
         kv_key_prefix: &str,
         kv_key_suffix: Cow<'req, K>,
     ) -> Result<Option<Guarded<'req, K, V>>>
+    where
+        K: Clone + Eq + std::hash::Hash + ToString,
+        V: for<'de> Deserialize<'de>,
+        'srv: 'req,
+    {
+        self.kv_get_cached_checked(map, kv_key_prefix, kv_key_suffix, |_| Ok(()))
+            .await
+    }
+
+    /// Like `kv_get_cached()`, except that `check` is run on a value when it is loaded from KV. If
+    /// the check fails, then the value is not cached and the error is returned.
+    async fn kv_get_cached_checked<'srv, 'req, K, V>(
+        &self,
+        map: &'srv Arc<RwLock<HashMap<K, V>>>,
+        kv_key_prefix: &str,
+        kv_key_suffix: Cow<'req, K>,
+        check: impl Fn(&V) -> Result<()>,
+    ) -> Result<Option<Guarded<'req, K, V>>>
     where
         K: Clone + Eq + std::hash::Hash + ToString,
         V: for<'de> Deserialize<'de>,
@@ -312,6 +375,8 @@ impl<D> DaphneWorkerConfig<D> {
         let kv_store = self.kv()?;
         let builder = kv_store.get(&kv_key);
         if let Some(kv_value) = builder.json::<V>().await? {
+            check(&kv_value)?;
+
             // TODO(cjpatton) Consider indicating whether the value is known to not exist. For HPKE
             // configs, this would avoid hitting KV multiple times when the same expired config is
             // used for multiple reports.
@@ -336,15 +401,20 @@ impl<D> DaphneWorkerConfig<D> {
     }
 
     /// Get a reference to the HPKE receiver configs, ensuring that the config indicated by
-    /// `hpke_config_id` is cached (if it exists).
+    /// `hpke_config_id` is cached (if it exists). A config is checked with
+    /// [`HpkeReceiverConfig::self_check`] when it is loaded from KV, so that a secret key that
+    /// doesn't match the advertised config is detected before it is used.
     pub(crate) async fn get_hpke_receiver_config(
         &self,
         hpke_config_id: u8,
     ) -> Result<Option<GuardedHpkeReceiverConfig>> {
-        self.kv_get_cached(
+        self.kv_get_cached_checked(
             &self.hpke_receiver_configs,
             KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
             Cow::Owned(hpke_config_id),
+            |hpke_receiver_config: &HpkeReceiverConfig| {
+                hpke_receiver_config.self_check().map_err(int_err)
+            },
         )
         .await
     }
@@ -393,8 +463,13 @@ impl<D> DaphneWorkerConfig<D> {
     where
         'srv: 'req,
     {
-        self.kv_get_cached(&self.tasks, KV_KEY_PREFIX_TASK_CONFIG, task_id)
-            .await
+        let task_config = self
+            .kv_get_cached(&self.tasks, KV_KEY_PREFIX_TASK_CONFIG, task_id)
+            .await?;
+        if let Some(ref task_config) = task_config {
+            task_config.as_ref().validate().map_err(int_err)?;
+        }
+        Ok(task_config)
     }
 
     /// Define a task in KV
@@ -534,12 +609,18 @@ impl<D> DaphneWorkerConfig<D> {
             .get_decoded_verify_key(&vdaf_verify_key_data)
             .map_err(int_err)?;
 
-        // Collector HPKE config.
-        let collector_hpke_config_data =
-            base64::decode_config(&cmd.collector_hpke_config, base64::URL_SAFE_NO_PAD)
-                .map_err(int_err)?;
-        let collector_hpke_config =
-            HpkeConfig::get_decoded(&collector_hpke_config_data).map_err(int_err)?;
+        // Collector HPKE configs.
+        let mut collector_hpke_configs = Vec::with_capacity(cmd.collector_hpke_configs.len());
+        for collector_hpke_config in cmd.collector_hpke_configs.iter() {
+            let collector_hpke_config_data =
+                base64::decode_config(collector_hpke_config, base64::URL_SAFE_NO_PAD)
+                    .map_err(int_err)?;
+            collector_hpke_configs
+                .push(HpkeConfig::get_decoded(&collector_hpke_config_data).map_err(int_err)?);
+        }
+        if collector_hpke_configs.is_empty() {
+            return Err(int_err("command failed: no collector HPKE config"));
+        }
 
         // Leader authentication token.
         let token = BearerToken::from(cmd.leader_authentication_token);
@@ -600,12 +681,19 @@ impl<D> DaphneWorkerConfig<D> {
                     leader_url: cmd.leader,
                     helper_url: cmd.helper,
                     time_precision: cmd.time_precision,
+                    storage_precision: None,
+                    start: 0,
                     expiration: cmd.task_expiration,
                     min_batch_size: cmd.min_batch_size,
                     query,
                     vdaf,
                     vdaf_verify_key,
-                    collector_hpke_config,
+                    collector_hpke_configs,
+                    max_pending_reports: None,
+                    allowed_extension_types: None,
+                    require_latest_hpke_config: false,
+                    require_aligned_report_time: false,
+                    global_config_override: None,
                 },
             )
             .await?
@@ -633,15 +721,45 @@ impl<D> DaphneWorkerConfig<D> {
         Ok(DapVersion::from(version))
     }
 
+    /// Convert a request received by the Worker into a DAP request. If a header defined by Daphne
+    /// is malformed, then the request is rejected with the returned abort.
     pub(crate) async fn worker_request_to_dap(
         &self,
         mut req: Request,
-    ) -> Result<DapRequest<BearerToken>> {
+    ) -> Result<std::result::Result<DapRequest<BearerToken>, DapAbort>> {
         let sender_auth = req.headers().get("DAP-Auth-Token")?.map(BearerToken::from);
         let content_type = req.headers().get("Content-Type")?;
+        let collector_hpke_config_id = match req
+            .headers()
+            .get(constants::HTTP_HEADER_COLLECTOR_HPKE_CONFIG_ID)?
+        {
+            Some(s) => match s.parse() {
+                Ok(collector_hpke_config_id) => Some(collector_hpke_config_id),
+                Err(_) => {
+                    return Ok(Err(DapAbort::BadRequest(
+                        "failed to parse collector HPKE config ID".into(),
+                    )))
+                }
+            },
+            None => None,
+        };
+        let idempotency_key = match req.headers().get(constants::HTTP_HEADER_IDEMPOTENCY_KEY)? {
+            Some(s) => match base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+                .ok()
+                .and_then(|bytes| Id::get_decoded(&bytes).ok())
+            {
+                Some(idempotency_key) => Some(idempotency_key),
+                None => {
+                    return Ok(Err(DapAbort::BadRequest(
+                        "failed to parse idempotency key".into(),
+                    )))
+                }
+            },
+            None => None,
+        };
 
         let media_type = match content_type {
-            Some(s) => constants::media_type_for(&s),
+            Some(s) => constants::parse_media_type(&s),
             None => None,
         };
 
@@ -659,14 +777,16 @@ impl<D> DaphneWorkerConfig<D> {
         let mut r = Cursor::new(payload.as_ref());
         let task_id = Id::decode(&mut r).ok();
 
-        Ok(DapRequest {
+        Ok(Ok(DapRequest {
             version,
             task_id,
             payload,
             url: req.url()?,
             media_type,
             sender_auth,
-        })
+            idempotency_key,
+            collector_hpke_config_id,
+        }))
     }
 }
 
@@ -19,20 +19,28 @@ use crate::{
         },
         durable_name_agg_store, durable_name_queue, durable_name_task,
         helper_state_store::{
-            durable_helper_state_name, DURABLE_HELPER_STATE_GET, DURABLE_HELPER_STATE_PUT,
+            durable_helper_agg_init_resp_name, durable_helper_state_name, DURABLE_HELPER_STATE_GET,
+            DURABLE_HELPER_STATE_GET_AGG_INIT_RESP, DURABLE_HELPER_STATE_PUT,
+            DURABLE_HELPER_STATE_PUT_AGG_INIT_RESP,
         },
         leader_agg_job_queue::DURABLE_LEADER_AGG_JOB_QUEUE_GET,
         leader_batch_queue::{
-            BatchCount, DURABLE_LEADER_BATCH_QUEUE_ASSIGN, DURABLE_LEADER_BATCH_QUEUE_REMOVE,
+            BatchCount, DURABLE_LEADER_BATCH_QUEUE_ASSIGN, DURABLE_LEADER_BATCH_QUEUE_LIST,
+            DURABLE_LEADER_BATCH_QUEUE_REMOVE,
         },
         leader_col_job_queue::{
+            DURABLE_LEADER_COL_JOB_QUEUE_EXPIRE, DURABLE_LEADER_COL_JOB_QUEUE_FAIL,
             DURABLE_LEADER_COL_JOB_QUEUE_FINISH, DURABLE_LEADER_COL_JOB_QUEUE_GET,
             DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT, DURABLE_LEADER_COL_JOB_QUEUE_PUT,
         },
         reports_pending::{
-            ReportsPendingResult, DURABLE_REPORTS_PENDING_GET, DURABLE_REPORTS_PENDING_PUT,
+            ReportsPendingResult, DURABLE_REPORTS_PENDING_CONTAINS, DURABLE_REPORTS_PENDING_GET,
+            DURABLE_REPORTS_PENDING_PUT,
+        },
+        reports_processed::{
+            DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED, DURABLE_REPORTS_PROCESSED_PUT_REJECTED,
+            DURABLE_REPORTS_PROCESSED_STATUS,
         },
-        reports_processed::DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
         BINDING_DAP_AGGREGATE_STORE, BINDING_DAP_HELPER_STATE_STORE,
         BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_LEADER_BATCH_QUEUE,
         BINDING_DAP_LEADER_COL_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING,
@@ -47,12 +55,13 @@ use daphne::{
     hpke::HpkeDecrypter,
     messages::{
         BatchSelector, CollectReq, CollectResp, HpkeCiphertext, Id, PartialBatchSelector, Report,
-        ReportId, ReportMetadata, TransitionFailure,
+        ReportId, ReportMetadata, Time, TransitionFailure,
     },
-    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
+    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapHttpClient, DapLeader},
     taskprov::{bad_request, get_taskprov_task_config},
-    DapAggregateShare, DapBatchBucket, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapOutputShare, DapQueryConfig, DapRequest, DapResponse, DapTaskConfig, DapVersion,
+    DapAbort, DapAggregateShare, DapBatchBucket, DapCollectJob, DapError, DapGlobalConfig,
+    DapHelperState, DapOutputShare, DapQueryConfig, DapRequest, DapResponse, DapStoredAggInitResp,
+    DapTaskConfig, DapVersion, ProblemDetails, ReportStatus,
 };
 use futures::future::try_join_all;
 use prio::codec::{Decode, Encode};
@@ -250,6 +259,100 @@ impl<D> DapAuthorizedSender<BearerToken> for DaphneWorkerConfig<D> {
     }
 }
 
+#[async_trait(?Send)]
+impl<D> DapHttpClient<BearerToken> for DaphneWorkerConfig<D> {
+    async fn post(
+        &self,
+        req: DapRequest<BearerToken>,
+    ) -> std::result::Result<DapResponse, DapError> {
+        let (payload, url) = (req.payload, req.url);
+
+        let mut headers = reqwest_wasm::header::HeaderMap::new();
+        if let Some(content_type) = req.media_type {
+            headers.insert(
+                reqwest_wasm::header::CONTENT_TYPE,
+                reqwest_wasm::header::HeaderValue::from_str(content_type)
+                    .map_err(|e| DapError::Fatal(e.to_string()))?,
+            );
+        }
+
+        if let Some(bearer_token) = req.sender_auth {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static("dap-auth-token"),
+                reqwest_wasm::header::HeaderValue::from_str(bearer_token.as_ref())
+                    .map_err(|e| DapError::Fatal(e.to_string()))?,
+            );
+        }
+
+        if let Some(idempotency_key) = req.idempotency_key {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static(
+                    constants::HTTP_HEADER_IDEMPOTENCY_KEY,
+                ),
+                reqwest_wasm::header::HeaderValue::from_str(&idempotency_key.to_base64url())
+                    .map_err(|e| DapError::Fatal(e.to_string()))?,
+            );
+        }
+
+        if let Some(collector_hpke_config_id) = req.collector_hpke_config_id {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static(
+                    constants::HTTP_HEADER_COLLECTOR_HPKE_CONFIG_ID,
+                ),
+                reqwest_wasm::header::HeaderValue::from(u16::from(collector_hpke_config_id)),
+            );
+        }
+
+        let reqwest_req = self
+            .client
+            .as_ref()
+            .ok_or_else(|| DapError::Fatal("helper cannot send HTTP requests".into()))?
+            .post(url.as_str())
+            .body(payload)
+            .headers(headers);
+
+        let start = Date::now().as_millis();
+        let reqwest_resp = reqwest_req
+            .send()
+            .await
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let end = Date::now().as_millis();
+        console_log!("request to {} completed in {}ms", url, end - start);
+        let status = reqwest_resp.status();
+        if status == 200 {
+            // Translate the reqwest response into a Worker response.
+            let content_type = reqwest_resp
+                .headers()
+                .get(reqwest_wasm::header::CONTENT_TYPE)
+                .ok_or_else(|| DapError::fatal(INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE))?
+                .to_str()
+                .map_err(|e| DapError::Fatal(e.to_string()))?;
+            let media_type = constants::parse_media_type(content_type);
+
+            let payload = reqwest_resp
+                .bytes()
+                .await
+                .map_err(|e| DapError::Fatal(e.to_string()))?
+                .to_vec();
+
+            Ok(DapResponse {
+                payload,
+                media_type,
+            })
+        } else {
+            console_error!("{}: request failed: {:?}", url, reqwest_resp);
+
+            // The Leader handles the Helper aborting with "batchOverlap", so pass it on.
+            if let Ok(problem_details) = reqwest_resp.json::<ProblemDetails>().await {
+                if problem_details.typ == DapAbort::BatchOverlap.to_problem_details().typ {
+                    return Err(DapError::Abort(DapAbort::BatchOverlap));
+                }
+            }
+            Err(DapError::fatal(INT_ERR_PEER_ABORT))
+        }
+    }
+}
+
 #[async_trait(?Send)]
 impl<'srv, 'req, D> DapAggregator<'srv, 'req, BearerToken> for DaphneWorkerConfig<D>
 where
@@ -325,6 +428,7 @@ where
             )?;
 
             // This is the opt-in / opt-out decision point.
+            global_config.check_taskprov_task_config(&task_config, self.get_current_time())?;
             if !self.taskprov_opt_in_decision(&task_config)? {
                 return Err(DapError::Abort(daphne::DapAbort::InvalidTask));
             }
@@ -485,6 +589,9 @@ where
         let span = task_config
             .as_ref()
             .batch_span_for_meta(part_batch_sel, report_meta)?;
+        let global_config = task_config.as_ref().global_config(&self.global_config);
+        let now = self.get_current_time();
+        let mut early_fails = HashMap::new();
 
         // Coalesce reports pertaining to the same ReportsProcessed or AggregateStore instance.
         let mut reports_processed_request_data: HashMap<String, Vec<String>> = HashMap::new();
@@ -498,8 +605,18 @@ where
             ));
             agg_store_request_bucket.push(bucket);
             for metadata in report_meta {
-                let durable_name =
-                    self.durable_name_report_store(task_config.as_ref(), &task_id_hex, metadata);
+                // Reports outside of the report storage window can't be checked for replay.
+                if !global_config.is_report_in_storage_window(now, metadata.time) {
+                    early_fails.insert(metadata.id.clone(), TransitionFailure::ReportDropped);
+                    continue;
+                }
+
+                let durable_name = self.durable_name_report_store(
+                    task_config.as_ref(),
+                    &task_id_hex,
+                    &metadata.id,
+                    metadata.time,
+                );
                 let report_id_hex = hex::encode(metadata.id.get_encoded());
                 let report_id_hex_set = reports_processed_request_data
                     .entry(durable_name)
@@ -548,12 +665,14 @@ where
         // Decide which reports to reject early. A report will be rejected if has been processed
         // but not collected or if it has not been proceessed but pertains to a batch that was
         // previously collected.
-        let mut early_fails = HashMap::new();
         for (bucket, collected) in agg_store_request_bucket
             .iter()
             .zip(agg_store_responses.into_iter())
         {
             for metadata in span.get(bucket).unwrap() {
+                if early_fails.contains_key(&metadata.id) {
+                    continue;
+                }
                 let processed = reports_processed.contains(&metadata.id);
                 if processed && !collected {
                     early_fails.insert(metadata.id.clone(), TransitionFailure::ReportReplayed);
@@ -573,6 +692,8 @@ where
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
 
+        // Each bucket is held by its own durable object, so the buckets are not marked atomically:
+        // if one of the requests fails, then the others may still succeed.
         let durable = self.durable();
         let mut requests = Vec::new();
         for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
@@ -590,6 +711,102 @@ where
         Ok(())
     }
 
+    async fn put_rejected_reports(
+        &self,
+        task_id: &Id,
+        rejected: &[(ReportId, Time, TransitionFailure)],
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+
+        // Record the failure reasons alongside the set of processed reports. Coalesce reports
+        // pertaining to the same ReportsProcessed instance.
+        let mut reports_processed_request_data: HashMap<String, Vec<(String, TransitionFailure)>> =
+            HashMap::new();
+        for (report_id, time, failure_reason) in rejected.iter() {
+            // A replay does not change the status of the original report.
+            if *failure_reason == TransitionFailure::ReportReplayed {
+                continue;
+            }
+
+            let durable_name = self.durable_name_report_store(
+                task_config.as_ref(),
+                &task_id_hex,
+                report_id,
+                *time,
+            );
+            reports_processed_request_data
+                .entry(durable_name)
+                .or_default()
+                .push((hex::encode(report_id.get_encoded()), *failure_reason));
+        }
+
+        let durable = self.durable();
+        let mut requests = Vec::new();
+        for (durable_name, rejected) in reports_processed_request_data.into_iter() {
+            requests.push(durable.post::<_, ()>(
+                BINDING_DAP_REPORTS_PROCESSED,
+                DURABLE_REPORTS_PROCESSED_PUT_REJECTED,
+                durable_name,
+                rejected,
+            ));
+        }
+        try_join_all(requests).await.map_err(dap_err)?;
+        Ok(())
+    }
+
+    async fn report_status(
+        &self,
+        task_id: &Id,
+        report_id: &ReportId,
+    ) -> std::result::Result<ReportStatus, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+        let report_id_hex = hex::encode(report_id.get_encoded());
+        let durable = self.durable();
+
+        // Reports are sharded by their timestamp, which is not known here. Look for the report in
+        // each epoch of the report storage window.
+        for epoch in self.report_storage_window_epochs() {
+            let durable_name = self.durable_name_report_store(
+                task_config.as_ref(),
+                &task_id_hex,
+                report_id,
+                epoch,
+            );
+            let status: Option<ReportStatus> = durable
+                .post(
+                    BINDING_DAP_REPORTS_PROCESSED,
+                    DURABLE_REPORTS_PROCESSED_STATUS,
+                    durable_name.clone(),
+                    &report_id_hex,
+                )
+                .await
+                .map_err(dap_err)?;
+            if let Some(status) = status {
+                return Ok(status);
+            }
+
+            // Only the Leader stores reports that are waiting to be processed.
+            if self.is_leader {
+                let pending: bool = durable
+                    .post(
+                        BINDING_DAP_REPORTS_PENDING,
+                        DURABLE_REPORTS_PENDING_CONTAINS,
+                        durable_name,
+                        &report_id_hex,
+                    )
+                    .await
+                    .map_err(dap_err)?;
+                if pending {
+                    return Ok(ReportStatus::Pending);
+                }
+            }
+        }
+
+        Ok(ReportStatus::Unknown)
+    }
+
     async fn current_batch(&self, task_id: &Id) -> std::result::Result<Id, DapError> {
         self.internal_current_batch(task_id).await
     }
@@ -614,7 +831,8 @@ where
                 self.durable_name_report_store(
                     task_config.as_ref(),
                     &task_id_hex,
-                    &report.metadata,
+                    &report.metadata.id,
+                    report.metadata.time,
                 ),
                 &report_hex,
             )
@@ -700,14 +918,14 @@ where
                 DapQueryConfig::TimeInterval => {
                     reports_per_part.insert(PartialBatchSelector::TimeInterval, reports);
                 }
-                DapQueryConfig::FixedSize { .. } => {
+                DapQueryConfig::FixedSize { max_batch_size } => {
                     let num_unassigned = reports.len();
                     let batch_assignments: Vec<BatchCount> = durable
                         .post(
                             BINDING_DAP_LEADER_BATCH_QUEUE,
                             DURABLE_LEADER_BATCH_QUEUE_ASSIGN,
                             durable_name_task(&task_config.as_ref().version, &task_id_hex),
-                            &(task_config.as_ref().min_batch_size, num_unassigned),
+                            &(max_batch_size, num_unassigned),
                         )
                         .await
                         .map_err(dap_err)?;
@@ -748,6 +966,7 @@ where
     async fn init_collect_job(
         &self,
         collect_req: &CollectReq,
+        created_at: Time,
     ) -> std::result::Result<Url, DapError> {
         let task_config = self.try_get_task_config(&collect_req.task_id).await?;
 
@@ -759,7 +978,7 @@ where
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_PUT,
                 durable_name_queue(0),
-                &collect_req,
+                (collect_req, created_at),
             )
             .await
             .map_err(dap_err)?;
@@ -815,6 +1034,7 @@ where
         task_id: &Id,
         collect_id: &Id,
         collect_resp: &CollectResp,
+        completed_at: Time,
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
         let durable = self.durable();
@@ -837,76 +1057,141 @@ where
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_FINISH,
                 durable_name_queue(0),
-                (collect_id, collect_resp),
+                (collect_id, collect_resp, completed_at),
             )
             .await
             .map_err(dap_err)?;
         Ok(())
     }
 
-    async fn send_http_post(
+    async fn fail_collect_job(
         &self,
-        req: DapRequest<BearerToken>,
-    ) -> std::result::Result<DapResponse, DapError> {
-        let (payload, url) = (req.payload, req.url);
+        _task_id: &Id,
+        collect_id: &Id,
+    ) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_FAIL,
+                durable_name_queue(0),
+                collect_id,
+            )
+            .await
+            .map_err(dap_err)
+    }
 
-        let mut headers = reqwest_wasm::header::HeaderMap::new();
-        if let Some(content_type) = req.media_type {
-            headers.insert(
-                reqwest_wasm::header::CONTENT_TYPE,
-                reqwest_wasm::header::HeaderValue::from_str(content_type)
-                    .map_err(|e| DapError::Fatal(e.to_string()))?,
-            );
-        }
+    async fn expire_collect_jobs(&self, now: Time) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_EXPIRE,
+                durable_name_queue(0),
+                (now, self.global_config.collect_job_ttl),
+            )
+            .await
+            .map_err(dap_err)
+    }
 
-        if let Some(bearer_token) = req.sender_auth {
-            headers.insert(
-                reqwest_wasm::header::HeaderName::from_static("dap-auth-token"),
-                reqwest_wasm::header::HeaderValue::from_str(bearer_token.as_ref())
-                    .map_err(|e| DapError::Fatal(e.to_string()))?,
-            );
-        }
+    async fn has_pending_reports(
+        &self,
+        task_id: &Id,
+        batch_sel: &BatchSelector,
+    ) -> std::result::Result<bool, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let count = match batch_sel {
+            // Reports in the batch interval are stored in the epochs that overlap with it.
+            BatchSelector::TimeInterval { batch_interval } => {
+                let mut epochs = Vec::new();
+                let mut epoch = self
+                    .global_config
+                    .report_storage_epoch(batch_interval.start);
+                while epoch < batch_interval.end() {
+                    epochs.push(epoch);
+                    epoch += self.global_config.report_storage_epoch_duration;
+                }
+                self.count_pending_reports(
+                    task_config.as_ref(),
+                    task_id,
+                    &epochs,
+                    Some(batch_interval),
+                )
+                .await?
+            }
 
-        let reqwest_req = self
-            .client
-            .as_ref()
-            .ok_or_else(|| DapError::Fatal("helper cannot send HTTP requests".into()))?
-            .post(url.as_str())
-            .body(payload)
-            .headers(headers);
+            // Reports are assigned to a batch only once they are drained for aggregation. No more
+            // reports are assigned to a saturated batch, but any pending report may be assigned to
+            // the batch currently being filled.
+            BatchSelector::FixedSizeByBatchId { batch_id } => {
+                let saturated_batch_ids: Vec<Id> = self
+                    .durable()
+                    .get(
+                        BINDING_DAP_LEADER_BATCH_QUEUE,
+                        DURABLE_LEADER_BATCH_QUEUE_LIST,
+                        durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+                    )
+                    .await
+                    .map_err(dap_err)?;
+                if saturated_batch_ids.contains(batch_id) {
+                    return Ok(false);
+                }
 
-        let start = Date::now().as_millis();
-        let reqwest_resp = reqwest_req
-            .send()
+                self.count_pending_reports(
+                    task_config.as_ref(),
+                    task_id,
+                    &self.report_storage_window_epochs(),
+                    None,
+                )
+                .await?
+            }
+        };
+        Ok(count > 0)
+    }
+
+    async fn pending_report_count(&self, task_id: &Id) -> std::result::Result<u64, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.count_pending_reports(
+            task_config.as_ref(),
+            task_id,
+            &self.report_storage_window_epochs(),
+            None,
+        )
+        .await
+    }
+
+    async fn ready_batches(&self, task_id: &Id) -> std::result::Result<Vec<Id>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let max_batch_size = match task_config.as_ref().query {
+            DapQueryConfig::FixedSize { max_batch_size } => max_batch_size,
+            DapQueryConfig::TimeInterval => return Err(DapError::fatal("query type mismatch")),
+        };
+
+        let saturated_batch_ids: Vec<Id> = self
+            .durable()
+            .get(
+                BINDING_DAP_LEADER_BATCH_QUEUE,
+                DURABLE_LEADER_BATCH_QUEUE_LIST,
+                durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+            )
             .await
-            .map_err(|e| DapError::Fatal(e.to_string()))?;
-        let end = Date::now().as_millis();
-        console_log!("request to {} completed in {}ms", url, end - start);
-        let status = reqwest_resp.status();
-        if status == 200 {
-            // Translate the reqwest response into a Worker response.
-            let content_type = reqwest_resp
-                .headers()
-                .get(reqwest_wasm::header::CONTENT_TYPE)
-                .ok_or_else(|| DapError::fatal(INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE))?
-                .to_str()
-                .map_err(|e| DapError::Fatal(e.to_string()))?;
-            let media_type = constants::media_type_for(content_type);
+            .map_err(dap_err)?;
 
-            let payload = reqwest_resp
-                .bytes()
-                .await
-                .map_err(|e| DapError::Fatal(e.to_string()))?
-                .to_vec();
+        // A batch is saturated once `max_batch_size` reports have been assigned to it. Check that
+        // these reports have also been aggregated.
+        let mut batch_ids = Vec::with_capacity(saturated_batch_ids.len());
+        for batch_id in saturated_batch_ids.into_iter() {
+            let batch_sel = BatchSelector::FixedSizeByBatchId {
+                batch_id: batch_id.clone(),
+            };
+            if self.is_batch_overlapping(task_id, &batch_sel).await? {
+                continue;
+            }
 
-            Ok(DapResponse {
-                payload,
-                media_type,
-            })
-        } else {
-            console_error!("{}: request failed: {:?}", url, reqwest_resp);
-            Err(DapError::fatal(INT_ERR_PEER_ABORT))
+            let agg_share = self.get_agg_share(task_id, &batch_sel).await?;
+            if agg_share.report_count >= max_batch_size {
+                batch_ids.push(batch_id);
+            }
         }
+        Ok(batch_ids)
     }
 }
 
@@ -962,4 +1247,58 @@ where
             None => Ok(None),
         }
     }
+
+    async fn put_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+        stored_agg_init_resp: &DapStoredAggInitResp,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .post(
+                BINDING_DAP_HELPER_STATE_STORE,
+                DURABLE_HELPER_STATE_PUT_AGG_INIT_RESP,
+                durable_helper_agg_init_resp_name(
+                    &task_config.as_ref().version,
+                    task_id,
+                    idempotency_key,
+                ),
+                hex::encode(stored_agg_init_resp.get_encoded()),
+            )
+            .await
+            .map_err(dap_err)?;
+        Ok(())
+    }
+
+    async fn get_agg_init_resp(
+        &self,
+        task_id: &Id,
+        idempotency_key: &Id,
+    ) -> std::result::Result<Option<DapStoredAggInitResp>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let res: Option<String> = self
+            .durable()
+            .post(
+                BINDING_DAP_HELPER_STATE_STORE,
+                DURABLE_HELPER_STATE_GET_AGG_INIT_RESP,
+                durable_helper_agg_init_resp_name(
+                    &task_config.as_ref().version,
+                    task_id,
+                    idempotency_key,
+                ),
+                (),
+            )
+            .await
+            .map_err(dap_err)?;
+
+        match res {
+            Some(stored_agg_init_resp_hex) => {
+                let data = hex::decode(&stored_agg_init_resp_hex)
+                    .map_err(|e| DapError::Fatal(e.to_string()))?;
+                Ok(Some(DapStoredAggInitResp::get_decoded(&data)?))
+            }
+            None => Ok(None),
+        }
+    }
 }
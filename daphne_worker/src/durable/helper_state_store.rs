@@ -5,7 +5,7 @@ use crate::{
     durable::{state_get, state_set_if_not_exists},
     int_err,
 };
-use daphne::{messages::Id, DapVersion};
+use daphne::{messages::Id, DapGlobalConfig, DapVersion};
 use std::time::Duration;
 use worker::*;
 
@@ -22,8 +22,25 @@ pub(crate) fn durable_helper_state_name(
     )
 }
 
+pub(crate) fn durable_helper_agg_init_resp_name(
+    version: &DapVersion,
+    task_id: &Id,
+    idempotency_key: &Id,
+) -> String {
+    format!(
+        "{}/task/{}/idempotency_key/{}",
+        version.as_ref(),
+        task_id.to_hex(),
+        idempotency_key.to_hex()
+    )
+}
+
 pub(crate) const DURABLE_HELPER_STATE_PUT: &str = "/internal/do/helper_state/put";
 pub(crate) const DURABLE_HELPER_STATE_GET: &str = "/internal/do/helper_state/get";
+pub(crate) const DURABLE_HELPER_STATE_PUT_AGG_INIT_RESP: &str =
+    "/internal/do/helper_state/put_agg_init_resp";
+pub(crate) const DURABLE_HELPER_STATE_GET_AGG_INIT_RESP: &str =
+    "/internal/do/helper_state/get_agg_init_resp";
 
 /// Durable Object (DO) for storing the Helper's state for a given aggregation job.
 ///
@@ -31,8 +48,13 @@ pub(crate) const DURABLE_HELPER_STATE_GET: &str = "/internal/do/helper_state/get
 ///
 /// - `DURABLE_HELPER_STATE_PUT`: Stores Helper's hex-encoded state.
 /// - `DURABLE_HELPER_STATE_GET`: Drains the Helper's hex-encoded state.
+/// - `DURABLE_HELPER_STATE_PUT_AGG_INIT_RESP`: Stores the hex-encoded response to an aggregate
+///   initialization request carrying an idempotency key, along with the request it answers.
+/// - `DURABLE_HELPER_STATE_GET_AGG_INIT_RESP`: Fetches the hex-encoded response to an aggregate
+///   initialization request carrying an idempotency key, along with the request it answers.
 ///
-/// The state blob is stored in `helper_state`.
+/// The state blob is stored in `helper_state` and the response in `agg_init_resp`. Responses are
+/// stored in an instance named by the idempotency key rather than the aggregation job.
 #[durable_object]
 pub struct HelperStateStore {
     state: State,
@@ -51,7 +73,8 @@ impl DurableObject for HelperStateStore {
     }
 
     async fn fetch(&mut self, mut req: Request) -> Result<Response> {
-        // Ensure this DO instance is garbage collected eventually.
+        // Ensure this DO instance is garbage collected eventually. The state of an aggregation job
+        // is deleted once the job's time to live has elapsed, if that happens first.
         if !self.touched
             && !state_set_if_not_exists::<bool>(&self.state, "touched", &true)
                 .await?
@@ -63,7 +86,10 @@ impl DurableObject for HelperStateStore {
                 .to_string()
                 .parse()
                 .map_err(int_err)?;
-            let scheduled_time = Duration::from_secs(secs);
+            let global_config: DapGlobalConfig =
+                serde_json::from_str(self.env.var("DAP_GLOBAL_CONFIG")?.to_string().as_ref())
+                    .map_err(int_err)?;
+            let scheduled_time = Duration::from_secs(secs.min(global_config.job_ttl));
             self.state.storage().set_alarm(scheduled_time).await?;
             self.touched = true;
         }
@@ -100,6 +126,26 @@ impl DurableObject for HelperStateStore {
                 Response::from_json(&helper_state)
             }
 
+            // Store the response to an aggregate initialization request.
+            //
+            // Input: `agg_resp_hex: String` (hex-encoded response)
+            (DURABLE_HELPER_STATE_PUT_AGG_INIT_RESP, Method::Post) => {
+                let agg_resp_hex: String = req.json().await?;
+                self.state
+                    .storage()
+                    .put("agg_init_resp", agg_resp_hex)
+                    .await?;
+                Response::from_json(&())
+            }
+
+            // Fetch the response to an aggregate initialization request.
+            //
+            // Output: `Option<String>` (hex-encoded response)
+            (DURABLE_HELPER_STATE_GET_AGG_INIT_RESP, Method::Post) => {
+                let agg_resp_hex: Option<String> = state_get(&self.state, "agg_init_resp").await?;
+                Response::from_json(&agg_resp_hex)
+            }
+
             _ => Err(int_err(format!(
                 "HelperStateStore: unexpected request: method={:?}; path={:?}",
                 req.method(),
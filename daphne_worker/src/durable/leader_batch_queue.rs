@@ -14,6 +14,7 @@ pub(crate) const DURABLE_LEADER_BATCH_QUEUE_ASSIGN: &str = "/internal/do/leader_
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_CURRENT: &str =
     "/internal/do/leader_batch_queue/current";
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_REMOVE: &str = "/internal/do/leader_batch_queue/remove";
+pub(crate) const DURABLE_LEADER_BATCH_QUEUE_LIST: &str = "/internal/do/leader_batch_queue/list";
 
 const CURRENT: &str = "current";
 const PENDING_PREFIX: &str = "pending";
@@ -35,9 +36,12 @@ pub(crate) enum LeaderBatchQueueResult {
 ///
 /// This object implements the following API endpoints:
 ///
-/// - `DURABLE_LEADER_BATCH_QUEUE_ASSIGN`: Assign the requested number of reports to batches.
+/// - `DURABLE_LEADER_BATCH_QUEUE_ASSIGN`: Assign the requested number of reports to batches,
+///   starting a new batch once `max_batch_size` reports have been assigned to the current one.
 /// - `DURABLE_LEADER_BATCH_QUEUE_CURRENT`: Return the ID of the oldest, non-yet-collected batch.
 /// - `DURABLE_LEADER_BATCH_QUEUE_REMOVE`: Remove the given batch from the queue.
+/// - `DURABLE_LEADER_BATCH_QUEUE_LIST`: Return the IDs of the saturated, not-yet-collected
+///   batches.
 ///
 /// The schema for data stored in instances of this DO is as follows:
 ///
@@ -115,6 +119,26 @@ impl DurableObject for LeaderBatchQueue {
                 }
             }
 
+            // Return the IDs of the not-yet-collected batches to which no more reports will be
+            // assigned, oldest first. These are all of the batches in the queue except for the one
+            // currently being filled.
+            //
+            // Output: `Vec<Id>`
+            (DURABLE_LEADER_BATCH_QUEUE_LIST, Method::Get) => {
+                let curr: Option<BatchCount> = state_get(&self.state, CURRENT).await?;
+                let queued: Vec<DurableOrdered<BatchCount>> =
+                    DurableOrdered::get_all(&self.state, PENDING_PREFIX).await?;
+                let batch_ids: Vec<Id> = queued
+                    .into_iter()
+                    .map(|queued| queued.into_item().batch_id)
+                    .filter(|batch_id| {
+                        curr.as_ref()
+                            .map_or(true, |curr| &curr.batch_id != batch_id)
+                    })
+                    .collect();
+                Response::from_json(&batch_ids)
+            }
+
             // Assign the requested number of reports to a sequence of batch IDs. For each batch
             // ID, return the number of reports assigned to the batch.
             //
@@ -160,7 +184,8 @@ impl DurableObject for LeaderBatchQueue {
             }
 
             // Remove the indicated batch (i.e., the hex-encoded batch ID) from the queue. This is
-            // done after the corresponding collect job is finished.
+            // done after the corresponding collect job is finished. If the batch is the one
+            // currently being filled, then subsequent reports are assigned to a new batch.
             //
             // Input: `batch_id_hex: String`
             (DURABLE_LEADER_BATCH_QUEUE_REMOVE, Method::Post) => {
@@ -170,6 +195,11 @@ impl DurableObject for LeaderBatchQueue {
                     self.state.storage().delete(&lookup_val).await?;
                 }
 
+                let curr: Option<BatchCount> = state_get(&self.state, CURRENT).await?;
+                if curr.map_or(false, |curr| curr.batch_id.to_hex() == batch_id_hex) {
+                    self.state.storage().delete(CURRENT).await?;
+                }
+
                 self.state.storage().delete(&lookup_key).await?;
                 console_debug!("LeaderBatchQueue: removed batch {}", batch_id_hex);
                 Response::from_json(&())
@@ -6,7 +6,7 @@ use crate::{
     int_err,
 };
 use daphne::{
-    messages::{CollectReq, CollectResp, Id},
+    messages::{CollectReq, CollectResp, Duration, Id, Time},
     DapCollectJob, DapVersion,
 };
 use prio::{
@@ -17,6 +17,10 @@ use worker::*;
 
 const PENDING_PREFIX: &str = "pending";
 const PROCESSED_PREFIX: &str = "processed";
+const CREATED_AT_PREFIX: &str = "created_at";
+const COMPLETED_AT_PREFIX: &str = "completed_at";
+const FAILED_PREFIX: &str = "failed";
+const EXPIRED_PREFIX: &str = "expired";
 
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_PUT: &str = "/internal/do/leader_col_job_queue/put";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET: &str = "/internal/do/leader_col_job_queue/get";
@@ -24,6 +28,9 @@ pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_FINISH: &str =
     "/internal/do/leader_col_job_queue/finish";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT: &str =
     "/internal/do/leader_col_job_queue/get_result";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_FAIL: &str = "/internal/do/leader_col_job_queue/fail";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_EXPIRE: &str =
+    "/internal/do/leader_col_job_queue/expire";
 
 /// Durable Object (DO) for storing the Leader's state for a given task.
 ///
@@ -32,6 +39,10 @@ pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT: &str =
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_PUT:` Create a collection job for a CollectReq.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_GET`: Get the entire list of pending collection jobs.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_FINISH`: Complete a collection job and store the CollectResp.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_FAIL`: Remove a collection job that can't be completed from
+///   the pending queue.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_EXPIRE`: Remove the collection jobs that have been pending for
+///   too long from the pending queue.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT`: Poll the queue to see if a collect job is
 ///   complete.
 ///
@@ -42,6 +53,10 @@ pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT: &str =
 /// [Pending queue]     pending/next_ordinal -> u64
 /// [Pending queue]     pending/item/order/<order> -> (Id, CollectReq)
 /// [Processed]         processed/<collect_id> -> CollectResp
+/// [Created at]        created_at/<collect_id> -> Time
+/// [Completed at]      completed_at/<collect_id> -> Time
+/// [Failed]            failed/<collect_id> -> bool
+/// [Expired]           expired/<collect_id> -> bool
 /// ```
 ///
 /// Note that the queue ordinal format is inherited from [`DurableOrdered::new_strictly_ordered`].
@@ -72,10 +87,10 @@ impl DurableObject for LeaderCollectionJobQueue {
         match (req.path().as_ref(), req.method()) {
             // Create a collect job for a collect request issued by the Collector.
             //
-            // Input: `collect_req: CollectReq`
+            // Input: `(collect_req, created_at): (CollectReq, Time)`
             // Output: `Id` (collect job ID)
             (DURABLE_LEADER_COL_JOB_QUEUE_PUT, Method::Post) => {
-                let collect_req: CollectReq = req.json().await?;
+                let (collect_req, created_at): (CollectReq, Time) = req.json().await?;
 
                 // Compute the collect job ID, used to derive the collect URI for this request.
                 // This value is computed by applying a pseudorandom function to the request. This
@@ -123,6 +138,13 @@ impl DurableObject for LeaderCollectionJobQueue {
                         .storage()
                         .put(&lookup_key(&collect_id_hex), &queued.key())
                         .await?;
+                    self.state
+                        .storage()
+                        .put(
+                            &format!("{}/{}", CREATED_AT_PREFIX, collect_id_hex),
+                            created_at,
+                        )
+                        .await?;
                 }
                 Response::from_json(&collect_id_hex)
             }
@@ -142,9 +164,10 @@ impl DurableObject for LeaderCollectionJobQueue {
 
             // Remove a collection job from the pending queue and store the CollectResp.
             //
-            // Input: `(collect_id, collect_resp): (Id, CollectResp)`
+            // Input: `(collect_id, collect_resp, completed_at): (Id, CollectResp, Time)`
             (DURABLE_LEADER_COL_JOB_QUEUE_FINISH, Method::Post) => {
-                let (collect_id, collect_resp): (Id, CollectResp) = req.json().await?;
+                let (collect_id, collect_resp, completed_at): (Id, CollectResp, Time) =
+                    req.json().await?;
                 let collect_id_hex = collect_id.to_hex();
                 let processed_key = format!("{}/{}", PROCESSED_PREFIX, collect_id_hex);
                 let processed: Option<CollectResp> = state_get(&self.state, &processed_key).await?;
@@ -165,17 +188,77 @@ impl DurableObject for LeaderCollectionJobQueue {
                 let mut storage = self.state.storage();
                 let f = storage.delete(&pending_lookup_key);
 
-                // Store the CollectResp.
+                // Store the CollectResp and the completion time.
                 self.state
                     .storage()
                     .put(&processed_key, collect_resp)
                     .await?;
+                self.state
+                    .storage()
+                    .put(
+                        &format!("{}/{}", COMPLETED_AT_PREFIX, collect_id_hex),
+                        completed_at,
+                    )
+                    .await?;
 
                 // Remove the lookup key.
                 f.await?;
                 Response::from_json(&())
             }
 
+            // Remove a collection job that can't be completed from the pending queue and mark it
+            // as failed. Jobs that are not pending are left as is.
+            //
+            // Input: `collect_id: Id`
+            (DURABLE_LEADER_COL_JOB_QUEUE_FAIL, Method::Post) => {
+                let collect_id: Id = req.json().await?;
+                let collect_id_hex = collect_id.to_hex();
+                let pending_lookup_key = lookup_key(&collect_id_hex);
+                if let Some(lookup_val) =
+                    state_get::<String>(&self.state, &pending_lookup_key).await?
+                {
+                    self.state.storage().delete(&lookup_val).await?;
+                    self.state
+                        .storage()
+                        .put(&format!("{}/{}", FAILED_PREFIX, collect_id_hex), true)
+                        .await?;
+                    self.state.storage().delete(&pending_lookup_key).await?;
+                }
+                Response::from_json(&())
+            }
+
+            // Remove each collection job that was created more than `collect_job_ttl` seconds
+            // before `now` from the pending queue and mark it as expired.
+            //
+            // Input: `(now, collect_job_ttl): (Time, Duration)`
+            (DURABLE_LEADER_COL_JOB_QUEUE_EXPIRE, Method::Post) => {
+                let (now, collect_job_ttl): (Time, Duration) = req.json().await?;
+                let queue: Vec<DurableOrdered<(Id, CollectReq)>> =
+                    DurableOrdered::get_all(&self.state, PENDING_PREFIX).await?;
+                for queued in queue {
+                    let collect_id_hex = queued.as_ref().0.to_hex();
+                    let created_at: Time = state_get_or_default(
+                        &self.state,
+                        &format!("{}/{}", CREATED_AT_PREFIX, collect_id_hex),
+                    )
+                    .await?;
+                    if now.saturating_sub(created_at) <= collect_job_ttl {
+                        continue;
+                    }
+
+                    self.state.storage().delete(&queued.key()).await?;
+                    self.state
+                        .storage()
+                        .put(&format!("{}/{}", EXPIRED_PREFIX, collect_id_hex), true)
+                        .await?;
+                    self.state
+                        .storage()
+                        .delete(&lookup_key(&collect_id_hex))
+                        .await?;
+                }
+                Response::from_json(&())
+            }
+
             // Check if a collection job is complete.
             //
             // Input: `collect_id: Id`
@@ -189,13 +272,41 @@ impl DurableObject for LeaderCollectionJobQueue {
                     .is_some();
                 let processed_key = format!("{}/{}", PROCESSED_PREFIX, collect_id_hex);
                 let processed: Option<CollectResp> = state_get(&self.state, &processed_key).await?;
+                let created_at: Time = state_get_or_default(
+                    &self.state,
+                    &format!("{}/{}", CREATED_AT_PREFIX, collect_id_hex),
+                )
+                .await?;
                 if let Some(collect_resp) = processed {
                     if pending {
                         self.state.storage().delete(&pending_lookup_key).await?;
                     }
-                    Response::from_json(&DapCollectJob::Done(collect_resp))
+                    let completed_at: Time = state_get_or_default(
+                        &self.state,
+                        &format!("{}/{}", COMPLETED_AT_PREFIX, collect_id_hex),
+                    )
+                    .await?;
+                    Response::from_json(&DapCollectJob::Done {
+                        collect_resp,
+                        created_at,
+                        completed_at,
+                    })
                 } else if pending {
-                    Response::from_json(&DapCollectJob::Pending)
+                    Response::from_json(&DapCollectJob::Pending { created_at })
+                } else if state_get_or_default(
+                    &self.state,
+                    &format!("{}/{}", FAILED_PREFIX, collect_id_hex),
+                )
+                .await?
+                {
+                    Response::from_json(&DapCollectJob::Failed { created_at })
+                } else if state_get_or_default(
+                    &self.state,
+                    &format!("{}/{}", EXPIRED_PREFIX, collect_id_hex),
+                )
+                .await?
+                {
+                    Response::from_json(&DapCollectJob::Expired { created_at })
                 } else {
                     Response::from_json(&DapCollectJob::Unknown)
                 }
@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{int_err, now};
-use daphne::{messages::Id, DapBatchBucket, DapVersion};
+use daphne::{
+    messages::{Id, Time},
+    DapBatchBucket, DapVersion,
+};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use worker::*;
@@ -236,6 +239,21 @@ pub(crate) fn report_id_hex_from_report(report_hex: &str) -> Option<&str> {
     Some(&report_hex[..32])
 }
 
+pub(crate) fn report_time_from_report(report_hex: &str) -> Option<Time> {
+    // task_id (32 bytes), metadata.id (16 bytes)
+    if report_hex.len() < 96 {
+        return None;
+    }
+    let report_hex = &report_hex[96..];
+
+    // metadata.time
+    if report_hex.len() < 16 {
+        return None;
+    }
+    let time_bytes = hex::decode(&report_hex[..16]).ok()?;
+    Some(Time::from_be_bytes(time_bytes.try_into().ok()?))
+}
+
 /// Reference to a DO instance, used by the garbage collector.
 #[derive(Deserialize, Serialize)]
 pub(crate) struct DurableReference {
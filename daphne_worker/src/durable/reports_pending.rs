@@ -7,16 +7,20 @@ use crate::{
         leader_agg_job_queue::{
             DURABLE_LEADER_AGG_JOB_QUEUE_FINISH, DURABLE_LEADER_AGG_JOB_QUEUE_PUT,
         },
-        report_id_hex_from_report, state_get, state_set_if_not_exists, DurableConnector,
-        DurableOrdered, BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING,
+        report_id_hex_from_report, report_time_from_report, state_get, state_set_if_not_exists,
+        DurableConnector, DurableOrdered, BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+        BINDING_DAP_REPORTS_PENDING,
     },
     int_err,
 };
+use daphne::messages::Interval;
 use serde::{Deserialize, Serialize};
 use worker::*;
 
 pub(crate) const DURABLE_REPORTS_PENDING_GET: &str = "/internal/do/reports_pending/get";
 pub(crate) const DURABLE_REPORTS_PENDING_PUT: &str = "/internal/do/reports_pending/put";
+pub(crate) const DURABLE_REPORTS_PENDING_CONTAINS: &str = "/internal/do/reports_pending/contains";
+pub(crate) const DURABLE_REPORTS_PENDING_COUNT: &str = "/internal/do/reports_pending/count";
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -38,6 +42,12 @@ pub(crate) enum ReportsPendingResult {
 ///   aggregated. Whenever the instance becomes empty, the aggregation job is removed from
 ///   `LeadeerAggregationJobQueue`.
 ///
+/// - `DURABLE_REPORTS_PENDING_CONTAINS`: Used to check whether a report with a given ID is
+///   waiting to be processed.
+///
+/// - `DURABLE_REPORTS_PENDING_COUNT`: Used to count the reports waiting to be processed,
+///   optionally only those whose timestamp falls into a given interval.
+///
 /// The schema for stored reports is as follows:
 ///
 /// ```text
@@ -220,6 +230,48 @@ impl DurableObject for ReportsPending {
                 Response::from_json(&ReportsPendingResult::Ok)
             }
 
+            // Check whether a report is waiting to be processed.
+            //
+            // Input: `report_id_hex: String` (hex-encoded report ID)
+            // Output: `bool`
+            (DURABLE_REPORTS_PENDING_CONTAINS, Method::Post) => {
+                let report_id_hex: String = req.json().await?;
+                let report_hex: Option<String> =
+                    state_get(&self.state, &format!("pending/{}", report_id_hex)).await?;
+                Response::from_json(&report_hex.is_some())
+            }
+
+            // Count the reports waiting to be processed. If an interval is given, then only the
+            // reports whose timestamp falls into the interval are counted.
+            //
+            // Input: `interval: Option<Interval>`
+            // Output: `u64`
+            (DURABLE_REPORTS_PENDING_COUNT, Method::Post) => {
+                let interval: Option<Interval> = req.json().await?;
+                let iter = self
+                    .state
+                    .storage()
+                    .list_with_options(ListOptions::new().prefix("pending/"))
+                    .await?
+                    .entries();
+                let mut count = 0_u64;
+                let mut item = iter.next()?;
+                while !item.done() {
+                    // TODO(issue #118) Remove this deprecated dependency.
+                    #[allow(deprecated)]
+                    let (_key, report_hex): (String, String) = item.value().into_serde()?;
+                    let time = report_time_from_report(&report_hex)
+                        .ok_or_else(|| int_err("failed to parse time from report"))?;
+                    if interval.as_ref().map_or(true, |interval| {
+                        interval.start <= time && time < interval.end()
+                    }) {
+                        count += 1;
+                    }
+                    item = iter.next()?;
+                }
+                Response::from_json(&count)
+            }
+
             _ => Err(int_err(format!(
                 "ReportsPending: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -2,25 +2,36 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{
-    durable::{state_set_if_not_exists, BINDING_DAP_REPORTS_PROCESSED},
+    durable::{state_get, state_set_if_not_exists, BINDING_DAP_REPORTS_PROCESSED},
     int_err,
 };
+use daphne::{messages::TransitionFailure, ReportStatus};
 use futures::future::try_join_all;
 use worker::*;
 
 pub(crate) const DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED: &str =
     "/internal/do/report_store/mark_aggregated";
+pub(crate) const DURABLE_REPORTS_PROCESSED_PUT_REJECTED: &str =
+    "/internal/do/report_store/put_rejected";
+pub(crate) const DURABLE_REPORTS_PROCESSED_STATUS: &str = "/internal/do/report_store/status";
 
 /// Durable Object (DO) for tracking which reports have been processed.
 ///
-/// This object defines a single API endpoint, `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`, which
-/// is used to mark a set of reports as aggregated. It returns the set of reports in that have
-/// already been aggregated (and thus need to be rejected by the caller).
+/// This object implements the following API endpoints:
+///
+/// - `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`: Used to mark a set of reports as aggregated. It
+///   returns the set of reports in that have already been aggregated (and thus need to be
+///   rejected by the caller).
+/// - `DURABLE_REPORTS_PROCESSED_PUT_REJECTED`: Used to record the reason each of a set of reports
+///   was rejected.
+/// - `DURABLE_REPORTS_PROCESSED_STATUS`: Used to look up the status of a report. A report that was
+///   processed and not recorded as rejected was aggregated.
 ///
 /// The schema for stored report IDs is as follows:
 ///
 /// ```text
 ///     processed/<report_id> -> bool
+///     rejected/<report_id> -> TransitionFailure
 /// ```
 ///
 /// where `<report_id>` is the hex-encoded report ID.
@@ -79,6 +90,37 @@ impl DurableObject for ReportsProcessed {
                 Response::from_json(&res)
             }
 
+            // Record the reason each of a set of reports was rejected.
+            //
+            // Input: `rejected: Vec<(String, TransitionFailure)>` (hex-encoded report IDs and
+            // failure reasons)
+            (DURABLE_REPORTS_PROCESSED_PUT_REJECTED, Method::Post) => {
+                let rejected: Vec<(String, TransitionFailure)> = req.json().await?;
+                for (report_id_hex, failure_reason) in rejected.into_iter() {
+                    let key = format!("rejected/{}", report_id_hex);
+                    self.state.storage().put(&key, failure_reason).await?;
+                }
+                Response::from_json(&())
+            }
+
+            // Look up the status of a report.
+            //
+            // Input: `report_id_hex: String` (hex-encoded report ID)
+            // Output: `Option<ReportStatus>` (`None` if the report has not been processed)
+            (DURABLE_REPORTS_PROCESSED_STATUS, Method::Post) => {
+                let report_id_hex: String = req.json().await?;
+                let rejected: Option<TransitionFailure> =
+                    state_get(&self.state, &format!("rejected/{}", report_id_hex)).await?;
+                let processed: Option<bool> =
+                    state_get(&self.state, &format!("processed/{}", report_id_hex)).await?;
+                let status = match (rejected, processed) {
+                    (Some(failure_reason), _) => Some(ReportStatus::Rejected(failure_reason)),
+                    (None, Some(true)) => Some(ReportStatus::Aggregated),
+                    (None, _) => None,
+                };
+                Response::from_json(&status)
+            }
+
             _ => Err(int_err(format!(
                 "ReportsProcessed: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -221,7 +221,10 @@ impl DaphneWorkerRouter {
         let router = Router::new()
             .get_async("/:version/hpke_config", |req, ctx| async move {
                 let config = DaphneWorkerConfig::from_worker_context(ctx)?;
-                let req = config.worker_request_to_dap(req).await?;
+                let req = match config.worker_request_to_dap(req).await? {
+                    Ok(req) => req,
+                    Err(e) => return abort(e),
+                };
 
                 // TODO(cjpatton) Have this method return a DapResponse.
                 match config.http_get_hpke_config(&req).await {
@@ -229,6 +232,13 @@ impl DaphneWorkerRouter {
                     Err(e) => abort(e),
                 }
             })
+            .get_async("/aggregator_info", |_req, ctx| async move {
+                let config = DaphneWorkerConfig::from_worker_context(ctx)?;
+                match config.http_get_aggregator_info().await {
+                    Ok(resp) => dap_response_to_worker(resp),
+                    Err(e) => abort(e),
+                }
+            })
             .post_async("/task", |mut req, ctx| async move {
                 let config = DaphneWorkerConfig::from_worker_context(ctx)?;
                 let admin_token = req
@@ -256,7 +266,10 @@ impl DaphneWorkerRouter {
                 router
                     .post_async("/:version/upload", |req, ctx| async move {
                         let config = DaphneWorkerConfig::from_worker_context(ctx)?;
-                        let req = config.worker_request_to_dap(req).await?;
+                        let req = match config.worker_request_to_dap(req).await? {
+                            Ok(req) => req,
+                            Err(e) => return abort(e),
+                        };
 
                         match config.http_post_upload(&req).await {
                             Ok(()) => Response::empty(),
@@ -265,7 +278,10 @@ impl DaphneWorkerRouter {
                     })
                     .post_async("/:version/collect", |req, ctx| async move {
                         let config = DaphneWorkerConfig::from_worker_context(ctx)?;
-                        let req = config.worker_request_to_dap(req).await?;
+                        let req = match config.worker_request_to_dap(req).await? {
+                            Ok(req) => req,
+                            Err(e) => return abort(e),
+                        };
 
                         match config.http_post_collect(&req).await {
                             Ok(collect_uri) => {
@@ -286,16 +302,22 @@ impl DaphneWorkerRouter {
                             let collect_id = parse_id!(ctx.param("collect_id"));
                             let config = DaphneWorkerConfig::from_worker_context(ctx)?;
                             match config.poll_collect_job(&task_id, &collect_id).await {
-                                Ok(DapCollectJob::Done(collect_resp)) => {
+                                Ok(DapCollectJob::Done { collect_resp, .. }) => {
                                     dap_response_to_worker(DapResponse {
                                         media_type: Some(constants::MEDIA_TYPE_COLLECT_RESP),
                                         payload: collect_resp.get_encoded(),
                                     })
                                 }
-                                Ok(DapCollectJob::Pending) => {
+                                Ok(DapCollectJob::Pending { .. }) => {
                                     Ok(Response::empty().unwrap().with_status(202))
                                 }
                                 // TODO spec: Decide whether to define this behavior.
+                                Ok(DapCollectJob::Expired { .. }) => {
+                                    abort(DapAbort::BadRequest("collect job expired".into()))
+                                }
+                                Ok(DapCollectJob::Failed { .. }) => {
+                                    abort(DapAbort::BadRequest("collect job failed".into()))
+                                }
                                 Ok(DapCollectJob::Unknown) => {
                                     abort(DapAbort::BadRequest("unknown collect id".into()))
                                 }
@@ -303,6 +325,26 @@ impl DaphneWorkerRouter {
                             }
                         },
                     )
+                    .get_async(
+                        "/:version/fixed_size_batches/task/:task_id",
+                        |req, ctx| async move {
+                            let task_id = parse_id!(ctx.param("task_id"));
+                            let config = DaphneWorkerConfig::from_worker_context(ctx)?;
+                            let mut req = match config.worker_request_to_dap(req).await? {
+                                Ok(req) => req,
+                                Err(e) => return abort(e),
+                            };
+
+                            // The task ID is taken from the URL rather than from the request
+                            // payload, which is empty.
+                            req.task_id = Some(task_id);
+                            req.media_type = Some(constants::MEDIA_TYPE_FIXED_SIZE_BATCHES);
+                            match config.http_get_fixed_size_batches(&req).await {
+                                Ok(resp) => dap_response_to_worker(resp),
+                                Err(e) => abort(e),
+                            }
+                        },
+                    )
                     .post_async("/internal/process", |mut req, ctx| async move {
                         // TODO(cjpatton) Only enable this if `self.enable_internal_test` is set.
                         let config = DaphneWorkerConfig::from_worker_context(ctx)?;
@@ -337,7 +379,10 @@ impl DaphneWorkerRouter {
             "helper" => router
                 .post_async("/:version/aggregate", |req, ctx| async move {
                     let config = DaphneWorkerConfig::from_worker_context(ctx)?;
-                    let req = config.worker_request_to_dap(req).await?;
+                    let req = match config.worker_request_to_dap(req).await? {
+                        Ok(req) => req,
+                        Err(e) => return abort(e),
+                    };
 
                     match config.http_post_aggregate(&req).await {
                         Ok(resp) => dap_response_to_worker(resp),
@@ -346,7 +391,10 @@ impl DaphneWorkerRouter {
                 })
                 .post_async("/:version/aggregate_share", |req, ctx| async move {
                     let config = DaphneWorkerConfig::from_worker_context(ctx)?;
-                    let req = config.worker_request_to_dap(req).await?;
+                    let req = match config.worker_request_to_dap(req).await? {
+                        Ok(req) => req,
+                        Err(e) => return abort(e),
+                    };
 
                     match config.http_post_aggregate_share(&req).await {
                         Ok(resp) => dap_response_to_worker(resp),
@@ -452,8 +500,14 @@ fn abort(e: DapAbort) -> Result<Response> {
         _ => {
             let mut headers = Headers::new();
             headers.set("Content-Type", "application/problem+json")?;
+            let status = if let Some(retry_after) = e.retry_after() {
+                headers.set("Retry-After", &retry_after.to_string())?;
+                429
+            } else {
+                400
+            };
             Ok(Response::from_json(&e.to_problem_details())?
-                .with_status(400)
+                .with_status(status)
                 .with_headers(headers))
         }
     }
@@ -496,7 +550,11 @@ pub(crate) struct InternalTestAddTask {
     #[serde(skip_serializing_if = "Option::is_none")]
     max_batch_size: Option<u64>,
     time_precision: Duration,
-    collector_hpke_config: String, // base64url
+    #[serde(
+        alias = "collector_hpke_config",
+        deserialize_with = "daphne::deserialize_one_or_many"
+    )]
+    collector_hpke_configs: Vec<String>, // base64url
     task_expiration: Time,
 }
 
@@ -15,7 +15,7 @@ use daphne::{
         Report, ReportId, ReportMetadata,
     },
     taskprov::{compute_task_id, TaskprovVersion},
-    DapAggregateResult, DapMeasurement, DapTaskConfig, DapVersion,
+    AggShareCompression, DapAggregateResult, DapMeasurement, DapTaskConfig, DapVersion,
 };
 use daphne_worker::DaphneWorkerReportSelector;
 use paste::paste;
@@ -354,7 +354,7 @@ async fn e2e_leader_upload(version: DapVersion) {
             },
         ],
         query_config: QueryConfig {
-            time_precision: 0x01,
+            time_precision: TIME_PRECISION,
             max_batch_query_count: 128,
             min_batch_size: 1024,
             var: QueryConfigVar::FixedSize {
@@ -646,6 +646,7 @@ async fn e2e_leader_collect_ok(version: DapVersion) {
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -695,6 +696,7 @@ async fn e2e_leader_collect_ok(version: DapVersion) {
             },
             collect_resp.report_count,
             collect_resp.encrypted_agg_shares.clone(),
+            AggShareCompression::None,
             version,
         )
         .await
@@ -783,6 +785,7 @@ async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let _collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -834,6 +837,7 @@ async fn e2e_leader_collect_not_ready_min_batch_size(version: DapVersion) {
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -902,6 +906,7 @@ async fn e2e_leader_collect_accept_global_config_max_batch_duration(version: Dap
         task_id: t.task_id.clone(),
         query: Query::TimeInterval { batch_interval },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let _collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -926,6 +931,7 @@ async fn e2e_leader_collect_abort_invalid_batch_interval(version: DapVersion) {
             },
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     t.leader_post_expect_abort(
         &client,
@@ -948,6 +954,7 @@ async fn e2e_leader_collect_abort_invalid_batch_interval(version: DapVersion) {
             },
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     t.leader_post_expect_abort(
         &client,
@@ -999,6 +1006,7 @@ async fn e2e_leader_collect_abort_overlapping_batch_interval(version: DapVersion
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let _collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -1041,6 +1049,7 @@ async fn e2e_leader_collect_abort_overlapping_batch_interval(version: DapVersion
             },
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     t.leader_post_expect_abort(
         &client,
@@ -1123,6 +1132,7 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
             }
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded_with_param(&t.version))
@@ -1158,6 +1168,7 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
             },
             collect_resp.report_count,
             collect_resp.encrypted_agg_shares.clone(),
+            AggShareCompression::None,
             version,
         )
         .await
@@ -1218,6 +1229,7 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
                 batch_id: prev_batch_id.clone(),
             },
             agg_param: Vec::new(),
+            collector_hpke_config_id: None,
         }
         .get_encoded_with_param(&t.version),
         400,
@@ -1313,6 +1325,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let collect_uri = t
         .leader_post_collect_using_token(
@@ -1365,6 +1378,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             },
             collect_resp.report_count,
             collect_resp.encrypted_agg_shares.clone(),
+            AggShareCompression::None,
             version,
         )
         .await
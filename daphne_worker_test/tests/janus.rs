@@ -109,6 +109,7 @@ async fn janus_helper() {
             batch_interval: batch_interval.clone(),
         },
         agg_param: Vec::new(),
+        collector_hpke_config_id: None,
     };
     let collect_uri = t
         .leader_post_collect(&client, collect_req.get_encoded())
@@ -9,7 +9,8 @@ use daphne::{
     hpke::HpkeReceiverConfig,
     messages::{Duration, HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId, Id, Interval},
     taskprov::TaskprovVersion,
-    DapGlobalConfig, DapLeaderProcessTelemetry, DapQueryConfig, DapTaskConfig, DapVersion,
+    AggShareCompression, ChecksumAlgorithm, DapGlobalConfig, DapLeaderProcessTelemetry,
+    DapQueryConfig, DapTaskConfig, DapVersion, HpkeConfigMissingTaskIdPolicy, NoiseSplit,
     Prio3Config, VdafConfig,
 };
 use daphne_worker::DaphneWorkerReportSelector;
@@ -109,24 +110,45 @@ impl TestRunner {
             version: version,
             leader_url: leader_url.clone(),
             helper_url: helper_url.clone(),
+            start: 0,
             expiration: now + 604800, // one week from now
             time_precision: TIME_PRECISION,
+            storage_precision: None,
             min_batch_size: MIN_BATCH_SIZE,
             query: query_config.clone(),
             vdaf: VDAF_CONFIG.clone(),
             vdaf_verify_key: VDAF_CONFIG.gen_verify_key(),
-            collector_hpke_config: collector_hpke_receiver.config.clone(),
+            collector_hpke_configs: vec![collector_hpke_receiver.config.clone()],
+            max_pending_reports: None,
+            allowed_extension_types: None,
+            require_latest_hpke_config: false,
+            require_aligned_report_time: false,
+            global_config_override: None,
         };
 
         // This block needs to be kept in-sync with daphne_worker_test/wrangler.toml.
         let global_config = DapGlobalConfig {
             report_storage_epoch_duration: 604800,
             max_batch_duration: 360000,
+            enforce_fixed_size_batch_duration: false,
             min_batch_interval_start: 259200,
             max_batch_interval_end: 259200,
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            max_extensions: 16,
+            max_extension_len: 4096,
             allow_taskprov: true,
             taskprov_version: TaskprovVersion::Draft02,
+            min_time_precision: 3600,
+            max_batch_buckets: 8760,
+            allowed_query_types: None,
+            max_pending_collect_jobs: 100,
+            max_agg_share_buckets: 8760,
+            job_ttl: 3600,
+            collect_job_ttl: 3600,
+            hpke_config_missing_task_id_policy: HpkeConfigMissingTaskIdPolicy::ReturnDefault,
+            agg_share_compression: AggShareCompression::None,
+            noise_split: NoiseSplit::None,
+            checksum_algorithm: ChecksumAlgorithm::Sha256Xor,
         };
         let taskprov_vdaf_verify_key_init =
             hex::decode("0074a5dd6e9dac501f73f7a961193b2b").unwrap();